@@ -16,6 +16,91 @@ SET xmloption = content;
 SET client_min_messages = warning;
 SET row_security = off;
 
+--
+-- Name: audit_user_change(); Type: FUNCTION; Schema: public; Owner: -
+--
+
+CREATE FUNCTION public.audit_user_change() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+DECLARE
+    action TEXT;
+    target users;
+    old_row JSONB;
+    new_row JSONB;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        action := 'user.created';
+        target := NEW;
+        new_row := to_jsonb(NEW) - ARRAY['tenant_id', 'email_hash', 'search_tokens', 'search_vector'];
+    ELSIF TG_OP = 'DELETE' THEN
+        action := 'user.purged';
+        target := OLD;
+        old_row := to_jsonb(OLD) - ARRAY['tenant_id', 'email_hash', 'search_tokens', 'search_vector'];
+    ELSE
+        IF NEW.id::text = current_setting('app.erased_user_id', true) THEN
+            action := 'user.erased';
+        ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+            action := 'user.deleted';
+        ELSE
+            action := 'user.updated';
+        END IF;
+        target := NEW;
+        SELECT jsonb_object_agg(key, old_json -> key), jsonb_object_agg(key, new_json -> key)
+        INTO old_row, new_row
+        FROM (
+            SELECT
+                to_jsonb(OLD) - ARRAY['tenant_id', 'email_hash', 'search_tokens', 'search_vector'] AS old_json,
+                to_jsonb(NEW) - ARRAY['tenant_id', 'email_hash', 'search_tokens', 'search_vector'] AS new_json
+        ) AS rows,
+            jsonb_object_keys(new_json) AS key
+        WHERE new_json -> key IS DISTINCT FROM old_json -> key;
+
+        IF new_row IS NULL THEN
+            RETURN NULL;
+        END IF;
+        IF action = 'user.erased' THEN
+            old_row := old_row - ARRAY['first_name', 'last_name', 'email_address'];
+            new_row := new_row - ARRAY['first_name', 'last_name', 'email_address'];
+        END IF;
+    END IF;
+
+    INSERT INTO audit_events(
+        id, tenant_id, actor_id, action, target_type, target_id, before, after,
+        request_id, ip_address, created_at
+    )
+    VALUES (
+        uuid_generate_v7(),
+        target.tenant_id,
+        NULLIF(current_setting('app.actor_id', true), '')::uuid,
+        action,
+        'user',
+        target.id,
+        old_row,
+        new_row,
+        NULLIF(current_setting('app.request_id', true), ''),
+        NULLIF(current_setting('app.ip_address', true), ''),
+        timezone('utc', now())
+    );
+    RETURN NULL;
+END;
+$$;
+
+
+--
+-- Name: current_tenant_id(); Type: FUNCTION; Schema: public; Owner: -
+--
+
+CREATE FUNCTION public.current_tenant_id() RETURNS uuid
+    LANGUAGE sql STABLE
+    AS $$
+    SELECT COALESCE(
+        NULLIF(current_setting('app.tenant_id', true), ''),
+        '00000000-0000-0000-0000-000000000000'
+    )::uuid
+$$;
+
+
 --
 -- Name: diesel_manage_updated_at(regclass); Type: FUNCTION; Schema: public; Owner: -
 --
@@ -49,6 +134,139 @@ END;
 $$;
 
 
+--
+-- Name: erase_user_audit_events(uuid); Type: FUNCTION; Schema: public; Owner: -
+--
+
+CREATE FUNCTION public.erase_user_audit_events(target uuid) RETURNS void
+    LANGUAGE sql SECURITY DEFINER
+    SET search_path TO 'public'
+    AS $$
+    UPDATE audit_events
+    SET before = before - ARRAY['first_name', 'last_name', 'email_address'],
+        after = after - ARRAY['first_name', 'last_name', 'email_address']
+    WHERE target_type = 'user' AND target_id = target AND tenant_id = current_tenant_id()
+$$;
+
+
+--
+-- Name: increment_user_version(); Type: FUNCTION; Schema: public; Owner: -
+--
+
+CREATE FUNCTION public.increment_user_version() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+    IF NEW IS DISTINCT FROM OLD THEN
+        NEW.version := OLD.version + 1;
+    END IF;
+    RETURN NEW;
+END;
+$$;
+
+
+--
+-- Name: notify_user_change(); Type: FUNCTION; Schema: public; Owner: -
+--
+
+CREATE FUNCTION public.notify_user_change() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+DECLARE
+    event TEXT;
+    payload users;
+BEGIN
+    IF TG_OP <> 'INSERT' AND OLD.deleted_at IS NOT NULL THEN
+        RETURN NULL;
+    END IF;
+
+    IF TG_OP = 'INSERT' THEN
+        event := 'created';
+        payload := NEW;
+    ELSIF TG_OP = 'DELETE' THEN
+        event := 'deleted';
+        payload := OLD;
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        event := 'deleted';
+        payload := NEW;
+    ELSE
+        event := 'updated';
+        payload := NEW;
+    END IF;
+
+    PERFORM pg_notify(
+        'user_events',
+        json_build_object('event', event, 'id', payload.id)::text
+    );
+    RETURN NULL;
+END;
+$$;
+
+
+--
+-- Name: record_user_version(); Type: FUNCTION; Schema: public; Owner: -
+--
+
+CREATE FUNCTION public.record_user_version() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+DECLARE
+    timestamp TIMESTAMP := timezone('utc', now());
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW IS NOT DISTINCT FROM OLD THEN
+        RETURN NULL;
+    END IF;
+
+    UPDATE users_history SET valid_to = timestamp
+    WHERE id = NEW.id AND valid_to IS NULL AND valid_from < timestamp;
+
+    INSERT INTO users_history(
+        version_id, id, tenant_id, first_name, last_name, email_address, created_at,
+        updated_at, deleted_at, email_verified_at, version, valid_from
+    )
+    VALUES (
+        uuid_generate_v7(), NEW.id, NEW.tenant_id, NEW.first_name, NEW.last_name,
+        NEW.email_address, NEW.created_at, NEW.updated_at, NEW.deleted_at,
+        NEW.email_verified_at, NEW.version, timestamp
+    )
+    ON CONFLICT (id, valid_from) DO UPDATE SET
+        first_name = EXCLUDED.first_name,
+        last_name = EXCLUDED.last_name,
+        email_address = EXCLUDED.email_address,
+        created_at = EXCLUDED.created_at,
+        updated_at = EXCLUDED.updated_at,
+        deleted_at = EXCLUDED.deleted_at,
+        email_verified_at = EXCLUDED.email_verified_at,
+        version = EXCLUDED.version;
+    RETURN NULL;
+END;
+$$;
+
+
+--
+-- Name: uuid_generate_v7(); Type: FUNCTION; Schema: public; Owner: -
+--
+
+CREATE FUNCTION public.uuid_generate_v7() RETURNS uuid
+    LANGUAGE sql
+    AS $$
+    SELECT encode(
+        set_bit(
+            set_bit(
+                overlay(
+                    uuid_send(gen_random_uuid())
+                    PLACING substring(int8send((extract(epoch FROM clock_timestamp()) * 1000)::BIGINT) FROM 3)
+                    FROM 1 FOR 6
+                ),
+                52, 1
+            ),
+            53, 1
+        ),
+        'hex'
+    )::UUID
+$$;
+
+
 SET default_tablespace = '';
 
 SET default_table_access_method = heap;
@@ -63,6 +281,287 @@ CREATE TABLE public.__diesel_schema_migrations (
 );
 
 
+--
+-- Name: audit_events; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.audit_events (
+    id uuid NOT NULL,
+    tenant_id uuid NOT NULL,
+    actor_id uuid,
+    action text NOT NULL,
+    target_type text NOT NULL,
+    target_id uuid NOT NULL,
+    before jsonb,
+    after jsonb,
+    request_id text,
+    ip_address text,
+    created_at timestamp without time zone NOT NULL
+);
+
+
+--
+-- Name: email_changes; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.email_changes (
+    user_id uuid NOT NULL,
+    email_address text NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    email_hash text
+);
+
+
+--
+-- Name: idempotency_keys; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.idempotency_keys (
+    key text NOT NULL,
+    request_hash text NOT NULL,
+    response text,
+    created_at timestamp without time zone NOT NULL,
+    expires_at timestamp without time zone NOT NULL,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL,
+    viewer_id uuid NOT NULL
+);
+
+
+--
+-- Name: invitations; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.invitations (
+    id uuid NOT NULL,
+    organization_id uuid NOT NULL,
+    email_address text NOT NULL,
+    role text NOT NULL,
+    token_hash text NOT NULL,
+    invited_by uuid,
+    expires_at timestamp without time zone NOT NULL,
+    accepted_at timestamp without time zone,
+    revoked_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL,
+    email_hash text,
+    CONSTRAINT invitations_role_check CHECK ((role = ANY (ARRAY['admin'::text, 'member'::text])))
+);
+
+
+--
+-- Name: jobs; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.jobs (
+    id uuid NOT NULL,
+    name text NOT NULL,
+    payload jsonb NOT NULL,
+    priority integer DEFAULT 0 NOT NULL,
+    status text NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    max_attempts integer NOT NULL,
+    run_at timestamp without time zone NOT NULL,
+    locked_at timestamp without time zone,
+    completed_at timestamp without time zone,
+    last_error text,
+    created_at timestamp without time zone NOT NULL,
+    updated_at timestamp without time zone NOT NULL
+);
+
+
+--
+-- Name: masking_rules; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.masking_rules (
+    role_id uuid NOT NULL,
+    field text NOT NULL,
+    strategy text NOT NULL,
+    CONSTRAINT masking_rules_field_check CHECK ((field = ANY (ARRAY['first_name'::text, 'last_name'::text, 'email_address'::text]))),
+    CONSTRAINT masking_rules_strategy_check CHECK ((strategy = ANY (ARRAY['full'::text, 'partial'::text, 'hash'::text, 'null'::text])))
+);
+
+
+--
+-- Name: memberships; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.memberships (
+    organization_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    role text NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL,
+    CONSTRAINT memberships_role_check CHECK ((role = ANY (ARRAY['owner'::text, 'admin'::text, 'member'::text])))
+);
+
+
+--
+-- Name: organizations; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.organizations (
+    id uuid NOT NULL,
+    name text NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    updated_at timestamp without time zone NOT NULL,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL
+);
+
+
+--
+-- Name: outbox; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.outbox (
+    id bigint NOT NULL,
+    event_type text NOT NULL,
+    aggregate_id uuid NOT NULL,
+    payload jsonb NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    next_attempt_at timestamp without time zone NOT NULL,
+    delivered_at timestamp without time zone,
+    last_error text,
+    dead_at timestamp without time zone
+);
+
+
+--
+-- Name: outbox_id_seq; Type: SEQUENCE; Schema: public; Owner: -
+--
+
+CREATE SEQUENCE public.outbox_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+--
+-- Name: outbox_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: -
+--
+
+ALTER SEQUENCE public.outbox_id_seq OWNED BY public.outbox.id;
+
+
+--
+-- Name: permissions; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.permissions (
+    role_id uuid NOT NULL,
+    name text NOT NULL
+);
+
+
+--
+-- Name: roles; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.roles (
+    id uuid NOT NULL,
+    name text NOT NULL,
+    description text NOT NULL,
+    created_at timestamp without time zone NOT NULL
+);
+
+
+--
+-- Name: scheduled_tasks; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.scheduled_tasks (
+    name text NOT NULL,
+    last_run_at timestamp without time zone NOT NULL
+);
+
+
+--
+-- Name: sessions; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.sessions (
+    id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    token_hash text NOT NULL,
+    expires_at timestamp without time zone NOT NULL,
+    revoked_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL
+);
+
+
+--
+-- Name: tenants; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.tenants (
+    id uuid NOT NULL,
+    name text NOT NULL,
+    created_at timestamp without time zone NOT NULL
+);
+
+
+--
+-- Name: user_credentials; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.user_credentials (
+    user_id uuid NOT NULL,
+    password_hash text NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    updated_at timestamp without time zone NOT NULL
+);
+
+
+--
+-- Name: user_exports; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.user_exports (
+    id uuid NOT NULL,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL,
+    requested_by uuid,
+    format text NOT NULL,
+    filter jsonb NOT NULL,
+    status text NOT NULL,
+    rows_exported integer DEFAULT 0 NOT NULL,
+    file_name text NOT NULL,
+    last_error text,
+    created_at timestamp without time zone NOT NULL,
+    updated_at timestamp without time zone NOT NULL,
+    completed_at timestamp without time zone
+);
+
+
+--
+-- Name: user_roles; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.user_roles (
+    user_id uuid NOT NULL,
+    role_id uuid NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL
+);
+
+
+--
+-- Name: user_tokens; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.user_tokens (
+    id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    purpose text NOT NULL,
+    token_hash text NOT NULL,
+    expires_at timestamp without time zone NOT NULL,
+    consumed_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL
+);
+
+
 --
 -- Name: users; Type: TABLE; Schema: public; Owner: -
 --
@@ -74,10 +573,84 @@ CREATE TABLE public.users (
     email_address text NOT NULL,
     created_at timestamp without time zone NOT NULL,
     updated_at timestamp without time zone NOT NULL,
-    deleted_at timestamp without time zone
+    deleted_at timestamp without time zone,
+    email_verified_at timestamp without time zone,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL,
+    version integer DEFAULT 1 NOT NULL,
+    email_hash text,
+    search_tokens text,
+    search_vector tsvector GENERATED ALWAYS AS (array_to_tsvector(string_to_array(COALESCE(search_tokens, ''::text), ' '::text))) STORED,
+    anonymized_at timestamp without time zone
+);
+
+
+--
+-- Name: users_history; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.users_history (
+    version_id uuid NOT NULL,
+    id uuid NOT NULL,
+    tenant_id uuid NOT NULL,
+    first_name text NOT NULL,
+    last_name text NOT NULL,
+    email_address text NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    updated_at timestamp without time zone NOT NULL,
+    deleted_at timestamp without time zone,
+    email_verified_at timestamp without time zone,
+    valid_from timestamp without time zone NOT NULL,
+    valid_to timestamp without time zone,
+    version integer DEFAULT 1 NOT NULL
+);
+
+
+--
+-- Name: webhook_deliveries; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.webhook_deliveries (
+    id uuid NOT NULL,
+    webhook_id uuid NOT NULL,
+    event_type text NOT NULL,
+    payload jsonb NOT NULL,
+    status text NOT NULL,
+    attempts integer DEFAULT 0 NOT NULL,
+    response_code integer,
+    last_error text,
+    next_attempt_at timestamp without time zone NOT NULL,
+    delivered_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL,
+    updated_at timestamp without time zone NOT NULL,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL
+);
+
+
+--
+-- Name: webhooks; Type: TABLE; Schema: public; Owner: -
+--
+
+CREATE TABLE public.webhooks (
+    id uuid NOT NULL,
+    url text NOT NULL,
+    secret text NOT NULL,
+    event_types text[] NOT NULL,
+    enabled boolean NOT NULL,
+    failure_count integer DEFAULT 0 NOT NULL,
+    created_at timestamp without time zone NOT NULL,
+    updated_at timestamp without time zone NOT NULL,
+    disabled_at timestamp without time zone,
+    tenant_id uuid DEFAULT public.current_tenant_id() NOT NULL
 );
 
 
+--
+-- Name: outbox id; Type: DEFAULT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.outbox ALTER COLUMN id SET DEFAULT nextval('public.outbox_id_seq'::regclass);
+
+
 --
 -- Name: __diesel_schema_migrations __diesel_schema_migrations_pkey; Type: CONSTRAINT; Schema: public; Owner: -
 --
@@ -87,12 +660,753 @@ ALTER TABLE ONLY public.__diesel_schema_migrations
 
 
 --
--- Name: users users_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+-- Name: audit_events audit_events_pkey; Type: CONSTRAINT; Schema: public; Owner: -
 --
 
-ALTER TABLE ONLY public.users
-    ADD CONSTRAINT users_pkey PRIMARY KEY (id);
+ALTER TABLE ONLY public.audit_events
+    ADD CONSTRAINT audit_events_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: email_changes email_changes_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.email_changes
+    ADD CONSTRAINT email_changes_pkey PRIMARY KEY (user_id);
+
+
+--
+-- Name: idempotency_keys idempotency_keys_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.idempotency_keys
+    ADD CONSTRAINT idempotency_keys_pkey PRIMARY KEY (tenant_id, viewer_id, key);
+
+
+--
+-- Name: invitations invitations_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.invitations
+    ADD CONSTRAINT invitations_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: invitations invitations_token_hash_key; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.invitations
+    ADD CONSTRAINT invitations_token_hash_key UNIQUE (token_hash);
+
+
+--
+-- Name: jobs jobs_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.jobs
+    ADD CONSTRAINT jobs_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: masking_rules masking_rules_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.masking_rules
+    ADD CONSTRAINT masking_rules_pkey PRIMARY KEY (role_id, field);
+
+
+--
+-- Name: memberships memberships_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.memberships
+    ADD CONSTRAINT memberships_pkey PRIMARY KEY (organization_id, user_id);
+
+
+--
+-- Name: organizations organizations_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.organizations
+    ADD CONSTRAINT organizations_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: outbox outbox_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.outbox
+    ADD CONSTRAINT outbox_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: permissions permissions_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.permissions
+    ADD CONSTRAINT permissions_pkey PRIMARY KEY (role_id, name);
+
+
+--
+-- Name: roles roles_name_key; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.roles
+    ADD CONSTRAINT roles_name_key UNIQUE (name);
+
+
+--
+-- Name: roles roles_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.roles
+    ADD CONSTRAINT roles_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: scheduled_tasks scheduled_tasks_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.scheduled_tasks
+    ADD CONSTRAINT scheduled_tasks_pkey PRIMARY KEY (name);
+
+
+--
+-- Name: sessions sessions_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: sessions sessions_token_hash_key; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_token_hash_key UNIQUE (token_hash);
+
+
+--
+-- Name: tenants tenants_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.tenants
+    ADD CONSTRAINT tenants_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: user_credentials user_credentials_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_credentials
+    ADD CONSTRAINT user_credentials_pkey PRIMARY KEY (user_id);
+
+
+--
+-- Name: user_exports user_exports_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_exports
+    ADD CONSTRAINT user_exports_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: user_roles user_roles_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_roles
+    ADD CONSTRAINT user_roles_pkey PRIMARY KEY (user_id, role_id);
+
+
+--
+-- Name: user_tokens user_tokens_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_tokens
+    ADD CONSTRAINT user_tokens_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: user_tokens user_tokens_token_hash_key; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_tokens
+    ADD CONSTRAINT user_tokens_token_hash_key UNIQUE (token_hash);
+
+
+--
+-- Name: users_history users_history_id_valid_from_key; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.users_history
+    ADD CONSTRAINT users_history_id_valid_from_key UNIQUE (id, valid_from);
+
+
+--
+-- Name: users_history users_history_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.users_history
+    ADD CONSTRAINT users_history_pkey PRIMARY KEY (version_id);
+
+
+--
+-- Name: users users_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.users
+    ADD CONSTRAINT users_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: webhook_deliveries webhook_deliveries_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.webhook_deliveries
+    ADD CONSTRAINT webhook_deliveries_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: webhooks webhooks_pkey; Type: CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.webhooks
+    ADD CONSTRAINT webhooks_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: audit_events_actor_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX audit_events_actor_id_idx ON public.audit_events USING btree (actor_id, id);
+
+
+--
+-- Name: audit_events_target_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX audit_events_target_idx ON public.audit_events USING btree (target_type, target_id, id);
+
+
+--
+-- Name: idempotency_keys_expires_at_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX idempotency_keys_expires_at_idx ON public.idempotency_keys USING btree (expires_at);
+
+
+--
+-- Name: invitations_email_hash_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX invitations_email_hash_idx ON public.invitations USING btree (email_hash);
+
+
+--
+-- Name: invitations_pending_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX invitations_pending_idx ON public.invitations USING btree (organization_id, id) WHERE ((accepted_at IS NULL) AND (revoked_at IS NULL));
+
+
+--
+-- Name: jobs_pending_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX jobs_pending_idx ON public.jobs USING btree (priority DESC, run_at) WHERE (status = 'pending'::text);
+
+
+--
+-- Name: jobs_running_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX jobs_running_idx ON public.jobs USING btree (locked_at) WHERE (status = 'running'::text);
+
+
+--
+-- Name: memberships_owner_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE UNIQUE INDEX memberships_owner_idx ON public.memberships USING btree (organization_id) WHERE (role = 'owner'::text);
+
+
+--
+-- Name: memberships_user_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX memberships_user_id_idx ON public.memberships USING btree (user_id, organization_id);
+
+
+--
+-- Name: organizations_tenant_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX organizations_tenant_id_idx ON public.organizations USING btree (tenant_id);
+
+
+--
+-- Name: outbox_pending_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX outbox_pending_idx ON public.outbox USING btree (id) WHERE ((delivered_at IS NULL) AND (dead_at IS NULL));
+
+
+--
+-- Name: sessions_user_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX sessions_user_id_idx ON public.sessions USING btree (user_id);
+
+
+--
+-- Name: user_exports_tenant_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX user_exports_tenant_id_idx ON public.user_exports USING btree (tenant_id);
+
+
+--
+-- Name: user_roles_role_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX user_roles_role_id_idx ON public.user_roles USING btree (role_id);
+
+
+--
+-- Name: user_tokens_user_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX user_tokens_user_id_idx ON public.user_tokens USING btree (user_id, purpose);
+
+
+--
+-- Name: users_email_hash_key; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE UNIQUE INDEX users_email_hash_key ON public.users USING btree (email_hash) WHERE (deleted_at IS NULL);
+
+
+--
+-- Name: users_history_version_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX users_history_version_idx ON public.users_history USING btree (id, version_id);
+
+
+--
+-- Name: users_search_vector_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX users_search_vector_idx ON public.users USING gin (search_vector);
+
+
+--
+-- Name: users_tenant_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX users_tenant_id_idx ON public.users USING btree (tenant_id);
+
+
+--
+-- Name: webhook_deliveries_pending_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX webhook_deliveries_pending_idx ON public.webhook_deliveries USING btree (next_attempt_at) WHERE (status = 'pending'::text);
+
+
+--
+-- Name: webhook_deliveries_webhook_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX webhook_deliveries_webhook_id_idx ON public.webhook_deliveries USING btree (webhook_id, id);
+
+
+--
+-- Name: webhooks_tenant_id_idx; Type: INDEX; Schema: public; Owner: -
+--
+
+CREATE INDEX webhooks_tenant_id_idx ON public.webhooks USING btree (tenant_id);
+
+
+--
+-- Name: users audit_user_change; Type: TRIGGER; Schema: public; Owner: -
+--
+
+CREATE TRIGGER audit_user_change AFTER INSERT OR DELETE OR UPDATE ON public.users FOR EACH ROW WHEN ((current_setting('app.reencrypting'::text, true) IS DISTINCT FROM 'on'::text)) EXECUTE FUNCTION public.audit_user_change();
+
+
+--
+-- Name: users increment_user_version; Type: TRIGGER; Schema: public; Owner: -
+--
+
+CREATE TRIGGER increment_user_version BEFORE UPDATE ON public.users FOR EACH ROW WHEN ((current_setting('app.reencrypting'::text, true) IS DISTINCT FROM 'on'::text)) EXECUTE FUNCTION public.increment_user_version();
+
+
+--
+-- Name: users notify_user_change; Type: TRIGGER; Schema: public; Owner: -
+--
+
+CREATE TRIGGER notify_user_change AFTER INSERT OR DELETE OR UPDATE ON public.users FOR EACH ROW WHEN ((current_setting('app.reencrypting'::text, true) IS DISTINCT FROM 'on'::text)) EXECUTE FUNCTION public.notify_user_change();
+
+
+--
+-- Name: users record_user_version; Type: TRIGGER; Schema: public; Owner: -
+--
+
+CREATE TRIGGER record_user_version AFTER INSERT OR UPDATE ON public.users FOR EACH ROW WHEN ((current_setting('app.reencrypting'::text, true) IS DISTINCT FROM 'on'::text)) EXECUTE FUNCTION public.record_user_version();
+
+
+--
+-- Name: audit_events audit_events_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.audit_events
+    ADD CONSTRAINT audit_events_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: email_changes email_changes_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.email_changes
+    ADD CONSTRAINT email_changes_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: idempotency_keys idempotency_keys_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.idempotency_keys
+    ADD CONSTRAINT idempotency_keys_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: invitations invitations_invited_by_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.invitations
+    ADD CONSTRAINT invitations_invited_by_fkey FOREIGN KEY (invited_by) REFERENCES public.users(id) ON DELETE SET NULL;
+
+
+--
+-- Name: invitations invitations_organization_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.invitations
+    ADD CONSTRAINT invitations_organization_id_fkey FOREIGN KEY (organization_id) REFERENCES public.organizations(id) ON DELETE CASCADE;
+
+
+--
+-- Name: invitations invitations_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.invitations
+    ADD CONSTRAINT invitations_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: masking_rules masking_rules_role_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.masking_rules
+    ADD CONSTRAINT masking_rules_role_id_fkey FOREIGN KEY (role_id) REFERENCES public.roles(id) ON DELETE CASCADE;
+
+
+--
+-- Name: memberships memberships_organization_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.memberships
+    ADD CONSTRAINT memberships_organization_id_fkey FOREIGN KEY (organization_id) REFERENCES public.organizations(id) ON DELETE CASCADE;
+
+
+--
+-- Name: memberships memberships_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.memberships
+    ADD CONSTRAINT memberships_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: memberships memberships_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.memberships
+    ADD CONSTRAINT memberships_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: organizations organizations_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.organizations
+    ADD CONSTRAINT organizations_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: permissions permissions_role_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.permissions
+    ADD CONSTRAINT permissions_role_id_fkey FOREIGN KEY (role_id) REFERENCES public.roles(id) ON DELETE CASCADE;
+
+
+--
+-- Name: sessions sessions_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.sessions
+    ADD CONSTRAINT sessions_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: user_credentials user_credentials_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_credentials
+    ADD CONSTRAINT user_credentials_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: user_exports user_exports_requested_by_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_exports
+    ADD CONSTRAINT user_exports_requested_by_fkey FOREIGN KEY (requested_by) REFERENCES public.users(id) ON DELETE SET NULL;
+
+
+--
+-- Name: user_exports user_exports_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_exports
+    ADD CONSTRAINT user_exports_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: user_roles user_roles_role_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_roles
+    ADD CONSTRAINT user_roles_role_id_fkey FOREIGN KEY (role_id) REFERENCES public.roles(id) ON DELETE CASCADE;
+
+
+--
+-- Name: user_roles user_roles_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_roles
+    ADD CONSTRAINT user_roles_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: user_roles user_roles_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_roles
+    ADD CONSTRAINT user_roles_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: user_tokens user_tokens_user_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.user_tokens
+    ADD CONSTRAINT user_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: users_history users_history_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.users_history
+    ADD CONSTRAINT users_history_id_fkey FOREIGN KEY (id) REFERENCES public.users(id) ON DELETE CASCADE;
+
+
+--
+-- Name: users_history users_history_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.users_history
+    ADD CONSTRAINT users_history_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: users users_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.users
+    ADD CONSTRAINT users_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: webhook_deliveries webhook_deliveries_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.webhook_deliveries
+    ADD CONSTRAINT webhook_deliveries_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: webhook_deliveries webhook_deliveries_webhook_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.webhook_deliveries
+    ADD CONSTRAINT webhook_deliveries_webhook_id_fkey FOREIGN KEY (webhook_id) REFERENCES public.webhooks(id) ON DELETE CASCADE;
+
+
+--
+-- Name: webhooks webhooks_tenant_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: -
+--
+
+ALTER TABLE ONLY public.webhooks
+    ADD CONSTRAINT webhooks_tenant_id_fkey FOREIGN KEY (tenant_id) REFERENCES public.tenants(id);
+
+
+--
+-- Name: audit_events; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.audit_events ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: idempotency_keys; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.idempotency_keys ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: invitations; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.invitations ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: memberships; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.memberships ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: organizations; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.organizations ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: audit_events tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.audit_events TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: idempotency_keys tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.idempotency_keys TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: invitations tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.invitations TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: memberships tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.memberships TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: organizations tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.organizations TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: user_exports tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.user_exports TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: user_roles tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.user_roles TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: users tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.users TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: users_history tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.users_history TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: webhook_deliveries tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.webhook_deliveries TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: webhooks tenant_isolation; Type: POLICY; Schema: public; Owner: -
+--
+
+CREATE POLICY tenant_isolation ON public.webhooks TO app_tenant USING ((tenant_id = (current_setting('app.tenant_id'::text))::uuid));
+
+
+--
+-- Name: user_exports; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.user_exports ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: user_roles; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.user_roles ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: users; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.users ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: users_history; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.users_history ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: webhook_deliveries; Type: ROW SECURITY; Schema: public; Owner: -
+--
+
+ALTER TABLE public.webhook_deliveries ENABLE ROW LEVEL SECURITY;
+
+--
+-- Name: webhooks; Type: ROW SECURITY; Schema: public; Owner: -
+--
 
+ALTER TABLE public.webhooks ENABLE ROW LEVEL SECURITY;
 
 --
 -- PostgreSQL database dump complete
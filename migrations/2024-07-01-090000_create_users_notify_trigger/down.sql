@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS notify_user_change ON users;
+DROP FUNCTION IF EXISTS notify_user_change();
@@ -0,0 +1,33 @@
+-- Your SQL goes here
+-- Notify the `user_events` channel of every change to a user, so that every
+-- replica listening to the channel sees every change after it is committed.
+CREATE OR REPLACE FUNCTION notify_user_change() RETURNS trigger AS $$
+DECLARE
+    event TEXT;
+    payload users;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        event := 'created';
+        payload := NEW;
+    ELSIF TG_OP = 'DELETE' THEN
+        event := 'deleted';
+        payload := OLD;
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        event := 'deleted';
+        payload := NEW;
+    ELSE
+        event := 'updated';
+        payload := NEW;
+    END IF;
+
+    PERFORM pg_notify(
+        'user_events',
+        json_build_object('event', event, 'user', row_to_json(payload))::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_user_change
+    AFTER INSERT OR UPDATE OR DELETE ON users
+    FOR EACH ROW EXECUTE PROCEDURE notify_user_change();
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS sessions;
+DROP TABLE IF EXISTS user_credentials;
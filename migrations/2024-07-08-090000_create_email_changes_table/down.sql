@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS email_changes;
+DROP INDEX IF EXISTS users_email_address_key;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_address_key ON users(lower(email_address))
+    WHERE deleted_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS email_changes(
+    user_id UUID NOT NULL PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    email_address TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,35 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS roles(
+    id UUID NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+-- The permissions granted by each role.
+CREATE TABLE IF NOT EXISTS permissions(
+    role_id UUID NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    PRIMARY KEY (role_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS user_roles(
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role_id UUID NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, role_id)
+);
+
+CREATE INDEX IF NOT EXISTS user_roles_role_id_idx ON user_roles(role_id);
+
+INSERT INTO roles(id, name, description, created_at)
+VALUES (gen_random_uuid(), 'admin', 'Manages users, roles and webhooks', now())
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO permissions(role_id, name)
+SELECT roles.id, permission.name
+FROM roles, unnest(ARRAY[
+    'users:read', 'users:write', 'users:delete', 'roles:manage', 'webhooks:manage'
+]) AS permission(name)
+WHERE roles.name = 'admin'
+ON CONFLICT DO NOTHING;
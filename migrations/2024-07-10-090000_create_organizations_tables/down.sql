@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM permissions WHERE name = 'organizations:manage';
+DROP TABLE IF EXISTS memberships;
+DROP TABLE IF EXISTS organizations;
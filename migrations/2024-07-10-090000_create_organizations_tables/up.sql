@@ -0,0 +1,25 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS organizations(
+    id UUID NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS memberships(
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (organization_id, user_id)
+);
+
+-- Every organization has a single owner.
+CREATE UNIQUE INDEX IF NOT EXISTS memberships_owner_idx ON memberships(organization_id)
+    WHERE role = 'owner';
+
+CREATE INDEX IF NOT EXISTS memberships_user_id_idx ON memberships(user_id, organization_id);
+
+INSERT INTO permissions(role_id, name)
+SELECT id, 'organizations:manage' FROM roles WHERE name = 'admin'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,69 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS tenants(
+    id UUID NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+
+-- The tenant of the existing data, and of the data created outside of a
+-- tenant, like by anonymous requests.
+INSERT INTO tenants(id, name, created_at)
+VALUES ('00000000-0000-0000-0000-000000000000', 'Default', now())
+ON CONFLICT DO NOTHING;
+
+-- The tenant of the current transaction, or the default tenant outside of one.
+CREATE OR REPLACE FUNCTION current_tenant_id() RETURNS UUID AS $$
+    SELECT COALESCE(
+        NULLIF(current_setting('app.tenant_id', true), ''),
+        '00000000-0000-0000-0000-000000000000'
+    )::uuid
+$$ LANGUAGE sql STABLE;
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id);
+ALTER TABLE user_roles
+    ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id);
+ALTER TABLE organizations
+    ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id);
+ALTER TABLE memberships
+    ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id);
+ALTER TABLE invitations
+    ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id);
+
+CREATE INDEX IF NOT EXISTS users_tenant_id_idx ON users(tenant_id);
+CREATE INDEX IF NOT EXISTS organizations_tenant_id_idx ON organizations(tenant_id);
+
+-- The role that requests switch to for the duration of a transaction, which
+-- the row-level security policies apply to. The table owner bypasses them,
+-- so that background processes see every tenant.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'app_tenant') THEN
+        CREATE ROLE app_tenant NOLOGIN;
+    END IF;
+END
+$$;
+GRANT app_tenant TO CURRENT_USER;
+GRANT USAGE ON SCHEMA public TO app_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON ALL TABLES IN SCHEMA public TO app_tenant;
+GRANT USAGE, SELECT ON ALL SEQUENCES IN SCHEMA public TO app_tenant;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public
+    GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO app_tenant;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public GRANT USAGE, SELECT ON SEQUENCES TO app_tenant;
+
+-- Without `app.tenant_id`, the policies fail rather than show nothing.
+ALTER TABLE users ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON users TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
+ALTER TABLE user_roles ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_roles TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
+ALTER TABLE organizations ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON organizations TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
+ALTER TABLE memberships ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON memberships TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
+ALTER TABLE invitations ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON invitations TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM permissions WHERE name = 'audit:read';
+DROP TRIGGER IF EXISTS audit_user_change ON users;
+DROP FUNCTION IF EXISTS audit_user_change();
+DROP TABLE IF EXISTS audit_events;
+DROP FUNCTION IF EXISTS uuid_generate_v7();
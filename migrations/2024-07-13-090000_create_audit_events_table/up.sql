@@ -0,0 +1,109 @@
+-- Your SQL goes here
+-- A time-ordered UUID (version 7), like the ids generated by the application.
+CREATE OR REPLACE FUNCTION uuid_generate_v7() RETURNS UUID AS $$
+    SELECT encode(
+        set_bit(
+            set_bit(
+                overlay(
+                    uuid_send(gen_random_uuid())
+                    PLACING substring(int8send((extract(epoch FROM clock_timestamp()) * 1000)::BIGINT) FROM 3)
+                    FROM 1 FOR 6
+                ),
+                52, 1
+            ),
+            53, 1
+        ),
+        'hex'
+    )::UUID
+$$ LANGUAGE sql VOLATILE;
+
+-- Every change to an audited row, with the fields it changed and who changed
+-- it. The actor and the request come from the `app.actor_id`,
+-- `app.request_id` and `app.ip_address` settings of the transaction.
+CREATE TABLE IF NOT EXISTS audit_events(
+    id UUID NOT NULL PRIMARY KEY,
+    tenant_id UUID NOT NULL REFERENCES tenants(id),
+    actor_id UUID,
+    action TEXT NOT NULL,
+    target_type TEXT NOT NULL,
+    target_id UUID NOT NULL,
+    before JSONB,
+    after JSONB,
+    request_id TEXT,
+    ip_address TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_target_idx ON audit_events(target_type, target_id, id);
+CREATE INDEX IF NOT EXISTS audit_events_actor_id_idx ON audit_events(actor_id, id);
+
+-- Requests can read and record audit events of their tenant, but never
+-- rewrite them.
+ALTER TABLE audit_events ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON audit_events TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
+REVOKE UPDATE, DELETE ON audit_events FROM app_tenant;
+
+-- Record every change to a user in the transaction of the change. Updates
+-- only keep the fields they changed, and changes of nothing are skipped.
+CREATE OR REPLACE FUNCTION audit_user_change() RETURNS trigger AS $$
+DECLARE
+    action TEXT;
+    target users;
+    old_row JSONB;
+    new_row JSONB;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        action := 'user.created';
+        target := NEW;
+        new_row := to_jsonb(NEW) - 'tenant_id';
+    ELSIF TG_OP = 'DELETE' THEN
+        action := 'user.purged';
+        target := OLD;
+        old_row := to_jsonb(OLD) - 'tenant_id';
+    ELSE
+        IF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+            action := 'user.deleted';
+        ELSE
+            action := 'user.updated';
+        END IF;
+        target := NEW;
+        SELECT jsonb_object_agg(key, old_json -> key), jsonb_object_agg(key, new_json -> key)
+        INTO old_row, new_row
+        FROM (SELECT to_jsonb(OLD) - 'tenant_id' AS old_json, to_jsonb(NEW) - 'tenant_id' AS new_json) AS rows,
+            jsonb_object_keys(new_json) AS key
+        WHERE new_json -> key IS DISTINCT FROM old_json -> key;
+
+        IF new_row IS NULL THEN
+            RETURN NULL;
+        END IF;
+    END IF;
+
+    INSERT INTO audit_events(
+        id, tenant_id, actor_id, action, target_type, target_id, before, after,
+        request_id, ip_address, created_at
+    )
+    VALUES (
+        uuid_generate_v7(),
+        target.tenant_id,
+        NULLIF(current_setting('app.actor_id', true), '')::uuid,
+        action,
+        'user',
+        target.id,
+        old_row,
+        new_row,
+        NULLIF(current_setting('app.request_id', true), ''),
+        NULLIF(current_setting('app.ip_address', true), ''),
+        timezone('utc', now())
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_user_change
+    AFTER INSERT OR UPDATE OR DELETE ON users
+    FOR EACH ROW EXECUTE PROCEDURE audit_user_change();
+
+INSERT INTO permissions(role_id, name)
+SELECT id, 'audit:read' FROM roles WHERE name = 'admin'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS record_user_version ON users;
+DROP FUNCTION IF EXISTS record_user_version();
+DROP TABLE IF EXISTS users_history;
@@ -0,0 +1,74 @@
+-- Your SQL goes here
+-- Every version of every user, valid from the transaction that wrote it to
+-- the transaction that replaced it.
+CREATE TABLE IF NOT EXISTS users_history(
+    version_id UUID NOT NULL PRIMARY KEY,
+    id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    tenant_id UUID NOT NULL REFERENCES tenants(id),
+    first_name TEXT NOT NULL,
+    last_name TEXT NOT NULL,
+    email_address TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    deleted_at TIMESTAMP,
+    email_verified_at TIMESTAMP,
+    valid_from TIMESTAMP NOT NULL,
+    valid_to TIMESTAMP,
+    UNIQUE (id, valid_from)
+);
+
+CREATE INDEX IF NOT EXISTS users_history_version_idx ON users_history(id, version_id);
+
+ALTER TABLE users_history ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON users_history TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
+
+-- Close the current version of the user and open a new one. A transaction
+-- changing a user more than once only keeps its last version.
+CREATE OR REPLACE FUNCTION record_user_version() RETURNS trigger AS $$
+DECLARE
+    timestamp TIMESTAMP := timezone('utc', now());
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW IS NOT DISTINCT FROM OLD THEN
+        RETURN NULL;
+    END IF;
+
+    UPDATE users_history SET valid_to = timestamp
+    WHERE id = NEW.id AND valid_to IS NULL AND valid_from < timestamp;
+
+    INSERT INTO users_history(
+        version_id, id, tenant_id, first_name, last_name, email_address, created_at,
+        updated_at, deleted_at, email_verified_at, valid_from
+    )
+    VALUES (
+        uuid_generate_v7(), NEW.id, NEW.tenant_id, NEW.first_name, NEW.last_name,
+        NEW.email_address, NEW.created_at, NEW.updated_at, NEW.deleted_at,
+        NEW.email_verified_at, timestamp
+    )
+    ON CONFLICT (id, valid_from) DO UPDATE SET
+        first_name = EXCLUDED.first_name,
+        last_name = EXCLUDED.last_name,
+        email_address = EXCLUDED.email_address,
+        created_at = EXCLUDED.created_at,
+        updated_at = EXCLUDED.updated_at,
+        deleted_at = EXCLUDED.deleted_at,
+        email_verified_at = EXCLUDED.email_verified_at;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER record_user_version
+    AFTER INSERT OR UPDATE ON users
+    FOR EACH ROW EXECUTE PROCEDURE record_user_version();
+
+-- The earlier versions of the existing users are unknown, so their current
+-- version is valid from their last update.
+INSERT INTO users_history(
+    version_id, id, tenant_id, first_name, last_name, email_address, created_at,
+    updated_at, deleted_at, email_verified_at, valid_from
+)
+SELECT
+    uuid_generate_v7(), id, tenant_id, first_name, last_name, email_address, created_at,
+    updated_at, deleted_at, email_verified_at, updated_at
+FROM users
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,39 @@
+-- This file should undo anything in `up.sql`
+-- Close the current version of the user and open a new one. A transaction
+-- changing a user more than once only keeps its last version.
+CREATE OR REPLACE FUNCTION record_user_version() RETURNS trigger AS $$
+DECLARE
+    timestamp TIMESTAMP := timezone('utc', now());
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW IS NOT DISTINCT FROM OLD THEN
+        RETURN NULL;
+    END IF;
+
+    UPDATE users_history SET valid_to = timestamp
+    WHERE id = NEW.id AND valid_to IS NULL AND valid_from < timestamp;
+
+    INSERT INTO users_history(
+        version_id, id, tenant_id, first_name, last_name, email_address, created_at,
+        updated_at, deleted_at, email_verified_at, valid_from
+    )
+    VALUES (
+        uuid_generate_v7(), NEW.id, NEW.tenant_id, NEW.first_name, NEW.last_name,
+        NEW.email_address, NEW.created_at, NEW.updated_at, NEW.deleted_at,
+        NEW.email_verified_at, timestamp
+    )
+    ON CONFLICT (id, valid_from) DO UPDATE SET
+        first_name = EXCLUDED.first_name,
+        last_name = EXCLUDED.last_name,
+        email_address = EXCLUDED.email_address,
+        created_at = EXCLUDED.created_at,
+        updated_at = EXCLUDED.updated_at,
+        deleted_at = EXCLUDED.deleted_at,
+        email_verified_at = EXCLUDED.email_verified_at;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS increment_user_version ON users;
+DROP FUNCTION IF EXISTS increment_user_version();
+ALTER TABLE users_history DROP COLUMN IF EXISTS version;
+ALTER TABLE users DROP COLUMN IF EXISTS version;
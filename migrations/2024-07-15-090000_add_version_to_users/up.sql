@@ -0,0 +1,53 @@
+-- Your SQL goes here
+-- The version of a user, increased by every change to it, so that a change
+-- can require the version it was based on.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE users_history ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+
+CREATE OR REPLACE FUNCTION increment_user_version() RETURNS trigger AS $$
+BEGIN
+    IF NEW IS DISTINCT FROM OLD THEN
+        NEW.version := OLD.version + 1;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER increment_user_version
+    BEFORE UPDATE ON users
+    FOR EACH ROW EXECUTE PROCEDURE increment_user_version();
+
+-- Close the current version of the user and open a new one. A transaction
+-- changing a user more than once only keeps its last version.
+CREATE OR REPLACE FUNCTION record_user_version() RETURNS trigger AS $$
+DECLARE
+    timestamp TIMESTAMP := timezone('utc', now());
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW IS NOT DISTINCT FROM OLD THEN
+        RETURN NULL;
+    END IF;
+
+    UPDATE users_history SET valid_to = timestamp
+    WHERE id = NEW.id AND valid_to IS NULL AND valid_from < timestamp;
+
+    INSERT INTO users_history(
+        version_id, id, tenant_id, first_name, last_name, email_address, created_at,
+        updated_at, deleted_at, email_verified_at, version, valid_from
+    )
+    VALUES (
+        uuid_generate_v7(), NEW.id, NEW.tenant_id, NEW.first_name, NEW.last_name,
+        NEW.email_address, NEW.created_at, NEW.updated_at, NEW.deleted_at,
+        NEW.email_verified_at, NEW.version, timestamp
+    )
+    ON CONFLICT (id, valid_from) DO UPDATE SET
+        first_name = EXCLUDED.first_name,
+        last_name = EXCLUDED.last_name,
+        email_address = EXCLUDED.email_address,
+        created_at = EXCLUDED.created_at,
+        updated_at = EXCLUDED.updated_at,
+        deleted_at = EXCLUDED.deleted_at,
+        email_verified_at = EXCLUDED.email_verified_at,
+        version = EXCLUDED.version;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
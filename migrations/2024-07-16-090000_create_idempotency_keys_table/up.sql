@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- The responses of the mutations sent with an `Idempotency-Key` header,
+-- replayed to the retries of the same request until the key expires. The
+-- response is null while the first request is still running.
+CREATE TABLE IF NOT EXISTS idempotency_keys(
+    key TEXT NOT NULL PRIMARY KEY,
+    request_hash TEXT NOT NULL,
+    response JSONB,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_at_idx ON idempotency_keys(expires_at);
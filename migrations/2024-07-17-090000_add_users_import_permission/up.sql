@@ -0,0 +1,4 @@
+-- Your SQL goes here
+INSERT INTO permissions(role_id, name)
+SELECT id, 'users:import' FROM roles WHERE name = 'admin'
+ON CONFLICT DO NOTHING;
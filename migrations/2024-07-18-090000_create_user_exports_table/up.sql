@@ -0,0 +1,27 @@
+-- Your SQL goes here
+-- The exports of users to files in the export directory, run by a job, with
+-- their progress.
+CREATE TABLE IF NOT EXISTS user_exports(
+    id UUID NOT NULL PRIMARY KEY,
+    tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id),
+    requested_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    format TEXT NOT NULL,
+    filter JSONB NOT NULL,
+    status TEXT NOT NULL,
+    rows_exported INTEGER NOT NULL DEFAULT 0,
+    file_name TEXT NOT NULL,
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    completed_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS user_exports_tenant_id_idx ON user_exports(tenant_id);
+
+ALTER TABLE user_exports ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_exports TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
+
+INSERT INTO permissions(role_id, name)
+SELECT id, 'users:export' FROM roles WHERE name = 'admin'
+ON CONFLICT DO NOTHING;
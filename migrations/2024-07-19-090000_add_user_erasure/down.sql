@@ -0,0 +1,57 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM permissions WHERE name = 'users:erase';
+DROP FUNCTION IF EXISTS erase_user_audit_events(UUID);
+
+CREATE OR REPLACE FUNCTION audit_user_change() RETURNS trigger AS $$
+DECLARE
+    action TEXT;
+    target users;
+    old_row JSONB;
+    new_row JSONB;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        action := 'user.created';
+        target := NEW;
+        new_row := to_jsonb(NEW) - 'tenant_id';
+    ELSIF TG_OP = 'DELETE' THEN
+        action := 'user.purged';
+        target := OLD;
+        old_row := to_jsonb(OLD) - 'tenant_id';
+    ELSE
+        IF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+            action := 'user.deleted';
+        ELSE
+            action := 'user.updated';
+        END IF;
+        target := NEW;
+        SELECT jsonb_object_agg(key, old_json -> key), jsonb_object_agg(key, new_json -> key)
+        INTO old_row, new_row
+        FROM (SELECT to_jsonb(OLD) - 'tenant_id' AS old_json, to_jsonb(NEW) - 'tenant_id' AS new_json) AS rows,
+            jsonb_object_keys(new_json) AS key
+        WHERE new_json -> key IS DISTINCT FROM old_json -> key;
+
+        IF new_row IS NULL THEN
+            RETURN NULL;
+        END IF;
+    END IF;
+
+    INSERT INTO audit_events(
+        id, tenant_id, actor_id, action, target_type, target_id, before, after,
+        request_id, ip_address, created_at
+    )
+    VALUES (
+        uuid_generate_v7(),
+        target.tenant_id,
+        NULLIF(current_setting('app.actor_id', true), '')::uuid,
+        action,
+        'user',
+        target.id,
+        old_row,
+        new_row,
+        NULLIF(current_setting('app.request_id', true), ''),
+        NULLIF(current_setting('app.ip_address', true), ''),
+        timezone('utc', now())
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,75 @@
+-- Your SQL goes here
+-- Record the erasure of the user in `app.erased_user_id` as `user.erased`,
+-- without the personal data it removes.
+CREATE OR REPLACE FUNCTION audit_user_change() RETURNS trigger AS $$
+DECLARE
+    action TEXT;
+    target users;
+    old_row JSONB;
+    new_row JSONB;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        action := 'user.created';
+        target := NEW;
+        new_row := to_jsonb(NEW) - 'tenant_id';
+    ELSIF TG_OP = 'DELETE' THEN
+        action := 'user.purged';
+        target := OLD;
+        old_row := to_jsonb(OLD) - 'tenant_id';
+    ELSE
+        IF NEW.id::text = current_setting('app.erased_user_id', true) THEN
+            action := 'user.erased';
+        ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+            action := 'user.deleted';
+        ELSE
+            action := 'user.updated';
+        END IF;
+        target := NEW;
+        SELECT jsonb_object_agg(key, old_json -> key), jsonb_object_agg(key, new_json -> key)
+        INTO old_row, new_row
+        FROM (SELECT to_jsonb(OLD) - 'tenant_id' AS old_json, to_jsonb(NEW) - 'tenant_id' AS new_json) AS rows,
+            jsonb_object_keys(new_json) AS key
+        WHERE new_json -> key IS DISTINCT FROM old_json -> key;
+
+        IF new_row IS NULL THEN
+            RETURN NULL;
+        END IF;
+        IF action = 'user.erased' THEN
+            old_row := old_row - ARRAY['first_name', 'last_name', 'email_address'];
+            new_row := new_row - ARRAY['first_name', 'last_name', 'email_address'];
+        END IF;
+    END IF;
+
+    INSERT INTO audit_events(
+        id, tenant_id, actor_id, action, target_type, target_id, before, after,
+        request_id, ip_address, created_at
+    )
+    VALUES (
+        uuid_generate_v7(),
+        target.tenant_id,
+        NULLIF(current_setting('app.actor_id', true), '')::uuid,
+        action,
+        'user',
+        target.id,
+        old_row,
+        new_row,
+        NULLIF(current_setting('app.request_id', true), ''),
+        NULLIF(current_setting('app.ip_address', true), ''),
+        timezone('utc', now())
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Remove the personal data from the audit events of an erased user of the
+-- current tenant. Requests cannot rewrite audit events otherwise.
+CREATE OR REPLACE FUNCTION erase_user_audit_events(target UUID) RETURNS void AS $$
+    UPDATE audit_events
+    SET before = before - ARRAY['first_name', 'last_name', 'email_address'],
+        after = after - ARRAY['first_name', 'last_name', 'email_address']
+    WHERE target_type = 'user' AND target_id = target AND tenant_id = current_tenant_id()
+$$ LANGUAGE sql SECURITY DEFINER SET search_path = public;
+
+INSERT INTO permissions(role_id, name)
+SELECT id, 'users:erase' FROM roles WHERE name = 'admin'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,82 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS increment_user_version ON users;
+CREATE TRIGGER increment_user_version BEFORE UPDATE ON users FOR EACH ROW
+    EXECUTE FUNCTION increment_user_version();
+
+DROP TRIGGER IF EXISTS audit_user_change ON users;
+CREATE TRIGGER audit_user_change AFTER INSERT OR DELETE OR UPDATE ON users FOR EACH ROW
+    EXECUTE FUNCTION audit_user_change();
+
+DROP TRIGGER IF EXISTS notify_user_change ON users;
+CREATE TRIGGER notify_user_change AFTER INSERT OR DELETE OR UPDATE ON users FOR EACH ROW
+    EXECUTE FUNCTION notify_user_change();
+
+DROP TRIGGER IF EXISTS record_user_version ON users;
+CREATE TRIGGER record_user_version AFTER INSERT OR UPDATE ON users FOR EACH ROW
+    EXECUTE FUNCTION record_user_version();
+
+CREATE OR REPLACE FUNCTION audit_user_change() RETURNS trigger AS $$
+DECLARE
+    action TEXT;
+    target users;
+    old_row JSONB;
+    new_row JSONB;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        action := 'user.created';
+        target := NEW;
+        new_row := to_jsonb(NEW) - 'tenant_id';
+    ELSIF TG_OP = 'DELETE' THEN
+        action := 'user.purged';
+        target := OLD;
+        old_row := to_jsonb(OLD) - 'tenant_id';
+    ELSE
+        IF NEW.id::text = current_setting('app.erased_user_id', true) THEN
+            action := 'user.erased';
+        ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+            action := 'user.deleted';
+        ELSE
+            action := 'user.updated';
+        END IF;
+        target := NEW;
+        SELECT jsonb_object_agg(key, old_json -> key), jsonb_object_agg(key, new_json -> key)
+        INTO old_row, new_row
+        FROM (SELECT to_jsonb(OLD) - 'tenant_id' AS old_json, to_jsonb(NEW) - 'tenant_id' AS new_json) AS rows,
+            jsonb_object_keys(new_json) AS key
+        WHERE new_json -> key IS DISTINCT FROM old_json -> key;
+
+        IF new_row IS NULL THEN
+            RETURN NULL;
+        END IF;
+        IF action = 'user.erased' THEN
+            old_row := old_row - ARRAY['first_name', 'last_name', 'email_address'];
+            new_row := new_row - ARRAY['first_name', 'last_name', 'email_address'];
+        END IF;
+    END IF;
+
+    INSERT INTO audit_events(
+        id, tenant_id, actor_id, action, target_type, target_id, before, after,
+        request_id, ip_address, created_at
+    )
+    VALUES (
+        uuid_generate_v7(),
+        target.tenant_id,
+        NULLIF(current_setting('app.actor_id', true), '')::uuid,
+        action,
+        'user',
+        target.id,
+        old_row,
+        new_row,
+        NULLIF(current_setting('app.request_id', true), ''),
+        NULLIF(current_setting('app.ip_address', true), ''),
+        timezone('utc', now())
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP INDEX IF EXISTS users_email_hash_key;
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_address_key ON users(lower(email_address))
+    WHERE deleted_at IS NULL;
+
+ALTER TABLE users DROP COLUMN IF EXISTS email_hash;
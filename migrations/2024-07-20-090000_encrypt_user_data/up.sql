@@ -0,0 +1,90 @@
+-- Your SQL goes here
+-- The blind index of the email address, a keyed hash of it in lower case, to
+-- look users up by email address and keep it unique now that it is encrypted.
+ALTER TABLE users ADD COLUMN email_hash TEXT;
+
+DROP INDEX IF EXISTS users_email_address_key;
+CREATE UNIQUE INDEX users_email_hash_key ON users(email_hash) WHERE deleted_at IS NULL;
+
+-- Leave the blind index out of the audit events.
+CREATE OR REPLACE FUNCTION audit_user_change() RETURNS trigger AS $$
+DECLARE
+    action TEXT;
+    target users;
+    old_row JSONB;
+    new_row JSONB;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        action := 'user.created';
+        target := NEW;
+        new_row := to_jsonb(NEW) - ARRAY['tenant_id', 'email_hash'];
+    ELSIF TG_OP = 'DELETE' THEN
+        action := 'user.purged';
+        target := OLD;
+        old_row := to_jsonb(OLD) - ARRAY['tenant_id', 'email_hash'];
+    ELSE
+        IF NEW.id::text = current_setting('app.erased_user_id', true) THEN
+            action := 'user.erased';
+        ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+            action := 'user.deleted';
+        ELSE
+            action := 'user.updated';
+        END IF;
+        target := NEW;
+        SELECT jsonb_object_agg(key, old_json -> key), jsonb_object_agg(key, new_json -> key)
+        INTO old_row, new_row
+        FROM (SELECT to_jsonb(OLD) - ARRAY['tenant_id', 'email_hash'] AS old_json, to_jsonb(NEW) - ARRAY['tenant_id', 'email_hash'] AS new_json) AS rows,
+            jsonb_object_keys(new_json) AS key
+        WHERE new_json -> key IS DISTINCT FROM old_json -> key;
+
+        IF new_row IS NULL THEN
+            RETURN NULL;
+        END IF;
+        IF action = 'user.erased' THEN
+            old_row := old_row - ARRAY['first_name', 'last_name', 'email_address'];
+            new_row := new_row - ARRAY['first_name', 'last_name', 'email_address'];
+        END IF;
+    END IF;
+
+    INSERT INTO audit_events(
+        id, tenant_id, actor_id, action, target_type, target_id, before, after,
+        request_id, ip_address, created_at
+    )
+    VALUES (
+        uuid_generate_v7(),
+        target.tenant_id,
+        NULLIF(current_setting('app.actor_id', true), '')::uuid,
+        action,
+        'user',
+        target.id,
+        old_row,
+        new_row,
+        NULLIF(current_setting('app.request_id', true), ''),
+        NULLIF(current_setting('app.ip_address', true), ''),
+        timezone('utc', now())
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Re-encrypting users with `app.reencrypting` set changes no data, and is not
+-- versioned, audited nor notified.
+DROP TRIGGER IF EXISTS increment_user_version ON users;
+CREATE TRIGGER increment_user_version BEFORE UPDATE ON users FOR EACH ROW
+    WHEN (current_setting('app.reencrypting', true) IS DISTINCT FROM 'on')
+    EXECUTE FUNCTION increment_user_version();
+
+DROP TRIGGER IF EXISTS audit_user_change ON users;
+CREATE TRIGGER audit_user_change AFTER INSERT OR DELETE OR UPDATE ON users FOR EACH ROW
+    WHEN (current_setting('app.reencrypting', true) IS DISTINCT FROM 'on')
+    EXECUTE FUNCTION audit_user_change();
+
+DROP TRIGGER IF EXISTS notify_user_change ON users;
+CREATE TRIGGER notify_user_change AFTER INSERT OR DELETE OR UPDATE ON users FOR EACH ROW
+    WHEN (current_setting('app.reencrypting', true) IS DISTINCT FROM 'on')
+    EXECUTE FUNCTION notify_user_change();
+
+DROP TRIGGER IF EXISTS record_user_version ON users;
+CREATE TRIGGER record_user_version AFTER INSERT OR UPDATE ON users FOR EACH ROW
+    WHEN (current_setting('app.reencrypting', true) IS DISTINCT FROM 'on')
+    EXECUTE FUNCTION record_user_version();
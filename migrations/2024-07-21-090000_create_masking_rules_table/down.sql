@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS masking_rules;
+DELETE FROM roles WHERE name = 'support';
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- How each role sees the personal data of other users. A field without a rule
+-- is shown in full.
+CREATE TABLE IF NOT EXISTS masking_rules(
+    role_id UUID NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+    field TEXT NOT NULL CHECK (field IN ('first_name', 'last_name', 'email_address')),
+    strategy TEXT NOT NULL CHECK (strategy IN ('full', 'partial', 'hash', 'null')),
+    PRIMARY KEY (role_id, field)
+);
+
+INSERT INTO roles(id, name, description, created_at)
+VALUES (gen_random_uuid(), 'support', 'Helps users, without their full personal data', now())
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO permissions(role_id, name)
+SELECT id, 'users:read' FROM roles WHERE name = 'support'
+ON CONFLICT DO NOTHING;
+
+INSERT INTO masking_rules(role_id, field, strategy)
+SELECT roles.id, rule.field, rule.strategy
+FROM roles, (VALUES ('last_name', 'partial'), ('email_address', 'partial')) AS rule(field, strategy)
+WHERE roles.name = 'support'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE outbox ADD COLUMN dead_at TIMESTAMP;
+
+DROP INDEX IF EXISTS outbox_pending_idx;
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON outbox(id)
+    WHERE delivered_at IS NULL AND dead_at IS NULL;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE webhooks
+    ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id);
+ALTER TABLE webhook_deliveries
+    ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id);
+
+CREATE INDEX IF NOT EXISTS webhooks_tenant_id_idx ON webhooks(tenant_id);
+
+ALTER TABLE webhooks ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON webhooks TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
+ALTER TABLE webhook_deliveries ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON webhook_deliveries TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
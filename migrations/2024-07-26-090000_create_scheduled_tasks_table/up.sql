@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- The last run of every recurring job, so that a new scheduler leader can
+-- catch up on a run missed while there was none.
+CREATE TABLE IF NOT EXISTS scheduled_tasks(
+    name TEXT NOT NULL PRIMARY KEY,
+    last_run_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- The time the personal data of the user was removed. Its anonymized values
+-- are encrypted like any other, so they cannot be told apart otherwise.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS anonymized_at TIMESTAMP;
+
+-- Marking the users anonymized so far changes none of their data, and is
+-- neither versioned, audited nor notified.
+SELECT set_config('app.reencrypting', 'on', false);
+UPDATE users SET anonymized_at = updated_at WHERE email_address LIKE '%@anonymized.invalid';
+SELECT set_config('app.reencrypting', '', false);
@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM idempotency_keys;
+
+DROP POLICY IF EXISTS tenant_isolation ON idempotency_keys;
+ALTER TABLE idempotency_keys DISABLE ROW LEVEL SECURITY;
+
+ALTER TABLE idempotency_keys DROP CONSTRAINT IF EXISTS idempotency_keys_pkey;
+ALTER TABLE idempotency_keys ALTER COLUMN response TYPE JSONB USING response::jsonb;
+ALTER TABLE idempotency_keys DROP COLUMN IF EXISTS viewer_id;
+ALTER TABLE idempotency_keys DROP COLUMN IF EXISTS tenant_id;
+ALTER TABLE idempotency_keys ADD PRIMARY KEY (key);
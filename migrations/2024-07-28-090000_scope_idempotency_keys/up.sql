@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- Scope the keys to the tenant and the viewer that sent them, anonymous
+-- requests having the nil viewer, and encrypt the stored responses, which
+-- hold the personal data returned by mutations. The keys stored so far are
+-- only kept for retries, and dropped.
+DELETE FROM idempotency_keys;
+
+ALTER TABLE idempotency_keys DROP CONSTRAINT IF EXISTS idempotency_keys_pkey;
+ALTER TABLE idempotency_keys
+    ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT current_tenant_id() REFERENCES tenants(id);
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS viewer_id UUID NOT NULL;
+ALTER TABLE idempotency_keys ALTER COLUMN response TYPE TEXT USING response::text;
+ALTER TABLE idempotency_keys ADD PRIMARY KEY (tenant_id, viewer_id, key);
+
+ALTER TABLE idempotency_keys ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON idempotency_keys TO app_tenant
+    USING (tenant_id = current_setting('app.tenant_id')::uuid);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS invitations_email_hash_idx;
+
+ALTER TABLE invitations DROP COLUMN IF EXISTS email_hash;
+ALTER TABLE email_changes DROP COLUMN IF EXISTS email_hash;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- The blind indexes of the email addresses of the pending changes and of the
+-- invitations, to look them up now that the addresses are encrypted. The rows
+-- written before are encrypted and indexed by `rpg --reencrypt`.
+ALTER TABLE email_changes ADD COLUMN email_hash TEXT;
+ALTER TABLE invitations ADD COLUMN email_hash TEXT;
+
+CREATE INDEX invitations_email_hash_idx ON invitations(email_hash);
@@ -6,6 +6,25 @@ use std::sync::OnceLock;
 pub struct Config {
     pub database_url: String,
     pub endpoint_url: String,
+    pub graph_max_depth: usize,
+    pub graph_max_complexity: usize,
+    pub graph_max_aliases: usize,
+    pub graph_max_root_fields: usize,
+    pub graph_max_tokens: usize,
+    pub graph_manifest_path: Option<String>,
+    pub graph_allowlist: bool,
+    pub outbox_sink: String,
+    pub mailer: String,
+    pub mail_from: String,
+    pub worker_concurrency: usize,
+    pub retention_schedule: String,
+    pub retention_days: i64,
+    pub retention_mode: String,
+    pub export_dir: String,
+    pub encryption_keys: Option<String>,
+    pub encryption_key_file: Option<String>,
+    pub encryption_allow_plaintext: bool,
+    pub blind_index_key: String,
 }
 
 impl Config {
@@ -14,6 +33,28 @@ impl Config {
         Ok(Config {
             database_url: env::var("DATABASE_URL").unwrap(),
             endpoint_url: env::var("ENDPOINT_URL").unwrap(),
+            graph_max_depth: env::var("GRAPH_MAX_DEPTH").unwrap().parse().unwrap(),
+            graph_max_complexity: env::var("GRAPH_MAX_COMPLEXITY").unwrap().parse().unwrap(),
+            graph_max_aliases: env::var("GRAPH_MAX_ALIASES").unwrap().parse().unwrap(),
+            graph_max_root_fields: env::var("GRAPH_MAX_ROOT_FIELDS").unwrap().parse().unwrap(),
+            graph_max_tokens: env::var("GRAPH_MAX_TOKENS").unwrap().parse().unwrap(),
+            graph_manifest_path: env::var("GRAPH_MANIFEST_PATH").ok(),
+            graph_allowlist: env::var("GRAPH_ALLOWLIST").unwrap().parse().unwrap(),
+            outbox_sink: env::var("OUTBOX_SINK").unwrap(),
+            mailer: env::var("MAILER").unwrap(),
+            mail_from: env::var("MAIL_FROM").unwrap(),
+            worker_concurrency: env::var("WORKER_CONCURRENCY").unwrap().parse().unwrap(),
+            retention_schedule: env::var("RETENTION_SCHEDULE").unwrap(),
+            retention_days: env::var("RETENTION_DAYS").unwrap().parse().unwrap(),
+            retention_mode: env::var("RETENTION_MODE").unwrap(),
+            export_dir: env::var("EXPORT_DIR").unwrap(),
+            encryption_keys: env::var("ENCRYPTION_KEYS").ok(),
+            encryption_key_file: env::var("ENCRYPTION_KEY_FILE").ok(),
+            encryption_allow_plaintext: env::var("ENCRYPTION_ALLOW_PLAINTEXT")
+                .unwrap()
+                .parse()
+                .unwrap(),
+            blind_index_key: env::var("BLIND_INDEX_KEY").unwrap(),
         })
     }
 }
@@ -1,3 +1,26 @@
+pub mod audit;
+pub mod authz;
+pub mod email_changes;
+pub mod encryption;
+pub mod events;
+pub mod exports;
+pub mod idempotency;
+pub mod imports;
+pub mod invitations;
+pub mod jobs;
+pub mod mailer;
+pub mod masking;
 pub mod models;
+pub mod organizations;
+pub mod outbox;
+pub mod passwords;
+pub mod privacy;
 pub mod repo;
+pub mod retention;
+pub mod scheduler;
+pub mod search;
+pub mod sessions;
+pub mod tokens;
 pub mod users;
+pub mod verification;
+pub mod webhooks;
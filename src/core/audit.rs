@@ -0,0 +1,163 @@
+use crate::core::encryption::decrypt_json;
+use crate::core::models::schema::audit_events;
+use crate::core::models::AuditEvent;
+use crate::core::repo::ScopedResult;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel::PgConnection;
+use uuid::Uuid;
+
+/// Who makes a change, for the audit log: the signed-in user, if any, and the
+/// request it comes from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Actor {
+    pub user_id: Option<Uuid>,
+    pub request_id: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// The audit events to list. Every field left out matches every event.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    pub actor_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<Uuid>,
+}
+
+/// Attribute the changes of the current transaction to the actor. The audit
+/// trigger reads it from the settings of the transaction.
+pub fn set_actor(conn: &mut PgConnection, actor: &Actor) -> QueryResult<()> {
+    diesel::sql_query(
+        "SELECT set_config('app.actor_id', $1, true), \
+         set_config('app.request_id', $2, true), \
+         set_config('app.ip_address', $3, true)",
+    )
+    .bind::<Text, _>(
+        actor
+            .user_id
+            .map(|user_id| user_id.to_string())
+            .unwrap_or_default(),
+    )
+    .bind::<Text, _>(actor.request_id.clone().unwrap_or_default())
+    .bind::<Text, _>(actor.ip_address.clone().unwrap_or_default())
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Run the function in a transaction attributed to the actor. Its result is
+/// committed either way, like when the function runs on its own.
+pub fn with_actor<R, F>(conn: &mut PgConnection, actor: &Actor, f: F) -> R
+where
+    R: ScopedResult,
+    F: FnOnce(&mut PgConnection) -> R,
+{
+    conn.transaction(|conn| {
+        set_actor(conn, actor)?;
+        Ok(f(conn))
+    })
+    .unwrap_or_else(R::from_query_error)
+}
+
+/// List the audit events matching the filter, newest first, after the event
+/// with the given id.
+pub fn list_events(
+    conn: &mut PgConnection,
+    filter: &EventFilter,
+    after: Option<Uuid>,
+    limit: i64,
+) -> QueryResult<Vec<AuditEvent>> {
+    let mut query = audit_events::table
+        .order(audit_events::id.desc())
+        .limit(limit)
+        .select(AuditEvent::as_select())
+        .into_boxed();
+    if let Some(actor_id) = filter.actor_id {
+        query = query.filter(audit_events::actor_id.eq(actor_id));
+    }
+    if let Some(action) = &filter.action {
+        query = query.filter(audit_events::action.eq(action.clone()));
+    }
+    if let Some(target_type) = &filter.target_type {
+        query = query.filter(audit_events::target_type.eq(target_type.clone()));
+    }
+    if let Some(target_id) = filter.target_id {
+        query = query.filter(audit_events::target_id.eq(target_id));
+    }
+    if let Some(after) = after {
+        query = query.filter(audit_events::id.lt(after));
+    }
+    let events = query.load::<AuditEvent>(conn)?;
+
+    // The personal data of users is recorded encrypted, as it is stored.
+    Ok(events
+        .into_iter()
+        .map(|event| AuditEvent {
+            before: event.before.map(decrypt_json),
+            after: event.after.map(decrypt_json),
+            ..event
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::users;
+    use crate::core::users::UpdateUserAttrs;
+    use crate::test::factory;
+    use serde_json::json;
+
+    #[test]
+    fn test_with_actor() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let actor = Actor {
+            user_id: Some(Uuid::now_v7()),
+            request_id: Some("req-1".to_string()),
+            ip_address: Some("127.0.0.1".to_string()),
+        };
+        let attrs = UpdateUserAttrs {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+
+        with_actor(&mut conn, &actor, |conn| {
+            users::update_user(conn, user.id, attrs, None)
+        })
+        .unwrap()
+        .unwrap();
+        users::delete_user(&mut conn, user.id, None)
+            .unwrap()
+            .unwrap();
+
+        let filter = EventFilter {
+            target_type: Some("user".to_string()),
+            target_id: Some(user.id),
+            ..EventFilter::default()
+        };
+        let events = list_events(&mut conn, &filter, None, 10).unwrap();
+        let actions: Vec<&str> = events.iter().map(|event| event.action.as_str()).collect();
+        assert_eq!(actions, ["user.deleted", "user.updated", "user.created"]);
+
+        let updated = &events[1];
+        assert_eq!(updated.actor_id, actor.user_id);
+        assert_eq!(updated.request_id, actor.request_id);
+        assert_eq!(updated.ip_address, actor.ip_address);
+        let before = updated.before.as_ref().unwrap();
+        assert_eq!(before["first_name"], json!(user.first_name));
+        assert_eq!(
+            updated.after.as_ref().unwrap()["first_name"],
+            json!("Janet")
+        );
+        assert!(before.get("last_name").is_none());
+        assert_eq!(events[0].actor_id, None);
+
+        let after = list_events(&mut conn, &filter, Some(events[1].id), 10).unwrap();
+        assert_eq!(after, events[2..]);
+    }
+}
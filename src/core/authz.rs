@@ -0,0 +1,204 @@
+use crate::core::models::schema::permissions;
+use crate::core::models::schema::roles;
+use crate::core::models::schema::user_roles;
+use crate::core::models::Role;
+use crate::core::models::User;
+use crate::core::models::UserRole;
+use crate::core::users::get_tenant_id;
+use crate::core::users::get_user;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::collections::HashSet;
+use std::io::Error;
+use std::io::ErrorKind;
+use uuid::Uuid;
+
+/// The permission to grant and revoke roles.
+pub const MANAGE_ROLES: &str = "roles:manage";
+
+/// The permission to manage every organization, whether a member or not.
+pub const MANAGE_ORGANIZATIONS: &str = "organizations:manage";
+
+/// The permission to read the audit log.
+pub const READ_AUDIT: &str = "audit:read";
+
+/// The permission to import users in bulk.
+pub const IMPORT_USERS: &str = "users:import";
+
+/// The permission to export users in bulk.
+pub const EXPORT_USERS: &str = "users:export";
+
+/// The permission to erase the personal data of a user.
+pub const ERASE_USERS: &str = "users:erase";
+
+/// The permission to read users and subscribe to their changes.
+pub const READ_USERS: &str = "users:read";
+
+/// The permission to change other users.
+pub const WRITE_USERS: &str = "users:write";
+
+/// The permission to delete other users.
+pub const DELETE_USERS: &str = "users:delete";
+
+/// The permission to manage the webhooks of the tenant and read their deliveries.
+pub const MANAGE_WEBHOOKS: &str = "webhooks:manage";
+
+/// The roles of a user and the permissions they grant.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Grants {
+    pub roles: HashSet<String>,
+    pub permissions: HashSet<String>,
+}
+
+impl Grants {
+    /// Check whether one of the roles of the user grants the permission.
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.contains(permission)
+    }
+}
+
+/// Get the roles of the user and the permissions they grant.
+pub fn get_grants(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Grants> {
+    let rows = user_roles::table
+        .inner_join(roles::table.left_join(permissions::table))
+        .filter(user_roles::user_id.eq(user_id))
+        .select((roles::name, permissions::name.nullable()))
+        .load::<(String, Option<String>)>(conn)?;
+
+    let mut grants = Grants::default();
+    for (role, permission) in rows {
+        grants.roles.insert(role);
+        grants.permissions.extend(permission);
+    }
+
+    Ok(grants)
+}
+
+/// Get the names of the roles of the user.
+pub fn get_role_names(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Vec<String>> {
+    user_roles::table
+        .inner_join(roles::table)
+        .filter(user_roles::user_id.eq(user_id))
+        .order(roles::name)
+        .select(roles::name)
+        .load(conn)
+}
+
+/// Get the role with the name, failing with `ErrorKind::NotFound` if there
+/// is none.
+fn find_role(conn: &mut PgConnection, name: &str) -> Result<Role, Error> {
+    let role = roles::table
+        .filter(roles::name.eq(name))
+        .select(Role::as_select())
+        .first(conn)
+        .optional()
+        .map_err(Error::other)?;
+
+    role.ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Unknown role: {}", name)))
+}
+
+/// Grant the role to the user, if it does not have it yet.
+pub fn grant_role(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    role_name: &str,
+) -> Result<Option<User>, Error> {
+    let user = match get_user(conn, user_id)? {
+        Some(user) if user.deleted_at.is_none() => user,
+        _ => return Ok(None),
+    };
+    let role = find_role(conn, role_name)?;
+    // The grant belongs to the tenant of the user, even outside of a tenant.
+    let tenant_id = get_tenant_id(conn, user.id)
+        .map_err(Error::other)?
+        .unwrap_or_default();
+
+    diesel::insert_into(user_roles::table)
+        .values((
+            &UserRole {
+                user_id: user.id,
+                role_id: role.id,
+                created_at: Utc::now().naive_utc(),
+            },
+            user_roles::tenant_id.eq(tenant_id),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .map_err(Error::other)?;
+
+    Ok(Some(user))
+}
+
+/// Revoke the role from the user, if it has it.
+pub fn revoke_role(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    role_name: &str,
+) -> Result<Option<User>, Error> {
+    let user = match get_user(conn, user_id)? {
+        Some(user) if user.deleted_at.is_none() => user,
+        _ => return Ok(None),
+    };
+    let role = find_role(conn, role_name)?;
+
+    diesel::delete(user_roles::table.find((user.id, role.id)))
+        .execute(conn)
+        .map_err(Error::other)?;
+
+    Ok(Some(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::test::factory;
+    use diesel::Connection;
+
+    /// The role seeded with every permission.
+    const ADMIN: &str = "admin";
+
+    #[test]
+    fn test_grant_role() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+
+        assert_eq!(get_grants(&mut conn, user.id).unwrap(), Grants::default());
+
+        grant_role(&mut conn, user.id, ADMIN).unwrap().unwrap();
+        grant_role(&mut conn, user.id, ADMIN).unwrap().unwrap();
+        let grants = get_grants(&mut conn, user.id).unwrap();
+
+        assert!(grants.roles.contains(ADMIN));
+        assert!(grants.has_permission(MANAGE_ROLES));
+        assert_eq!(
+            get_role_names(&mut conn, user.id).unwrap(),
+            vec![ADMIN.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_revoke_role() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        grant_role(&mut conn, user.id, ADMIN).unwrap();
+
+        revoke_role(&mut conn, user.id, ADMIN).unwrap().unwrap();
+
+        assert_eq!(get_grants(&mut conn, user.id).unwrap(), Grants::default());
+    }
+
+    #[test]
+    fn test_grant_role_unknown() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let error = grant_role(&mut conn, user.id, "unknown").unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(grant_role(&mut conn, Uuid::now_v7(), ADMIN).unwrap(), None);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::core::encryption::blind_index;
+use crate::core::encryption::Encrypted;
+use crate::core::mailer::Email;
+use crate::core::mailer::Mailer;
+use crate::core::models::schema::email_changes;
+use crate::core::models::schema::users;
+use crate::core::models::EmailChange;
+use crate::core::models::User;
+use crate::core::search::search_tokens;
+use crate::core::tokens;
+use crate::core::tokens::EMAIL_CHANGE;
+use crate::core::tokens::EMAIL_VERIFICATION;
+use crate::core::tokens::PASSWORD_RESET;
+use crate::core::users::enqueue_change;
+use crate::core::users::get_user;
+use chrono::Duration;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel::PgConnection;
+use std::io::Error;
+use std::io::ErrorKind;
+use tracing::warn;
+use uuid::Uuid;
+
+/// The time an email change confirmation token stays valid.
+const CHANGE_TTL: Duration = Duration::hours(24);
+
+/// Store a pending change of the email address of the user, replacing any
+/// previous one, mail a confirmation token to the new address and a notice
+/// to the current one. Whether the new address is taken is only checked at
+/// confirmation, so that requesting a change does not reveal it.
+pub fn request_email_change(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    user_id: Uuid,
+    new_email_address: &str,
+) -> Result<Option<User>, Error> {
+    let user = match get_user(conn, user_id)? {
+        Some(user) if user.deleted_at.is_none() => user,
+        _ => return Ok(None),
+    };
+    if user.email_address.eq_ignore_ascii_case(new_email_address) {
+        return Ok(Some(user));
+    }
+
+    let change = EmailChange {
+        user_id: user.id,
+        email_address: new_email_address.to_string(),
+        created_at: Utc::now().naive_utc(),
+    };
+    let token = conn
+        .transaction(|conn| {
+            let hash = blind_index(&change.email_address);
+            diesel::insert_into(email_changes::table)
+                .values((change.clone(), email_changes::email_hash.eq(&hash)))
+                .on_conflict(email_changes::user_id)
+                .do_update()
+                .set((
+                    email_changes::email_address.eq(Encrypted(change.email_address.clone())),
+                    email_changes::email_hash.eq(&hash),
+                    email_changes::created_at.eq(change.created_at),
+                ))
+                .execute(conn)?;
+            tokens::issue_token(conn, user.id, EMAIL_CHANGE, CHANGE_TTL)
+        })
+        .map_err(Error::other)?;
+
+    mailer.send(&Email {
+        to: change.email_address.clone(),
+        subject: "Confirm your new email address".to_string(),
+        body: format!(
+            "Use this token to confirm your new email address within 24 hours:\n\n{}",
+            token
+        ),
+    })?;
+    mailer.send(&Email {
+        to: user.email_address.clone(),
+        subject: "Your email address is being changed".to_string(),
+        body: format!(
+            "A change of your email address to {} was requested. It takes effect once \
+             confirmed from the new address. If you did not request it, secure your account.",
+            change.email_address
+        ),
+    })?;
+
+    Ok(Some(user))
+}
+
+/// Apply the pending change of email address of the user of the token, which
+/// also verifies the new address, and mail a notice to the previous address.
+/// Fails with `ErrorKind::AlreadyExists` if the address has been taken by
+/// another user since the change was requested.
+pub fn confirm_email_change(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    token: &str,
+) -> Result<Option<User>, Error> {
+    let timestamp = Utc::now().naive_utc();
+
+    // TODO: Handle all the errors
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let user_id = match tokens::consume_token(conn, EMAIL_CHANGE, token)? {
+                Some(user_id) => user_id,
+                None => return Ok(None),
+            };
+            let address = diesel::delete(email_changes::table.find(user_id))
+                .returning(email_changes::email_address)
+                .get_result::<Encrypted>(conn)?
+                .0;
+            let hash = blind_index(&address);
+            let current = users::table
+                .find(user_id)
+                .filter(users::deleted_at.is_null())
+                .select(User::as_select())
+                .first(conn)?;
+            let tokens = search_tokens(&current.first_name, &current.last_name, &address);
+            let user = diesel::update(
+                users::table
+                    .find(user_id)
+                    .filter(users::deleted_at.is_null()),
+            )
+            .set((
+                users::email_address.eq(Encrypted(address)),
+                users::email_hash.eq(hash),
+                users::search_tokens.eq(tokens),
+                users::email_verified_at.eq(timestamp),
+                users::updated_at.eq(timestamp),
+            ))
+            .returning(User::as_returning())
+            .get_result(conn)?;
+            // Tokens mailed to the previous address must not outlive it.
+            tokens::revoke_tokens(conn, user_id, EMAIL_VERIFICATION)?;
+            tokens::revoke_tokens(conn, user_id, PASSWORD_RESET)?;
+            enqueue_change(conn, "user.updated", &user)?;
+            Ok(Some((user, current.email_address)))
+        })
+        .optional();
+
+    match result {
+        Ok(Some(Some((user, previous_address)))) => {
+            // The change is committed, so a notice that fails to send is not
+            // a reason to report it as failed.
+            let notice = mailer.send(&Email {
+                to: previous_address,
+                subject: "Your email address was changed".to_string(),
+                body: format!(
+                    "The email address of your account was changed to {}. If you did not \
+                     change it, secure your account.",
+                    user.email_address
+                ),
+            });
+            if let Err(e) = notice {
+                warn!("Failed to notify user {} of the change: {}", user.id, e);
+            }
+            Ok(Some(user))
+        }
+        Ok(Some(None)) => Ok(None),
+        Ok(None) => Ok(None),
+        Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "The email address is already in use",
+            ))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::mailer::MemoryMailer;
+    use crate::test::factory;
+    use diesel::Connection;
+
+    /// Get the token from the last email sent to the address.
+    fn sent_token(mailer: &MemoryMailer, to: &str) -> String {
+        let email = mailer.last_sent_to(to).unwrap();
+
+        email.body.split_whitespace().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_confirm_email_change() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+        let new_email_address = format!("janet.{}@doe.com", user.id.simple());
+        request_email_change(&mut conn, &mailer, user.id, &new_email_address)
+            .unwrap()
+            .unwrap();
+        let token = sent_token(&mailer, &new_email_address);
+
+        assert_eq!(
+            get_user(&mut conn, user.id).unwrap().unwrap().email_address,
+            user.email_address
+        );
+        assert!(mailer
+            .last_sent_to(&user.email_address)
+            .unwrap()
+            .body
+            .contains(&new_email_address));
+
+        let result = confirm_email_change(&mut conn, &mailer, &token)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.email_address, new_email_address);
+        assert_ne!(result.email_verified_at, None);
+        assert_eq!(
+            mailer.last_sent_to(&user.email_address).unwrap().subject,
+            "Your email address was changed"
+        );
+        assert_eq!(
+            confirm_email_change(&mut conn, &mailer, &token).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_confirm_email_change_taken() {
+        let user = factory::insert_user();
+        let other = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+        let new_email_address = other.email_address.to_uppercase();
+        request_email_change(&mut conn, &mailer, user.id, &new_email_address).unwrap();
+        let token = sent_token(&mailer, &new_email_address);
+
+        let error = confirm_email_change(&mut conn, &mailer, &token).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(
+            get_user(&mut conn, user.id).unwrap().unwrap().email_address,
+            user.email_address
+        );
+    }
+}
@@ -0,0 +1,886 @@
+use crate::config;
+use crate::config::Config;
+use crate::core::models::schema::audit_events;
+use crate::core::models::schema::email_changes;
+use crate::core::models::schema::invitations;
+use crate::core::models::schema::users;
+use crate::core::models::schema::users_history;
+use crate::core::search::search_tokens;
+use aes_gcm::aead::Aead;
+use aes_gcm::aead::AeadCore;
+use aes_gcm::aead::KeyInit;
+use aes_gcm::aead::OsRng;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::Nonce;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use diesel::deserialize;
+use diesel::deserialize::FromSql;
+use diesel::deserialize::FromSqlRow;
+use diesel::expression::AsExpression;
+use diesel::pg::Pg;
+use diesel::pg::PgValue;
+use diesel::prelude::*;
+use diesel::serialize;
+use diesel::serialize::IsNull;
+use diesel::serialize::Output;
+use diesel::serialize::ToSql;
+use diesel::sql_types::Text;
+use diesel::PgConnection;
+use hmac::Hmac;
+use hmac::Mac;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+/// The prefix of an encrypted value, followed by the id of its key, a colon,
+/// and the base64 of the wrapped data key and of the ciphertext.
+const PREFIX: &str = "enc:";
+
+/// The size of the nonces of AES-256-GCM.
+const NONCE_SIZE: usize = 12;
+
+/// The size of a data key wrapped by a key: the data key and its tag.
+const WRAPPED_KEY_SIZE: usize = 32 + 16;
+
+/// The fields holding personal data, encrypted in the users, their versions
+/// and their audit events.
+const FIELDS: [&str; 3] = ["first_name", "last_name", "email_address"];
+
+/// The number of rows re-encrypted in a transaction.
+const BATCH_SIZE: i64 = 500;
+
+/// The keys encrypting the personal data of users, by id, and the key of the
+/// blind index of their email addresses.
+///
+/// Every value is encrypted with its own data key, wrapped by the current
+/// key: the first one configured. The other keys only decrypt the values
+/// encrypted before a rotation, until they are re-encrypted.
+///
+/// A value that is not encrypted is rejected, unless plaintext is allowed
+/// while migrating the values written before encryption.
+pub struct Keyring {
+    current: String,
+    keys: HashMap<String, Aes256Gcm>,
+    blind_index_key: Vec<u8>,
+    allow_plaintext: bool,
+}
+
+impl Keyring {
+    /// Parse the `id:key` pairs of base64 keys, separated by commas or lines,
+    /// and the base64 key of the blind index. Blank lines and lines starting
+    /// with `#` are ignored.
+    pub fn parse(keys: &str, blind_index_key: &str) -> Result<Keyring, Error> {
+        let mut current = None;
+        let mut ciphers = HashMap::new();
+        let entries = keys
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty() && !entry.starts_with('#'));
+        for entry in entries {
+            let (id, key) = match entry.split_once(':') {
+                Some((id, key)) if !id.is_empty() => (id, key),
+                _ => return Err(invalid("An encryption key is not an `id:key` pair")),
+            };
+            let key = STANDARD
+                .decode(key)
+                .map_err(|_| invalid("An encryption key is not base64"))?;
+            let cipher = Aes256Gcm::new_from_slice(&key)
+                .map_err(|_| invalid("An encryption key is not 32 bytes long"))?;
+            if ciphers.insert(id.to_string(), cipher).is_some() {
+                return Err(invalid(&format!("The encryption key {} appears twice", id)));
+            }
+            current.get_or_insert_with(|| id.to_string());
+        }
+        let blind_index_key = STANDARD
+            .decode(blind_index_key.trim())
+            .map_err(|_| invalid("The blind index key is not base64"))?;
+
+        match current {
+            Some(current) => Ok(Keyring {
+                current,
+                keys: ciphers,
+                blind_index_key,
+                allow_plaintext: false,
+            }),
+            None => Err(invalid("There is no encryption key")),
+        }
+    }
+
+    /// Load the keys of the configuration, then those of the key file.
+    fn load(config: &Config) -> Result<Keyring, Error> {
+        let mut keys = config.encryption_keys.clone().unwrap_or_default();
+        if let Some(path) = &config.encryption_key_file {
+            keys.push('\n');
+            keys.push_str(&fs::read_to_string(path)?);
+        }
+
+        let mut keyring = Keyring::parse(&keys, &config.blind_index_key)?;
+        keyring.allow_plaintext = config.encryption_allow_plaintext;
+
+        Ok(keyring)
+    }
+
+    /// Encrypt the value with a new data key, wrapped by the current key.
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let data_nonce = Aes256Gcm::generate_nonce(OsRng);
+        let ciphertext = Aes256Gcm::new(&data_key)
+            .encrypt(&data_nonce, plaintext.as_bytes())
+            .unwrap();
+        let key_nonce = Aes256Gcm::generate_nonce(OsRng);
+        let wrapped_key = self.keys[&self.current]
+            .encrypt(&key_nonce, data_key.as_slice())
+            .unwrap();
+
+        let mut bytes = Vec::with_capacity(2 * NONCE_SIZE + WRAPPED_KEY_SIZE + ciphertext.len());
+        bytes.extend_from_slice(&key_nonce);
+        bytes.extend_from_slice(&wrapped_key);
+        bytes.extend_from_slice(&data_nonce);
+        bytes.extend_from_slice(&ciphertext);
+
+        format!("{}{}:{}", PREFIX, self.current, STANDARD.encode(bytes))
+    }
+
+    /// Decrypt the value. A value that is not encrypted is rejected, unless
+    /// plaintext is allowed, and then returned as it is.
+    pub fn decrypt(&self, value: &str) -> Result<String, Error> {
+        match self.allow_plaintext {
+            true => self.plaintext(value),
+            false => self.decrypt_encrypted(value),
+        }
+    }
+
+    /// Decrypt the value, or return it as it is if it is not encrypted, like
+    /// the values written before encryption, to encrypt them.
+    fn plaintext(&self, value: &str) -> Result<String, Error> {
+        match value.starts_with(PREFIX) {
+            true => self.decrypt_encrypted(value),
+            false => Ok(value.to_string()),
+        }
+    }
+
+    fn decrypt_encrypted(&self, value: &str) -> Result<String, Error> {
+        let (id, encoded) = value
+            .strip_prefix(PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .ok_or_else(|| invalid("The value is not encrypted"))?;
+        let cipher = self
+            .keys
+            .get(id)
+            .ok_or_else(|| invalid(&format!("The encryption key {} is unknown", id)))?;
+        let bytes = STANDARD
+            .decode(encoded)
+            .map_err(|_| invalid("The value is not base64"))?;
+        if bytes.len() < 2 * NONCE_SIZE + WRAPPED_KEY_SIZE {
+            return Err(invalid("The value is truncated"));
+        }
+
+        let (key_nonce, rest) = bytes.split_at(NONCE_SIZE);
+        let (wrapped_key, rest) = rest.split_at(WRAPPED_KEY_SIZE);
+        let (data_nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+        let data_key = cipher
+            .decrypt(Nonce::from_slice(key_nonce), wrapped_key)
+            .map_err(|_| invalid("The data key cannot be decrypted"))?;
+        let plaintext = Aes256Gcm::new_from_slice(&data_key)
+            .map_err(|_| invalid("The data key is not 32 bytes long"))?
+            .decrypt(Nonce::from_slice(data_nonce), ciphertext)
+            .map_err(|_| invalid("The value cannot be decrypted"))?;
+
+        String::from_utf8(plaintext).map_err(|_| invalid("The value is not UTF-8"))
+    }
+
+    /// Check whether the value is encrypted with the current key.
+    pub fn is_current(&self, value: &str) -> bool {
+        match value
+            .strip_prefix(PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+        {
+            Some((id, _)) => id == self.current,
+            None => false,
+        }
+    }
+
+    /// Encrypt the value with the current key, unless it already is. A value
+    /// that is not encrypted yet is encrypted.
+    pub fn reencrypt(&self, value: &str) -> Result<String, Error> {
+        match self.is_current(value) {
+            true => Ok(value.to_string()),
+            false => Ok(self.encrypt(&self.plaintext(value)?)),
+        }
+    }
+
+    /// Compute the blind index of the email address: a keyed hash of it in
+    /// lower case, to look it up and keep it unique without decrypting it.
+    pub fn blind_index(&self, email_address: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.blind_index_key).unwrap();
+        mac.update(email_address.to_lowercase().as_bytes());
+
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Get the keyring of the configuration.
+pub fn keyring() -> &'static Keyring {
+    static INSTANCE: OnceLock<Keyring> = OnceLock::new();
+
+    INSTANCE.get_or_init(|| {
+        Keyring::load(config::get_config())
+            .unwrap_or_else(|e| panic!("Failed to load encryption keys: {:?}", e))
+    })
+}
+
+/// Compute the blind index of the email address, to look users up by it.
+pub fn blind_index(email_address: &str) -> String {
+    keyring().blind_index(email_address)
+}
+
+/// Generate a new base64 encryption key, to add to the keys.
+pub fn generate_key() -> String {
+    STANDARD.encode(Aes256Gcm::generate_key(OsRng))
+}
+
+/// Decrypt the encrypted strings of the JSON value, like the personal data in
+/// the changes recorded by audit events. Those that cannot be decrypted are
+/// left as they are.
+pub fn decrypt_json(value: Value) -> Value {
+    match value {
+        Value::String(string) => match keyring().decrypt(&string) {
+            Ok(plaintext) => Value::String(plaintext),
+            Err(_) => Value::String(string),
+        },
+        Value::Array(values) => Value::Array(values.into_iter().map(decrypt_json).collect()),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, decrypt_json(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Encrypt the personal data in the JSON object of a user, like the payload
+/// of the events about it, which are kept until delivered.
+pub fn encrypt_fields(mut value: Value) -> Value {
+    if let Value::Object(map) = &mut value {
+        for field in FIELDS {
+            if let Some(Value::String(plaintext)) = map.get_mut(field) {
+                *plaintext = keyring().encrypt(plaintext);
+            }
+        }
+    }
+
+    value
+}
+
+/// Decrypt the personal data in the JSON object of a user, encrypted by
+/// `encrypt_fields`, to deliver it.
+pub fn decrypt_fields(mut value: Value) -> Result<Value, Error> {
+    if let Value::Object(map) = &mut value {
+        for field in FIELDS {
+            if let Some(Value::String(encrypted)) = map.get_mut(field) {
+                *encrypted = keyring().decrypt(encrypted)?;
+            }
+        }
+    }
+
+    Ok(value)
+}
+
+/// A text column encrypted at rest: encrypted with the current key when
+/// written, and decrypted when read.
+#[derive(AsExpression, Debug, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub struct Encrypted(pub String);
+
+impl From<String> for Encrypted {
+    fn from(value: String) -> Self {
+        Encrypted(value)
+    }
+}
+
+impl From<Encrypted> for String {
+    fn from(value: Encrypted) -> Self {
+        value.0
+    }
+}
+
+impl FromSql<Text, Pg> for Encrypted {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let value = <String as FromSql<Text, Pg>>::from_sql(bytes)?;
+
+        Ok(Encrypted(keyring().decrypt(&value)?))
+    }
+}
+
+impl ToSql<Text, Pg> for Encrypted {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(keyring().encrypt(&self.0).as_bytes())?;
+
+        Ok(IsNull::No)
+    }
+}
+
+/// The number of rows re-encrypted in each table.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reencrypted {
+    pub users: usize,
+    pub versions: usize,
+    pub events: usize,
+    pub email_changes: usize,
+    pub invitations: usize,
+}
+
+/// Re-encrypt the personal data of the users, of their versions, of their
+/// audit events, of their pending email changes and of the invitations with
+/// the current key, and fill in the blind indexes and search tokens missing
+/// or out of date. The values written before
+/// encryption are encrypted too.
+/// Once done, the keys rotated out can be removed, and plaintext no longer
+/// needs to be allowed. The changes to users are neither versioned, audited
+/// nor notified, as their data stays the same.
+pub fn reencrypt_users(conn: &mut PgConnection) -> Result<Reencrypted, Error> {
+    Ok(Reencrypted {
+        users: reencrypt_user_rows(conn)?,
+        versions: reencrypt_versions(conn)?,
+        events: reencrypt_events(conn)?,
+        email_changes: reencrypt_email_changes(conn)?,
+        invitations: reencrypt_invitations(conn)?,
+    })
+}
+
+type UserFields = (
+    Uuid,
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<NaiveDateTime>,
+);
+
+fn reencrypt_user_rows(conn: &mut PgConnection) -> Result<usize, Error> {
+    let keyring = keyring();
+    let mut count = 0;
+    let mut after = None;
+
+    loop {
+        let mut query = users::table
+            .order(users::id)
+            .limit(BATCH_SIZE)
+            .select((
+                users::id,
+                users::first_name,
+                users::last_name,
+                users::email_address,
+                users::email_hash,
+                users::search_tokens,
+                users::anonymized_at,
+            ))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(users::id.gt(after));
+        }
+        let rows = query.load::<UserFields>(conn).map_err(Error::other)?;
+        let last = match rows.last() {
+            Some(row) => row.0,
+            None => return Ok(count),
+        };
+
+        let mut changes = Vec::new();
+        for (id, first_name, last_name, email_address, email_hash, tokens, anonymized_at) in rows {
+            let address = keyring.plaintext(&email_address)?;
+            let names = (
+                keyring.plaintext(&first_name)?,
+                keyring.plaintext(&last_name)?,
+            );
+            // The anonymized users are neither indexed nor searchable.
+            let (hash, search) = match anonymized_at {
+                Some(_) => (None, None),
+                None => (
+                    Some(keyring.blind_index(&address)),
+                    Some(search_tokens(&names.0, &names.1, &address)),
+                ),
+            };
+            let current = [&first_name, &last_name, &email_address]
+                .iter()
+                .all(|value| keyring.is_current(value));
+            if current && email_hash == hash && tokens == search {
+                continue;
+            }
+            changes.push((
+                id,
+                keyring.reencrypt(&first_name)?,
+                keyring.reencrypt(&last_name)?,
+                keyring.reencrypt(&email_address)?,
+                hash,
+                search,
+            ));
+        }
+        count += changes.len();
+        conn.transaction(|conn| {
+            diesel::sql_query("SELECT set_config('app.reencrypting', 'on', true)").execute(conn)?;
+            for (id, first_name, last_name, email_address, hash, search) in changes {
+                diesel::update(users::table.find(id))
+                    .set((
+                        users::first_name.eq(first_name),
+                        users::last_name.eq(last_name),
+                        users::email_address.eq(email_address),
+                        users::email_hash.eq(hash),
+                        users::search_tokens.eq(search),
+                    ))
+                    .execute(conn)?;
+            }
+            QueryResult::Ok(())
+        })
+        .map_err(Error::other)?;
+        after = Some(last);
+    }
+}
+
+fn reencrypt_versions(conn: &mut PgConnection) -> Result<usize, Error> {
+    let keyring = keyring();
+    let mut count = 0;
+    let mut after = None;
+
+    loop {
+        let mut query = users_history::table
+            .order(users_history::version_id)
+            .limit(BATCH_SIZE)
+            .select((
+                users_history::version_id,
+                users_history::first_name,
+                users_history::last_name,
+                users_history::email_address,
+            ))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(users_history::version_id.gt(after));
+        }
+        let rows = query
+            .load::<(Uuid, String, String, String)>(conn)
+            .map_err(Error::other)?;
+        let last = match rows.last() {
+            Some(row) => row.0,
+            None => return Ok(count),
+        };
+
+        let mut changes = Vec::new();
+        for (version_id, first_name, last_name, email_address) in rows {
+            if [&first_name, &last_name, &email_address]
+                .iter()
+                .all(|value| keyring.is_current(value))
+            {
+                continue;
+            }
+            changes.push((
+                version_id,
+                keyring.reencrypt(&first_name)?,
+                keyring.reencrypt(&last_name)?,
+                keyring.reencrypt(&email_address)?,
+            ));
+        }
+        count += changes.len();
+        conn.transaction(|conn| {
+            for (version_id, first_name, last_name, email_address) in changes {
+                diesel::update(users_history::table.find(version_id))
+                    .set((
+                        users_history::first_name.eq(first_name),
+                        users_history::last_name.eq(last_name),
+                        users_history::email_address.eq(email_address),
+                    ))
+                    .execute(conn)?;
+            }
+            QueryResult::Ok(())
+        })
+        .map_err(Error::other)?;
+        after = Some(last);
+    }
+}
+
+/// Re-encrypt the personal data in the changes recorded by an audit event,
+/// telling whether any was.
+fn reencrypt_changes(keyring: &Keyring, changes: &mut Option<Value>) -> Result<bool, Error> {
+    let mut changed = false;
+    if let Some(Value::Object(map)) = changes {
+        for field in FIELDS {
+            if let Some(Value::String(value)) = map.get_mut(field) {
+                if !keyring.is_current(value) {
+                    *value = keyring.reencrypt(value)?;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+fn reencrypt_events(conn: &mut PgConnection) -> Result<usize, Error> {
+    let keyring = keyring();
+    let mut count = 0;
+    let mut after = None;
+
+    loop {
+        let mut query = audit_events::table
+            .filter(audit_events::target_type.eq("user"))
+            .order(audit_events::id)
+            .limit(BATCH_SIZE)
+            .select((audit_events::id, audit_events::before, audit_events::after))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(audit_events::id.gt(after));
+        }
+        let rows = query
+            .load::<(Uuid, Option<Value>, Option<Value>)>(conn)
+            .map_err(Error::other)?;
+        let last = match rows.last() {
+            Some(row) => row.0,
+            None => return Ok(count),
+        };
+
+        let mut changes = Vec::new();
+        for (id, mut before, mut after) in rows {
+            let before_changed = reencrypt_changes(keyring, &mut before)?;
+            if reencrypt_changes(keyring, &mut after)? || before_changed {
+                changes.push((id, before, after));
+            }
+        }
+        count += changes.len();
+        conn.transaction(|conn| {
+            for (id, before, after) in changes {
+                diesel::update(audit_events::table.find(id))
+                    .set((
+                        audit_events::before.eq(before),
+                        audit_events::after.eq(after),
+                    ))
+                    .execute(conn)?;
+            }
+            QueryResult::Ok(())
+        })
+        .map_err(Error::other)?;
+        after = Some(last);
+    }
+}
+
+/// Re-encrypt the email addresses of rows keyed by id, with their blind
+/// indexes, telling which changed.
+fn reencrypt_addresses(
+    keyring: &Keyring,
+    rows: Vec<(Uuid, String, Option<String>)>,
+) -> Result<Vec<(Uuid, String, Option<String>)>, Error> {
+    let mut changes = Vec::new();
+    for (id, email_address, email_hash) in rows {
+        let hash = Some(keyring.blind_index(&keyring.plaintext(&email_address)?));
+        if keyring.is_current(&email_address) && email_hash == hash {
+            continue;
+        }
+        changes.push((id, keyring.reencrypt(&email_address)?, hash));
+    }
+
+    Ok(changes)
+}
+
+fn reencrypt_email_changes(conn: &mut PgConnection) -> Result<usize, Error> {
+    let keyring = keyring();
+    let mut count = 0;
+    let mut after = None;
+
+    loop {
+        let mut query = email_changes::table
+            .order(email_changes::user_id)
+            .limit(BATCH_SIZE)
+            .select((
+                email_changes::user_id,
+                email_changes::email_address,
+                email_changes::email_hash,
+            ))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(email_changes::user_id.gt(after));
+        }
+        let rows = query
+            .load::<(Uuid, String, Option<String>)>(conn)
+            .map_err(Error::other)?;
+        let last = match rows.last() {
+            Some(row) => row.0,
+            None => return Ok(count),
+        };
+
+        let changes = reencrypt_addresses(keyring, rows)?;
+        count += changes.len();
+        conn.transaction(|conn| {
+            for (user_id, email_address, hash) in changes {
+                diesel::update(email_changes::table.find(user_id))
+                    .set((
+                        email_changes::email_address.eq(email_address),
+                        email_changes::email_hash.eq(hash),
+                    ))
+                    .execute(conn)?;
+            }
+            QueryResult::Ok(())
+        })
+        .map_err(Error::other)?;
+        after = Some(last);
+    }
+}
+
+fn reencrypt_invitations(conn: &mut PgConnection) -> Result<usize, Error> {
+    let keyring = keyring();
+    let mut count = 0;
+    let mut after = None;
+
+    loop {
+        let mut query = invitations::table
+            .order(invitations::id)
+            .limit(BATCH_SIZE)
+            .select((
+                invitations::id,
+                invitations::email_address,
+                invitations::email_hash,
+            ))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(invitations::id.gt(after));
+        }
+        let rows = query
+            .load::<(Uuid, String, Option<String>)>(conn)
+            .map_err(Error::other)?;
+        let last = match rows.last() {
+            Some(row) => row.0,
+            None => return Ok(count),
+        };
+
+        let changes = reencrypt_addresses(keyring, rows)?;
+        count += changes.len();
+        conn.transaction(|conn| {
+            for (id, email_address, hash) in changes {
+                diesel::update(invitations::table.find(id))
+                    .set((
+                        invitations::email_address.eq(email_address),
+                        invitations::email_hash.eq(hash),
+                    ))
+                    .execute(conn)?;
+            }
+            QueryResult::Ok(())
+        })
+        .map_err(Error::other)?;
+        after = Some(last);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::invitations::create_invitation;
+    use crate::core::mailer::MemoryMailer;
+    use crate::core::organizations::create_organization;
+    use crate::core::organizations::MEMBER;
+    use crate::core::users::get_user;
+    use crate::core::users::update_user;
+    use crate::core::users::UpdateUserAttrs;
+    use crate::test::factory;
+
+    const KEY: &str = "kqHkjx54Bdu0vUZdPtiRH13GPRlN6na6YWXBPF8dWlk=";
+    const OTHER_KEY: &str = "SJ1Czyp9gkk7oLREq1ax+CeGM6iLNt7MY9nT4SzG1sw=";
+
+    fn connect() -> PgConnection {
+        PgConnection::establish(&config::get_config().database_url).unwrap()
+    }
+
+    #[test]
+    fn test_encrypt() {
+        let keyring = Keyring::parse(&format!("new:{}\nold:{}", OTHER_KEY, KEY), KEY).unwrap();
+        let old = Keyring::parse(&format!("old:{}", KEY), KEY).unwrap();
+
+        let encrypted = keyring.encrypt("Jane");
+        assert!(encrypted.starts_with("enc:new:"));
+        assert_ne!(encrypted, keyring.encrypt("Jane"));
+        assert_eq!(keyring.decrypt(&encrypted).unwrap(), "Jane");
+        assert!(keyring.decrypt("Jane").is_err());
+        assert!(old.decrypt(&encrypted).is_err());
+        assert!(keyring.is_current(&keyring.reencrypt("Jane").unwrap()));
+
+        let mut migrating = Keyring::parse(&format!("new:{}", OTHER_KEY), KEY).unwrap();
+        migrating.allow_plaintext = true;
+        assert_eq!(migrating.decrypt("Jane").unwrap(), "Jane");
+        assert_eq!(migrating.decrypt(&encrypted).unwrap(), "Jane");
+
+        let rotated = keyring.reencrypt(&old.encrypt("Jane")).unwrap();
+        assert!(keyring.is_current(&rotated));
+        assert_eq!(keyring.decrypt(&rotated).unwrap(), "Jane");
+        assert!(!old.is_current(&rotated));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Keyring::parse("", KEY).is_err());
+        assert!(Keyring::parse(KEY, KEY).is_err());
+        assert!(Keyring::parse("a:c2hvcnQ=", KEY).is_err());
+        assert!(Keyring::parse(&format!("a:{},a:{}", KEY, OTHER_KEY), KEY).is_err());
+
+        let keyring = Keyring::parse(&format!("# keys\n\nb:{}, a:{}", KEY, OTHER_KEY), KEY);
+        assert_eq!(keyring.unwrap().current, "b");
+    }
+
+    #[test]
+    fn test_blind_index() {
+        let keyring = Keyring::parse(&format!("a:{}", KEY), KEY).unwrap();
+        let other = Keyring::parse(&format!("a:{}", KEY), OTHER_KEY).unwrap();
+
+        assert_eq!(
+            keyring.blind_index("Jane@Doe.com"),
+            keyring.blind_index("jane@doe.com")
+        );
+        assert_ne!(
+            keyring.blind_index("jane@doe.com"),
+            keyring.blind_index("john@doe.com")
+        );
+        assert_ne!(
+            keyring.blind_index("jane@doe.com"),
+            other.blind_index("jane@doe.com")
+        );
+    }
+
+    #[test]
+    fn test_encrypted_columns() {
+        let user = factory::insert_user();
+        let mut conn = connect();
+
+        let (first_name, email_address, email_hash) = users::table
+            .find(user.id)
+            .select((users::first_name, users::email_address, users::email_hash))
+            .first::<(String, String, Option<String>)>(&mut conn)
+            .unwrap();
+
+        assert!(keyring().is_current(&first_name));
+        assert!(keyring().is_current(&email_address));
+        assert_eq!(email_hash, Some(blind_index(&user.email_address)));
+        assert_eq!(
+            get_user(&mut conn, user.id).unwrap().unwrap().first_name,
+            "Jane"
+        );
+    }
+
+    #[test]
+    fn test_encrypt_fields() {
+        let value =
+            serde_json::json!({ "id": 1, "first_name": "Jane", "email_address": "j@doe.com" });
+
+        let encrypted = encrypt_fields(value.clone());
+
+        assert_eq!(encrypted["id"], 1);
+        assert!(keyring().is_current(encrypted["first_name"].as_str().unwrap()));
+        assert!(!encrypted.to_string().contains("j@doe.com"));
+        assert_eq!(decrypt_fields(encrypted).unwrap(), value);
+        assert!(decrypt_fields(value).is_err());
+    }
+
+    #[test]
+    fn test_reencrypt_users() {
+        let user = factory::insert_user();
+        let mut conn = connect();
+        // The user is only written before encryption in the transaction, so
+        // that it is never read as such elsewhere.
+        conn.begin_test_transaction().unwrap();
+        let attrs = UpdateUserAttrs {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+        update_user(&mut conn, user.id, attrs, None).unwrap();
+        // Write the user as it was before encryption.
+        diesel::update(users::table.find(user.id))
+            .set((
+                users::last_name.eq("Doe"),
+                users::email_hash.eq(None::<String>),
+                users::search_tokens.eq(None::<String>),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        let version = users::table
+            .find(user.id)
+            .select(users::version)
+            .first::<i32>(&mut conn)
+            .unwrap();
+
+        let reencrypted = reencrypt_users(&mut conn).unwrap();
+
+        assert!(reencrypted.users >= 1);
+        let (last_name, email_hash, tokens) = users::table
+            .find(user.id)
+            .select((users::last_name, users::email_hash, users::search_tokens))
+            .first::<(String, Option<String>, Option<String>)>(&mut conn)
+            .unwrap();
+        assert!(keyring().is_current(&last_name));
+        assert_eq!(email_hash, Some(blind_index(&user.email_address)));
+        assert_eq!(
+            tokens,
+            Some(search_tokens("Janet", "Doe", &user.email_address))
+        );
+        let result = get_user(&mut conn, user.id).unwrap().unwrap();
+        assert_eq!(
+            (result.first_name.as_str(), result.version),
+            ("Janet", version)
+        );
+        assert_eq!(reencrypt_users(&mut conn).unwrap().users, 0);
+    }
+
+    #[test]
+    fn test_reencrypt_invitations() {
+        let owner = factory::insert_user();
+        let mut conn = connect();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        let invitation = create_invitation(
+            &mut conn,
+            &MemoryMailer::default(),
+            organization.id,
+            owner.id,
+            "Jane.Invited@doe.com",
+            MEMBER,
+        )
+        .unwrap()
+        .unwrap();
+        let (email_address, email_hash) = invitations::table
+            .find(invitation.id)
+            .select((invitations::email_address, invitations::email_hash))
+            .first::<(String, Option<String>)>(&mut conn)
+            .unwrap();
+
+        assert!(keyring().is_current(&email_address));
+        assert_eq!(email_hash, Some(blind_index("jane.invited@doe.com")));
+
+        // The invitation is only written before encryption in the
+        // transaction, so that it is never read as such elsewhere.
+        conn.begin_test_transaction().unwrap();
+        diesel::update(invitations::table.find(invitation.id))
+            .set((
+                invitations::email_address.eq("Jane.Invited@doe.com"),
+                invitations::email_hash.eq(None::<String>),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+
+        assert!(reencrypt_users(&mut conn).unwrap().invitations >= 1);
+        let (email_address, email_hash) = invitations::table
+            .find(invitation.id)
+            .select((invitations::email_address, invitations::email_hash))
+            .first::<(Encrypted, Option<String>)>(&mut conn)
+            .unwrap();
+        assert_eq!(email_address.0, "Jane.Invited@doe.com");
+        assert_eq!(email_hash, Some(blind_index("jane.invited@doe.com")));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::core::models::schema::users;
+use crate::core::models::User;
+use crate::core::repo;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::Deserialize;
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::Sender;
+use tracing::warn;
+use uuid::Uuid;
+
+/// The number of events a slow subscriber can fall behind before it lags.
+const CAPACITY: usize = 1024;
+
+/// The channel the database notifies of user changes.
+const USER_EVENTS: &str = "user_events";
+
+/// What happened to a user.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// A domain event, published after a write is committed, with the tenant of
+/// the user so that subscribers only see their own.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub change: Change,
+    pub tenant_id: Uuid,
+    pub user: User,
+}
+
+/// The payload of a `user_events` notification: the id of the changed user
+/// only, to stay within the size limit of notifications.
+#[derive(Deserialize)]
+struct UserNotification {
+    event: String,
+    id: Uuid,
+}
+
+impl UserNotification {
+    /// Load the user of the notification as it is now. A user deleted from
+    /// the table since has no event.
+    fn into_event(self, conn: &mut PgConnection) -> QueryResult<Option<Event>> {
+        let change = match self.event.as_str() {
+            "created" => Change::Created,
+            "updated" => Change::Updated,
+            "deleted" => Change::Deleted,
+            _ => return Ok(None),
+        };
+        let row = users::table
+            .find(self.id)
+            .select((users::tenant_id, User::as_select()))
+            .first::<(Uuid, User)>(conn)
+            .optional()?;
+
+        Ok(row.map(|(tenant_id, user)| Event {
+            change,
+            tenant_id,
+            user,
+        }))
+    }
+}
+
+/// Get the event bus.
+fn bus() -> &'static Sender<Event> {
+    static INSTANCE: OnceLock<Sender<Event>> = OnceLock::new();
+
+    INSTANCE.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+/// Publish an event to all subscribers.
+pub fn publish(event: Event) {
+    // Sending only fails when there are no subscribers, which is fine.
+    let _ = bus().send(event);
+}
+
+/// Subscribe to all events published from now on.
+pub fn subscribe() -> Receiver<Event> {
+    bus().subscribe()
+}
+
+/// Publish the changes notified by the database, from this or any other replica.
+///
+/// Returns once the database listener is connected.
+pub async fn listen(database_url: &str) {
+    let mut notifications = repo::listen_database(database_url, USER_EVENTS).await;
+    let pool = repo::connect_database(&database_url.to_string());
+
+    tokio::spawn(async move {
+        while let Some(payload) = notifications.recv().await {
+            let notification = match serde_json::from_str::<UserNotification>(&payload) {
+                Ok(notification) => notification,
+                Err(e) => {
+                    warn!("Invalid {} notification: {}", USER_EVENTS, e);
+                    continue;
+                }
+            };
+            let conn = match pool.get().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("Failed to load the user of a notification: {}", e);
+                    continue;
+                }
+            };
+            match conn.interact(|conn| notification.into_event(conn)).await {
+                Ok(Ok(Some(event))) => publish(event),
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => warn!("Failed to load the user of a notification: {}", e),
+                Err(e) => warn!("Failed to load the user of a notification: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::users::create_user;
+    use crate::core::users::CreateUserAttrs;
+    use crate::test::factory;
+
+    #[tokio::test]
+    async fn test_listen() {
+        let config = config::get_config();
+        listen(&config.database_url).await;
+        let mut receiver = subscribe();
+        let user = factory::insert_user();
+
+        loop {
+            let event = receiver.recv().await.unwrap();
+            if event.change == Change::Created && event.user == user {
+                assert_eq!(event.tenant_id, repo::DEFAULT_TENANT);
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listen_large_user() {
+        let config = config::get_config();
+        listen(&config.database_url).await;
+        let mut receiver = subscribe();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        // A user larger than a notification payload can hold.
+        let attrs = CreateUserAttrs {
+            first_name: "Jane".repeat(5000),
+            last_name: "Doe".to_string(),
+            email_address: format!("jane.{}@doe.com", Uuid::now_v7().simple()),
+        };
+        let user = create_user(&mut conn, attrs).unwrap().unwrap();
+
+        loop {
+            let event = receiver.recv().await.unwrap();
+            if event.change == Change::Created && event.user == user {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,794 @@
+use crate::config::get_config;
+use crate::core::jobs;
+use crate::core::jobs::Job;
+use crate::core::masking::get_masking_policy;
+use crate::core::masking::Field;
+use crate::core::masking::MaskingPolicy;
+use crate::core::models::schema::user_exports;
+use crate::core::models::schema::users;
+use crate::core::models::User;
+use crate::core::models::UserExport;
+use crate::core::repo;
+use arrow_array::ArrayRef;
+use arrow_array::Int32Array;
+use arrow_array::RecordBatch;
+use arrow_array::StringArray;
+use arrow_array::TimestampMicrosecondArray;
+use arrow_schema::DataType;
+use arrow_schema::Field as Column;
+use arrow_schema::Schema;
+use arrow_schema::SchemaRef;
+use arrow_schema::TimeUnit;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::result::Error::SerializationError;
+use diesel::PgConnection;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+/// The status of an export waiting for a worker.
+pub const PENDING: &str = "pending";
+
+/// The status of an export being written.
+pub const RUNNING: &str = "running";
+
+/// The status of an export whose file is complete.
+pub const COMPLETED: &str = "completed";
+
+/// The status of an export that failed, until its job is retried.
+pub const FAILED: &str = "failed";
+
+/// The number of users read at once.
+const BATCH_SIZE: i64 = 1000;
+
+/// The columns of the exported users, in order.
+const COLUMNS: [&str; 9] = [
+    "id",
+    "first_name",
+    "last_name",
+    "email_address",
+    "created_at",
+    "updated_at",
+    "deleted_at",
+    "email_verified_at",
+    "version",
+];
+
+/// The formats users are exported to.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Comma-separated values, with a header row.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+    /// Apache Parquet, with a row group per batch of users.
+    Parquet,
+}
+
+impl ExportFormat {
+    /// Guess the format from the extension of a file name.
+    pub fn from_file_name(name: &str) -> Option<ExportFormat> {
+        let extension = name
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase());
+
+        match extension.as_deref() {
+            Some("csv") => Some(ExportFormat::Csv),
+            Some("ndjson") | Some("jsonl") => Some(ExportFormat::Ndjson),
+            Some("parquet") => Some(ExportFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    /// Parse the format as stored with an export.
+    pub fn parse(format: &str) -> ExportFormat {
+        match format {
+            "csv" => ExportFormat::Csv,
+            "ndjson" => ExportFormat::Ndjson,
+            "parquet" => ExportFormat::Parquet,
+            _ => panic!("Unknown export format: {}", format),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// The users to export: by default, those not deleted.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ExportFilter {
+    pub include_deleted: bool,
+    pub email_verified: Option<bool>,
+    pub created_after: Option<NaiveDateTime>,
+    pub created_before: Option<NaiveDateTime>,
+}
+
+/// Write the users of the tenant matching the filter, in batches by id, so
+/// that they are never all in memory, with their personal data masked by the
+/// policy. Calls `progress` with the number of users written so far after
+/// each batch. Returns the number of users written.
+pub fn write_users<W, F>(
+    conn: &mut PgConnection,
+    tenant_id: Uuid,
+    writer: &mut W,
+    format: ExportFormat,
+    filter: &ExportFilter,
+    masking: &MaskingPolicy,
+    mut progress: F,
+) -> Result<usize, Error>
+where
+    W: Write + Send,
+    F: FnMut(&mut PgConnection, usize) -> Result<(), Error>,
+{
+    let mut output = Output::new(writer, format)?;
+    let mut count = 0;
+    let mut after = None;
+
+    loop {
+        let batch = repo::with_tenant(conn, tenant_id, |conn| load_batch(conn, filter, after))
+            .map_err(Error::other)?;
+        output.write_batch(&batch, masking)?;
+        count += batch.len();
+        progress(conn, count)?;
+
+        match batch.last() {
+            Some(user) if batch.len() as i64 == BATCH_SIZE => after = Some(user.id),
+            _ => break,
+        }
+    }
+    output.finish()?;
+
+    Ok(count)
+}
+
+/// An export file being written, in its format.
+enum Output<'a, W: Write + Send> {
+    Csv(&'a mut W),
+    Ndjson(&'a mut W),
+    Parquet(Box<ArrowWriter<&'a mut W>>),
+}
+
+impl<'a, W: Write + Send> Output<'a, W> {
+    fn new(writer: &'a mut W, format: ExportFormat) -> Result<Self, Error> {
+        match format {
+            ExportFormat::Csv => {
+                writeln!(writer, "{}", COLUMNS.join(","))?;
+                Ok(Output::Csv(writer))
+            }
+            ExportFormat::Ndjson => Ok(Output::Ndjson(writer)),
+            ExportFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                let writer = ArrowWriter::try_new(writer, parquet_schema(), Some(properties))
+                    .map_err(Error::other)?;
+                Ok(Output::Parquet(Box::new(writer)))
+            }
+        }
+    }
+
+    fn write_batch(&mut self, batch: &[User], masking: &MaskingPolicy) -> Result<(), Error> {
+        match self {
+            Output::Csv(writer) => {
+                for user in batch {
+                    write_csv_row(writer, user, masking)?;
+                }
+            }
+            Output::Ndjson(writer) => {
+                for user in batch {
+                    serde_json::to_writer(&mut **writer, &to_json(user, masking)?)?;
+                    writeln!(writer)?;
+                }
+            }
+            // Every batch is flushed as a row group, so that no more than a
+            // batch is ever buffered.
+            Output::Parquet(writer) if !batch.is_empty() => {
+                writer
+                    .write(&to_record_batch(batch, masking)?)
+                    .map_err(Error::other)?;
+                writer.flush().map_err(Error::other)?;
+            }
+            Output::Parquet(_) => {}
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self {
+            Output::Parquet(writer) => writer.close().map(|_| ()).map_err(Error::other),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Get the schema of the exported users in Parquet, with the columns in order.
+fn parquet_schema() -> SchemaRef {
+    let timestamp = DataType::Timestamp(TimeUnit::Microsecond, None);
+
+    Arc::new(Schema::new(vec![
+        Column::new(COLUMNS[0], DataType::Utf8, false),
+        Column::new(COLUMNS[1], DataType::Utf8, true),
+        Column::new(COLUMNS[2], DataType::Utf8, true),
+        Column::new(COLUMNS[3], DataType::Utf8, true),
+        Column::new(COLUMNS[4], timestamp.clone(), false),
+        Column::new(COLUMNS[5], timestamp.clone(), false),
+        Column::new(COLUMNS[6], timestamp.clone(), true),
+        Column::new(COLUMNS[7], timestamp, true),
+        Column::new(COLUMNS[8], DataType::Int32, false),
+    ]))
+}
+
+/// Mask the personal data of the users, in a Parquet record batch.
+fn to_record_batch(batch: &[User], masking: &MaskingPolicy) -> Result<RecordBatch, Error> {
+    let mask = |field: Field| -> ArrayRef {
+        Arc::new(
+            batch
+                .iter()
+                .map(|user| masking.mask(user.id, field, field.value(user)))
+                .collect::<StringArray>(),
+        )
+    };
+    let timestamp = |datetime: fn(&User) -> Option<NaiveDateTime>| -> ArrayRef {
+        Arc::new(
+            batch
+                .iter()
+                .map(|user| datetime(user).map(|datetime| datetime.and_utc().timestamp_micros()))
+                .collect::<TimestampMicrosecondArray>(),
+        )
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            batch.iter().map(|user| user.id.to_string()),
+        )),
+        mask(Field::FirstName),
+        mask(Field::LastName),
+        mask(Field::EmailAddress),
+        timestamp(|user| Some(user.created_at)),
+        timestamp(|user| Some(user.updated_at)),
+        timestamp(|user| user.deleted_at),
+        timestamp(|user| user.email_verified_at),
+        Arc::new(Int32Array::from_iter_values(
+            batch.iter().map(|user| user.version),
+        )),
+    ];
+
+    RecordBatch::try_new(parquet_schema(), columns).map_err(Error::other)
+}
+
+/// Load the next batch of users matching the filter, after the user id.
+fn load_batch(
+    conn: &mut PgConnection,
+    filter: &ExportFilter,
+    after: Option<Uuid>,
+) -> QueryResult<Vec<User>> {
+    let mut query = users::table
+        .select(User::as_select())
+        .order(users::id)
+        .into_boxed();
+
+    if let Some(after) = after {
+        query = query.filter(users::id.gt(after));
+    }
+    if !filter.include_deleted {
+        query = query.filter(users::deleted_at.is_null());
+    }
+    match filter.email_verified {
+        Some(true) => query = query.filter(users::email_verified_at.is_not_null()),
+        Some(false) => query = query.filter(users::email_verified_at.is_null()),
+        None => {}
+    }
+    if let Some(created_after) = filter.created_after {
+        query = query.filter(users::created_at.ge(created_after));
+    }
+    if let Some(created_before) = filter.created_before {
+        query = query.filter(users::created_at.lt(created_before));
+    }
+
+    query.limit(BATCH_SIZE).load(conn)
+}
+
+/// Mask the personal data of the user, in a JSON object.
+fn to_json(user: &User, masking: &MaskingPolicy) -> Result<Value, Error> {
+    let mut value = serde_json::to_value(user)?;
+    for field in Field::ALL {
+        value[field.as_str()] = masking.mask(user.id, field, field.value(user)).into();
+    }
+
+    Ok(value)
+}
+
+fn write_csv_row<W: Write>(
+    writer: &mut W,
+    user: &User,
+    masking: &MaskingPolicy,
+) -> Result<(), Error> {
+    let timestamp = |datetime: Option<NaiveDateTime>| {
+        datetime.map(|datetime| datetime.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+    };
+    let mask = |field: Field| {
+        masking
+            .mask(user.id, field, field.value(user))
+            .unwrap_or_default()
+    };
+    let fields = [
+        user.id.to_string(),
+        mask(Field::FirstName),
+        mask(Field::LastName),
+        mask(Field::EmailAddress),
+        timestamp(Some(user.created_at)).unwrap_or_default(),
+        timestamp(Some(user.updated_at)).unwrap_or_default(),
+        timestamp(user.deleted_at).unwrap_or_default(),
+        timestamp(user.email_verified_at).unwrap_or_default(),
+        user.version.to_string(),
+    ];
+    let fields: Vec<String> = fields.iter().map(|field| escape_csv(field)).collect();
+
+    writeln!(writer, "{}", fields.join(","))
+}
+
+/// Quote a CSV field if it contains a separator, a quote or a line break.
+fn escape_csv(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// Get the path of an export file in the export directory.
+pub fn export_path(file_name: &str) -> PathBuf {
+    Path::new(&get_config().export_dir).join(file_name)
+}
+
+/// Request an export of the users of the current tenant, written by a job.
+pub fn request_export(
+    conn: &mut PgConnection,
+    format: ExportFormat,
+    filter: &ExportFilter,
+    requested_by: Option<Uuid>,
+) -> QueryResult<UserExport> {
+    let timestamp = Utc::now().naive_utc();
+    let id = Uuid::now_v7();
+    let changes = UserExport {
+        id,
+        requested_by,
+        format: format.as_str().to_string(),
+        filter: serde_json::to_value(filter).map_err(|e| SerializationError(Box::new(e)))?,
+        status: PENDING.to_string(),
+        rows_exported: 0,
+        file_name: format!("users-{}.{}", id, format.as_str()),
+        last_error: None,
+        created_at: timestamp,
+        updated_at: timestamp,
+        completed_at: None,
+    };
+
+    conn.transaction(|conn| {
+        let export = diesel::insert_into(user_exports::table)
+            .values(&changes)
+            .returning(UserExport::as_returning())
+            .get_result(conn)?;
+        jobs::enqueue(
+            conn,
+            &ExportUsers {
+                export_id: export.id,
+            },
+        )?;
+        Ok(export)
+    })
+}
+
+/// Get an export of the current tenant.
+pub fn get_export(conn: &mut PgConnection, export_id: Uuid) -> QueryResult<Option<UserExport>> {
+    user_exports::table
+        .find(export_id)
+        .select(UserExport::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// Write the completed and running exports of the tenant again, once the
+/// users they may hold have changed, like when a user is erased. Only the
+/// exports last written at or after `since` are written again, each by a new
+/// job. Returns the number of exports to write again.
+pub fn regenerate_exports(
+    conn: &mut PgConnection,
+    tenant_id: Uuid,
+    since: NaiveDateTime,
+) -> QueryResult<usize> {
+    let export_ids = diesel::update(
+        user_exports::table
+            .filter(user_exports::tenant_id.eq(tenant_id))
+            .filter(user_exports::status.eq_any([COMPLETED, RUNNING]))
+            .filter(user_exports::updated_at.ge(since)),
+    )
+    .set((
+        user_exports::status.eq(PENDING),
+        user_exports::rows_exported.eq(0),
+        user_exports::updated_at.eq(Utc::now().naive_utc()),
+        user_exports::completed_at.eq(None::<NaiveDateTime>),
+    ))
+    .returning(user_exports::id)
+    .get_results::<Uuid>(conn)?;
+
+    for export_id in &export_ids {
+        jobs::enqueue(
+            conn,
+            &ExportUsers {
+                export_id: *export_id,
+            },
+        )?;
+    }
+
+    Ok(export_ids.len())
+}
+
+/// Write the file of a requested export to the export directory, updating its
+/// progress as it goes. The file is only put in place once complete.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExportUsers {
+    pub export_id: Uuid,
+}
+
+impl Job for ExportUsers {
+    const NAME: &'static str = "exports.export_users";
+
+    fn perform(&self, conn: &mut PgConnection) -> Result<(), Error> {
+        let (export, tenant_id) = user_exports::table
+            .find(self.export_id)
+            .select((UserExport::as_select(), user_exports::tenant_id))
+            .first::<(UserExport, Uuid)>(conn)
+            .map_err(Error::other)?;
+        set_status(conn, export.id, RUNNING, 0, None)?;
+
+        let result = write_export(conn, &export, tenant_id);
+        match &result {
+            Ok(count) => set_status(conn, export.id, COMPLETED, *count, None)?,
+            Err(e) => set_status(conn, export.id, FAILED, 0, Some(e.to_string()))?,
+        }
+        let count = result?;
+
+        info!("Exported {} users to {}", count, export.file_name);
+        Ok(())
+    }
+}
+
+fn write_export(
+    conn: &mut PgConnection,
+    export: &UserExport,
+    tenant_id: Uuid,
+) -> Result<usize, Error> {
+    let filter: ExportFilter = serde_json::from_value(export.filter.clone())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let path = export_path(&export.file_name);
+    let partial = path.with_extension("part");
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    // A file written before is removed first, so that it is not left behind
+    // with the data it held if this one fails.
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    // The users are masked as the requester sees them.
+    let masking = match export.requested_by {
+        Some(user_id) => get_masking_policy(conn, user_id).map_err(Error::other)?,
+        None => MaskingPolicy::default(),
+    };
+    let mut writer = BufWriter::new(File::create(&partial)?);
+
+    let count = write_users(
+        conn,
+        tenant_id,
+        &mut writer,
+        ExportFormat::parse(&export.format),
+        &filter,
+        &masking,
+        |conn, count| set_status(conn, export.id, RUNNING, count, None),
+    )?;
+    writer.flush()?;
+    std::fs::rename(&partial, &path)?;
+
+    Ok(count)
+}
+
+fn set_status(
+    conn: &mut PgConnection,
+    export_id: Uuid,
+    status: &str,
+    rows_exported: usize,
+    last_error: Option<String>,
+) -> Result<(), Error> {
+    let timestamp = Utc::now().naive_utc();
+    let completed_at = (status == COMPLETED).then_some(timestamp);
+
+    diesel::update(user_exports::table.find(export_id))
+        .set((
+            user_exports::status.eq(status),
+            user_exports::rows_exported.eq(rows_exported as i32),
+            user_exports::last_error.eq(last_error),
+            user_exports::updated_at.eq(timestamp),
+            user_exports::completed_at.eq(completed_at),
+        ))
+        .execute(conn)
+        .map_err(Error::other)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::masking::Strategy;
+    use crate::core::models::schema::tenants;
+    use crate::core::users::create_user;
+    use crate::core::users::CreateUserAttrs;
+
+    fn connect() -> PgConnection {
+        PgConnection::establish(&config::get_config().database_url).unwrap()
+    }
+
+    /// Create a tenant with users named by their first names.
+    fn insert_tenant_with(conn: &mut PgConnection, first_names: &[&str]) -> Uuid {
+        let tenant_id = diesel::insert_into(tenants::table)
+            .values((
+                tenants::id.eq(Uuid::now_v7()),
+                tenants::name.eq("Doe Inc."),
+                tenants::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .returning(tenants::id)
+            .get_result(conn)
+            .unwrap();
+        for first_name in first_names {
+            let attrs = CreateUserAttrs {
+                first_name: first_name.to_string(),
+                last_name: "Doe, \"Jr\"".to_string(),
+                email_address: format!("{}.{}@doe.com", first_name, Uuid::now_v7().simple()),
+            };
+            repo::with_tenant(conn, tenant_id, |conn| create_user(conn, attrs)).unwrap();
+        }
+
+        tenant_id
+    }
+
+    #[test]
+    fn test_write_users_csv() {
+        let mut conn = connect();
+        let tenant_id = insert_tenant_with(&mut conn, &["jane", "john"]);
+        let mut output = Vec::new();
+        let mut progress = Vec::new();
+
+        let count = write_users(
+            &mut conn,
+            tenant_id,
+            &mut output,
+            ExportFormat::Csv,
+            &ExportFilter::default(),
+            &MaskingPolicy::default(),
+            |_, count| {
+                progress.push(count);
+                Ok(())
+            },
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(count, 2);
+        assert_eq!(progress, vec![2]);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], COLUMNS.join(","));
+        assert!(lines[1].contains(",jane,\"Doe, \"\"Jr\"\"\",jane."));
+    }
+
+    #[test]
+    fn test_write_users_filter() {
+        let mut conn = connect();
+        let tenant_id = insert_tenant_with(&mut conn, &["jane", "john"]);
+        let mut output = Vec::new();
+        repo::with_tenant(&mut conn, tenant_id, |conn| {
+            let users = users::table.select(User::as_select()).load(conn)?;
+            let john = users.iter().find(|user| user.first_name == "john").unwrap();
+            diesel::update(users::table.find(john.id))
+                .set(users::deleted_at.eq(Utc::now().naive_utc()))
+                .execute(conn)
+        })
+        .unwrap();
+
+        let filter = ExportFilter::default();
+        let count = write_users(
+            &mut conn,
+            tenant_id,
+            &mut output,
+            ExportFormat::Ndjson,
+            &filter,
+            &MaskingPolicy::default(),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        let users: Vec<User> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(count, 1);
+        assert_eq!(users[0].first_name, "jane");
+
+        let filter = ExportFilter {
+            include_deleted: true,
+            ..ExportFilter::default()
+        };
+        let count = write_users(
+            &mut conn,
+            tenant_id,
+            &mut Vec::new(),
+            ExportFormat::Csv,
+            &filter,
+            &MaskingPolicy::default(),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(count, 2);
+
+        let filter = ExportFilter {
+            email_verified: Some(true),
+            ..ExportFilter::default()
+        };
+        let count = write_users(
+            &mut conn,
+            tenant_id,
+            &mut Vec::new(),
+            ExportFormat::Csv,
+            &filter,
+            &MaskingPolicy::default(),
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_write_users_masked() {
+        let mut conn = connect();
+        let tenant_id = insert_tenant_with(&mut conn, &["jane"]);
+        let masking = MaskingPolicy {
+            user_id: None,
+            strategies: [
+                (Field::LastName, Strategy::Partial),
+                (Field::EmailAddress, Strategy::Null),
+            ]
+            .into(),
+        };
+        let mut csv = Vec::new();
+        let mut ndjson = Vec::new();
+
+        write_users(
+            &mut conn,
+            tenant_id,
+            &mut csv,
+            ExportFormat::Csv,
+            &ExportFilter::default(),
+            &masking,
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        write_users(
+            &mut conn,
+            tenant_id,
+            &mut ndjson,
+            ExportFormat::Ndjson,
+            &ExportFilter::default(),
+            &masking,
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let user: Value = serde_json::from_slice(&ndjson).unwrap();
+
+        assert!(csv.lines().nth(1).unwrap().contains(",jane,D***,,"));
+        assert_eq!(user["first_name"], "jane");
+        assert_eq!(user["last_name"], "D***");
+        assert_eq!(user["email_address"], Value::Null);
+    }
+
+    #[test]
+    fn test_export_users() {
+        let mut conn = connect();
+        let tenant_id = insert_tenant_with(&mut conn, &["jane"]);
+        let export = repo::with_tenant(&mut conn, tenant_id, |conn| {
+            request_export(conn, ExportFormat::Ndjson, &ExportFilter::default(), None)
+        })
+        .unwrap();
+
+        assert_eq!(export.status, PENDING);
+
+        ExportUsers {
+            export_id: export.id,
+        }
+        .perform(&mut conn)
+        .unwrap();
+        let export = get_export(&mut conn, export.id).unwrap().unwrap();
+        let content = std::fs::read_to_string(export_path(&export.file_name)).unwrap();
+        std::fs::remove_file(export_path(&export.file_name)).unwrap();
+
+        assert_eq!(export.status, COMPLETED);
+        assert_eq!(export.rows_exported, 1);
+        assert_ne!(export.completed_at, None);
+        assert_eq!(content.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_write_users_parquet() {
+        use arrow_array::Array;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let mut conn = connect();
+        let tenant_id = insert_tenant_with(&mut conn, &["jane", "john"]);
+        let masking = MaskingPolicy {
+            user_id: None,
+            strategies: [(Field::EmailAddress, Strategy::Null)].into(),
+        };
+        let path = std::env::temp_dir().join(format!("users-{}.parquet", Uuid::now_v7()));
+        let mut output = File::create(&path).unwrap();
+
+        let count = write_users(
+            &mut conn,
+            tenant_id,
+            &mut output,
+            ExportFormat::Parquet,
+            &ExportFilter::default(),
+            &masking,
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        let batches: Vec<RecordBatch> =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+                .unwrap()
+                .build()
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let batch = &batches[0];
+        let column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap()
+                .clone()
+        };
+
+        assert_eq!(count, 2);
+        assert_eq!(batch.schema(), parquet_schema());
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(column("first_name").value(0), "jane");
+        assert_eq!(column("last_name").value(1), "Doe, \"Jr\"");
+        assert!(column("email_address").is_null(0));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::core::encryption::Encrypted;
+use crate::core::jobs::Job;
+use crate::core::models::schema::idempotency_keys;
+use crate::core::models::IdempotencyKey;
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::Deserialize;
+use serde::Serialize;
+use std::io::Error;
+use tracing::info;
+use uuid::Uuid;
+
+/// The time the response of a request is replayed to its retries.
+pub const IDEMPOTENCY_TTL: Duration = Duration::hours(24);
+
+/// The longest idempotency key accepted.
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// What to do with a request sent with an idempotency key.
+#[derive(Debug, PartialEq)]
+pub enum Claim {
+    /// The key is new: run the request and complete the key with its response.
+    Started,
+    /// The same request already ran: replay its response.
+    Completed(serde_json::Value),
+    /// The same request is still running.
+    InProgress,
+    /// The key was already used for a different request.
+    Mismatch,
+}
+
+/// Claim the key of the viewer for the request, identified by the hash of its
+/// content. An expired key is claimed again as a new one. The keys of each
+/// tenant are kept apart by the tenant the connection is scoped to.
+pub fn claim_key(
+    conn: &mut PgConnection,
+    viewer_id: Uuid,
+    key: &str,
+    request_hash: &str,
+) -> QueryResult<Claim> {
+    conn.transaction(|conn| {
+        let timestamp = Utc::now().naive_utc();
+
+        diesel::delete(
+            idempotency_keys::table
+                .filter(idempotency_keys::viewer_id.eq(viewer_id))
+                .filter(idempotency_keys::key.eq(key))
+                .filter(idempotency_keys::expires_at.le(timestamp)),
+        )
+        .execute(conn)?;
+        let inserted = diesel::insert_into(idempotency_keys::table)
+            .values(&IdempotencyKey {
+                key: key.to_string(),
+                viewer_id,
+                request_hash: request_hash.to_string(),
+                response: None,
+                created_at: timestamp,
+                expires_at: timestamp + IDEMPOTENCY_TTL,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        if inserted == 1 {
+            return Ok(Claim::Started);
+        }
+
+        let (claimed_hash, response) = idempotency_keys::table
+            .filter(idempotency_keys::viewer_id.eq(viewer_id))
+            .filter(idempotency_keys::key.eq(key))
+            .select((idempotency_keys::request_hash, idempotency_keys::response))
+            .first::<(String, Option<Encrypted>)>(conn)?;
+
+        Ok(match response {
+            _ if claimed_hash != request_hash => Claim::Mismatch,
+            Some(Encrypted(response)) => match serde_json::from_str(&response) {
+                Ok(response) => Claim::Completed(response),
+                Err(error) => {
+                    return Err(diesel::result::Error::DeserializationError(error.into()))
+                }
+            },
+            None => Claim::InProgress,
+        })
+    })
+}
+
+/// Store the response of the request that claimed the key, for its retries.
+/// It holds personal data, so it is encrypted at rest.
+pub fn complete_key(
+    conn: &mut PgConnection,
+    viewer_id: Uuid,
+    key: &str,
+    response: serde_json::Value,
+) -> QueryResult<()> {
+    diesel::update(
+        idempotency_keys::table
+            .filter(idempotency_keys::viewer_id.eq(viewer_id))
+            .filter(idempotency_keys::key.eq(key)),
+    )
+    .set(idempotency_keys::response.eq(Encrypted(response.to_string())))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Release the key of a request that failed to run, so that a retry runs it
+/// again.
+pub fn release_key(conn: &mut PgConnection, viewer_id: Uuid, key: &str) -> QueryResult<()> {
+    diesel::delete(
+        idempotency_keys::table
+            .filter(idempotency_keys::viewer_id.eq(viewer_id))
+            .filter(idempotency_keys::key.eq(key)),
+    )
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Delete the keys that expired before the timestamp. Returns the number of
+/// keys deleted.
+pub fn purge_expired_keys(conn: &mut PgConnection, before: NaiveDateTime) -> QueryResult<usize> {
+    diesel::delete(idempotency_keys::table.filter(idempotency_keys::expires_at.le(before)))
+        .execute(conn)
+}
+
+/// Purge the expired idempotency keys.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PurgeExpiredKeys;
+
+impl Job for PurgeExpiredKeys {
+    const NAME: &'static str = "idempotency.purge_expired_keys";
+
+    fn perform(&self, conn: &mut PgConnection) -> Result<(), Error> {
+        let before = Utc::now().naive_utc();
+        let count = purge_expired_keys(conn, before).map_err(Error::other)?;
+
+        info!(
+            "Purged {} idempotency keys expired before {}",
+            count, before
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use serde_json::json;
+
+    fn connect() -> PgConnection {
+        PgConnection::establish(&config::get_config().database_url).unwrap()
+    }
+
+    #[test]
+    fn test_claim_key() {
+        let mut conn = connect();
+        let viewer_id = Uuid::now_v7();
+        let key = Uuid::now_v7().to_string();
+
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "a").unwrap(),
+            Claim::Started
+        );
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "a").unwrap(),
+            Claim::InProgress
+        );
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "b").unwrap(),
+            Claim::Mismatch
+        );
+
+        complete_key(&mut conn, viewer_id, &key, json!({"data": {"id": 1}})).unwrap();
+
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "a").unwrap(),
+            Claim::Completed(json!({"data": {"id": 1}}))
+        );
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "b").unwrap(),
+            Claim::Mismatch
+        );
+    }
+
+    #[test]
+    fn test_release_key() {
+        let mut conn = connect();
+        let viewer_id = Uuid::now_v7();
+        let key = Uuid::now_v7().to_string();
+
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "a").unwrap(),
+            Claim::Started
+        );
+        release_key(&mut conn, viewer_id, &key).unwrap();
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "b").unwrap(),
+            Claim::Started
+        );
+    }
+
+    #[test]
+    fn test_viewer_keys() {
+        let mut conn = connect();
+        let (viewer_id, other_id) = (Uuid::now_v7(), Uuid::now_v7());
+        let key = Uuid::now_v7().to_string();
+
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "a").unwrap(),
+            Claim::Started
+        );
+        assert_eq!(
+            claim_key(&mut conn, other_id, &key, "a").unwrap(),
+            Claim::Started
+        );
+
+        complete_key(
+            &mut conn,
+            viewer_id,
+            &key,
+            json!({"data": {"email": "jane@doe.com"}}),
+        )
+        .unwrap();
+        let stored: Option<String> = idempotency_keys::table
+            .filter(idempotency_keys::viewer_id.eq(viewer_id))
+            .select(idempotency_keys::response)
+            .first(&mut conn)
+            .unwrap();
+
+        assert!(stored.unwrap().starts_with("enc:"));
+        assert_eq!(
+            claim_key(&mut conn, other_id, &key, "a").unwrap(),
+            Claim::InProgress
+        );
+    }
+
+    #[test]
+    fn test_expired_key() {
+        let mut conn = connect();
+        let viewer_id = Uuid::now_v7();
+        let key = Uuid::now_v7().to_string();
+
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "a").unwrap(),
+            Claim::Started
+        );
+        complete_key(&mut conn, viewer_id, &key, json!({"data": null})).unwrap();
+        diesel::update(idempotency_keys::table.filter(idempotency_keys::viewer_id.eq(viewer_id)))
+            .set(idempotency_keys::expires_at.eq(Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .unwrap();
+
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "b").unwrap(),
+            Claim::Started
+        );
+
+        diesel::update(idempotency_keys::table.filter(idempotency_keys::viewer_id.eq(viewer_id)))
+            .set(idempotency_keys::expires_at.eq(Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(purge_expired_keys(&mut conn, Utc::now().naive_utc()).unwrap() >= 1);
+        assert_eq!(
+            claim_key(&mut conn, viewer_id, &key, "a").unwrap(),
+            Claim::Started
+        );
+    }
+}
@@ -0,0 +1,581 @@
+use crate::core::encryption::blind_index;
+use crate::core::models::schema::users;
+use crate::core::models::User;
+use crate::core::search::search_tokens;
+use crate::core::users::enqueue_change;
+use crate::core::users::is_valid_email_address;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::result::Error::RollbackTransaction;
+use diesel::PgConnection;
+use serde::de::DeserializeSeed;
+use serde::de::SeqAccess;
+use serde::de::Visitor;
+use serde::Deserialize;
+use serde::Deserializer;
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use uuid::Uuid;
+
+/// The number of users inserted at once.
+const CHUNK_SIZE: usize = 500;
+
+/// The formats users are imported from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportFormat {
+    /// Comma-separated values, with a header row naming the columns.
+    Csv,
+    /// A JSON array of objects.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl ImportFormat {
+    /// Guess the format from the extension of a file name.
+    pub fn from_file_name(name: &str) -> Option<ImportFormat> {
+        let extension = name
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase());
+
+        match extension.as_deref() {
+            Some("csv") => Some(ImportFormat::Csv),
+            Some("json") => Some(ImportFormat::Json),
+            Some("ndjson") | Some("jsonl") => Some(ImportFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// A user to import, as read from a row, before validation.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+pub struct ImportRow {
+    #[serde(alias = "firstName")]
+    pub first_name: Option<String>,
+    #[serde(alias = "lastName")]
+    pub last_name: Option<String>,
+    #[serde(alias = "emailAddress", alias = "email")]
+    pub email_address: Option<String>,
+}
+
+/// The failure to import a row, numbered from 1 without the CSV header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowError {
+    pub row: usize,
+    pub message: String,
+}
+
+/// The outcome of an import: the rows read, the users imported, and why the
+/// other rows were not.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub rows: usize,
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// Import the users read from the reader, in chunks. A row that fails to
+/// parse or validate, or whose email address is taken, is reported and
+/// skipped. In a dry run, every row is checked but no user is saved.
+///
+/// Fails without a report if the content cannot be read at all, like a CSV
+/// without the required columns or JSON that is not an array.
+pub fn import_users<R: Read>(
+    conn: &mut PgConnection,
+    reader: R,
+    format: ImportFormat,
+    dry_run: bool,
+) -> Result<ImportReport, Error> {
+    let mut importer = Importer {
+        dry_run,
+        ..Importer::default()
+    };
+    let mut reader = BufReader::new(reader);
+
+    match format {
+        ImportFormat::Csv => read_csv(&mut reader, |row| importer.add(conn, row))?,
+        ImportFormat::Json => read_json(&mut reader, |row| importer.add(conn, row))?,
+        ImportFormat::Ndjson => read_ndjson(&mut reader, |row| importer.add(conn, row))?,
+    }
+    importer.flush(conn).map_err(Error::other)?;
+    importer.report.errors.sort_by_key(|error| error.row);
+
+    Ok(importer.report)
+}
+
+/// The state of an import: the valid users waiting to be inserted, and the
+/// email addresses already seen in the content.
+#[derive(Default)]
+struct Importer {
+    dry_run: bool,
+    report: ImportReport,
+    seen: HashSet<String>,
+    chunk: Vec<(usize, User)>,
+}
+
+impl Importer {
+    /// Validate the next row and queue it, inserting the chunk once full.
+    fn add(
+        &mut self,
+        conn: &mut PgConnection,
+        row: Result<ImportRow, String>,
+    ) -> Result<(), Error> {
+        self.report.rows += 1;
+        let number = self.report.rows;
+
+        match row.and_then(validate_row) {
+            Ok(user) if !self.seen.insert(user.email_address.to_lowercase()) => {
+                self.fail(number, "The email address appears in an earlier row");
+            }
+            Ok(user) => self.chunk.push((number, user)),
+            Err(message) => self.fail(number, &message),
+        }
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.flush(conn).map_err(Error::other)?;
+        }
+
+        Ok(())
+    }
+
+    /// Insert the queued users, reporting those whose email address is taken.
+    /// In a dry run, the insert is rolled back.
+    fn flush(&mut self, conn: &mut PgConnection) -> QueryResult<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::take(&mut self.chunk);
+        let values: Vec<_> = chunk
+            .iter()
+            .map(|(_, user)| {
+                (
+                    user.clone(),
+                    users::email_hash.eq(blind_index(&user.email_address)),
+                    users::search_tokens.eq(search_tokens(
+                        &user.first_name,
+                        &user.last_name,
+                        &user.email_address,
+                    )),
+                )
+            })
+            .collect();
+        let mut inserted = Vec::new();
+
+        let result = conn.transaction(|conn| {
+            inserted = diesel::insert_into(users::table)
+                .values(values)
+                .on_conflict_do_nothing()
+                .returning(users::id)
+                .get_results::<Uuid>(conn)?;
+            for (_, user) in chunk.iter().filter(|(_, user)| inserted.contains(&user.id)) {
+                enqueue_change(conn, "user.created", user)?;
+            }
+            match self.dry_run {
+                true => Err(RollbackTransaction),
+                false => Ok(()),
+            }
+        });
+        match result {
+            Ok(()) | Err(RollbackTransaction) => {}
+            Err(e) => return Err(e),
+        }
+
+        self.report.imported += inserted.len();
+        for (number, user) in chunk {
+            if !inserted.contains(&user.id) {
+                self.fail(number, "The email address is already taken");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fail(&mut self, row: usize, message: &str) {
+        self.report.errors.push(RowError {
+            row,
+            message: message.to_string(),
+        });
+    }
+}
+
+/// Check a row with the rules of the user input, into the user to insert.
+fn validate_row(row: ImportRow) -> Result<User, String> {
+    let first_name = required(row.first_name, "first name")?;
+    let last_name = required(row.last_name, "last name")?;
+    let email_address = required(row.email_address, "email address")?;
+    if !is_valid_email_address(&email_address) {
+        return Err("The email address is invalid".to_string());
+    }
+    let timestamp = Utc::now().naive_utc();
+
+    Ok(User {
+        id: Uuid::now_v7(),
+        first_name,
+        last_name,
+        email_address,
+        created_at: timestamp,
+        updated_at: timestamp,
+        deleted_at: None,
+        email_verified_at: None,
+        version: 1,
+    })
+}
+
+fn required(value: Option<String>, name: &str) -> Result<String, String> {
+    match value.map(|value| value.trim().to_string()) {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(format!("The {} is required", name)),
+    }
+}
+
+/// Read the rows of a CSV with a header row, matching the columns by name
+/// regardless of case and separators, like `first_name` or `First Name`.
+fn read_csv<R, F>(reader: &mut R, mut f: F) -> Result<(), Error>
+where
+    R: BufRead,
+    F: FnMut(Result<ImportRow, String>) -> Result<(), Error>,
+{
+    let header = read_record(reader)?.unwrap_or_default();
+    let columns: Vec<String> = header
+        .iter()
+        .map(|name| {
+            let name = name.trim_start_matches('\u{feff}').to_lowercase();
+            name.chars().filter(|c| c.is_alphanumeric()).collect()
+        })
+        .collect();
+    let position = |names: &[&str]| columns.iter().position(|column| names.contains(&&**column));
+    let first_name = position(&["firstname"]);
+    let last_name = position(&["lastname"]);
+    let email_address = position(&["emailaddress", "email"]);
+    if first_name.is_none() || last_name.is_none() || email_address.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "The CSV header must name the first name, last name and email address columns",
+        ));
+    }
+
+    while let Some(record) = read_record(reader)? {
+        if record.iter().all(|field| field.is_empty()) {
+            continue;
+        }
+        let row = match record.len() == columns.len() {
+            true => Ok(ImportRow {
+                first_name: first_name.map(|index| record[index].clone()),
+                last_name: last_name.map(|index| record[index].clone()),
+                email_address: email_address.map(|index| record[index].clone()),
+            }),
+            false => Err(format!(
+                "The row has {} fields, not {}",
+                record.len(),
+                columns.len()
+            )),
+        };
+        f(row)?;
+    }
+
+    Ok(())
+}
+
+/// Read the next record of a CSV, with its fields unquoted, or `None` at the
+/// end. A quoted field may span lines.
+fn read_record<R: BufRead>(reader: &mut R) -> Result<Option<Vec<String>>, Error> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+
+    loop {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => quoted = false,
+                (true, c) => field.push(c),
+                (false, '"') if field.is_empty() => quoted = true,
+                (false, ',') => fields.push(std::mem::take(&mut field)),
+                (false, '\r' | '\n') => {}
+                (false, c) => field.push(c),
+            }
+        }
+        if !quoted {
+            break;
+        }
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "The CSV ends in a quoted field",
+            ));
+        }
+    }
+    fields.push(field);
+
+    Ok(Some(fields))
+}
+
+/// Read the rows of NDJSON, skipping blank lines.
+fn read_ndjson<R, F>(reader: &mut R, mut f: F) -> Result<(), Error>
+where
+    R: BufRead,
+    F: FnMut(Result<ImportRow, String>) -> Result<(), Error>,
+{
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        f(serde_json::from_str(&line).map_err(|e| format!("The row is invalid: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+/// Read the rows of a JSON array one at a time, without loading it whole.
+fn read_json<R, F>(reader: &mut R, mut f: F) -> Result<(), Error>
+where
+    R: BufRead,
+    F: FnMut(Result<ImportRow, String>) -> Result<(), Error>,
+{
+    let mut failure = None;
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let result = RowSeed {
+        f: &mut f,
+        failure: &mut failure,
+    }
+    .deserialize(&mut deserializer);
+
+    match failure {
+        Some(e) => Err(e),
+        None => result
+            .and_then(|_| deserializer.end())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+    }
+}
+
+/// Pass the elements of a JSON array to the function as they are read.
+struct RowSeed<'a, F> {
+    f: &'a mut F,
+    failure: &'a mut Option<Error>,
+}
+
+impl<'de, F> DeserializeSeed<'de> for RowSeed<'_, F>
+where
+    F: FnMut(Result<ImportRow, String>) -> Result<(), Error>,
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F> Visitor<'de> for RowSeed<'_, F>
+where
+    F: FnMut(Result<ImportRow, String>) -> Result<(), Error>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of users")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(value) = seq.next_element::<serde_json::Value>()? {
+            let row =
+                serde_json::from_value(value).map_err(|e| format!("The row is invalid: {}", e));
+            if let Err(e) = (self.f)(row) {
+                *self.failure = Some(e);
+                return Err(serde::de::Error::custom("The import failed"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::models::schema::users::dsl;
+
+    fn connect() -> PgConnection {
+        PgConnection::establish(&config::get_config().database_url).unwrap()
+    }
+
+    fn read(format: ImportFormat, content: &str) -> Result<Vec<Result<ImportRow, String>>, Error> {
+        let mut rows = Vec::new();
+        let mut reader = content.as_bytes();
+        let f = |row| {
+            rows.push(row);
+            Ok(())
+        };
+
+        match format {
+            ImportFormat::Csv => read_csv(&mut reader, f)?,
+            ImportFormat::Json => read_json(&mut reader, f)?,
+            ImportFormat::Ndjson => read_ndjson(&mut reader, f)?,
+        }
+        Ok(rows)
+    }
+
+    fn jane() -> ImportRow {
+        ImportRow {
+            first_name: Some("Jane".to_string()),
+            last_name: Some("Doe".to_string()),
+            email_address: Some("jane@doe.com".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_from_file_name() {
+        assert_eq!(
+            ImportFormat::from_file_name("users.CSV"),
+            Some(ImportFormat::Csv)
+        );
+        assert_eq!(
+            ImportFormat::from_file_name("users.json"),
+            Some(ImportFormat::Json)
+        );
+        assert_eq!(
+            ImportFormat::from_file_name("users.jsonl"),
+            Some(ImportFormat::Ndjson)
+        );
+        assert_eq!(ImportFormat::from_file_name("users"), None);
+    }
+
+    #[test]
+    fn test_read_csv() {
+        let content = "\u{feff}Email,First Name,last_name\r\n\
+            jane@doe.com,Jane,Doe\r\n\
+            \r\n\
+            \"john@doe.com\",\"John \"\"J\"\"\nJunior\",Doe\n\
+            jim@doe.com,Jim\n";
+
+        let rows = read(ImportFormat::Csv, content).unwrap();
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], Ok(jane()));
+        assert_eq!(
+            rows[1].as_ref().unwrap().first_name.as_deref(),
+            Some("John \"J\"\nJunior")
+        );
+        assert_eq!(rows[2], Err("The row has 2 fields, not 3".to_string()));
+    }
+
+    #[test]
+    fn test_read_csv_invalid() {
+        let missing_column = read(ImportFormat::Csv, "first_name,last_name\nJane,Doe\n");
+        let unterminated = read(
+            ImportFormat::Csv,
+            "first,last,email\n\"Jane,Doe,jane@doe.com\n",
+        );
+
+        assert_eq!(missing_column.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(unterminated.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_json() {
+        let content = r#"[
+            {"firstName": "Jane", "lastName": "Doe", "emailAddress": "jane@doe.com"},
+            {"first_name": 1}
+        ]"#;
+
+        let rows = read(ImportFormat::Json, content).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], Ok(jane()));
+        assert!(rows[1].is_err());
+        assert!(read(ImportFormat::Json, r#"{"first_name": "Jane"}"#).is_err());
+        assert!(read(ImportFormat::Json, "[] []").is_err());
+    }
+
+    #[test]
+    fn test_read_ndjson() {
+        let content =
+            "{\"first_name\": \"Jane\", \"last_name\": \"Doe\", \"email\": \"jane@doe.com\"}\n\
+            \n\
+            not json\n";
+
+        let rows = read(ImportFormat::Ndjson, content).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], Ok(jane()));
+        assert!(rows[1].is_err());
+    }
+
+    #[test]
+    fn test_import_users() {
+        let mut conn = connect();
+        let suffix = Uuid::now_v7().simple();
+        let content = format!(
+            "first_name,last_name,email_address\n\
+            Jane,Doe,jane.{suffix}@doe.com\n\
+            John,,john.{suffix}@doe.com\n\
+            Jim,Doe,jim.{suffix}\n\
+            Janet,Doe,JANE.{suffix}@doe.com\n\
+            Jack,Doe,jack.{suffix}@doe.com\n"
+        );
+
+        let report = import_users(&mut conn, content.as_bytes(), ImportFormat::Csv, false).unwrap();
+
+        assert_eq!((report.rows, report.imported), (5, 2));
+        assert_eq!(
+            report.errors,
+            vec![
+                RowError {
+                    row: 2,
+                    message: "The last name is required".to_string()
+                },
+                RowError {
+                    row: 3,
+                    message: "The email address is invalid".to_string()
+                },
+                RowError {
+                    row: 4,
+                    message: "The email address appears in an earlier row".to_string()
+                },
+            ]
+        );
+
+        // A second import of the same users finds their email addresses taken.
+        let report = import_users(&mut conn, content.as_bytes(), ImportFormat::Csv, false).unwrap();
+
+        assert_eq!(report.imported, 0);
+        assert_eq!(
+            report.errors[0].message,
+            "The email address is already taken"
+        );
+    }
+
+    #[test]
+    fn test_import_users_dry_run() {
+        let mut conn = connect();
+        let email = format!("jane.{}@doe.com", Uuid::now_v7().simple());
+        let content = format!(
+            "{{\"first_name\": \"Jane\", \"last_name\": \"Doe\", \"email_address\": \"{}\"}}",
+            email
+        );
+
+        let report =
+            import_users(&mut conn, content.as_bytes(), ImportFormat::Ndjson, true).unwrap();
+        let count: i64 = dsl::users
+            .filter(dsl::email_hash.eq(blind_index(&email)))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+
+        assert_eq!((report.rows, report.imported), (1, 1));
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::core::encryption::blind_index;
+use crate::core::mailer::Email;
+use crate::core::mailer::Mailer;
+use crate::core::models::schema::invitations;
+use crate::core::models::schema::memberships;
+use crate::core::models::schema::users;
+use crate::core::models::Invitation;
+use crate::core::models::Membership;
+use crate::core::models::User;
+use crate::core::organizations::get_membership;
+use crate::core::organizations::get_organization;
+use crate::core::passwords;
+use crate::core::repo;
+use crate::core::search::search_tokens;
+use crate::core::tokens;
+use crate::core::users::enqueue_change;
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel::PgConnection;
+use std::io::Error;
+use std::io::ErrorKind;
+use uuid::Uuid;
+
+/// The time an invitation stays valid.
+const INVITATION_TTL: Duration = Duration::days(7);
+
+/// The user to create when the invited email address has no account yet.
+pub struct NewInvitee {
+    pub first_name: String,
+    pub last_name: String,
+    pub password: Option<String>,
+}
+
+/// Invite the email address to join the organization with the role, and
+/// mail it the invitation token. Replaces the pending invitations of the
+/// address to the organization. Fails with `ErrorKind::AlreadyExists` if
+/// the address belongs to a member already.
+pub fn create_invitation(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    organization_id: Uuid,
+    invited_by: Uuid,
+    email_address: &str,
+    role: &str,
+) -> Result<Option<Invitation>, Error> {
+    let organization = match get_organization(conn, organization_id)? {
+        Some(organization) => organization,
+        None => return Ok(None),
+    };
+    let timestamp = Utc::now().naive_utc();
+    let token = tokens::generate_token();
+
+    let invitation = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let member = memberships::table
+                .inner_join(users::table)
+                .filter(memberships::organization_id.eq(organization_id))
+                .filter(users::email_hash.eq(blind_index(email_address)))
+                .filter(users::deleted_at.is_null());
+            if diesel::select(exists(member)).get_result(conn)? {
+                let message = "The user is already a member of the organization";
+                return Ok(Err(Error::new(ErrorKind::AlreadyExists, message)));
+            }
+
+            diesel::update(
+                pending_invitations(timestamp)
+                    .filter(invitations::organization_id.eq(organization_id))
+                    .filter(invitations::email_hash.eq(blind_index(email_address))),
+            )
+            .set(invitations::revoked_at.eq(timestamp))
+            .execute(conn)?;
+            let invitation = Invitation {
+                id: Uuid::now_v7(),
+                organization_id,
+                email_address: email_address.to_string(),
+                role: role.to_string(),
+                token_hash: tokens::hash_token(&token),
+                invited_by: Some(invited_by),
+                expires_at: timestamp + INVITATION_TTL,
+                accepted_at: None,
+                revoked_at: None,
+                created_at: timestamp,
+            };
+            let hash = blind_index(&invitation.email_address);
+            let invitation = diesel::insert_into(invitations::table)
+                .values((invitation, invitations::email_hash.eq(hash)))
+                .returning(Invitation::as_returning())
+                .get_result(conn)?;
+
+            Ok(Ok(invitation))
+        })
+        .map_err(Error::other)??;
+
+    mailer.send(&Email {
+        to: invitation.email_address.clone(),
+        subject: format!("You are invited to join {}", organization.name),
+        body: format!(
+            "Use this token to accept the invitation within 7 days:\n\n{}",
+            token
+        ),
+    })?;
+
+    Ok(Some(invitation))
+}
+
+/// Accept the invitation of the token, adding its user to the organization.
+/// The user with the invited email address is created in the tenant of the
+/// organization if there is none, with its address verified since the token
+/// was mailed to it. Fails with `ErrorKind::InvalidInput` if the user has to
+/// be created but no invitee is given, and with `ErrorKind::AlreadyExists`
+/// if the address belongs to a user of another tenant.
+pub fn accept_invitation(
+    conn: &mut PgConnection,
+    token: &str,
+    invitee: Option<NewInvitee>,
+) -> Result<Option<(Membership, User)>, Error> {
+    let password_hash = match invitee
+        .as_ref()
+        .and_then(|invitee| invitee.password.as_deref())
+    {
+        Some(password) => Some(passwords::hash_password(password)?),
+        None => None,
+    };
+    let timestamp = Utc::now().naive_utc();
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let invitation = pending_invitations(timestamp)
+            .filter(invitations::token_hash.eq(tokens::hash_token(token)))
+            .for_update()
+            .select((Invitation::as_select(), invitations::tenant_id))
+            .first(conn)
+            .optional()?;
+        let invitation = match invitation {
+            Some((invitation, tenant_id)) => {
+                repo::set_tenant(conn, tenant_id)?;
+                invitation
+            }
+            None => return Ok(Ok(None)),
+        };
+
+        let user = users::table
+            .filter(users::email_hash.eq(blind_index(&invitation.email_address)))
+            .filter(users::deleted_at.is_null())
+            .select(User::as_select())
+            .first(conn)
+            .optional()?;
+        let user = match (user, invitee) {
+            (Some(user), _) => user,
+            (None, Some(invitee)) => {
+                let user = User {
+                    id: Uuid::now_v7(),
+                    first_name: invitee.first_name,
+                    last_name: invitee.last_name,
+                    email_address: invitation.email_address.clone(),
+                    created_at: timestamp,
+                    updated_at: timestamp,
+                    deleted_at: None,
+                    email_verified_at: Some(timestamp),
+                    version: 1,
+                };
+                let hash = blind_index(&user.email_address);
+                let tokens = search_tokens(&user.first_name, &user.last_name, &user.email_address);
+                let user = diesel::insert_into(users::table)
+                    .values((
+                        user,
+                        users::email_hash.eq(hash),
+                        users::search_tokens.eq(tokens),
+                    ))
+                    .returning(User::as_returning())
+                    .get_result(conn)?;
+                if let Some(hash) = &password_hash {
+                    passwords::set_password_hash(conn, user.id, hash)?;
+                }
+                enqueue_change(conn, "user.created", &user)?;
+                user
+            }
+            (None, None) => {
+                let message = "A first and last name are required to create the user";
+                return Ok(Err(Error::new(ErrorKind::InvalidInput, message)));
+            }
+        };
+
+        // Members keep their current role.
+        diesel::insert_into(memberships::table)
+            .values(&Membership {
+                organization_id: invitation.organization_id,
+                user_id: user.id,
+                role: invitation.role.clone(),
+                created_at: timestamp,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        diesel::update(invitations::table.find(invitation.id))
+            .set(invitations::accepted_at.eq(timestamp))
+            .execute(conn)?;
+        let membership = get_membership(conn, invitation.organization_id, user.id)?;
+
+        Ok(Ok(membership.map(|membership| (membership, user))))
+    })
+    .map_err(|e| match e {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Error::new(
+            ErrorKind::AlreadyExists,
+            "The email address is already in use",
+        ),
+        e => Error::other(e),
+    })?
+}
+
+/// Get the invitation.
+pub fn get_invitation(
+    conn: &mut PgConnection,
+    invitation_id: Uuid,
+) -> QueryResult<Option<Invitation>> {
+    invitations::table
+        .find(invitation_id)
+        .select(Invitation::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// Revoke the invitation, unless it is no longer pending.
+pub fn revoke_invitation(
+    conn: &mut PgConnection,
+    invitation_id: Uuid,
+) -> QueryResult<Option<Invitation>> {
+    let timestamp = Utc::now().naive_utc();
+
+    diesel::update(pending_invitations(timestamp).filter(invitations::id.eq(invitation_id)))
+        .set(invitations::revoked_at.eq(timestamp))
+        .returning(Invitation::as_returning())
+        .get_result(conn)
+        .optional()
+}
+
+/// List the pending invitations to the organization, by id.
+pub fn list_invitations(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    after: Option<Uuid>,
+    limit: i64,
+) -> QueryResult<Vec<Invitation>> {
+    let mut query = pending_invitations(Utc::now().naive_utc())
+        .filter(invitations::organization_id.eq(organization_id))
+        .order(invitations::id)
+        .limit(limit)
+        .select(Invitation::as_select())
+        .into_boxed();
+    if let Some(after) = after {
+        query = query.filter(invitations::id.gt(after));
+    }
+
+    query.load(conn)
+}
+
+/// The invitations neither accepted, revoked nor expired at the time.
+#[diesel::dsl::auto_type]
+fn pending_invitations(timestamp: NaiveDateTime) -> _ {
+    invitations::table
+        .filter(invitations::accepted_at.is_null())
+        .filter(invitations::revoked_at.is_null())
+        .filter(invitations::expires_at.gt(timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::mailer::MemoryMailer;
+    use crate::core::organizations::create_organization;
+    use crate::core::organizations::MEMBER;
+    use crate::core::sessions;
+    use crate::test::factory;
+    use diesel::Connection;
+
+    /// Get the token from the last email sent to the address.
+    fn sent_token(mailer: &MemoryMailer, email_address: &str) -> String {
+        let email = mailer.last_sent_to(email_address).unwrap();
+
+        email.body.split_whitespace().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_accept_invitation_creates_user() {
+        let owner = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        let email_address = format!("invitee.{}@doe.com", Uuid::new_v4());
+        create_invitation(
+            &mut conn,
+            &mailer,
+            organization.id,
+            owner.id,
+            &email_address,
+            MEMBER,
+        )
+        .unwrap()
+        .unwrap();
+        let token = sent_token(&mailer, &email_address);
+
+        let error = accept_invitation(&mut conn, &token, None).unwrap_err();
+        let invitee = NewInvitee {
+            first_name: "Jane".to_string(),
+            last_name: "Roe".to_string(),
+            password: Some("correct horse".to_string()),
+        };
+        let (membership, user) = accept_invitation(&mut conn, &token, Some(invitee))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(user.email_address, email_address);
+        assert_ne!(user.email_verified_at, None);
+        assert_eq!(membership.role, MEMBER);
+        assert_ne!(
+            sessions::sign_in(&mut conn, &email_address, "correct horse").unwrap(),
+            None
+        );
+        assert_eq!(accept_invitation(&mut conn, &token, None).unwrap(), None);
+        assert_eq!(
+            list_invitations(&mut conn, organization.id, None, 10).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_accept_invitation_links_user() {
+        let owner = factory::insert_user();
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        create_invitation(
+            &mut conn,
+            &mailer,
+            organization.id,
+            owner.id,
+            &user.email_address,
+            MEMBER,
+        )
+        .unwrap();
+        let token = sent_token(&mailer, &user.email_address);
+
+        let (_, member) = accept_invitation(&mut conn, &token, None).unwrap().unwrap();
+        let error = create_invitation(
+            &mut conn,
+            &mailer,
+            organization.id,
+            owner.id,
+            &user.email_address,
+            MEMBER,
+        )
+        .unwrap_err();
+
+        assert_eq!(member.id, user.id);
+        assert_ne!(
+            get_membership(&mut conn, organization.id, user.id).unwrap(),
+            None
+        );
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_revoke_invitation() {
+        let owner = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        let email_address = format!("invitee.{}@doe.com", Uuid::new_v4());
+        let first = create_invitation(
+            &mut conn,
+            &mailer,
+            organization.id,
+            owner.id,
+            &email_address,
+            MEMBER,
+        )
+        .unwrap()
+        .unwrap();
+        let first_token = sent_token(&mailer, &email_address);
+        let second = create_invitation(
+            &mut conn,
+            &mailer,
+            organization.id,
+            owner.id,
+            &email_address,
+            MEMBER,
+        )
+        .unwrap()
+        .unwrap();
+        let second_token = sent_token(&mailer, &email_address);
+
+        let pending = list_invitations(&mut conn, organization.id, None, 10).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, second.id);
+        assert_eq!(
+            accept_invitation(&mut conn, &first_token, None).unwrap(),
+            None
+        );
+        assert_eq!(revoke_invitation(&mut conn, first.id).unwrap(), None);
+        assert_ne!(revoke_invitation(&mut conn, second.id).unwrap(), None);
+        assert_eq!(
+            accept_invitation(&mut conn, &second_token, None).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,499 @@
+use crate::core::exports::ExportUsers;
+use crate::core::idempotency::PurgeExpiredKeys;
+use crate::core::models::schema::jobs;
+use crate::core::models::QueuedJob;
+use crate::core::outbox::backoff;
+use crate::core::retention::PurgeDeletedUsers;
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use diesel::prelude::*;
+use diesel::result::Error::SerializationError;
+use diesel::PgConnection;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tokio::time::sleep;
+use tracing::info;
+use tracing::warn;
+use uuid::Uuid;
+
+/// The status of a job that is waiting to run.
+pub const PENDING: &str = "pending";
+
+/// The status of a job that a worker is running.
+pub const RUNNING: &str = "running";
+
+/// The status of a job that ran successfully.
+pub const COMPLETED: &str = "completed";
+
+/// The status of a job that ran out of attempts.
+pub const DEAD: &str = "dead";
+
+/// The number of attempts before a job is dead-lettered, unless overridden.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// The time after which a running job whose worker stopped sending heartbeats
+/// is assumed to belong to a crashed worker.
+const LOCK_TIMEOUT: Duration = Duration::minutes(2);
+
+/// The wait between the heartbeats of a running job, well within the lock timeout.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// The wait between polls when there are no due jobs.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A job with a typed payload, registered with the worker by its name.
+pub trait Job: Serialize + DeserializeOwned {
+    /// The name the job is stored and registered under.
+    const NAME: &'static str;
+
+    /// Run the job, or return an error to retry it later.
+    fn perform(&self, conn: &mut PgConnection) -> Result<(), Error>;
+}
+
+type Handler = Box<dyn Fn(&mut PgConnection, serde_json::Value) -> Result<(), Error> + Send + Sync>;
+
+/// The job handlers, by name.
+#[derive(Default)]
+pub struct Registry {
+    handlers: HashMap<&'static str, Handler>,
+}
+
+impl Registry {
+    /// Register the handler of a job.
+    pub fn register<J: Job + 'static>(mut self) -> Registry {
+        self.handlers.insert(
+            J::NAME,
+            Box::new(|conn, payload| {
+                let job: J = serde_json::from_value(payload)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                job.perform(conn)
+            }),
+        );
+        self
+    }
+
+    fn perform(&self, conn: &mut PgConnection, job: &QueuedJob) -> Result<(), Error> {
+        match self.handlers.get(job.name.as_str()) {
+            Some(handler) => handler(conn, job.payload.clone()),
+            None => Err(Error::other(format!("No handler for job {}", job.name))),
+        }
+    }
+}
+
+/// Get the registry of every job the worker runs.
+pub fn registry() -> Registry {
+    Registry::default()
+        .register::<PurgeDeletedUsers>()
+        .register::<PurgeExpiredKeys>()
+        .register::<ExportUsers>()
+}
+
+/// The scheduling options of a job.
+pub struct JobOptions {
+    /// Jobs with a higher priority run first.
+    pub priority: i32,
+    /// The earliest time to run the job, or now.
+    pub run_at: Option<NaiveDateTime>,
+    pub max_attempts: i32,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        JobOptions {
+            priority: 0,
+            run_at: None,
+            max_attempts: MAX_ATTEMPTS,
+        }
+    }
+}
+
+/// Add a job to the queue, to run as soon as possible.
+pub fn enqueue<J: Job>(conn: &mut PgConnection, job: &J) -> QueryResult<QueuedJob> {
+    enqueue_with(conn, job, JobOptions::default())
+}
+
+/// Add a job to the queue with the given options.
+pub fn enqueue_with<J: Job>(
+    conn: &mut PgConnection,
+    job: &J,
+    options: JobOptions,
+) -> QueryResult<QueuedJob> {
+    let timestamp = Utc::now().naive_utc();
+    let changes = QueuedJob {
+        id: Uuid::now_v7(),
+        name: J::NAME.to_string(),
+        payload: serde_json::to_value(job).map_err(|e| SerializationError(Box::new(e)))?,
+        priority: options.priority,
+        status: PENDING.to_string(),
+        attempts: 0,
+        max_attempts: options.max_attempts,
+        run_at: options.run_at.unwrap_or(timestamp),
+        locked_at: None,
+        completed_at: None,
+        last_error: None,
+        created_at: timestamp,
+        updated_at: timestamp,
+    };
+
+    diesel::insert_into(jobs::table)
+        .values(&changes)
+        .returning(QueuedJob::as_returning())
+        .get_result(conn)
+}
+
+/// Lock the next due job, by priority then run-at time, and mark it running.
+///
+/// Jobs left running by a crashed worker, which no longer sends heartbeats,
+/// are claimed again after a timeout.
+fn claim(conn: &mut PgConnection) -> QueryResult<Option<QueuedJob>> {
+    conn.transaction(|conn| {
+        let timestamp = Utc::now().naive_utc();
+        let job = jobs::table
+            .filter(
+                jobs::status
+                    .eq(PENDING)
+                    .and(jobs::run_at.le(timestamp))
+                    .or(jobs::status
+                        .eq(RUNNING)
+                        .and(jobs::locked_at.lt(timestamp - LOCK_TIMEOUT))),
+            )
+            .order((jobs::priority.desc(), jobs::run_at.asc()))
+            .for_update()
+            .skip_locked()
+            .select(QueuedJob::as_select())
+            .first(conn)
+            .optional()?;
+
+        match job {
+            Some(job) => diesel::update(jobs::table.find(job.id))
+                .set((
+                    jobs::status.eq(RUNNING),
+                    jobs::attempts.eq(job.attempts + 1),
+                    jobs::locked_at.eq(timestamp),
+                    jobs::updated_at.eq(timestamp),
+                ))
+                .returning(QueuedJob::as_returning())
+                .get_result(conn)
+                .optional(),
+            None => Ok(None),
+        }
+    })
+}
+
+/// Keep the claim of a running job alive, returning whether it is still held.
+///
+/// The attempt number fences the claim: once the job is claimed again by
+/// another worker, this one can no longer touch it.
+fn heartbeat(conn: &mut PgConnection, job: &QueuedJob) -> QueryResult<bool> {
+    diesel::update(
+        jobs::table
+            .find(job.id)
+            .filter(jobs::status.eq(RUNNING))
+            .filter(jobs::attempts.eq(job.attempts)),
+    )
+    .set(jobs::locked_at.eq(Utc::now().naive_utc()))
+    .execute(conn)
+    .map(|updated| updated > 0)
+}
+
+/// Perform a claimed job and record its result, unless it was claimed again
+/// by another worker in the meantime.
+///
+/// A failed job is retried with exponential backoff until it runs out of
+/// attempts, and is then dead-lettered for inspection.
+fn perform(conn: &mut PgConnection, registry: &Registry, job: &QueuedJob) -> QueryResult<()> {
+    let result = registry.perform(conn, job);
+    let timestamp = Utc::now().naive_utc();
+    let claimed = jobs::table
+        .find(job.id)
+        .filter(jobs::status.eq(RUNNING))
+        .filter(jobs::attempts.eq(job.attempts));
+
+    match result {
+        Ok(()) => {
+            diesel::update(claimed)
+                .set((
+                    jobs::status.eq(COMPLETED),
+                    jobs::locked_at.eq(None::<NaiveDateTime>),
+                    jobs::completed_at.eq(timestamp),
+                    jobs::last_error.eq(None::<String>),
+                    jobs::updated_at.eq(timestamp),
+                ))
+                .execute(conn)?;
+        }
+        Err(e) => {
+            let (status, run_at) = if job.attempts >= job.max_attempts {
+                warn!(
+                    "Job {} {} is dead after {} attempts: {}",
+                    job.name, job.id, job.attempts, e
+                );
+                (DEAD, job.run_at)
+            } else {
+                warn!("Job {} {} failed: {}", job.name, job.id, e);
+                (PENDING, timestamp + backoff(job.attempts - 1))
+            };
+            diesel::update(claimed)
+                .set((
+                    jobs::status.eq(status),
+                    jobs::run_at.eq(run_at),
+                    jobs::locked_at.eq(None::<NaiveDateTime>),
+                    jobs::last_error.eq(e.to_string()),
+                    jobs::updated_at.eq(timestamp),
+                ))
+                .execute(conn)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Send heartbeats for the running job, from another connection, until the
+/// task is aborted or the claim is lost.
+async fn keep_alive(pool: Pool, job: QueuedJob) {
+    loop {
+        sleep(HEARTBEAT_INTERVAL).await;
+        let job = job.clone();
+        let result = match pool.get().await {
+            Ok(conn) => conn
+                .interact(move |conn| heartbeat(conn, &job))
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => return,
+            Ok(Err(e)) => warn!("Failed to send a job heartbeat: {}", e),
+            Err(e) => warn!("Failed to send a job heartbeat: {}", e),
+        }
+    }
+}
+
+/// Run the next due job with heartbeats, returning whether there was one.
+async fn work_next(pool: &Pool, registry: Arc<Registry>) -> Result<bool, String> {
+    let conn = pool.get().await.map_err(|e| e.to_string())?;
+    let job = match conn.interact(claim).await.map_err(|e| e.to_string())? {
+        Ok(Some(job)) => job,
+        Ok(None) => return Ok(false),
+        Err(e) => return Err(e.to_string()),
+    };
+    let heartbeats = tokio::spawn(keep_alive(pool.clone(), job.clone()));
+    let result = conn
+        .interact(move |conn| perform(conn, registry.as_ref(), &job))
+        .await;
+    heartbeats.abort();
+
+    match result {
+        Ok(Ok(())) => Ok(true),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Run due jobs until the process exits.
+pub async fn work(pool: Pool, registry: Arc<Registry>) {
+    loop {
+        match work_next(&pool, registry.clone()).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => warn!("Failed to run jobs: {}", e),
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Run the worker with the given number of concurrent jobs.
+pub async fn start_worker(pool: Pool, registry: Registry, concurrency: usize) {
+    let registry = Arc::new(registry);
+    info!("Worker running {} jobs at once", concurrency);
+
+    let workers: Vec<_> = (0..concurrency)
+        .map(|_| tokio::spawn(work(pool.clone(), registry.clone())))
+        .collect();
+    for worker in workers {
+        worker.await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use diesel::Connection;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Serialize)]
+    struct Echo {
+        fail: bool,
+    }
+
+    impl Job for Echo {
+        const NAME: &'static str = "test.echo";
+
+        fn perform(&self, _conn: &mut PgConnection) -> Result<(), Error> {
+            if self.fail {
+                return Err(Error::other("failed"));
+            }
+            Ok(())
+        }
+    }
+
+    fn get_job(conn: &mut PgConnection, job_id: Uuid) -> Option<QueuedJob> {
+        jobs::table
+            .find(job_id)
+            .select(QueuedJob::as_select())
+            .first(conn)
+            .optional()
+            .unwrap()
+    }
+
+    /// Run the next due job without heartbeats, returning whether there was one.
+    fn run_next(conn: &mut PgConnection, registry: &Registry) -> QueryResult<bool> {
+        match claim(conn)? {
+            Some(job) => perform(conn, registry, &job).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Connect in a test transaction, with no other jobs queued.
+    fn establish() -> PgConnection {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        conn.begin_test_transaction().unwrap();
+        diesel::delete(jobs::table).execute(&mut conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_run_next() {
+        let mut conn = establish();
+        let registry = Registry::default().register::<Echo>();
+        let job = enqueue(&mut conn, &Echo { fail: false }).unwrap();
+
+        assert!(run_next(&mut conn, &registry).unwrap());
+        assert!(!run_next(&mut conn, &registry).unwrap());
+        let job = get_job(&mut conn, job.id).unwrap();
+
+        assert_eq!(job.status, COMPLETED);
+        assert_eq!(job.attempts, 1);
+        assert_ne!(job.completed_at, None);
+    }
+
+    #[test]
+    fn test_run_next_priority() {
+        let mut conn = establish();
+        let registry = Registry::default().register::<Echo>();
+        let low = enqueue(&mut conn, &Echo { fail: false }).unwrap();
+        let options = JobOptions {
+            priority: 10,
+            ..Default::default()
+        };
+        let high = enqueue_with(&mut conn, &Echo { fail: false }, options).unwrap();
+
+        run_next(&mut conn, &registry).unwrap();
+
+        assert_eq!(get_job(&mut conn, high.id).unwrap().status, COMPLETED);
+        assert_eq!(get_job(&mut conn, low.id).unwrap().status, PENDING);
+    }
+
+    #[test]
+    fn test_run_next_scheduled() {
+        let mut conn = establish();
+        let registry = Registry::default().register::<Echo>();
+        let run_at = Utc::now().naive_utc() + Duration::hours(1);
+        let options = JobOptions {
+            run_at: Some(run_at),
+            ..Default::default()
+        };
+        enqueue_with(&mut conn, &Echo { fail: false }, options).unwrap();
+
+        assert!(!run_next(&mut conn, &registry).unwrap());
+    }
+
+    #[test]
+    fn test_run_next_failed() {
+        let mut conn = establish();
+        let registry = Registry::default().register::<Echo>();
+        let options = JobOptions {
+            max_attempts: 2,
+            ..Default::default()
+        };
+        let job = enqueue_with(&mut conn, &Echo { fail: true }, options).unwrap();
+
+        run_next(&mut conn, &registry).unwrap();
+        let failed = get_job(&mut conn, job.id).unwrap();
+
+        assert_eq!(failed.status, PENDING);
+        assert_eq!(failed.last_error, Some("failed".to_string()));
+        assert!(failed.run_at > job.run_at);
+
+        diesel::update(jobs::table.find(job.id))
+            .set(jobs::run_at.eq(job.run_at))
+            .execute(&mut conn)
+            .unwrap();
+        run_next(&mut conn, &registry).unwrap();
+        let dead = get_job(&mut conn, job.id).unwrap();
+
+        assert_eq!(dead.status, DEAD);
+        assert_eq!(dead.attempts, 2);
+    }
+
+    #[test]
+    fn test_run_next_unregistered() {
+        let mut conn = establish();
+        let job = enqueue(&mut conn, &Echo { fail: false }).unwrap();
+
+        run_next(&mut conn, &Registry::default()).unwrap();
+        let job = get_job(&mut conn, job.id).unwrap();
+
+        assert_eq!(job.status, PENDING);
+        assert_eq!(
+            job.last_error,
+            Some("No handler for job test.echo".to_string())
+        );
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let mut conn = establish();
+        enqueue(&mut conn, &Echo { fail: false }).unwrap();
+        let job = claim(&mut conn).unwrap().unwrap();
+        let stale = Utc::now().naive_utc() - LOCK_TIMEOUT - Duration::minutes(1);
+        diesel::update(jobs::table.find(job.id))
+            .set(jobs::locked_at.eq(stale))
+            .execute(&mut conn)
+            .unwrap();
+
+        assert!(heartbeat(&mut conn, &job).unwrap());
+        assert_eq!(claim(&mut conn).unwrap(), None);
+        assert!(get_job(&mut conn, job.id).unwrap().locked_at.unwrap() > stale);
+    }
+
+    #[test]
+    fn test_perform_claimed_again() {
+        let mut conn = establish();
+        let registry = Registry::default().register::<Echo>();
+        enqueue(&mut conn, &Echo { fail: false }).unwrap();
+        let job = claim(&mut conn).unwrap().unwrap();
+        let stale = Utc::now().naive_utc() - LOCK_TIMEOUT - Duration::minutes(1);
+        diesel::update(jobs::table.find(job.id))
+            .set(jobs::locked_at.eq(stale))
+            .execute(&mut conn)
+            .unwrap();
+        let claimed = claim(&mut conn).unwrap().unwrap();
+
+        perform(&mut conn, &registry, &job).unwrap();
+        assert!(!heartbeat(&mut conn, &job).unwrap());
+        assert_eq!(get_job(&mut conn, job.id).unwrap().status, RUNNING);
+
+        perform(&mut conn, &registry, &claimed).unwrap();
+        assert_eq!(get_job(&mut conn, job.id).unwrap().status, COMPLETED);
+    }
+}
@@ -0,0 +1,150 @@
+use lettre::message::Mailbox;
+use lettre::Message;
+use lettre::SmtpTransport;
+use lettre::Transport;
+use std::fs;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// A plain text email.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// A way of sending emails.
+pub trait Mailer: Send + Sync {
+    /// Send the email, or return an error if it could not be handed over.
+    fn send(&self, email: &Email) -> Result<(), Error>;
+}
+
+/// Send emails through an SMTP server.
+pub struct SmtpMailer {
+    pub transport: SmtpTransport,
+    pub from: Mailbox,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), Error> {
+        let to: Mailbox = email
+            .to
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&email.subject)
+            .body(email.body.clone())
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(Error::other)
+    }
+}
+
+/// Write emails as files to a directory, for local development.
+pub struct FileMailer {
+    pub path: PathBuf,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), Error> {
+        fs::create_dir_all(&self.path)?;
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+
+        fs::write(self.path.join(format!("{}.eml", Uuid::now_v7())), contents)
+    }
+}
+
+/// Keep emails in memory, for tests.
+#[derive(Default)]
+pub struct MemoryMailer {
+    pub sent: Mutex<Vec<Email>>,
+}
+
+#[cfg(test)]
+impl MemoryMailer {
+    /// Get the last email sent to the address.
+    pub fn last_sent_to(&self, to: &str) -> Option<Email> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|email| email.to == to)
+            .cloned()
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send(&self, email: &Email) -> Result<(), Error> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+/// Build a mailer from its configuration: `memory`, `file:<path>` or an
+/// `smtp://` or `smtps://` URL.
+pub fn build_mailer(target: &str, from: &str) -> Arc<dyn Mailer> {
+    if target == "memory" {
+        Arc::new(MemoryMailer::default())
+    } else if let Some(path) = target.strip_prefix("file:") {
+        Arc::new(FileMailer { path: path.into() })
+    } else if target.starts_with("smtp://") || target.starts_with("smtps://") {
+        let transport = SmtpTransport::from_url(target)
+            .unwrap_or_else(|e| panic!("Invalid SMTP URL: {:?}", e))
+            .build();
+        let from = from
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid sender: {:?}", e));
+
+        Arc::new(SmtpMailer { transport, from })
+    } else {
+        panic!("Unknown mailer: {}", target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email() -> Email {
+        Email {
+            to: "jane@doe.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "Hello, Jane".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_memory_mailer() {
+        let mailer = MemoryMailer::default();
+        mailer.send(&email()).unwrap();
+
+        assert_eq!(mailer.last_sent_to("jane@doe.com"), Some(email()));
+        assert_eq!(mailer.last_sent_to("john@doe.com"), None);
+    }
+
+    #[test]
+    fn test_file_mailer() {
+        let path = std::env::temp_dir().join(format!("rpg-mail-{}", Uuid::now_v7()));
+        let mailer = FileMailer { path: path.clone() };
+        mailer.send(&email()).unwrap();
+        let entry = fs::read_dir(&path).unwrap().next().unwrap().unwrap();
+        let contents = fs::read_to_string(entry.path()).unwrap();
+
+        assert!(contents.starts_with("To: jane@doe.com\nSubject: Hello\n"));
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+use crate::core::authz::READ_USERS;
+use crate::core::encryption::blind_index;
+use crate::core::models::schema::masking_rules;
+use crate::core::models::schema::permissions;
+use crate::core::models::schema::roles;
+use crate::core::models::schema::user_roles;
+use crate::core::models::User;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The number of hex digits of a hashed value.
+const HASH_SIZE: usize = 16;
+
+/// The fields of a user holding personal data.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Field {
+    FirstName,
+    LastName,
+    EmailAddress,
+}
+
+impl Field {
+    pub const ALL: [Field; 3] = [Field::FirstName, Field::LastName, Field::EmailAddress];
+
+    /// Parse the field as named by a rule.
+    pub fn parse(field: &str) -> Option<Field> {
+        match field {
+            "first_name" => Some(Field::FirstName),
+            "last_name" => Some(Field::LastName),
+            "email_address" => Some(Field::EmailAddress),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Field::FirstName => "first_name",
+            Field::LastName => "last_name",
+            Field::EmailAddress => "email_address",
+        }
+    }
+
+    /// Get the value of the field of the user.
+    pub fn value<'a>(&self, user: &'a User) -> &'a str {
+        match self {
+            Field::FirstName => &user.first_name,
+            Field::LastName => &user.last_name,
+            Field::EmailAddress => &user.email_address,
+        }
+    }
+}
+
+/// How a field is shown, from the least to the most restrictive.
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Strategy {
+    /// The value as it is.
+    #[default]
+    Full,
+    /// The first character of the value, or of the local part and the domain
+    /// of an email address, like `j***@example.com`.
+    Partial,
+    /// A keyed hash of the value, the same for equal values.
+    Hash,
+    /// No value.
+    Null,
+}
+
+impl Strategy {
+    /// Parse the strategy as named by a rule.
+    pub fn parse(strategy: &str) -> Option<Strategy> {
+        match strategy {
+            "full" => Some(Strategy::Full),
+            "partial" => Some(Strategy::Partial),
+            "hash" => Some(Strategy::Hash),
+            "null" => Some(Strategy::Null),
+            _ => None,
+        }
+    }
+
+    /// Mask the value of the field.
+    pub fn apply(&self, field: Field, value: &str) -> Option<String> {
+        match self {
+            Strategy::Full => Some(value.to_string()),
+            Strategy::Partial => Some(partial(field, value)),
+            Strategy::Hash => Some(blind_index(value)[..HASH_SIZE].to_string()),
+            Strategy::Null => None,
+        }
+    }
+}
+
+fn partial(field: Field, value: &str) -> String {
+    let initial = |value: &str| match value.chars().next() {
+        Some(first) => format!("{}***", first),
+        None => String::new(),
+    };
+
+    match (field, value.rsplit_once('@')) {
+        (Field::EmailAddress, Some((local, domain))) => format!("{}@{}", initial(local), domain),
+        _ => initial(value),
+    }
+}
+
+/// How a viewer sees the personal data of other users, by field. The viewer
+/// always sees its own data in full.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaskingPolicy {
+    pub user_id: Option<Uuid>,
+    pub strategies: HashMap<Field, Strategy>,
+}
+
+impl MaskingPolicy {
+    /// Get how anonymous viewers see the personal data of users: not at all,
+    /// as the most restrictive strategy applies to every field.
+    pub fn anonymous() -> MaskingPolicy {
+        MaskingPolicy {
+            user_id: None,
+            strategies: Field::ALL
+                .into_iter()
+                .map(|field| (field, Strategy::Null))
+                .collect(),
+        }
+    }
+
+    /// Get how the field of the user is shown.
+    pub fn strategy(&self, user_id: Uuid, field: Field) -> Strategy {
+        match self.user_id == Some(user_id) {
+            true => Strategy::Full,
+            false => self.strategies.get(&field).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Mask the value of the field of the user.
+    pub fn mask(&self, user_id: Uuid, field: Field, value: &str) -> Option<String> {
+        self.strategy(user_id, field).apply(field, value)
+    }
+}
+
+/// Get how the user sees the personal data of other users, from the masking
+/// rules of its roles. A role without a rule for a field shows it in full if
+/// it grants `users:read`, and not at all otherwise. The least restrictive of
+/// its roles applies, so a user without roles sees no field.
+pub fn get_masking_policy(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<MaskingPolicy> {
+    let rows = user_roles::table
+        .inner_join(roles::table.left_join(masking_rules::table))
+        .filter(user_roles::user_id.eq(user_id))
+        .select((
+            roles::id,
+            masking_rules::field.nullable(),
+            masking_rules::strategy.nullable(),
+        ))
+        .load::<(Uuid, Option<String>, Option<String>)>(conn)?;
+    let readers = user_roles::table
+        .inner_join(permissions::table.on(permissions::role_id.eq(user_roles::role_id)))
+        .filter(user_roles::user_id.eq(user_id))
+        .filter(permissions::name.eq(READ_USERS))
+        .select(user_roles::role_id)
+        .load::<Uuid>(conn)?;
+
+    let mut rules = HashMap::<Uuid, HashMap<Field, Strategy>>::new();
+    for (role_id, field, strategy) in rows {
+        let role = rules.entry(role_id).or_default();
+        let field = field.as_deref().and_then(Field::parse);
+        let strategy = strategy.as_deref().and_then(Strategy::parse);
+        if let (Some(field), Some(strategy)) = (field, strategy) {
+            role.insert(field, strategy);
+        }
+    }
+
+    let mut policy = MaskingPolicy {
+        user_id: Some(user_id),
+        ..MaskingPolicy::default()
+    };
+    for field in Field::ALL {
+        let strategy = rules
+            .iter()
+            .map(|(role_id, role)| match role.get(&field) {
+                Some(strategy) => *strategy,
+                None if readers.contains(role_id) => Strategy::Full,
+                None => Strategy::Null,
+            })
+            .min()
+            .unwrap_or(Strategy::Null);
+        if strategy != Strategy::Full {
+            policy.strategies.insert(field, strategy);
+        }
+    }
+
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::authz;
+    use crate::test::factory;
+    use chrono::Utc;
+
+    /// Create a role masking the fields with the strategies.
+    fn insert_role(conn: &mut PgConnection, rules: &[(Field, &str)]) -> String {
+        let name = format!("role-{}", Uuid::now_v7().simple());
+        let role_id = diesel::insert_into(roles::table)
+            .values((
+                roles::id.eq(Uuid::now_v7()),
+                roles::name.eq(&name),
+                roles::description.eq(""),
+                roles::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .returning(roles::id)
+            .get_result::<Uuid>(conn)
+            .unwrap();
+        for (field, strategy) in rules {
+            diesel::insert_into(masking_rules::table)
+                .values((
+                    masking_rules::role_id.eq(role_id),
+                    masking_rules::field.eq(field.as_str()),
+                    masking_rules::strategy.eq(strategy),
+                ))
+                .execute(conn)
+                .unwrap();
+        }
+
+        name
+    }
+
+    #[test]
+    fn test_apply() {
+        let email = "jane@example.com";
+
+        assert_eq!(
+            Strategy::Full.apply(Field::EmailAddress, email).unwrap(),
+            email
+        );
+        assert_eq!(
+            Strategy::Partial.apply(Field::EmailAddress, email).unwrap(),
+            "j***@example.com"
+        );
+        assert_eq!(
+            Strategy::Partial.apply(Field::LastName, "Doe").unwrap(),
+            "D***"
+        );
+        assert_eq!(Strategy::Partial.apply(Field::FirstName, "").unwrap(), "");
+        assert_eq!(
+            Strategy::Hash
+                .apply(Field::EmailAddress, email)
+                .unwrap()
+                .len(),
+            HASH_SIZE
+        );
+        assert_eq!(
+            Strategy::Hash.apply(Field::EmailAddress, email),
+            Strategy::Hash.apply(Field::EmailAddress, "Jane@example.com")
+        );
+        assert_eq!(Strategy::Null.apply(Field::EmailAddress, email), None);
+    }
+
+    #[test]
+    fn test_anonymous() {
+        let policy = MaskingPolicy::anonymous();
+
+        for field in Field::ALL {
+            assert_eq!(policy.strategy(Uuid::now_v7(), field), Strategy::Null);
+        }
+        assert_eq!(policy.mask(Uuid::now_v7(), Field::FirstName, "Jane"), None);
+    }
+
+    #[test]
+    fn test_get_masking_policy() {
+        let viewer = factory::insert_user();
+        let user = factory::insert_user();
+        let mut conn = PgConnection::establish(&config::get_config().database_url).unwrap();
+
+        let policy = get_masking_policy(&mut conn, viewer.id).unwrap();
+        assert_eq!(
+            policy.strategy(user.id, Field::EmailAddress),
+            Strategy::Null
+        );
+        assert_eq!(
+            policy.strategy(viewer.id, Field::EmailAddress),
+            Strategy::Full
+        );
+
+        authz::grant_role(&mut conn, viewer.id, "support").unwrap();
+        let policy = get_masking_policy(&mut conn, viewer.id).unwrap();
+        assert_eq!(policy.strategy(user.id, Field::FirstName), Strategy::Full);
+        assert_eq!(
+            policy.strategy(user.id, Field::EmailAddress),
+            Strategy::Partial
+        );
+        assert_eq!(
+            policy.strategy(viewer.id, Field::EmailAddress),
+            Strategy::Full
+        );
+
+        let role = insert_role(
+            &mut conn,
+            &[(Field::EmailAddress, "null"), (Field::LastName, "hash")],
+        );
+        authz::grant_role(&mut conn, viewer.id, &role).unwrap();
+        let policy = get_masking_policy(&mut conn, viewer.id).unwrap();
+        assert_eq!(policy.strategy(user.id, Field::LastName), Strategy::Partial);
+        assert_eq!(
+            policy.strategy(user.id, Field::EmailAddress),
+            Strategy::Partial
+        );
+        authz::revoke_role(&mut conn, viewer.id, "support").unwrap();
+        let policy = get_masking_policy(&mut conn, viewer.id).unwrap();
+        assert_eq!(policy.strategy(user.id, Field::LastName), Strategy::Hash);
+        // The role does not grant `users:read`, so it hides the other fields.
+        assert_eq!(policy.strategy(user.id, Field::FirstName), Strategy::Null);
+        assert_eq!(
+            policy.mask(user.id, Field::EmailAddress, &user.email_address),
+            None
+        );
+
+        authz::grant_role(&mut conn, viewer.id, "admin").unwrap();
+        let policy = get_masking_policy(&mut conn, viewer.id).unwrap();
+        assert_eq!(
+            policy.strategy(user.id, Field::EmailAddress),
+            Strategy::Full
+        );
+    }
+}
@@ -1,22 +1,304 @@
+use crate::core::encryption::Encrypted;
+use crate::core::models::schema::audit_events;
+use crate::core::models::schema::email_changes;
+use crate::core::models::schema::idempotency_keys;
+use crate::core::models::schema::invitations;
+use crate::core::models::schema::jobs;
+use crate::core::models::schema::memberships;
+use crate::core::models::schema::organizations;
+use crate::core::models::schema::outbox;
+use crate::core::models::schema::roles;
+use crate::core::models::schema::sessions;
+use crate::core::models::schema::user_credentials;
+use crate::core::models::schema::user_exports;
+use crate::core::models::schema::user_roles;
+use crate::core::models::schema::user_tokens;
 use crate::core::models::schema::users;
+use crate::core::models::schema::users_history;
+use crate::core::models::schema::webhook_deliveries;
+use crate::core::models::schema::webhooks;
 use chrono::NaiveDateTime;
 use diesel::pg::Pg;
 use diesel::prelude::Insertable;
 use diesel::prelude::Queryable;
+use diesel::prelude::QueryableByName;
 use diesel::prelude::Selectable;
+use serde::Deserialize;
+use serde::Serialize;
 use uuid::Uuid;
 
 pub mod schema;
 
-#[derive(Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[derive(
+    Clone,
+    Debug,
+    Deserialize,
+    Insertable,
+    PartialEq,
+    Queryable,
+    QueryableByName,
+    Selectable,
+    Serialize,
+)]
 #[diesel(table_name = users)]
 #[diesel(check_for_backend(Pg))]
 pub struct User {
     pub id: Uuid,
+    #[diesel(deserialize_as = Encrypted, serialize_as = Encrypted)]
     pub first_name: String,
+    #[diesel(deserialize_as = Encrypted, serialize_as = Encrypted)]
     pub last_name: String,
+    #[diesel(deserialize_as = Encrypted, serialize_as = Encrypted)]
     pub email_address: String,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub version: i32,
+}
+
+/// A version of a user, as it was from `valid_from` until `valid_to`.
+#[derive(Clone, Debug, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = users_history)]
+#[diesel(check_for_backend(Pg))]
+pub struct UserVersion {
+    pub version_id: Uuid,
+    pub id: Uuid,
+    #[diesel(deserialize_as = Encrypted)]
+    pub first_name: String,
+    #[diesel(deserialize_as = Encrypted)]
+    pub last_name: String,
+    #[diesel(deserialize_as = Encrypted)]
+    pub email_address: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub version: i32,
+    pub valid_from: NaiveDateTime,
+    pub valid_to: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = email_changes)]
+#[diesel(check_for_backend(Pg))]
+pub struct EmailChange {
+    pub user_id: Uuid,
+    #[diesel(deserialize_as = Encrypted, serialize_as = Encrypted)]
+    pub email_address: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = user_credentials)]
+#[diesel(check_for_backend(Pg))]
+pub struct UserCredential {
+    pub user_id: Uuid,
+    pub password_hash: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = organizations)]
+#[diesel(check_for_backend(Pg))]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = memberships)]
+#[diesel(check_for_backend(Pg))]
+pub struct Membership {
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    pub role: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = invitations)]
+#[diesel(check_for_backend(Pg))]
+pub struct Invitation {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    #[diesel(deserialize_as = Encrypted, serialize_as = Encrypted)]
+    pub email_address: String,
+    pub role: String,
+    pub token_hash: String,
+    pub invited_by: Option<Uuid>,
+    pub expires_at: NaiveDateTime,
+    pub accepted_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = roles)]
+#[diesel(check_for_backend(Pg))]
+pub struct Role {
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = user_roles)]
+#[diesel(check_for_backend(Pg))]
+pub struct UserRole {
+    pub user_id: Uuid,
+    pub role_id: Uuid,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = sessions)]
+#[diesel(check_for_backend(Pg))]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = user_tokens)]
+#[diesel(check_for_backend(Pg))]
+pub struct UserToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub purpose: String,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub consumed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = jobs)]
+#[diesel(check_for_backend(Pg))]
+pub struct QueuedJob {
+    pub id: Uuid,
+    pub name: String,
+    pub payload: serde_json::Value,
+    pub priority: i32,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_at: NaiveDateTime,
+    pub locked_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = outbox)]
+#[diesel(check_for_backend(Pg))]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub event_type: String,
+    pub aggregate_id: Uuid,
+    pub payload: serde_json::Value,
+    pub created_at: NaiveDateTime,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub dead_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = outbox)]
+pub struct NewOutboxEvent {
+    pub event_type: String,
+    pub aggregate_id: Uuid,
+    pub payload: serde_json::Value,
+    pub created_at: NaiveDateTime,
+    pub next_attempt_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = webhooks)]
+#[diesel(check_for_backend(Pg))]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub event_types: Vec<Option<String>>,
+    pub enabled: bool,
+    pub failure_count: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub disabled_at: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = webhook_deliveries)]
+#[diesel(check_for_backend(Pg))]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub response_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = audit_events)]
+#[diesel(check_for_backend(Pg))]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Uuid,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub request_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = idempotency_keys)]
+#[diesel(check_for_backend(Pg))]
+pub struct IdempotencyKey {
+    pub key: String,
+    pub viewer_id: Uuid,
+    pub request_hash: String,
+    pub response: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = user_exports)]
+#[diesel(check_for_backend(Pg))]
+pub struct UserExport {
+    pub id: Uuid,
+    pub requested_by: Option<Uuid>,
+    pub format: String,
+    pub filter: serde_json::Value,
+    pub status: String,
+    pub rows_exported: i32,
+    pub file_name: String,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
 }
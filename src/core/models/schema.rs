@@ -1,5 +1,209 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_events (id) {
+        id -> Uuid,
+        tenant_id -> Uuid,
+        actor_id -> Nullable<Uuid>,
+        action -> Text,
+        target_type -> Text,
+        target_id -> Uuid,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        request_id -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    email_changes (user_id) {
+        user_id -> Uuid,
+        email_address -> Text,
+        created_at -> Timestamp,
+        email_hash -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    idempotency_keys (tenant_id, viewer_id, key) {
+        key -> Text,
+        request_hash -> Text,
+        response -> Nullable<Text>,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        tenant_id -> Uuid,
+        viewer_id -> Uuid,
+    }
+}
+
+diesel::table! {
+    invitations (id) {
+        id -> Uuid,
+        organization_id -> Uuid,
+        email_address -> Text,
+        role -> Text,
+        token_hash -> Text,
+        invited_by -> Nullable<Uuid>,
+        expires_at -> Timestamp,
+        accepted_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        tenant_id -> Uuid,
+        email_hash -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    jobs (id) {
+        id -> Uuid,
+        name -> Text,
+        payload -> Jsonb,
+        priority -> Int4,
+        status -> Text,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        run_at -> Timestamp,
+        locked_at -> Nullable<Timestamp>,
+        completed_at -> Nullable<Timestamp>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    masking_rules (role_id, field) {
+        role_id -> Uuid,
+        field -> Text,
+        strategy -> Text,
+    }
+}
+
+diesel::table! {
+    memberships (organization_id, user_id) {
+        organization_id -> Uuid,
+        user_id -> Uuid,
+        role -> Text,
+        created_at -> Timestamp,
+        tenant_id -> Uuid,
+    }
+}
+
+diesel::table! {
+    organizations (id) {
+        id -> Uuid,
+        name -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        tenant_id -> Uuid,
+    }
+}
+
+diesel::table! {
+    outbox (id) {
+        id -> Int8,
+        event_type -> Text,
+        aggregate_id -> Uuid,
+        payload -> Jsonb,
+        created_at -> Timestamp,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+        last_error -> Nullable<Text>,
+        dead_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    permissions (role_id, name) {
+        role_id -> Uuid,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    roles (id) {
+        id -> Uuid,
+        name -> Text,
+        description -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    scheduled_tasks (name) {
+        name -> Text,
+        last_run_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        token_hash -> Text,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    tenants (id) {
+        id -> Uuid,
+        name -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_credentials (user_id) {
+        user_id -> Uuid,
+        password_hash -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_exports (id) {
+        id -> Uuid,
+        tenant_id -> Uuid,
+        requested_by -> Nullable<Uuid>,
+        format -> Text,
+        filter -> Jsonb,
+        status -> Text,
+        rows_exported -> Int4,
+        file_name -> Text,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        completed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role_id) {
+        user_id -> Uuid,
+        role_id -> Uuid,
+        created_at -> Timestamp,
+        tenant_id -> Uuid,
+    }
+}
+
+diesel::table! {
+    user_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        purpose -> Text,
+        token_hash -> Text,
+        expires_at -> Timestamp,
+        consumed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -9,5 +213,114 @@ diesel::table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         deleted_at -> Nullable<Timestamp>,
+        email_verified_at -> Nullable<Timestamp>,
+        tenant_id -> Uuid,
+        version -> Int4,
+        email_hash -> Nullable<Text>,
+        search_tokens -> Nullable<Text>,
+        anonymized_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    users_history (version_id) {
+        version_id -> Uuid,
+        id -> Uuid,
+        tenant_id -> Uuid,
+        first_name -> Text,
+        last_name -> Text,
+        email_address -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+        email_verified_at -> Nullable<Timestamp>,
+        valid_from -> Timestamp,
+        valid_to -> Nullable<Timestamp>,
+        version -> Int4,
     }
 }
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Uuid,
+        webhook_id -> Uuid,
+        event_type -> Text,
+        payload -> Jsonb,
+        status -> Text,
+        attempts -> Int4,
+        response_code -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        tenant_id -> Uuid,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Uuid,
+        url -> Text,
+        secret -> Text,
+        event_types -> Array<Nullable<Text>>,
+        enabled -> Bool,
+        failure_count -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        disabled_at -> Nullable<Timestamp>,
+        tenant_id -> Uuid,
+    }
+}
+
+diesel::joinable!(audit_events -> tenants (tenant_id));
+diesel::joinable!(email_changes -> users (user_id));
+diesel::joinable!(idempotency_keys -> tenants (tenant_id));
+diesel::joinable!(invitations -> organizations (organization_id));
+diesel::joinable!(invitations -> tenants (tenant_id));
+diesel::joinable!(invitations -> users (invited_by));
+diesel::joinable!(masking_rules -> roles (role_id));
+diesel::joinable!(memberships -> organizations (organization_id));
+diesel::joinable!(memberships -> tenants (tenant_id));
+diesel::joinable!(memberships -> users (user_id));
+diesel::joinable!(organizations -> tenants (tenant_id));
+diesel::joinable!(permissions -> roles (role_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(user_credentials -> users (user_id));
+diesel::joinable!(user_exports -> tenants (tenant_id));
+diesel::joinable!(user_exports -> users (requested_by));
+diesel::joinable!(user_roles -> roles (role_id));
+diesel::joinable!(user_roles -> tenants (tenant_id));
+diesel::joinable!(user_roles -> users (user_id));
+diesel::joinable!(user_tokens -> users (user_id));
+diesel::joinable!(users -> tenants (tenant_id));
+diesel::joinable!(users_history -> tenants (tenant_id));
+diesel::joinable!(users_history -> users (id));
+diesel::joinable!(webhook_deliveries -> tenants (tenant_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+diesel::joinable!(webhooks -> tenants (tenant_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_events,
+    email_changes,
+    idempotency_keys,
+    invitations,
+    jobs,
+    masking_rules,
+    memberships,
+    organizations,
+    outbox,
+    permissions,
+    roles,
+    scheduled_tasks,
+    sessions,
+    tenants,
+    user_credentials,
+    user_exports,
+    user_roles,
+    user_tokens,
+    users,
+    users_history,
+    webhook_deliveries,
+    webhooks,
+);
@@ -0,0 +1,367 @@
+use crate::core::encryption::blind_index;
+use crate::core::models::schema::memberships;
+use crate::core::models::schema::organizations;
+use crate::core::models::schema::users;
+use crate::core::models::Membership;
+use crate::core::models::Organization;
+use crate::core::models::User;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::io::Error;
+use std::io::ErrorKind;
+use uuid::Uuid;
+
+/// The member who owns the organization, of which there is exactly one.
+pub const OWNER: &str = "owner";
+
+/// A member who manages the other members.
+pub const ADMIN: &str = "admin";
+
+/// A member without management rights.
+pub const MEMBER: &str = "member";
+
+/// Create an organization owned by the user.
+pub fn create_organization(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    name: &str,
+) -> Result<Option<Organization>, Error> {
+    let timestamp = Utc::now().naive_utc();
+    let organization = Organization {
+        id: Uuid::now_v7(),
+        name: name.to_string(),
+        created_at: timestamp,
+        updated_at: timestamp,
+    };
+
+    // TODO: Handle all the errors
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let organization = diesel::insert_into(organizations::table)
+                .values(&organization)
+                .returning(Organization::as_returning())
+                .get_result(conn)?;
+            diesel::insert_into(memberships::table)
+                .values(&Membership {
+                    organization_id: organization.id,
+                    user_id: owner_id,
+                    role: OWNER.to_string(),
+                    created_at: timestamp,
+                })
+                .execute(conn)?;
+            Ok(organization)
+        })
+        .optional();
+
+    match result {
+        Ok(Some(organization)) => Ok(Some(organization)),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Get the organization.
+pub fn get_organization(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+) -> Result<Option<Organization>, Error> {
+    organizations::table
+        .find(organization_id)
+        .select(Organization::as_select())
+        .first(conn)
+        .optional()
+        .map_err(Error::other)
+}
+
+/// Get the membership of the user in the organization.
+pub fn get_membership(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> QueryResult<Option<Membership>> {
+    memberships::table
+        .find((organization_id, user_id))
+        .select(Membership::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// Add the user with the email address to the organization with the role,
+/// unless it is a member already. Fails with `ErrorKind::NotFound` if there
+/// is no such user.
+pub fn add_member(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    email_address: &str,
+    role: &str,
+) -> Result<Option<(Membership, User)>, Error> {
+    if get_organization(conn, organization_id)?.is_none() {
+        return Ok(None);
+    }
+    let user = users::table
+        .filter(users::email_hash.eq(blind_index(email_address)))
+        .filter(users::deleted_at.is_null())
+        .select(User::as_select())
+        .first(conn)
+        .optional()
+        .map_err(Error::other)?
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "There is no user with this email address",
+            )
+        })?;
+
+    diesel::insert_into(memberships::table)
+        .values(&Membership {
+            organization_id,
+            user_id: user.id,
+            role: role.to_string(),
+            created_at: Utc::now().naive_utc(),
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .map_err(Error::other)?;
+    let membership = get_membership(conn, organization_id, user.id).map_err(Error::other)?;
+
+    Ok(membership.map(|membership| (membership, user)))
+}
+
+/// Remove the user from the organization. Fails with
+/// `ErrorKind::InvalidInput` for the owner, who has to transfer the
+/// ownership first.
+pub fn remove_member(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<(Membership, User)>, Error> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let result = memberships::table
+            .inner_join(users::table)
+            .filter(memberships::organization_id.eq(organization_id))
+            .filter(memberships::user_id.eq(user_id))
+            .for_update()
+            .select((Membership::as_select(), User::as_select()))
+            .first::<(Membership, User)>(conn)
+            .optional()?;
+        let (membership, user) = match result {
+            Some(result) => result,
+            None => return Ok(Ok(None)),
+        };
+        if membership.role == OWNER {
+            return Ok(Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The owner cannot be removed",
+            )));
+        }
+
+        diesel::delete(memberships::table.find((organization_id, user_id))).execute(conn)?;
+
+        Ok(Ok(Some((membership, user))))
+    })
+    .map_err(Error::other)?
+}
+
+/// Make the member the owner of the organization, and the previous owner an
+/// admin. Fails with `ErrorKind::NotFound` if the user is not a member.
+pub fn transfer_ownership(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<Organization>, Error> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        // Lock the organization, so that transfers happen one at a time.
+        let organization = organizations::table
+            .find(organization_id)
+            .for_update()
+            .select(Organization::as_select())
+            .first(conn)
+            .optional()?;
+        let organization = match organization {
+            Some(organization) => organization,
+            None => return Ok(Ok(None)),
+        };
+        let membership = match get_membership(conn, organization_id, user_id)? {
+            Some(membership) => membership,
+            None => {
+                let message = "The user is not a member of the organization";
+                return Ok(Err(Error::new(ErrorKind::NotFound, message)));
+            }
+        };
+        if membership.role == OWNER {
+            return Ok(Ok(Some(organization)));
+        }
+
+        diesel::update(
+            memberships::table
+                .filter(memberships::organization_id.eq(organization_id))
+                .filter(memberships::role.eq(OWNER)),
+        )
+        .set(memberships::role.eq(ADMIN))
+        .execute(conn)?;
+        diesel::update(memberships::table.find((organization_id, user_id)))
+            .set(memberships::role.eq(OWNER))
+            .execute(conn)?;
+
+        Ok(Ok(Some(organization)))
+    })
+    .map_err(Error::other)?
+}
+
+/// List the members of the organization with their users, by user id.
+pub fn list_members(
+    conn: &mut PgConnection,
+    organization_id: Uuid,
+    after: Option<Uuid>,
+    limit: i64,
+) -> QueryResult<Vec<(Membership, User)>> {
+    let mut query = memberships::table
+        .inner_join(users::table)
+        .filter(memberships::organization_id.eq(organization_id))
+        .order(memberships::user_id)
+        .limit(limit)
+        .select((Membership::as_select(), User::as_select()))
+        .into_boxed();
+    if let Some(after) = after {
+        query = query.filter(memberships::user_id.gt(after));
+    }
+
+    query.load(conn)
+}
+
+/// List the memberships of the user with their organizations, by
+/// organization id.
+pub fn list_organizations(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    after: Option<Uuid>,
+    limit: i64,
+) -> QueryResult<Vec<(Membership, Organization)>> {
+    let mut query = memberships::table
+        .inner_join(organizations::table)
+        .filter(memberships::user_id.eq(user_id))
+        .order(memberships::organization_id)
+        .limit(limit)
+        .select((Membership::as_select(), Organization::as_select()))
+        .into_boxed();
+    if let Some(after) = after {
+        query = query.filter(memberships::organization_id.gt(after));
+    }
+
+    query.load(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::test::factory;
+    use diesel::Connection;
+
+    #[test]
+    fn test_create_organization() {
+        let owner = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        let membership = get_membership(&mut conn, organization.id, owner.id)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(organization.name, "Doe Inc.");
+        assert_eq!(membership.role, OWNER);
+        assert_eq!(
+            list_organizations(&mut conn, owner.id, None, 10)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_add_member() {
+        let owner = factory::insert_user();
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+
+        let (membership, member) =
+            add_member(&mut conn, organization.id, &user.email_address, MEMBER)
+                .unwrap()
+                .unwrap();
+        add_member(&mut conn, organization.id, &user.email_address, ADMIN).unwrap();
+        let error = add_member(&mut conn, organization.id, "nobody@doe.com", MEMBER).unwrap_err();
+        let members = list_members(&mut conn, organization.id, None, 10).unwrap();
+
+        assert_eq!(member.id, user.id);
+        assert_eq!(membership.role, MEMBER);
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(members.len(), 2);
+        assert_eq!(
+            list_members(&mut conn, organization.id, Some(members[0].1.id), 10)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_remove_member() {
+        let owner = factory::insert_user();
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        add_member(&mut conn, organization.id, &user.email_address, MEMBER).unwrap();
+
+        let error = remove_member(&mut conn, organization.id, owner.id).unwrap_err();
+        remove_member(&mut conn, organization.id, user.id)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(
+            get_membership(&mut conn, organization.id, user.id).unwrap(),
+            None
+        );
+        assert_eq!(
+            remove_member(&mut conn, organization.id, user.id).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_transfer_ownership() {
+        let owner = factory::insert_user();
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+
+        let error = transfer_ownership(&mut conn, organization.id, user.id).unwrap_err();
+        add_member(&mut conn, organization.id, &user.email_address, MEMBER).unwrap();
+        transfer_ownership(&mut conn, organization.id, user.id)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        let previous = get_membership(&mut conn, organization.id, owner.id)
+            .unwrap()
+            .unwrap();
+        let current = get_membership(&mut conn, organization.id, user.id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(previous.role, ADMIN);
+        assert_eq!(current.role, OWNER);
+    }
+}
@@ -0,0 +1,407 @@
+//! The transactional outbox: events recorded with the changes they describe,
+//! and relayed to a sink afterwards.
+//!
+//! A single relay runs at a time, across all replicas, holding the advisory
+//! lock `RELAY_LOCK`. The events are delivered in the order they were
+//! recorded, and a failed event holds back the ones after it, so batches
+//! cannot be claimed with `FOR UPDATE SKIP LOCKED` like jobs are: a second
+//! relay would skip the events in flight and deliver the later ones first.
+//! The throughput of the outbox is that of one relay, and another replica
+//! takes over once the lock is released.
+
+use crate::core::encryption::decrypt_fields;
+use crate::core::models::schema::outbox;
+use crate::core::models::schema::outbox::dsl::*;
+use crate::core::models::NewOutboxEvent;
+use crate::core::models::OutboxEvent;
+use chrono::Duration;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use diesel::prelude::*;
+use diesel::result::Error::SerializationError;
+use diesel::sql_types::BigInt;
+use diesel::PgConnection;
+use serde::Serialize;
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::time::sleep;
+use tracing::info;
+use tracing::warn;
+use uuid::Uuid;
+
+/// The number of events delivered per batch.
+const BATCH_SIZE: i64 = 100;
+
+/// The wait between polls when the outbox is empty.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The longest wait before retrying a failed delivery.
+const MAX_BACKOFF_SECONDS: i64 = 3600;
+
+/// The number of failed deliveries after which an event is dead.
+const MAX_ATTEMPTS: i32 = 10;
+
+/// The longest wait for a webhook to respond.
+const HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// The advisory lock held by the relay.
+const RELAY_LOCK: i64 = 0x7270_675f_6f75_7462;
+
+define_sql_function! { fn pg_try_advisory_lock(key: BigInt) -> Bool; }
+define_sql_function! { fn pg_advisory_unlock(key: BigInt) -> Bool; }
+
+/// A destination for outbox events.
+pub trait Sink: Send + Sync {
+    /// Deliver the event, or return an error to retry it later.
+    fn deliver(&self, event: &OutboxEvent) -> Result<(), Error>;
+}
+
+/// Write events to the log.
+pub struct LogSink;
+
+impl Sink for LogSink {
+    fn deliver(&self, event: &OutboxEvent) -> Result<(), Error> {
+        info!(
+            "Delivered event {} {} {}",
+            event.id, event.event_type, event.payload
+        );
+        Ok(())
+    }
+}
+
+/// Append events as JSON lines to a file.
+pub struct FileSink {
+    pub path: PathBuf,
+}
+
+impl Sink for FileSink {
+    fn deliver(&self, event: &OutboxEvent) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(file, "{}", envelope(event)?)
+    }
+}
+
+/// Post events as JSON to a webhook URL.
+pub struct HttpSink {
+    pub url: String,
+}
+
+impl Sink for HttpSink {
+    fn deliver(&self, event: &OutboxEvent) -> Result<(), Error> {
+        ureq::post(&self.url)
+            .timeout(HTTP_TIMEOUT)
+            .set("Content-Type", "application/json")
+            .set("Idempotency-Key", &event.id.to_string())
+            .send_string(&envelope(event)?.to_string())
+            .map(|_| ())
+            .map_err(|e| Error::other(e.to_string()))
+    }
+}
+
+/// Build a sink from its configuration: `log`, `file:<path>` or an HTTP(S) URL.
+pub fn build_sink(target: &str) -> Arc<dyn Sink> {
+    if target == "log" {
+        Arc::new(LogSink)
+    } else if let Some(path) = target.strip_prefix("file:") {
+        Arc::new(FileSink { path: path.into() })
+    } else if target.starts_with("http://") || target.starts_with("https://") {
+        Arc::new(HttpSink {
+            url: target.to_string(),
+        })
+    } else {
+        panic!("Unknown outbox sink: {}", target)
+    }
+}
+
+/// Wrap the event payload, with its personal data decrypted, with its metadata.
+fn envelope(event: &OutboxEvent) -> Result<serde_json::Value, Error> {
+    Ok(json!({
+        "id": event.id,
+        "type": event.event_type,
+        "aggregateId": event.aggregate_id,
+        "createdAt": event.created_at.and_utc().to_rfc3339(),
+        "data": decrypt_fields(event.payload.clone())?,
+    }))
+}
+
+/// Add an event to the outbox, in the same transaction as the change it records.
+pub fn enqueue(
+    conn: &mut PgConnection,
+    kind: &str,
+    aggregate: Uuid,
+    data: &impl Serialize,
+) -> QueryResult<()> {
+    let timestamp = Utc::now().naive_utc();
+    let event = NewOutboxEvent {
+        event_type: kind.to_string(),
+        aggregate_id: aggregate,
+        payload: serde_json::to_value(data).map_err(|e| SerializationError(Box::new(e)))?,
+        created_at: timestamp,
+        next_attempt_at: timestamp,
+    };
+
+    diesel::insert_into(outbox::table)
+        .values(&event)
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Deliver a batch of pending events in order, returning the number delivered,
+/// or `None` when another relay holds the lock.
+///
+/// A single relay runs at a time, across all replicas, so that events are
+/// delivered in order. It holds an advisory lock rather than row locks, and
+/// records each delivery on its own, so that no transaction stays open while
+/// the sink is called.
+pub fn relay_batch(conn: &mut PgConnection, sink: &dyn Sink) -> QueryResult<Option<usize>> {
+    if !diesel::select(pg_try_advisory_lock(RELAY_LOCK)).get_result::<bool>(conn)? {
+        return Ok(None);
+    }
+    let result = deliver_batch(conn, sink);
+    diesel::select(pg_advisory_unlock(RELAY_LOCK)).get_result::<bool>(conn)?;
+
+    result.map(Some)
+}
+
+/// Deliver a batch of pending events in order, returning the number delivered.
+///
+/// A failed event is retried with exponential backoff, and holds back the
+/// events after it so that they are never delivered out of order, until it
+/// fails `MAX_ATTEMPTS` times and is set aside as dead.
+fn deliver_batch(conn: &mut PgConnection, sink: &dyn Sink) -> QueryResult<usize> {
+    let timestamp = Utc::now().naive_utc();
+    let events = outbox
+        .filter(delivered_at.is_null())
+        .filter(dead_at.is_null())
+        .order(id.asc())
+        .limit(BATCH_SIZE)
+        .select(OutboxEvent::as_select())
+        .load(conn)?;
+    let mut delivered = 0;
+
+    for event in events {
+        if event.next_attempt_at > timestamp {
+            break;
+        }
+
+        match sink.deliver(&event) {
+            Ok(()) => {
+                diesel::update(outbox.find(event.id))
+                    .set(delivered_at.eq(Utc::now().naive_utc()))
+                    .execute(conn)?;
+                delivered += 1;
+            }
+            Err(e) if event.attempts + 1 >= MAX_ATTEMPTS => {
+                warn!(
+                    "Gave up on event {} after {} attempts: {}",
+                    event.id, MAX_ATTEMPTS, e
+                );
+                diesel::update(outbox.find(event.id))
+                    .set((
+                        attempts.eq(event.attempts + 1),
+                        dead_at.eq(Utc::now().naive_utc()),
+                        last_error.eq(e.to_string()),
+                    ))
+                    .execute(conn)?;
+            }
+            Err(e) => {
+                warn!("Failed to deliver event {}: {}", event.id, e);
+                diesel::update(outbox.find(event.id))
+                    .set((
+                        attempts.eq(event.attempts + 1),
+                        next_attempt_at.eq(timestamp + backoff(event.attempts)),
+                        last_error.eq(e.to_string()),
+                    ))
+                    .execute(conn)?;
+                break;
+            }
+        }
+    }
+
+    Ok(delivered)
+}
+
+/// Get the wait before the next attempt, doubling with every failed attempt.
+pub fn backoff(failed_attempts: i32) -> Duration {
+    Duration::seconds(
+        2_i64
+            .saturating_pow(failed_attempts as u32)
+            .min(MAX_BACKOFF_SECONDS),
+    )
+}
+
+/// Relay pending events to the sink until the process exits.
+pub async fn relay(pool: Pool, sink: Arc<dyn Sink>) {
+    loop {
+        let sink = sink.clone();
+        let result = match pool.get().await {
+            Ok(conn) => conn
+                .interact(move |conn| relay_batch(conn, sink.as_ref()))
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(Ok(Some(delivered))) if delivered > 0 => continue,
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("Failed to relay outbox: {}", e),
+            Err(e) => warn!("Failed to relay outbox: {}", e),
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::test::factory;
+    use diesel::Connection;
+    use std::sync::Mutex;
+
+    /// Collect the delivered events, failing while `fail` is set.
+    #[derive(Default)]
+    struct TestSink {
+        fail: bool,
+        delivered: Mutex<Vec<i64>>,
+    }
+
+    impl Sink for TestSink {
+        fn deliver(&self, event: &OutboxEvent) -> Result<(), Error> {
+            if self.fail {
+                return Err(Error::other("unavailable"));
+            }
+            self.delivered.lock().unwrap().push(event.id);
+            Ok(())
+        }
+    }
+
+    fn pending_events(conn: &mut PgConnection, aggregate: Uuid) -> Vec<OutboxEvent> {
+        outbox
+            .filter(aggregate_id.eq(aggregate))
+            .order(id.asc())
+            .select(OutboxEvent::as_select())
+            .load(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_enqueue_with_user() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let events = pending_events(&mut conn, user.id);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "user.created");
+        assert_ne!(events[0].payload["email_address"], user.email_address);
+        assert_eq!(
+            envelope(&events[0]).unwrap()["data"]["email_address"],
+            user.email_address
+        );
+    }
+
+    #[test]
+    fn test_relay_batch() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let sink = TestSink::default();
+
+        while deliver_batch(&mut conn, &sink).unwrap() > 0 {}
+        let events = pending_events(&mut conn, user.id);
+
+        assert!(sink.delivered.lock().unwrap().contains(&events[0].id));
+        assert_ne!(events[0].delivered_at, None);
+    }
+
+    #[test]
+    fn test_relay_batch_failed() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        conn.begin_test_transaction().unwrap();
+        diesel::update(outbox.filter(delivered_at.is_null()))
+            .set(delivered_at.eq(Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .unwrap();
+        let aggregate = Uuid::now_v7();
+        enqueue(&mut conn, "test.failed", aggregate, &json!({})).unwrap();
+        let sink = TestSink {
+            fail: true,
+            ..Default::default()
+        };
+
+        assert_eq!(deliver_batch(&mut conn, &sink).unwrap(), 0);
+        let events = pending_events(&mut conn, aggregate);
+
+        assert_eq!(events[0].delivered_at, None);
+        assert_eq!(events[0].attempts, 1);
+        assert_eq!(events[0].last_error, Some("unavailable".to_string()));
+        assert!(events[0].next_attempt_at > events[0].created_at);
+    }
+
+    #[test]
+    fn test_relay_batch_dead() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        conn.begin_test_transaction().unwrap();
+        diesel::update(outbox.filter(delivered_at.is_null()))
+            .set(delivered_at.eq(Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .unwrap();
+        let dead = Uuid::now_v7();
+        enqueue(&mut conn, "test.dead", dead, &json!({})).unwrap();
+        diesel::update(outbox.filter(aggregate_id.eq(dead)))
+            .set(attempts.eq(MAX_ATTEMPTS - 1))
+            .execute(&mut conn)
+            .unwrap();
+        let next = Uuid::now_v7();
+        enqueue(&mut conn, "test.next", next, &json!({})).unwrap();
+        let sink = TestSink {
+            fail: true,
+            ..Default::default()
+        };
+
+        assert_eq!(deliver_batch(&mut conn, &sink).unwrap(), 0);
+        let events = pending_events(&mut conn, dead);
+        assert_ne!(events[0].dead_at, None);
+        assert_eq!(events[0].attempts, MAX_ATTEMPTS);
+
+        let events = pending_events(&mut conn, next);
+        assert_eq!(events[0].dead_at, None);
+        assert_eq!(events[0].attempts, 1);
+    }
+
+    #[test]
+    fn test_relay_batch_locked() {
+        let config = config::get_config();
+        let mut leader = PgConnection::establish(&config.database_url).unwrap();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let sink = TestSink::default();
+
+        assert!(diesel::select(pg_try_advisory_lock(RELAY_LOCK))
+            .get_result::<bool>(&mut leader)
+            .unwrap());
+        assert_eq!(relay_batch(&mut conn, &sink).unwrap(), None);
+        assert!(sink.delivered.lock().unwrap().is_empty());
+        diesel::select(pg_advisory_unlock(RELAY_LOCK))
+            .get_result::<bool>(&mut leader)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), Duration::seconds(1));
+        assert_eq!(backoff(3), Duration::seconds(8));
+        assert_eq!(backoff(100), Duration::seconds(MAX_BACKOFF_SECONDS));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::core::encryption::blind_index;
+use crate::core::mailer::Email;
+use crate::core::mailer::Mailer;
+use crate::core::models::schema::user_credentials;
+use crate::core::models::schema::users;
+use crate::core::models::User;
+use crate::core::models::UserCredential;
+use crate::core::sessions;
+use crate::core::tokens;
+use crate::core::tokens::PASSWORD_RESET;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::Argon2;
+use argon2::PasswordHash;
+use argon2::PasswordHasher;
+use argon2::PasswordVerifier;
+use chrono::Duration;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::io::Error;
+use tracing::warn;
+use uuid::Uuid;
+
+/// The minimum number of characters of a password.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// The time a password reset token stays valid.
+const RESET_TTL: Duration = Duration::hours(1);
+
+/// The number of password resets that can be requested for an email address
+/// within the throttle window.
+const MAX_RESET_REQUESTS: i64 = 3;
+
+/// The window over which password reset requests are throttled.
+const RESET_THROTTLE_WINDOW: Duration = Duration::hours(1);
+
+/// Hash the password with Argon2id and a random salt, in the PHC string format.
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| Error::other(e.to_string()))
+}
+
+/// Check the password against a hash produced by `hash_password`.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Set the password hash of the user, replacing the previous one.
+pub fn set_password_hash(conn: &mut PgConnection, user_id: Uuid, hash: &str) -> QueryResult<()> {
+    let timestamp = Utc::now().naive_utc();
+
+    diesel::insert_into(user_credentials::table)
+        .values(&UserCredential {
+            user_id,
+            password_hash: hash.to_string(),
+            created_at: timestamp,
+            updated_at: timestamp,
+        })
+        .on_conflict(user_credentials::user_id)
+        .do_update()
+        .set((
+            user_credentials::password_hash.eq(hash),
+            user_credentials::updated_at.eq(timestamp),
+        ))
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Mail a password reset token to the user with the email address, if there
+/// is one and it has not requested too many resets lately. The outcome is
+/// the same either way, so that it does not reveal whether the address is
+/// known.
+pub fn request_password_reset(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    email_address: &str,
+) -> Result<(), Error> {
+    let user = users::table
+        .filter(users::email_hash.eq(blind_index(email_address)))
+        .filter(users::deleted_at.is_null())
+        .order(users::created_at)
+        .select(User::as_select())
+        .first(conn)
+        .optional()
+        .map_err(Error::other)?;
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    let since = Utc::now().naive_utc() - RESET_THROTTLE_WINDOW;
+    let count =
+        tokens::count_issued_since(conn, user.id, PASSWORD_RESET, since).map_err(Error::other)?;
+    if count >= MAX_RESET_REQUESTS {
+        warn!("Throttled password reset for user {}", user.id);
+        return Ok(());
+    }
+
+    let token =
+        tokens::issue_token(conn, user.id, PASSWORD_RESET, RESET_TTL).map_err(Error::other)?;
+    let email = Email {
+        to: user.email_address,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Use this token to reset your password within 1 hour:\n\n{}",
+            token
+        ),
+    };
+    if let Err(e) = mailer.send(&email) {
+        warn!("Failed to send password reset to user {}: {:?}", user.id, e);
+    }
+
+    Ok(())
+}
+
+/// Set a new password for the user of the token, consuming the token and
+/// revoking all the sessions of the user.
+pub fn reset_password(
+    conn: &mut PgConnection,
+    token: &str,
+    new_password: &str,
+) -> Result<Option<User>, Error> {
+    let hash = hash_password(new_password)?;
+
+    // TODO: Handle all the errors
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let user_id = match tokens::consume_token(conn, PASSWORD_RESET, token)? {
+                Some(user_id) => user_id,
+                None => return Ok(None),
+            };
+            let user = users::table
+                .find(user_id)
+                .filter(users::deleted_at.is_null())
+                .select(User::as_select())
+                .first(conn)?;
+            set_password_hash(conn, user.id, &hash)?;
+            sessions::revoke_sessions(conn, user.id)?;
+            Ok(Some(user))
+        })
+        .optional();
+
+    match result {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::mailer::MemoryMailer;
+    use crate::test::factory;
+    use diesel::Connection;
+
+    /// Get the token from the last email sent to the user.
+    fn sent_token(mailer: &MemoryMailer, user: &User) -> String {
+        let email = mailer.last_sent_to(&user.email_address).unwrap();
+
+        email.body.split_whitespace().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_hash_password() {
+        let hash = hash_password("correct horse").unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "invalid"));
+    }
+
+    #[test]
+    fn test_reset_password() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+        set_password_hash(&mut conn, user.id, &hash_password("old password").unwrap()).unwrap();
+        sessions::create_session(&mut conn, user.id).unwrap();
+        request_password_reset(&mut conn, &mailer, &user.email_address).unwrap();
+        let token = sent_token(&mailer, &user);
+
+        let result = reset_password(&mut conn, &token, "new password")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.id, user.id);
+        assert_eq!(
+            sessions::sign_in(&mut conn, &user.email_address, "old password").unwrap(),
+            None
+        );
+        assert_ne!(
+            sessions::sign_in(&mut conn, &user.email_address, "new password").unwrap(),
+            None
+        );
+        assert_eq!(sessions::revoke_sessions(&mut conn, user.id).unwrap(), 1);
+        assert_eq!(
+            reset_password(&mut conn, &token, "other password").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_request_password_reset_unknown_email() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+
+        request_password_reset(&mut conn, &mailer, "nobody@doe.com").unwrap();
+
+        assert!(mailer.sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_request_password_reset_throttled() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+
+        for _ in 0..MAX_RESET_REQUESTS + 1 {
+            request_password_reset(&mut conn, &mailer, &user.email_address).unwrap();
+        }
+
+        assert_eq!(
+            mailer.sent.lock().unwrap().len(),
+            MAX_RESET_REQUESTS as usize
+        );
+    }
+}
@@ -0,0 +1,459 @@
+use crate::core::encryption::blind_index;
+use crate::core::encryption::decrypt_json;
+use crate::core::encryption::Encrypted;
+use crate::core::exports::regenerate_exports;
+use crate::core::models::schema::audit_events;
+use crate::core::models::schema::email_changes;
+use crate::core::models::schema::idempotency_keys;
+use crate::core::models::schema::invitations;
+use crate::core::models::schema::memberships;
+use crate::core::models::schema::organizations;
+use crate::core::models::schema::roles;
+use crate::core::models::schema::sessions;
+use crate::core::models::schema::user_credentials;
+use crate::core::models::schema::user_roles;
+use crate::core::models::schema::user_tokens;
+use crate::core::models::schema::users;
+use crate::core::models::schema::users_history;
+use crate::core::models::AuditEvent;
+use crate::core::models::Session;
+use crate::core::models::User;
+use crate::core::models::UserVersion;
+use crate::core::users::enqueue_change;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel::PgConnection;
+use serde_json::json;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Collect everything held about the user, for a data subject access
+/// request: its profile and past versions, sessions, memberships, roles,
+/// pending email change, and the audit events about it or by it. Secrets,
+/// like password and token hashes, are left out, and so are the changes made
+/// to other users by the user: only what was done to whom, and when.
+pub fn export_user_data(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Option<Value>> {
+    let user = match users::table
+        .find(user_id)
+        .select(User::as_select())
+        .first(conn)
+        .optional()?
+    {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+    let versions = users_history::table
+        .filter(users_history::id.eq(user_id))
+        .order(users_history::version_id)
+        .select(UserVersion::as_select())
+        .load(conn)?;
+    let sessions = sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .order(sessions::created_at)
+        .select(Session::as_select())
+        .load(conn)?;
+    let memberships = memberships::table
+        .inner_join(organizations::table)
+        .filter(memberships::user_id.eq(user_id))
+        .order(memberships::created_at)
+        .select((
+            organizations::id,
+            organizations::name,
+            memberships::role,
+            memberships::created_at,
+        ))
+        .load::<(Uuid, String, String, NaiveDateTime)>(conn)?;
+    let roles = user_roles::table
+        .inner_join(roles::table)
+        .filter(user_roles::user_id.eq(user_id))
+        .order(roles::name)
+        .select((roles::name, user_roles::created_at))
+        .load::<(String, NaiveDateTime)>(conn)?;
+    let email_change = email_changes::table
+        .find(user_id)
+        .select((email_changes::email_address, email_changes::created_at))
+        .first::<(Encrypted, NaiveDateTime)>(conn)
+        .optional()?
+        .map(|(Encrypted(email_address), created_at)| (email_address, created_at));
+    let events = audit_events::table
+        .filter(
+            audit_events::target_type
+                .eq("user")
+                .and(audit_events::target_id.eq(user_id))
+                .or(audit_events::actor_id.eq(user_id)),
+        )
+        .order(audit_events::id)
+        .select(AuditEvent::as_select())
+        .load(conn)?;
+
+    Ok(Some(json!({
+        "exported_at": Utc::now().naive_utc(),
+        "profile": user,
+        "versions": versions.iter().map(|version| json!({
+            "version": version.version,
+            "first_name": version.first_name,
+            "last_name": version.last_name,
+            "email_address": version.email_address,
+            "deleted_at": version.deleted_at,
+            "email_verified_at": version.email_verified_at,
+            "valid_from": version.valid_from,
+            "valid_to": version.valid_to,
+        })).collect::<Vec<_>>(),
+        "sessions": sessions.iter().map(|session| json!({
+            "id": session.id,
+            "created_at": session.created_at,
+            "expires_at": session.expires_at,
+            "revoked_at": session.revoked_at,
+        })).collect::<Vec<_>>(),
+        "memberships": memberships.iter().map(|(id, name, role, created_at)| json!({
+            "organization_id": id,
+            "organization_name": name,
+            "role": role,
+            "created_at": created_at,
+        })).collect::<Vec<_>>(),
+        "roles": roles.iter().map(|(name, created_at)| json!({
+            "name": name,
+            "created_at": created_at,
+        })).collect::<Vec<_>>(),
+        "email_change": email_change.map(|(email_address, created_at)| json!({
+            "email_address": email_address,
+            "created_at": created_at,
+        })),
+        "audit_events": events.iter().map(|event| match event.target_id == user_id {
+            true => json!({
+                "id": event.id,
+                "actor_id": event.actor_id,
+                "action": event.action,
+                "target_type": event.target_type,
+                "target_id": event.target_id,
+                "before": event.before.clone().map(decrypt_json),
+                "after": event.after.clone().map(decrypt_json),
+                "request_id": event.request_id,
+                "ip_address": event.ip_address,
+                "created_at": event.created_at,
+            }),
+            false => json!({
+                "id": event.id,
+                "action": event.action,
+                "target_type": event.target_type,
+                "target_id": event.target_id,
+                "created_at": event.created_at,
+            }),
+        }).collect::<Vec<_>>(),
+    })))
+}
+
+/// Erase the personal data of the user in place, for a data subject erasure
+/// request. The row is kept, anonymized and deleted, so that what refers to
+/// it stays valid. Its sessions, credentials, tokens, idempotency keys and
+/// past versions are removed, and the personal data is scrubbed from its audit events, from the
+/// events queued about it and from the invitations sent to its addresses,
+/// which are revoked. The exports of the tenant that may hold it are written
+/// again. The erasure itself is audited as `user.erased`, without the data it
+/// removed.
+pub fn erase_user(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Option<User>> {
+    let timestamp = Utc::now().naive_utc();
+
+    conn.transaction(|conn| {
+        diesel::sql_query("SELECT set_config('app.erased_user_id', $1, true)")
+            .bind::<Text, _>(user_id.to_string())
+            .execute(conn)?;
+        let (current, tenant_id) = match users::table
+            .find(user_id)
+            .select((User::as_select(), users::tenant_id))
+            .for_update()
+            .first::<(User, Uuid)>(conn)
+            .optional()?
+        {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let mut addresses: Vec<String> = users_history::table
+            .filter(users_history::id.eq(user_id))
+            .select(UserVersion::as_select())
+            .load(conn)?
+            .into_iter()
+            .map(|version| version.email_address)
+            .collect();
+        addresses.push(current.email_address);
+        addresses.extend(
+            email_changes::table
+                .find(user_id)
+                .select(email_changes::email_address)
+                .first::<Encrypted>(conn)
+                .optional()?
+                .map(String::from),
+        );
+        let hashes: Vec<String> = addresses
+            .iter()
+            .map(|address| blind_index(address))
+            .collect();
+        let erased_address = format!("erased-{}@anonymized.invalid", user_id);
+        let user = diesel::update(users::table.find(user_id))
+            .set((
+                users::first_name.eq(Encrypted(String::new())),
+                users::last_name.eq(Encrypted(String::new())),
+                users::email_address.eq(Encrypted(erased_address.clone())),
+                users::email_hash.eq(None::<String>),
+                users::search_tokens.eq(None::<String>),
+                users::anonymized_at.eq(timestamp),
+                users::email_verified_at.eq(None::<NaiveDateTime>),
+                users::deleted_at.eq(current.deleted_at.unwrap_or(timestamp)),
+                users::updated_at.eq(timestamp),
+            ))
+            .returning(User::as_returning())
+            .get_result(conn)?;
+
+        diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id))).execute(conn)?;
+        diesel::delete(user_credentials::table.find(user_id)).execute(conn)?;
+        diesel::delete(user_tokens::table.filter(user_tokens::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::delete(email_changes::table.find(user_id)).execute(conn)?;
+        // The responses replayed for its retries hold its data.
+        diesel::delete(idempotency_keys::table.filter(idempotency_keys::viewer_id.eq(user_id)))
+            .execute(conn)?;
+        // The earlier versions still hold the personal data.
+        diesel::delete(
+            users_history::table
+                .filter(users_history::id.eq(user_id))
+                .filter(users_history::valid_to.is_not_null()),
+        )
+        .execute(conn)?;
+        diesel::sql_query("SELECT erase_user_audit_events($1)")
+            .bind::<diesel::sql_types::Uuid, _>(user_id)
+            .execute(conn)?;
+        diesel::sql_query(
+            "UPDATE outbox \
+             SET payload = payload - ARRAY['first_name', 'last_name', 'email_address'] \
+             WHERE aggregate_id = $1",
+        )
+        .bind::<diesel::sql_types::Uuid, _>(user_id)
+        .execute(conn)?;
+        diesel::sql_query(
+            "UPDATE webhook_deliveries \
+             SET payload = payload - ARRAY['first_name', 'last_name', 'email_address'] \
+             WHERE payload ->> 'id' = $1",
+        )
+        .bind::<Text, _>(user_id.to_string())
+        .execute(conn)?;
+        diesel::update(
+            invitations::table
+                .filter(invitations::email_hash.eq_any(&hashes))
+                .filter(invitations::accepted_at.is_null())
+                .filter(invitations::revoked_at.is_null()),
+        )
+        .set(invitations::revoked_at.eq(timestamp))
+        .execute(conn)?;
+        diesel::update(invitations::table.filter(invitations::email_hash.eq_any(&hashes)))
+            .set((
+                invitations::email_address.eq(Encrypted(erased_address)),
+                invitations::email_hash.eq(None::<String>),
+            ))
+            .execute(conn)?;
+        regenerate_exports(conn, tenant_id, current.created_at)?;
+        enqueue_change(conn, "user.updated", &user)?;
+
+        Ok(Some(user))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::audit;
+    use crate::core::audit::Actor;
+    use crate::core::exports;
+    use crate::core::exports::ExportFilter;
+    use crate::core::exports::ExportFormat;
+    use crate::core::idempotency;
+    use crate::core::invitations::create_invitation;
+    use crate::core::mailer::MemoryMailer;
+    use crate::core::models::schema::jobs;
+    use crate::core::models::schema::outbox;
+    use crate::core::models::schema::user_exports;
+    use crate::core::organizations::create_organization;
+    use crate::core::organizations::MEMBER;
+    use crate::core::repo;
+    use crate::core::repo::DEFAULT_TENANT;
+    use crate::core::sessions::create_session;
+    use crate::core::users;
+    use crate::core::users::UpdateUserAttrs;
+    use crate::test::factory;
+
+    fn connect() -> PgConnection {
+        PgConnection::establish(&config::get_config().database_url).unwrap()
+    }
+
+    #[test]
+    fn test_export_user_data() {
+        let user = factory::insert_user();
+        let mut conn = connect();
+        create_session(&mut conn, user.id).unwrap();
+
+        let data = export_user_data(&mut conn, user.id).unwrap().unwrap();
+
+        assert_eq!(data["profile"]["email_address"], user.email_address);
+        assert_eq!(data["versions"].as_array().unwrap().len(), 1);
+        assert_eq!(data["sessions"].as_array().unwrap().len(), 1);
+        assert_eq!(data["sessions"][0].get("token_hash"), None);
+        assert_eq!(data["audit_events"][0]["action"], "user.created");
+        assert_eq!(export_user_data(&mut conn, Uuid::now_v7()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_export_user_data_of_actor() {
+        let admin = factory::insert_user();
+        let user = factory::insert_user();
+        let mut conn = connect();
+        let email_address = format!("bobby.{}@doe.com", Uuid::now_v7().simple());
+        let attrs = UpdateUserAttrs {
+            first_name: Some("Bobby".to_string()),
+            last_name: None,
+            email_address: Some(email_address.clone()),
+        };
+        let actor = Actor {
+            user_id: Some(admin.id),
+            ..Actor::default()
+        };
+        audit::with_actor(&mut conn, &actor, |conn| {
+            users::update_user(conn, user.id, attrs, None)
+        })
+        .unwrap();
+
+        let data = export_user_data(&mut conn, admin.id).unwrap().unwrap();
+        let text = data.to_string();
+        let update = data["audit_events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|event| event["target_id"] == user.id.to_string())
+            .unwrap();
+
+        assert_eq!(update["action"], "user.updated");
+        assert_eq!(update.get("before"), None);
+        assert_eq!(update.get("after"), None);
+        assert!(!text.contains(&user.email_address));
+        assert!(!text.contains(&email_address));
+        assert!(!text.contains("Bobby"));
+    }
+
+    #[test]
+    fn test_erase_user() {
+        let user = factory::insert_user();
+        let admin = factory::insert_user();
+        let mut conn = connect();
+        let attrs = UpdateUserAttrs {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+        users::update_user(&mut conn, user.id, attrs, None).unwrap();
+        create_session(&mut conn, user.id).unwrap();
+        let actor = Actor {
+            user_id: Some(admin.id),
+            ..Actor::default()
+        };
+
+        let erased = audit::with_actor(&mut conn, &actor, |conn| erase_user(conn, user.id))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(erased.first_name, "");
+        assert_eq!(
+            erased.email_address,
+            format!("erased-{}@anonymized.invalid", user.id)
+        );
+        assert_ne!(erased.deleted_at, None);
+
+        let data = export_user_data(&mut conn, user.id).unwrap().unwrap();
+        let text = data.to_string();
+        assert!(!text.contains(&user.email_address));
+        assert!(!text.contains("Janet"));
+        assert_eq!(data["versions"].as_array().unwrap().len(), 1);
+        assert!(data["sessions"].as_array().unwrap().is_empty());
+
+        let erasure = data["audit_events"]
+            .as_array()
+            .unwrap()
+            .last()
+            .unwrap()
+            .clone();
+        assert_eq!(erasure["action"], "user.erased");
+        assert_eq!(erasure["actor_id"], admin.id.to_string());
+        assert_eq!(erasure["after"].get("email_address"), None);
+
+        let payloads: Vec<Value> = outbox::table
+            .filter(outbox::aggregate_id.eq(user.id))
+            .select(outbox::payload)
+            .load(&mut conn)
+            .unwrap();
+        assert!(payloads
+            .iter()
+            .all(|payload| !payload.to_string().contains("Janet")));
+        assert_eq!(erase_user(&mut conn, Uuid::now_v7()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_erase_user_invitations_and_exports() {
+        let owner = factory::insert_user();
+        let user = factory::insert_user();
+        let mut conn = connect();
+        let organization = create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        let invitation = create_invitation(
+            &mut conn,
+            &MemoryMailer::default(),
+            organization.id,
+            owner.id,
+            &user.email_address.to_uppercase(),
+            MEMBER,
+        )
+        .unwrap()
+        .unwrap();
+        idempotency::claim_key(&mut conn, user.id, "key", "hash").unwrap();
+        let export = repo::with_tenant(&mut conn, DEFAULT_TENANT, |conn| {
+            exports::request_export(conn, ExportFormat::Csv, &ExportFilter::default(), None)
+        })
+        .unwrap();
+        diesel::update(user_exports::table.find(export.id))
+            .set(user_exports::status.eq(exports::COMPLETED))
+            .execute(&mut conn)
+            .unwrap();
+
+        erase_user(&mut conn, user.id).unwrap().unwrap();
+        let (email_address, revoked_at) = invitations::table
+            .find(invitation.id)
+            .select((invitations::email_address, invitations::revoked_at))
+            .first::<(Encrypted, Option<NaiveDateTime>)>(&mut conn)
+            .unwrap();
+        let status = user_exports::table
+            .find(export.id)
+            .select(user_exports::status)
+            .first::<String>(&mut conn)
+            .unwrap();
+        let idempotency_keys = idempotency_keys::table
+            .filter(idempotency_keys::viewer_id.eq(user.id))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        let export_jobs = jobs::table
+            .filter(jobs::name.eq("exports.export_users"))
+            .filter(jobs::payload.eq(serde_json::json!({ "export_id": export.id })))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+
+        assert_eq!(
+            email_address.0,
+            format!("erased-{}@anonymized.invalid", user.id)
+        );
+        assert_ne!(revoked_at, None);
+        assert_eq!(idempotency_keys, 0);
+        assert_eq!(status, exports::PENDING);
+        assert_eq!(export_jobs, 2);
+    }
+}
@@ -2,7 +2,48 @@ use deadpool_diesel::Manager;
 use deadpool_diesel::Pool;
 use deadpool_diesel::Runtime::Tokio1;
 use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use futures_util::stream::poll_fn;
+use futures_util::StreamExt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+use tokio_postgres::AsyncMessage;
+use tokio_postgres::NoTls;
 use tracing::info;
+use tracing::warn;
+use uuid::Uuid;
+
+/// The longest wait between attempts to reconnect the listener.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The tenant of the data created outside of a tenant, which anonymous
+/// requests are scoped to.
+pub const DEFAULT_TENANT: Uuid = Uuid::nil();
+
+/// The role that the row-level security policies apply to.
+const TENANT_ROLE: &str = "app_tenant";
+
+/// A result that can carry the failure to scope a transaction, to a tenant
+/// or an actor.
+pub trait ScopedResult {
+    fn from_query_error(error: diesel::result::Error) -> Self;
+}
+
+impl<T> ScopedResult for QueryResult<T> {
+    fn from_query_error(error: diesel::result::Error) -> Self {
+        Err(error)
+    }
+}
+
+impl<T> ScopedResult for Result<T, std::io::Error> {
+    fn from_query_error(error: diesel::result::Error) -> Self {
+        Err(std::io::Error::other(error))
+    }
+}
 
 /// Connect to the database.
 pub fn connect_database(database_url: &String) -> Pool<Manager<PgConnection>> {
@@ -15,3 +56,224 @@ pub fn connect_database(database_url: &String) -> Pool<Manager<PgConnection>> {
         .build()
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
 }
+
+/// Scope the current transaction to the tenant, until it ends: switch to the
+/// role subject to row-level security, which only shows the rows of the
+/// tenant in `app.tenant_id`.
+pub fn set_tenant(conn: &mut PgConnection, tenant_id: Uuid) -> QueryResult<()> {
+    diesel::sql_query(format!("SET LOCAL ROLE {}", TENANT_ROLE)).execute(conn)?;
+    diesel::sql_query("SELECT set_config('app.tenant_id', $1, true)")
+        .bind::<Text, _>(tenant_id.to_string())
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Run the function in a transaction scoped to the tenant. Its result is
+/// committed either way, like when the function runs on its own.
+pub fn with_tenant<R, F>(conn: &mut PgConnection, tenant_id: Uuid, f: F) -> R
+where
+    R: ScopedResult,
+    F: FnOnce(&mut PgConnection) -> R,
+{
+    conn.transaction(|conn| {
+        set_tenant(conn, tenant_id)?;
+        Ok(f(conn))
+    })
+    .unwrap_or_else(R::from_query_error)
+}
+
+/// Listen to the notifications on a channel with a dedicated connection.
+///
+/// The connection is re-established with backoff whenever it fails, so
+/// notifications sent while reconnecting are missed. Returns once the first
+/// connection is listening.
+pub async fn listen_database(database_url: &str, channel: &str) -> UnboundedReceiver<String> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let (ready, listening) = oneshot::channel();
+    let database_url = database_url.to_string();
+    let channel = channel.to_string();
+
+    tokio::spawn(async move {
+        let mut ready = Some(ready);
+        let mut backoff = Duration::from_secs(1);
+
+        while !sender.is_closed() {
+            let (client, mut connection) = match tokio_postgres::connect(&database_url, NoTls).await
+            {
+                Ok(connected) => connected,
+                Err(e) => {
+                    warn!("Failed to connect listener: {}", e);
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+            };
+            let notifications = sender.clone();
+            let messages = tokio::spawn(async move {
+                let mut messages = poll_fn(move |cx| connection.poll_message(cx));
+
+                while let Some(message) = messages.next().await {
+                    match message {
+                        Ok(AsyncMessage::Notification(notification)) => {
+                            if notifications
+                                .send(notification.payload().to_string())
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!("Listener connection failed: {}", e);
+                            break;
+                        }
+                    }
+                }
+            });
+
+            if let Err(e) = client.batch_execute(&format!("LISTEN {}", channel)).await {
+                warn!("Failed to listen to {}: {}", channel, e);
+                messages.abort();
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+
+            info!("Listening to {}", channel);
+            backoff = Duration::from_secs(1);
+            if let Some(ready) = ready.take() {
+                let _ = ready.send(());
+            }
+
+            // Keep the client alive for as long as the connection runs.
+            let _ = messages.await;
+            drop(client);
+            warn!("Lost listener connection to {}, reconnecting", channel);
+            sleep(backoff).await;
+        }
+    });
+
+    let _ = listening.await;
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::models::schema::tenants;
+    use crate::core::models::schema::users;
+    use crate::core::models::User;
+    use crate::core::users::create_user;
+    use crate::core::users::get_tenant_id;
+    use crate::core::users::CreateUserAttrs;
+    use chrono::Utc;
+
+    /// Create a tenant.
+    fn insert_tenant(conn: &mut PgConnection) -> Uuid {
+        diesel::insert_into(tenants::table)
+            .values((
+                tenants::id.eq(Uuid::now_v7()),
+                tenants::name.eq("Doe Inc."),
+                tenants::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .returning(tenants::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Create a user in the tenant.
+    fn insert_user_in(conn: &mut PgConnection, tenant_id: Uuid) -> User {
+        let attrs = CreateUserAttrs {
+            first_name: "Jane".to_string(),
+            last_name: "Doe".to_string(),
+            email_address: format!("jane.{}@doe.com", Uuid::now_v7().simple()),
+        };
+
+        with_tenant(conn, tenant_id, |conn| create_user(conn, attrs))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_with_tenant_isolation() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let tenant_a = insert_tenant(&mut conn);
+        let tenant_b = insert_tenant(&mut conn);
+        let user_a = insert_user_in(&mut conn, tenant_a);
+        let user_b = insert_user_in(&mut conn, tenant_b);
+
+        assert_eq!(get_tenant_id(&mut conn, user_a.id).unwrap(), Some(tenant_a));
+        assert_eq!(get_tenant_id(&mut conn, user_b.id).unwrap(), Some(tenant_b));
+
+        // Queries without a tenant filter still only see the rows of the tenant.
+        let ids = with_tenant(&mut conn, tenant_a, |conn| {
+            users::table.select(users::id).load::<Uuid>(conn)
+        })
+        .unwrap();
+        assert!(ids.contains(&user_a.id));
+        assert!(!ids.contains(&user_b.id));
+
+        let found = with_tenant(&mut conn, tenant_a, |conn| {
+            users::table
+                .find(user_b.id)
+                .select(users::id)
+                .first::<Uuid>(conn)
+                .optional()
+        })
+        .unwrap();
+        assert_eq!(found, None);
+
+        let updated = with_tenant(&mut conn, tenant_a, |conn| {
+            diesel::update(users::table.find(user_b.id))
+                .set(users::first_name.eq("Mallory"))
+                .execute(conn)
+        })
+        .unwrap();
+        let deleted = with_tenant(&mut conn, tenant_a, |conn| {
+            diesel::delete(users::table.find(user_b.id)).execute(conn)
+        })
+        .unwrap();
+        assert_eq!((updated, deleted), (0, 0));
+
+        // Outside of a tenant, every row is visible.
+        let found = users::table
+            .find(user_b.id)
+            .select(users::id)
+            .first::<Uuid>(&mut conn);
+        assert_eq!(found, Ok(user_b.id));
+    }
+
+    #[test]
+    fn test_with_tenant_rejects_other_tenant() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let tenant_a = insert_tenant(&mut conn);
+        let tenant_b = insert_tenant(&mut conn);
+        let user_a = insert_user_in(&mut conn, tenant_a);
+
+        let moved = with_tenant(&mut conn, tenant_a, |conn| {
+            diesel::update(users::table.find(user_a.id))
+                .set(users::tenant_id.eq(tenant_b))
+                .execute(conn)
+        });
+        let inserted = with_tenant(&mut conn, tenant_a, |conn| {
+            diesel::insert_into(users::table)
+                .values((
+                    User {
+                        id: Uuid::now_v7(),
+                        email_address: format!("john.{}@doe.com", Uuid::now_v7().simple()),
+                        ..user_a.clone()
+                    },
+                    users::tenant_id.eq(tenant_b),
+                ))
+                .execute(conn)
+        });
+
+        assert!(moved.is_err());
+        assert!(inserted.is_err());
+        assert_eq!(get_tenant_id(&mut conn, user_a.id).unwrap(), Some(tenant_a));
+    }
+}
@@ -0,0 +1,56 @@
+use crate::core::jobs::Job;
+use crate::core::users;
+use chrono::Duration;
+use chrono::Utc;
+use diesel::PgConnection;
+use serde::Deserialize;
+use serde::Serialize;
+use std::io::Error;
+use tracing::info;
+
+/// What happens to users once they have been deleted for the retention window.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionMode {
+    /// Remove the rows permanently.
+    Delete,
+    /// Keep the rows with their personal data removed.
+    Anonymize,
+}
+
+impl RetentionMode {
+    /// Parse the mode from its configuration: `delete` or `anonymize`.
+    pub fn parse(mode: &str) -> RetentionMode {
+        match mode {
+            "delete" => RetentionMode::Delete,
+            "anonymize" => RetentionMode::Anonymize,
+            _ => panic!("Unknown retention mode: {}", mode),
+        }
+    }
+}
+
+/// Purge the users deleted longer ago than the retention window.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PurgeDeletedUsers {
+    pub retention_days: i64,
+    pub mode: RetentionMode,
+}
+
+impl Job for PurgeDeletedUsers {
+    const NAME: &'static str = "retention.purge_deleted_users";
+
+    fn perform(&self, conn: &mut PgConnection) -> Result<(), Error> {
+        let before = Utc::now().naive_utc() - Duration::days(self.retention_days);
+        let result = match self.mode {
+            RetentionMode::Delete => users::purge_deleted_users(conn, before),
+            RetentionMode::Anonymize => users::anonymize_deleted_users(conn, before),
+        };
+        let count = result.map_err(Error::other)?;
+
+        info!(
+            "Purged {} users deleted before {} ({:?})",
+            count, before, self.mode
+        );
+        Ok(())
+    }
+}
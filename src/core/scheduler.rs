@@ -0,0 +1,281 @@
+use crate::config::get_config;
+use crate::core::idempotency::PurgeExpiredKeys;
+use crate::core::jobs;
+use crate::core::jobs::Job;
+use crate::core::models::schema::scheduled_tasks;
+use crate::core::retention::PurgeDeletedUsers;
+use crate::core::retention::RetentionMode;
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use cron::Schedule;
+use deadpool_diesel::postgres::Object;
+use deadpool_diesel::postgres::Pool;
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use diesel::PgConnection;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::info;
+use tracing::warn;
+
+/// The advisory lock held by the scheduler leader.
+const LEADER_LOCK: i64 = 0x7270_675f_7363_6864;
+
+/// The schedule of the purge of the expired idempotency keys: every hour.
+const IDEMPOTENCY_PURGE_SCHEDULE: &str = "0 0 * * * *";
+
+/// The wait between attempts to become the leader.
+const LEADER_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+define_sql_function! { fn pg_try_advisory_lock(key: BigInt) -> Bool; }
+define_sql_function! { fn pg_advisory_unlock(key: BigInt) -> Bool; }
+
+type Enqueue = Box<dyn Fn(&mut PgConnection) -> QueryResult<()> + Send + Sync>;
+
+/// A job enqueued on a cron schedule.
+struct Task {
+    name: &'static str,
+    schedule: Schedule,
+    enqueue: Enqueue,
+}
+
+/// The recurring jobs, enqueued by a single leader across all workers.
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<Task>,
+}
+
+impl Scheduler {
+    /// Enqueue the job on a cron expression with seconds, like `0 0 3 * * *`.
+    pub fn add<J: Job + Send + Sync + 'static>(mut self, expression: &str, job: J) -> Scheduler {
+        let schedule = Schedule::from_str(expression)
+            .unwrap_or_else(|e| panic!("Invalid schedule for {}: {:?}", J::NAME, e));
+
+        self.tasks.push(Task {
+            name: J::NAME,
+            schedule,
+            enqueue: Box::new(move |conn| jobs::enqueue(conn, &job).map(|_| ())),
+        });
+        self
+    }
+
+    /// Get the next time any task is due after the given time, with the tasks due then.
+    fn next_after(&self, after: &DateTime<Utc>) -> Option<(DateTime<Utc>, Vec<usize>)> {
+        let upcoming: Vec<(usize, DateTime<Utc>)> = self
+            .tasks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, task)| task.schedule.after(after).next().map(|at| (index, at)))
+            .collect();
+        let at = upcoming.iter().map(|(_, at)| *at).min()?;
+
+        Some((
+            at,
+            upcoming
+                .into_iter()
+                .filter(|(_, due)| *due == at)
+                .map(|(index, _)| index)
+                .collect(),
+        ))
+    }
+}
+
+/// Get the scheduler of every recurring job.
+pub fn scheduler() -> Scheduler {
+    let config = get_config();
+
+    Scheduler::default()
+        .add(
+            &config.retention_schedule,
+            PurgeDeletedUsers {
+                retention_days: config.retention_days,
+                mode: RetentionMode::parse(&config.retention_mode),
+            },
+        )
+        .add(IDEMPOTENCY_PURGE_SCHEDULE, PurgeExpiredKeys)
+}
+
+/// Try to become the leader, holding the lock for as long as the connection lives.
+fn try_lock(conn: &mut PgConnection) -> QueryResult<bool> {
+    diesel::select(pg_try_advisory_lock(LEADER_LOCK)).get_result(conn)
+}
+
+fn unlock(conn: &mut PgConnection) -> QueryResult<bool> {
+    diesel::select(pg_advisory_unlock(LEADER_LOCK)).get_result(conn)
+}
+
+/// Enqueue the tasks due by now, recording when they ran, and return their names.
+///
+/// A task is due when its schedule has a time between its last run and now,
+/// so that a run missed while there was no leader is caught up, once, by the
+/// next leader. A task that never ran is recorded as of now and not caught up.
+fn enqueue_due(
+    conn: &mut PgConnection,
+    scheduler: &Scheduler,
+    now: DateTime<Utc>,
+) -> QueryResult<Vec<&'static str>> {
+    conn.transaction(|conn| {
+        let mut enqueued = Vec::new();
+
+        for task in &scheduler.tasks {
+            diesel::insert_into(scheduled_tasks::table)
+                .values((
+                    scheduled_tasks::name.eq(task.name),
+                    scheduled_tasks::last_run_at.eq(now.naive_utc()),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            let last_run_at: NaiveDateTime = scheduled_tasks::table
+                .find(task.name)
+                .select(scheduled_tasks::last_run_at)
+                .first(conn)?;
+
+            let due = task
+                .schedule
+                .after(&last_run_at.and_utc())
+                .next()
+                .is_some_and(|at| at <= now);
+            if due {
+                (task.enqueue)(conn)?;
+                diesel::update(scheduled_tasks::table.find(task.name))
+                    .set(scheduled_tasks::last_run_at.eq(now.naive_utc()))
+                    .execute(conn)?;
+                enqueued.push(task.name);
+            }
+        }
+
+        Ok(enqueued)
+    })
+}
+
+/// Enqueue the due tasks on the leader connection until it fails.
+///
+/// Enqueueing through the connection that holds the lock means that a leader
+/// that lost its connection can never enqueue next to the one that replaced it.
+async fn lead(conn: &Object, scheduler: Arc<Scheduler>) {
+    loop {
+        let now = Utc::now();
+        let tasks = scheduler.clone();
+        let result = conn
+            .interact(move |conn| enqueue_due(conn, &tasks, now))
+            .await;
+
+        match result {
+            Ok(Ok(names)) => {
+                for name in names {
+                    info!("Scheduled {}", name);
+                }
+            }
+            Ok(Err(e)) => {
+                warn!("Failed to schedule jobs: {}", e);
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to schedule jobs: {}", e);
+                return;
+            }
+        }
+
+        let now = Utc::now();
+        let at = match scheduler.next_after(&now) {
+            Some((at, _)) => at,
+            None => return,
+        };
+        sleep((at - now).to_std().unwrap_or_default()).await;
+    }
+}
+
+/// Run the scheduler until the process exits. Only the worker holding the
+/// advisory lock enqueues jobs, and the others take over if it goes away.
+pub async fn start_scheduler(pool: Pool, scheduler: Scheduler) {
+    let scheduler = Arc::new(scheduler);
+
+    loop {
+        if let Ok(conn) = pool.get().await {
+            if let Ok(Ok(true)) = conn.interact(try_lock).await {
+                info!("Scheduler is the leader");
+                lead(&conn, scheduler.clone()).await;
+                // Release the lock before the connection goes back to the pool.
+                let _ = conn.interact(unlock).await;
+            }
+        }
+        sleep(LEADER_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use chrono::TimeZone;
+    use diesel::Connection;
+
+    #[test]
+    fn test_next_after() {
+        let job = PurgeDeletedUsers {
+            retention_days: 30,
+            mode: RetentionMode::Delete,
+        };
+        let scheduler = Scheduler::default()
+            .add("0 0 3 * * *", job.clone())
+            .add("0 0 * * * *", job.clone())
+            .add("0 30 * * * *", job);
+        let after = Utc.with_ymd_and_hms(2024, 7, 5, 2, 45, 0).unwrap();
+        let (at, due) = scheduler.next_after(&after).unwrap();
+
+        assert_eq!(at, Utc.with_ymd_and_hms(2024, 7, 5, 3, 0, 0).unwrap());
+        assert_eq!(due, vec![0, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid schedule")]
+    fn test_add_invalid() {
+        let job = PurgeDeletedUsers {
+            retention_days: 30,
+            mode: RetentionMode::Delete,
+        };
+        Scheduler::default().add("every day", job);
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let config = config::get_config();
+        let mut leader = PgConnection::establish(&config.database_url).unwrap();
+        let mut follower = PgConnection::establish(&config.database_url).unwrap();
+
+        assert!(try_lock(&mut leader).unwrap());
+        assert!(!try_lock(&mut follower).unwrap());
+        assert!(unlock(&mut leader).unwrap());
+        assert!(try_lock(&mut follower).unwrap());
+        assert!(unlock(&mut follower).unwrap());
+    }
+
+    #[test]
+    fn test_enqueue_due() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        conn.begin_test_transaction().unwrap();
+        diesel::delete(scheduled_tasks::table)
+            .execute(&mut conn)
+            .unwrap();
+        let scheduler = Scheduler::default().add("0 0 * * * *", PurgeExpiredKeys);
+        let now = Utc::now();
+
+        assert!(enqueue_due(&mut conn, &scheduler, now).unwrap().is_empty());
+
+        // The leader went away for a few hours and missed the runs since.
+        diesel::update(scheduled_tasks::table)
+            .set(scheduled_tasks::last_run_at.eq((now - chrono::Duration::hours(3)).naive_utc()))
+            .execute(&mut conn)
+            .unwrap();
+
+        assert_eq!(
+            enqueue_due(&mut conn, &scheduler, now).unwrap(),
+            vec![PurgeExpiredKeys::NAME]
+        );
+        assert!(enqueue_due(&mut conn, &scheduler, now).unwrap().is_empty());
+    }
+}
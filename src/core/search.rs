@@ -0,0 +1,381 @@
+use crate::core::encryption::blind_index;
+use crate::core::models::User;
+use diesel::prelude::*;
+use diesel::sql_types::Array;
+use diesel::sql_types::BigInt;
+use diesel::sql_types::Double;
+use diesel::sql_types::Text;
+use diesel::PgConnection;
+use std::collections::BTreeSet;
+
+/// The number of hex digits of a search token.
+const TOKEN_SIZE: usize = 16;
+
+/// The share of the trigrams of a search that a user must have to match it
+/// without a whole word, like the word similarity threshold of `pg_trgm`.
+const TRIGRAM_THRESHOLD: f64 = 0.5;
+
+/// The similarity of the trigrams of two words above which one is taken for
+/// a typo of the other, like the similarity threshold of `pg_trgm`.
+const SIMILARITY_THRESHOLD: f64 = 0.3;
+
+/// The number of characters of a word from which one of them may be a typo.
+const TYPO_MIN_LENGTH: usize = 4;
+
+/// The number of characters of a word above which its typos are not found,
+/// as it would have as many variants, each as long.
+const TYPO_MAX_LENGTH: usize = 24;
+
+/// A user matching a search, and how well it matches, from 0 to 1.
+#[derive(Debug, PartialEq, QueryableByName)]
+pub struct UserMatch {
+    #[diesel(embed)]
+    pub user: User,
+    #[diesel(sql_type = Double)]
+    pub rank: f64,
+}
+
+/// Split the value into its words in lower case, as searched.
+fn words(value: &str) -> Vec<String> {
+    value
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Get the trigrams of the word, padded like those of `pg_trgm`: two spaces
+/// before it and one after.
+fn trigrams(word: &str) -> Vec<String> {
+    let padded: Vec<char> = format!("  {} ", word).chars().collect();
+
+    padded
+        .windows(3)
+        .map(|window| window.iter().collect())
+        .collect()
+}
+
+/// Get the variants of the word that a typo of it may share, like SymSpell:
+/// the word itself and, unless too short or too long, the word without each
+/// of its characters. Two words within a typo, a character added, removed, replaced
+/// or swapped with the next one, share a variant, like `smith` and `smtih`
+/// share `smih`.
+fn variants(word: &str) -> BTreeSet<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut variants = BTreeSet::from([word.to_string()]);
+    if (TYPO_MIN_LENGTH..=TYPO_MAX_LENGTH).contains(&chars.len()) {
+        for i in 0..chars.len() {
+            variants.insert(chars[..i].iter().chain(&chars[i + 1..]).collect());
+        }
+    }
+
+    variants
+}
+
+/// Check whether the words are within a typo of each other.
+fn is_typo(a: &str, b: &str) -> bool {
+    !variants(a).is_disjoint(&variants(b))
+}
+
+/// Hash a word, a trigram or a variant into a search token, keyed so that the tokens do
+/// not reveal the personal data they are made of.
+fn token(kind: &str, value: &str) -> String {
+    blind_index(&format!("{}:{}", kind, value))[..TOKEN_SIZE].to_string()
+}
+
+/// The search tokens of values, by kind.
+struct Tokens {
+    words: BTreeSet<String>,
+    trigrams: BTreeSet<String>,
+    variants: BTreeSet<String>,
+}
+
+/// Get the tokens of the words of the values, of their trigrams and of their
+/// variants.
+fn tokens(values: &[&str]) -> Tokens {
+    let words: Vec<String> = values.iter().flat_map(|value| words(value)).collect();
+
+    Tokens {
+        words: words.iter().map(|word| token("word", word)).collect(),
+        trigrams: words
+            .iter()
+            .flat_map(|word| trigrams(word))
+            .map(|trigram| token("trigram", &trigram))
+            .collect(),
+        variants: words
+            .iter()
+            .flat_map(|word| variants(word))
+            .map(|variant| token("variant", &variant))
+            .collect(),
+    }
+}
+
+/// Get the search tokens of a user with the names and email address, as
+/// stored in `users.search_tokens`.
+pub fn search_tokens(first_name: &str, last_name: &str, email_address: &str) -> String {
+    let tokens = tokens(&[first_name, last_name, email_address]);
+
+    tokens
+        .words
+        .into_iter()
+        .chain(tokens.trigrams)
+        .chain(tokens.variants)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Search the users of the current tenant, not deleted, by their names and
+/// email address. A user matches with a whole word of the query, with a word
+/// within a typo of one, or with enough of its trigrams, which finds partial
+/// words. The users are ranked by the share of the words, of the variants and
+/// of the trigrams they match, best first, then by id.
+///
+/// A word is found with at most one typo, including two characters swapped,
+/// and only from 4 to 24 characters: shorter and longer words only match
+/// whole or by their trigrams. Each word of the query counts on its own, so a
+/// user matching some of them is found, below the users matching them all.
+pub fn search_users(
+    conn: &mut PgConnection,
+    query: &str,
+    offset: i64,
+    limit: i64,
+) -> QueryResult<Vec<UserMatch>> {
+    let Tokens {
+        words,
+        trigrams,
+        variants,
+    } = tokens(&[query]);
+    if words.is_empty() {
+        return Ok(Vec::new());
+    }
+    // The tokens are hex digits, which need no quoting.
+    let tsquery = words
+        .iter()
+        .chain(&trigrams)
+        .chain(&variants)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    diesel::sql_query(
+        "SELECT id, first_name, last_name, email_address, created_at, updated_at, deleted_at, \
+                email_verified_at, version, (word_rank + variant_rank + trigram_rank) / 3 AS rank \
+         FROM ( \
+             SELECT users.*, \
+                 (SELECT count(*) FROM unnest(search_vector) WHERE lexeme = ANY($1))::float8 \
+                     / cardinality($1) AS word_rank, \
+                 (SELECT count(*) FROM unnest(search_vector) WHERE lexeme = ANY($2))::float8 \
+                     / cardinality($2) AS trigram_rank, \
+                 (SELECT count(*) FROM unnest(search_vector) WHERE lexeme = ANY($3))::float8 \
+                     / cardinality($3) AS variant_rank \
+             FROM users \
+             WHERE deleted_at IS NULL AND search_vector @@ $4::tsquery \
+         ) AS matches \
+         WHERE word_rank > 0 OR variant_rank > 0 OR trigram_rank >= $5 \
+         ORDER BY rank DESC, id \
+         OFFSET $6 LIMIT $7",
+    )
+    .bind::<Array<Text>, _>(words.into_iter().collect::<Vec<_>>())
+    .bind::<Array<Text>, _>(trigrams.into_iter().collect::<Vec<_>>())
+    .bind::<Array<Text>, _>(variants.into_iter().collect::<Vec<_>>())
+    .bind::<Text, _>(tsquery)
+    .bind::<Double, _>(TRIGRAM_THRESHOLD)
+    .bind::<BigInt, _>(offset)
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+}
+
+/// Get the similarity of the trigrams of two words, like `pg_trgm`.
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: BTreeSet<String> = trigrams(a).into_iter().collect();
+    let b: BTreeSet<String> = trigrams(b).into_iter().collect();
+
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Highlight the parts of the value matching the query in `<b>` tags, with
+/// the rest of the value escaped as HTML. The words of the value containing a
+/// word of the query have it highlighted, and those within a typo of one or
+/// similar to one are highlighted whole. Returns `None` if nothing matches.
+pub fn highlight(value: &str, query: &str) -> Option<String> {
+    let terms = words(query);
+    let mut snippet = String::new();
+    let mut matched = false;
+    let mut rest = value;
+
+    while let Some(start) = rest.find(char::is_alphanumeric) {
+        let end = rest[start..]
+            .find(|c: char| !c.is_alphanumeric())
+            .map_or(rest.len(), |i| start + i);
+        let word = &rest[start..end];
+        let lower = word.to_lowercase();
+        snippet.push_str(&escape(&rest[..start]));
+
+        // The matching part is only found in words that keep their length in
+        // lower case, so that it is at the same place.
+        let part = match lower.len() == word.len() {
+            true => terms
+                .iter()
+                .filter_map(|term| lower.find(term.as_str()).map(|i| i..i + term.len()))
+                .max_by_key(|part| part.len()),
+            false => None,
+        };
+        match part {
+            Some(part) => {
+                snippet.push_str(&escape(&word[..part.start]));
+                snippet.push_str(&format!("<b>{}</b>", escape(&word[part.clone()])));
+                snippet.push_str(&escape(&word[part.end..]));
+                matched = true;
+            }
+            None if terms.iter().any(|term| {
+                is_typo(&lower, term) || similarity(&lower, term) >= SIMILARITY_THRESHOLD
+            }) =>
+            {
+                snippet.push_str(&format!("<b>{}</b>", escape(word)));
+                matched = true;
+            }
+            None => snippet.push_str(&escape(word)),
+        }
+        rest = &rest[end..];
+    }
+    snippet.push_str(&escape(rest));
+
+    matched.then_some(snippet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::models::schema::tenants;
+    use crate::core::models::schema::users;
+    use crate::core::repo;
+    use crate::core::users::create_user;
+    use crate::core::users::update_user;
+    use crate::core::users::CreateUserAttrs;
+    use crate::core::users::UpdateUserAttrs;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_search_tokens() {
+        let tokens = search_tokens("Jane", "Doe", "jane@doe.com");
+        let tokens: Vec<&str> = tokens.split(' ').collect();
+
+        // The words jane, doe and com, the trigrams of jane, doe and com, and
+        // the variants of jane, doe and com.
+        assert_eq!(tokens.len(), 3 + (5 + 4 + 4) + (5 + 1 + 1));
+        assert!(tokens.iter().all(|token| token.len() == TOKEN_SIZE));
+        assert!(!tokens.contains(&"jane"));
+        assert_eq!(
+            search_tokens("JANE", "doe", "Jane@Doe.com")
+                .split(' ')
+                .count(),
+            tokens.len()
+        );
+    }
+
+    #[test]
+    fn test_variants() {
+        assert_eq!(
+            variants("smith"),
+            BTreeSet::from(["smith", "mith", "sith", "smth", "smih", "smit"].map(String::from))
+        );
+        assert_eq!(variants("doe"), BTreeSet::from(["doe".to_string()]));
+        assert_eq!(variants(&"a".repeat(25)).len(), 1);
+        assert!(is_typo("smith", "smtih"));
+        assert!(is_typo("smith", "smyth"));
+        assert!(is_typo("smith", "smiths"));
+        assert!(is_typo("john", "jon"));
+        assert!(!is_typo("smith", "smooth"));
+        assert!(!is_typo("doe", "dan"));
+    }
+
+    #[test]
+    fn test_search_users() {
+        let mut conn = PgConnection::establish(&config::get_config().database_url).unwrap();
+        let tenant_id = diesel::insert_into(tenants::table)
+            .values((
+                tenants::id.eq(Uuid::now_v7()),
+                tenants::name.eq("Doe Inc."),
+                tenants::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .returning(tenants::id)
+            .get_result(&mut conn)
+            .unwrap();
+        let mut insert = |first_name: &str, last_name: &str| {
+            let attrs = CreateUserAttrs {
+                first_name: first_name.to_string(),
+                last_name: last_name.to_string(),
+                email_address: format!("{}.{}@example.com", first_name, Uuid::now_v7().simple()),
+            };
+            repo::with_tenant(&mut conn, tenant_id, |conn| create_user(conn, attrs))
+                .unwrap()
+                .unwrap()
+        };
+        let janet = insert("Janet", "Smith");
+        let jane = insert("Jane", "Doe");
+        let john = insert("John", "Doe");
+        let search = |conn: &mut PgConnection, query: &str| {
+            repo::with_tenant(conn, tenant_id, |conn| search_users(conn, query, 0, 10))
+                .unwrap()
+                .into_iter()
+                .map(|found| found.user.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(&mut conn, "jane"), vec![jane.id, janet.id]);
+        assert_eq!(search(&mut conn, "Jannet"), vec![janet.id]);
+        assert_eq!(search(&mut conn, "doe john"), vec![john.id, jane.id]);
+        assert_eq!(search(&mut conn, "smith"), vec![janet.id]);
+        assert_eq!(search(&mut conn, "Smtih"), vec![janet.id]);
+        assert_eq!(search(&mut conn, "Smyth"), vec![janet.id]);
+        assert_eq!(search(&mut conn, "jon"), vec![john.id]);
+        assert_eq!(search(&mut conn, "Jnae"), vec![jane.id]);
+        assert_eq!(search(&mut conn, "jane dooe")[0], jane.id);
+        assert_eq!(search(&mut conn, "Jnae Doe")[0], jane.id);
+        assert_eq!(search(&mut conn, "Janet Smiht")[0], janet.id);
+        assert!(search(&mut conn, "zzz").is_empty());
+        assert!(search(&mut conn, "").is_empty());
+
+        let attrs = UpdateUserAttrs {
+            first_name: None,
+            last_name: Some("Brown".to_string()),
+            email_address: None,
+        };
+        repo::with_tenant(&mut conn, tenant_id, |conn| {
+            update_user(conn, janet.id, attrs, None)
+        })
+        .unwrap();
+        diesel::update(users::table.find(john.id))
+            .set(users::deleted_at.eq(Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .unwrap();
+
+        assert!(search(&mut conn, "smith").is_empty());
+        assert_eq!(search(&mut conn, "brown"), vec![janet.id]);
+        assert!(search(&mut conn, "john").is_empty());
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(highlight("Janet", "jan").unwrap(), "<b>Jan</b>et");
+        assert_eq!(highlight("Jane Doe", "DOE").unwrap(), "Jane <b>Doe</b>");
+        assert_eq!(highlight("Janet", "Jannet").unwrap(), "<b>Janet</b>");
+        assert_eq!(highlight("Smith", "smtih").unwrap(), "<b>Smith</b>");
+        assert_eq!(
+            highlight("jane.doe@doe.com", "doe").unwrap(),
+            "jane.<b>doe</b>@<b>doe</b>.com"
+        );
+        assert_eq!(highlight("<Jane>", "jane").unwrap(), "&lt;<b>Jane</b>&gt;");
+        assert_eq!(highlight("Jane", "john"), None);
+    }
+}
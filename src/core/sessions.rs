@@ -0,0 +1,150 @@
+use crate::core::encryption::blind_index;
+use crate::core::models::schema::sessions;
+use crate::core::models::schema::user_credentials;
+use crate::core::models::schema::users;
+use crate::core::models::Session;
+use crate::core::models::User;
+use crate::core::passwords;
+use crate::core::tokens;
+use chrono::Duration;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::io::Error;
+use uuid::Uuid;
+
+/// The time a session stays valid.
+const SESSION_TTL: Duration = Duration::days(30);
+
+/// A session opened by signing in, with its token, which is never stored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedIn {
+    pub token: String,
+    pub session: Session,
+    pub user: User,
+}
+
+/// Open a session for the user. Returns the session and its token.
+pub fn create_session(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<(Session, String)> {
+    let timestamp = Utc::now().naive_utc();
+    let token = tokens::generate_token();
+    let session = diesel::insert_into(sessions::table)
+        .values(&Session {
+            id: Uuid::now_v7(),
+            user_id,
+            token_hash: tokens::hash_token(&token),
+            expires_at: timestamp + SESSION_TTL,
+            revoked_at: None,
+            created_at: timestamp,
+        })
+        .returning(Session::as_returning())
+        .get_result(conn)?;
+
+    Ok((session, token))
+}
+
+/// Open a session for the user with the email address and password.
+pub fn sign_in(
+    conn: &mut PgConnection,
+    email_address: &str,
+    password: &str,
+) -> Result<Option<SignedIn>, Error> {
+    let result = users::table
+        .inner_join(user_credentials::table)
+        .filter(users::email_hash.eq(blind_index(email_address)))
+        .filter(users::deleted_at.is_null())
+        .order(users::created_at)
+        .select((User::as_select(), user_credentials::password_hash))
+        .first::<(User, String)>(conn)
+        .optional()
+        .map_err(Error::other)?;
+
+    let user = match result {
+        Some((user, hash)) if passwords::verify_password(password, &hash) => user,
+        Some(_) => return Ok(None),
+        None => {
+            // Take the time a verification would, so that it does not reveal
+            // whether the address is known.
+            passwords::hash_password(password)?;
+            return Ok(None);
+        }
+    };
+    let (session, token) = create_session(conn, user.id).map_err(Error::other)?;
+
+    Ok(Some(SignedIn {
+        token,
+        session,
+        user,
+    }))
+}
+
+/// Get the user of the session with the token, if it is still open.
+pub fn get_session_user(conn: &mut PgConnection, token: &str) -> QueryResult<Option<Uuid>> {
+    sessions::table
+        .inner_join(users::table)
+        .filter(sessions::token_hash.eq(tokens::hash_token(token)))
+        .filter(sessions::revoked_at.is_null())
+        .filter(sessions::expires_at.gt(Utc::now().naive_utc()))
+        .filter(users::deleted_at.is_null())
+        .select(sessions::user_id)
+        .first(conn)
+        .optional()
+}
+
+/// Revoke the open sessions of the user.
+pub fn revoke_sessions(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<usize> {
+    diesel::update(
+        sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::revoked_at.is_null()),
+    )
+    .set(sessions::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::test::factory;
+    use diesel::Connection;
+
+    #[test]
+    fn test_sign_in() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let email_address = user.email_address.clone();
+        let hash = passwords::hash_password("correct horse").unwrap();
+        passwords::set_password_hash(&mut conn, user.id, &hash).unwrap();
+
+        let result = sign_in(&mut conn, &email_address, "correct horse")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.user.id, user.id);
+        assert_eq!(result.session.token_hash, tokens::hash_token(&result.token));
+        assert_eq!(
+            sign_in(&mut conn, &email_address, "wrong horse").unwrap(),
+            None
+        );
+        assert_eq!(
+            sign_in(&mut conn, "nobody@doe.com", "correct horse").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_revoke_sessions() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        create_session(&mut conn, user.id).unwrap();
+        let (_, token) = create_session(&mut conn, user.id).unwrap();
+
+        assert_eq!(get_session_user(&mut conn, &token).unwrap(), Some(user.id));
+        assert_eq!(revoke_sessions(&mut conn, user.id).unwrap(), 2);
+        assert_eq!(revoke_sessions(&mut conn, user.id).unwrap(), 0);
+        assert_eq!(get_session_user(&mut conn, &token).unwrap(), None);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::core::models::schema::user_tokens;
+use crate::core::models::UserToken;
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use sha2::Digest;
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// The purpose of a token that verifies an email address.
+pub const EMAIL_VERIFICATION: &str = "email_verification";
+
+/// The purpose of a token that confirms a change of email address.
+pub const EMAIL_CHANGE: &str = "email_change";
+
+/// The purpose of a token that resets a password.
+pub const PASSWORD_RESET: &str = "password_reset";
+
+/// Generate a random token of 64 hexadecimal characters.
+pub fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Hash the token as the hex-encoded SHA-256 digest, which is what is stored.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Issue a single-use token for the user, revoking the unused tokens issued
+/// for the same purpose before. Returns the token, which is never stored.
+pub fn issue_token(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    purpose: &str,
+    ttl: Duration,
+) -> QueryResult<String> {
+    let timestamp = Utc::now().naive_utc();
+    let token = generate_token();
+
+    conn.transaction(|conn| {
+        revoke_tokens(conn, user_id, purpose)?;
+        diesel::insert_into(user_tokens::table)
+            .values(&UserToken {
+                id: Uuid::now_v7(),
+                user_id,
+                purpose: purpose.to_string(),
+                token_hash: hash_token(&token),
+                expires_at: timestamp + ttl,
+                consumed_at: None,
+                created_at: timestamp,
+            })
+            .execute(conn)?;
+
+        Ok(token)
+    })
+}
+
+/// Consume the token if it is valid for the purpose, returning its user.
+pub fn consume_token(
+    conn: &mut PgConnection,
+    purpose: &str,
+    token: &str,
+) -> QueryResult<Option<Uuid>> {
+    let timestamp = Utc::now().naive_utc();
+
+    diesel::update(
+        user_tokens::table
+            .filter(user_tokens::token_hash.eq(hash_token(token)))
+            .filter(user_tokens::purpose.eq(purpose))
+            .filter(user_tokens::consumed_at.is_null())
+            .filter(user_tokens::expires_at.gt(timestamp)),
+    )
+    .set(user_tokens::consumed_at.eq(timestamp))
+    .returning(user_tokens::user_id)
+    .get_result(conn)
+    .optional()
+}
+
+/// Revoke the unused tokens of the user for the purpose.
+pub fn revoke_tokens(conn: &mut PgConnection, user_id: Uuid, purpose: &str) -> QueryResult<usize> {
+    diesel::update(
+        user_tokens::table
+            .filter(user_tokens::user_id.eq(user_id))
+            .filter(user_tokens::purpose.eq(purpose))
+            .filter(user_tokens::consumed_at.is_null()),
+    )
+    .set(user_tokens::consumed_at.eq(Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+/// Count the tokens issued to the user for the purpose since the given time.
+pub fn count_issued_since(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    purpose: &str,
+    since: NaiveDateTime,
+) -> QueryResult<i64> {
+    user_tokens::table
+        .filter(user_tokens::user_id.eq(user_id))
+        .filter(user_tokens::purpose.eq(purpose))
+        .filter(user_tokens::created_at.gt(since))
+        .count()
+        .get_result(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::test::factory;
+    use diesel::Connection;
+
+    #[test]
+    fn test_consume_token() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let token =
+            issue_token(&mut conn, user.id, EMAIL_VERIFICATION, Duration::hours(1)).unwrap();
+
+        assert_eq!(consume_token(&mut conn, "other", &token).unwrap(), None);
+        assert_eq!(
+            consume_token(&mut conn, EMAIL_VERIFICATION, &token).unwrap(),
+            Some(user.id)
+        );
+        assert_eq!(
+            consume_token(&mut conn, EMAIL_VERIFICATION, &token).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_consume_token_expired() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let token = issue_token(
+            &mut conn,
+            user.id,
+            EMAIL_VERIFICATION,
+            Duration::seconds(-1),
+        )
+        .unwrap();
+
+        assert_eq!(
+            consume_token(&mut conn, EMAIL_VERIFICATION, &token).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_issue_token_revokes_previous() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let first =
+            issue_token(&mut conn, user.id, EMAIL_VERIFICATION, Duration::hours(1)).unwrap();
+        let second =
+            issue_token(&mut conn, user.id, EMAIL_VERIFICATION, Duration::hours(1)).unwrap();
+
+        assert_eq!(
+            consume_token(&mut conn, EMAIL_VERIFICATION, &first).unwrap(),
+            None
+        );
+        assert_eq!(
+            consume_token(&mut conn, EMAIL_VERIFICATION, &second).unwrap(),
+            Some(user.id)
+        );
+    }
+
+    #[test]
+    fn test_count_issued_since() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let since = Utc::now().naive_utc() - Duration::minutes(1);
+        issue_token(&mut conn, user.id, PASSWORD_RESET, Duration::hours(1)).unwrap();
+        issue_token(&mut conn, user.id, PASSWORD_RESET, Duration::hours(1)).unwrap();
+
+        assert_eq!(
+            count_issued_since(&mut conn, user.id, PASSWORD_RESET, since).unwrap(),
+            2
+        );
+        assert_eq!(
+            count_issued_since(&mut conn, user.id, EMAIL_VERIFICATION, since).unwrap(),
+            0
+        );
+    }
+}
@@ -1,8 +1,18 @@
+use crate::core::encryption::blind_index;
+use crate::core::encryption::encrypt_fields;
+use crate::core::encryption::Encrypted;
 use crate::core::models::schema::users;
 use crate::core::models::schema::users::dsl::*;
+use crate::core::models::schema::users_history;
 use crate::core::models::User;
+use crate::core::models::UserVersion;
+use crate::core::outbox;
+use crate::core::search;
+use crate::core::webhooks;
+use chrono::NaiveDateTime;
 use chrono::Utc;
 use diesel::prelude::*;
+use diesel::result::Error::SerializationError;
 use diesel::PgConnection;
 use diesel::QueryDsl;
 use diesel::RunQueryDsl;
@@ -16,6 +26,63 @@ pub struct CreateUserAttrs {
     pub email_address: String,
 }
 
+#[derive(AsChangeset)]
+#[diesel(table_name = users)]
+pub struct UpdateUserAttrs {
+    #[diesel(serialize_as = Encrypted)]
+    pub first_name: Option<String>,
+    #[diesel(serialize_as = Encrypted)]
+    pub last_name: Option<String>,
+    #[diesel(serialize_as = Encrypted)]
+    pub email_address: Option<String>,
+}
+
+/// The user was changed since the version a change expected, by another
+/// change that came first.
+#[derive(Debug)]
+pub struct VersionConflict {
+    pub current_version: i32,
+}
+
+impl std::fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The user is at version {}", self.current_version)
+    }
+}
+
+impl std::error::Error for VersionConflict {}
+
+/// Check that the user is at the expected version, if any.
+fn check_version(user: &User, expected_version: Option<i32>) -> Result<(), Error> {
+    match expected_version {
+        Some(expected) if expected != user.version => Err(Error::other(VersionConflict {
+            current_version: user.version,
+        })),
+        _ => Ok(()),
+    }
+}
+
+/// Check that the email address has a local part and a domain.
+pub fn is_valid_email_address(address: &str) -> bool {
+    match address.split_once('@') {
+        Some((local, domain)) => !local.is_empty() && !domain.is_empty() && !domain.contains('@'),
+        None => false,
+    }
+}
+
+/// Record a change to the user in the outbox and for the subscribed
+/// webhooks of its tenant, in the same transaction as the change. Its
+/// personal data is encrypted until delivered.
+pub fn enqueue_change(conn: &mut PgConnection, kind: &str, user: &User) -> QueryResult<()> {
+    let payload = serde_json::to_value(user).map_err(|e| SerializationError(Box::new(e)))?;
+    let payload = encrypt_fields(payload);
+    outbox::enqueue(conn, kind, user.id, &payload)?;
+    match get_tenant_id(conn, user.id)? {
+        Some(tenant) => webhooks::enqueue(conn, tenant, kind, &payload),
+        None => Ok(()),
+    }
+}
+
 /// Get the user.
 pub fn get_user(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<User>, Error> {
     // TODO: Handle all the errors
@@ -32,9 +99,20 @@ pub fn get_user(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<User>,
     }
 }
 
+/// Get the tenant of the user.
+pub fn get_tenant_id(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Option<Uuid>> {
+    users::table
+        .find(user_id)
+        .select(users::tenant_id)
+        .first(conn)
+        .optional()
+}
+
 /// Create a user.
 pub fn create_user(conn: &mut PgConnection, attrs: CreateUserAttrs) -> Result<Option<User>, Error> {
     let timestamp = Utc::now().naive_utc();
+    let hash = blind_index(&attrs.email_address);
+    let tokens = search::search_tokens(&attrs.first_name, &attrs.last_name, &attrs.email_address);
     let changes = User {
         id: Uuid::now_v7(),
         first_name: attrs.first_name,
@@ -43,13 +121,20 @@ pub fn create_user(conn: &mut PgConnection, attrs: CreateUserAttrs) -> Result<Op
         created_at: timestamp,
         updated_at: timestamp,
         deleted_at: None,
+        email_verified_at: None,
+        version: 1,
     };
 
     // TODO: Handle all the errors
-    let result = diesel::insert_into(users::table)
-        .values(&changes)
-        .returning(User::as_returning())
-        .get_result(conn)
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let user = diesel::insert_into(users::table)
+                .values((changes, email_hash.eq(hash), search_tokens.eq(tokens)))
+                .returning(User::as_returning())
+                .get_result(conn)?;
+            enqueue_change(conn, "user.created", &user)?;
+            Ok(user)
+        })
         .optional();
 
     match result {
@@ -59,13 +144,232 @@ pub fn create_user(conn: &mut PgConnection, attrs: CreateUserAttrs) -> Result<Op
     }
 }
 
+/// Update a user, failing with a `VersionConflict` if it is no longer at the
+/// expected version.
+pub fn update_user(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    attrs: UpdateUserAttrs,
+    expected_version: Option<i32>,
+) -> Result<Option<User>, Error> {
+    let timestamp = Utc::now().naive_utc();
+
+    // TODO: Handle all the errors
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let current = users
+                .find(user_id)
+                .filter(deleted_at.is_null())
+                .for_update()
+                .select(User::as_select())
+                .first(conn)?;
+            if let Err(e) = check_version(&current, expected_version) {
+                return Ok(Err(e));
+            }
+            // A new email address has not been verified yet.
+            let verified_at = match &attrs.email_address {
+                Some(address) if *address != current.email_address => None,
+                _ => current.email_verified_at,
+            };
+            let hash = attrs
+                .email_address
+                .as_deref()
+                .map(|address| email_hash.eq(blind_index(address)));
+            let tokens = search::search_tokens(
+                attrs.first_name.as_deref().unwrap_or(&current.first_name),
+                attrs.last_name.as_deref().unwrap_or(&current.last_name),
+                attrs
+                    .email_address
+                    .as_deref()
+                    .unwrap_or(&current.email_address),
+            );
+            let user = diesel::update(users.find(user_id))
+                .set((
+                    attrs,
+                    hash,
+                    search_tokens.eq(tokens),
+                    email_verified_at.eq(verified_at),
+                    updated_at.eq(timestamp),
+                ))
+                .returning(User::as_returning())
+                .get_result(conn)?;
+            enqueue_change(conn, "user.updated", &user)?;
+            Ok(Ok(user))
+        })
+        .optional();
+
+    match result {
+        Ok(Some(Ok(user))) => Ok(Some(user)),
+        Ok(Some(Err(e))) => Err(e),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Delete a user, keeping the row with `deleted_at` set. Fails with a
+/// `VersionConflict` if it is no longer at the expected version.
+pub fn delete_user(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    expected_version: Option<i32>,
+) -> Result<Option<User>, Error> {
+    let timestamp = Utc::now().naive_utc();
+
+    // TODO: Handle all the errors
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let current = users
+                .find(user_id)
+                .filter(deleted_at.is_null())
+                .for_update()
+                .select(User::as_select())
+                .first(conn)?;
+            if let Err(e) = check_version(&current, expected_version) {
+                return Ok(Err(e));
+            }
+            let user = diesel::update(users.find(user_id))
+                .set((deleted_at.eq(timestamp), updated_at.eq(timestamp)))
+                .returning(User::as_returning())
+                .get_result(conn)?;
+            enqueue_change(conn, "user.deleted", &user)?;
+            Ok(Ok(user))
+        })
+        .optional();
+
+    match result {
+        Ok(Some(Ok(user))) => Ok(Some(user)),
+        Ok(Some(Err(e))) => Err(e),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Scrub the personal data of the user from its audit events, including the
+/// ones recorded for its purge or anonymization. The events are those of its
+/// tenant, whatever the tenant the connection is scoped to.
+fn erase_audit_events(
+    conn: &mut PgConnection,
+    user_tenant_id: Uuid,
+    user_id: Uuid,
+) -> QueryResult<()> {
+    diesel::sql_query("SELECT set_config('app.tenant_id', $1, true), erase_user_audit_events($2)")
+        .bind::<diesel::sql_types::Text, _>(user_tenant_id.to_string())
+        .bind::<diesel::sql_types::Uuid, _>(user_id)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Permanently delete the users deleted before the given time, and scrub
+/// their personal data from their audit events.
+pub fn purge_deleted_users(conn: &mut PgConnection, before: NaiveDateTime) -> QueryResult<usize> {
+    conn.transaction(|conn| {
+        let purged: Vec<(Uuid, Uuid)> = diesel::delete(users.filter(deleted_at.lt(before)))
+            .returning((id, tenant_id))
+            .get_results(conn)?;
+        for (user_id, user_tenant_id) in &purged {
+            erase_audit_events(conn, *user_tenant_id, *user_id)?;
+        }
+
+        Ok(purged.len())
+    })
+}
+
+/// Remove the personal data of the users deleted before the given time, from
+/// their rows, versions and audit events, keeping their rows for referential
+/// and statistical purposes.
+pub fn anonymize_deleted_users(
+    conn: &mut PgConnection,
+    before: NaiveDateTime,
+) -> QueryResult<usize> {
+    let timestamp = Utc::now().naive_utc();
+
+    conn.transaction(|conn| {
+        let anonymized: Vec<(Uuid, Uuid)> = users
+            .filter(deleted_at.lt(before))
+            .filter(anonymized_at.is_null())
+            .select((id, tenant_id))
+            .for_update()
+            .load(conn)?;
+        // The anonymized values are encrypted like any other.
+        for (user_id, user_tenant_id) in &anonymized {
+            diesel::update(users.find(user_id))
+                .set((
+                    first_name.eq(Encrypted(String::new())),
+                    last_name.eq(Encrypted(String::new())),
+                    email_address.eq(Encrypted(format!("deleted-{}@anonymized.invalid", user_id))),
+                    email_hash.eq(None::<String>),
+                    search_tokens.eq(None::<String>),
+                    anonymized_at.eq(timestamp),
+                    updated_at.eq(timestamp),
+                ))
+                .execute(conn)?;
+            erase_audit_events(conn, *user_tenant_id, *user_id)?;
+        }
+        let user_ids: Vec<Uuid> = anonymized.iter().map(|(user_id, _)| *user_id).collect();
+        // The earlier versions of the anonymized users still hold their personal data.
+        diesel::delete(
+            users_history::table
+                .filter(users_history::id.eq_any(&user_ids))
+                .filter(users_history::valid_to.is_not_null()),
+        )
+        .execute(conn)?;
+
+        Ok(user_ids.len())
+    })
+}
+
+/// Get the version of the user valid at the time.
+pub fn get_user_version(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    timestamp: NaiveDateTime,
+) -> QueryResult<Option<UserVersion>> {
+    users_history::table
+        .filter(users_history::id.eq(user_id))
+        .filter(users_history::valid_from.le(timestamp))
+        .filter(
+            users_history::valid_to
+                .is_null()
+                .or(users_history::valid_to.gt(timestamp)),
+        )
+        .select(UserVersion::as_select())
+        .first(conn)
+        .optional()
+}
+
+/// List the versions of the user, newest first, after the version with the
+/// given id.
+pub fn list_user_versions(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    after: Option<Uuid>,
+    limit: i64,
+) -> QueryResult<Vec<UserVersion>> {
+    let mut query = users_history::table
+        .filter(users_history::id.eq(user_id))
+        .order(users_history::version_id.desc())
+        .limit(limit)
+        .select(UserVersion::as_select())
+        .into_boxed();
+    if let Some(after) = after {
+        query = query.filter(users_history::version_id.lt(after));
+    }
+
+    query.load(conn)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config;
+    use crate::core::encryption::decrypt_json;
+    use crate::core::models::schema::audit_events;
     use crate::test::factory;
     use diesel::Connection;
     use diesel::PgConnection;
+    use serde_json::json;
+    use serde_json::Value;
 
     #[test]
     fn test_get_user() {
@@ -91,17 +395,18 @@ mod tests {
     fn test_create_user() {
         let config = config::get_config();
         let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let email = format!("jane.{}@doe.com", Uuid::now_v7().simple());
         let attrs = CreateUserAttrs {
             first_name: "Jane".to_string(),
             last_name: "Doe".to_string(),
-            email_address: "jane@doe.com".to_string(),
+            email_address: email.clone(),
         };
         let result = create_user(&mut conn, attrs).unwrap();
 
         if let Some(user) = result {
             assert_eq!(user.first_name, "Jane");
             assert_eq!(user.last_name, "Doe");
-            assert_eq!(user.email_address, "jane@doe.com");
+            assert_eq!(user.email_address, email);
             assert_eq!(user.created_at, user.updated_at);
             assert_eq!(user.deleted_at, None);
         } else {
@@ -113,4 +418,180 @@ mod tests {
     fn test_create_user_already_exists() {
         assert_eq!(true, true)
     }
+
+    #[test]
+    fn test_update_user() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let attrs = UpdateUserAttrs {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+        let result = update_user(&mut conn, user.id, attrs, None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.first_name, "Janet");
+        assert_eq!(result.last_name, user.last_name);
+        assert_eq!(result.email_address, user.email_address);
+        assert!(result.updated_at > user.updated_at);
+    }
+
+    #[test]
+    fn test_update_user_not_found() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let attrs = UpdateUserAttrs {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+        let result = update_user(&mut conn, Uuid::now_v7(), attrs, None).unwrap();
+
+        assert_eq!(result, None)
+    }
+
+    #[test]
+    fn test_delete_user() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let result = delete_user(&mut conn, user.id, None).unwrap().unwrap();
+
+        assert_eq!(result.id, user.id);
+        assert_ne!(result.deleted_at, None);
+        assert_eq!(delete_user(&mut conn, user.id, None).unwrap(), None);
+    }
+
+    /// Delete the user as if it happened a year ago.
+    fn delete_user_long_ago(conn: &mut PgConnection, user_id: Uuid) -> NaiveDateTime {
+        let long_ago = Utc::now().naive_utc() - chrono::Duration::days(365);
+        diesel::update(users.find(user_id))
+            .set(deleted_at.eq(long_ago))
+            .execute(conn)
+            .unwrap();
+
+        long_ago + chrono::Duration::days(1)
+    }
+
+    /// Get the audit events of the user, with their personal data decrypted.
+    fn audit_events_of(conn: &mut PgConnection, user_id: Uuid) -> Vec<String> {
+        audit_events::table
+            .filter(audit_events::target_id.eq(user_id))
+            .select((audit_events::before, audit_events::after))
+            .load::<(Option<Value>, Option<Value>)>(conn)
+            .unwrap()
+            .into_iter()
+            .map(|(before, after)| {
+                json!([before.map(decrypt_json), after.map(decrypt_json)]).to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_purge_deleted_users() {
+        let user = factory::insert_user();
+        let recent = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        delete_user(&mut conn, recent.id, None).unwrap();
+        let before = delete_user_long_ago(&mut conn, user.id);
+
+        assert!(purge_deleted_users(&mut conn, before).unwrap() >= 1);
+        assert_eq!(get_user(&mut conn, user.id).unwrap(), None);
+        assert_ne!(get_user(&mut conn, recent.id).unwrap(), None);
+        let events = audit_events_of(&mut conn, user.id);
+        assert!(events.len() >= 2);
+        assert!(events
+            .iter()
+            .all(|event| !event.contains(&user.email_address)));
+    }
+
+    #[test]
+    fn test_anonymize_deleted_users() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let before = delete_user_long_ago(&mut conn, user.id);
+
+        assert!(anonymize_deleted_users(&mut conn, before).unwrap() >= 1);
+        let result = get_user(&mut conn, user.id).unwrap().unwrap();
+
+        assert_eq!(result.first_name, "");
+        assert_eq!(
+            result.email_address,
+            format!("deleted-{}@anonymized.invalid", user.id)
+        );
+        let versions = list_user_versions(&mut conn, user.id, None, 10).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].email_address, result.email_address);
+        assert!(audit_events_of(&mut conn, user.id)
+            .iter()
+            .all(|event| !event.contains(&user.email_address)));
+    }
+
+    #[test]
+    fn test_list_user_versions() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let attrs = UpdateUserAttrs {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+        update_user(&mut conn, user.id, attrs, None)
+            .unwrap()
+            .unwrap();
+
+        let versions = list_user_versions(&mut conn, user.id, None, 10).unwrap();
+        let names: Vec<&str> = versions
+            .iter()
+            .map(|entry| entry.first_name.as_str())
+            .collect();
+        assert_eq!(names, ["Janet", user.first_name.as_str()]);
+        assert_eq!(versions[1].valid_to, Some(versions[0].valid_from));
+        assert_eq!(versions[0].valid_to, None);
+        let after = list_user_versions(&mut conn, user.id, Some(versions[0].version_id), 10);
+        assert_eq!(after.unwrap(), versions[1..]);
+
+        let created = get_user_version(&mut conn, user.id, versions[1].valid_from).unwrap();
+        assert_eq!(created.unwrap().first_name, user.first_name);
+        let updated = get_user_version(&mut conn, user.id, versions[0].valid_from).unwrap();
+        assert_eq!(updated.unwrap().first_name, "Janet");
+        let before = versions[1].valid_from - chrono::Duration::microseconds(1);
+        assert_eq!(get_user_version(&mut conn, user.id, before).unwrap(), None);
+    }
+
+    #[test]
+    fn test_update_user_version_conflict() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let attrs = || UpdateUserAttrs {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+
+        let updated = update_user(&mut conn, user.id, attrs(), Some(user.version)).unwrap();
+        assert_eq!(updated.unwrap().version, user.version + 1);
+        let error = update_user(&mut conn, user.id, attrs(), Some(user.version)).unwrap_err();
+        let conflict = error
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<VersionConflict>()
+            .unwrap();
+        assert_eq!(conflict.current_version, user.version + 1);
+        let error = delete_user(&mut conn, user.id, Some(user.version)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("The user is at version {}", user.version + 1)
+        );
+
+        let deleted = delete_user(&mut conn, user.id, Some(user.version + 1)).unwrap();
+        assert_eq!(deleted.unwrap().version, user.version + 2);
+    }
 }
@@ -0,0 +1,138 @@
+use crate::core::mailer::Email;
+use crate::core::mailer::Mailer;
+use crate::core::models::schema::users::dsl::*;
+use crate::core::models::User;
+use crate::core::tokens;
+use crate::core::tokens::EMAIL_VERIFICATION;
+use crate::core::users::enqueue_change;
+use crate::core::users::get_user;
+use chrono::Duration;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::io::Error;
+use uuid::Uuid;
+
+/// The time a verification token stays valid.
+const VERIFICATION_TTL: Duration = Duration::hours(24);
+
+/// Mail a verification token to the email address of the user, unless it is
+/// already verified.
+pub fn send_verification_email(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    user_id: Uuid,
+) -> Result<Option<User>, Error> {
+    let user = match get_user(conn, user_id)? {
+        Some(user) if user.deleted_at.is_none() => user,
+        _ => return Ok(None),
+    };
+    if user.email_verified_at.is_some() {
+        return Ok(Some(user));
+    }
+
+    let token = tokens::issue_token(conn, user.id, EMAIL_VERIFICATION, VERIFICATION_TTL)
+        .map_err(Error::other)?;
+    mailer.send(&Email {
+        to: user.email_address.clone(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Use this token to verify your email address within 24 hours:\n\n{}",
+            token
+        ),
+    })?;
+
+    Ok(Some(user))
+}
+
+/// Mark the email address of the user as verified, consuming the token.
+pub fn verify_email(conn: &mut PgConnection, token: &str) -> Result<Option<User>, Error> {
+    let timestamp = Utc::now().naive_utc();
+
+    // TODO: Handle all the errors
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let user_id = match tokens::consume_token(conn, EMAIL_VERIFICATION, token)? {
+                Some(user_id) => user_id,
+                None => return Ok(None),
+            };
+            let user = diesel::update(users.find(user_id).filter(deleted_at.is_null()))
+                .set((email_verified_at.eq(timestamp), updated_at.eq(timestamp)))
+                .returning(User::as_returning())
+                .get_result(conn)?;
+            enqueue_change(conn, "user.updated", &user)?;
+            Ok(Some(user))
+        })
+        .optional();
+
+    match result {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::mailer::MemoryMailer;
+    use crate::core::users::update_user;
+    use crate::core::users::UpdateUserAttrs;
+    use crate::test::factory;
+    use diesel::Connection;
+
+    /// Get the token from the last email sent to the user.
+    fn sent_token(mailer: &MemoryMailer, user: &User) -> String {
+        let email = mailer.last_sent_to(&user.email_address).unwrap();
+
+        email.body.split_whitespace().last().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_verify_email() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+        send_verification_email(&mut conn, &mailer, user.id)
+            .unwrap()
+            .unwrap();
+        let token = sent_token(&mailer, &user);
+
+        let result = verify_email(&mut conn, &token).unwrap().unwrap();
+
+        assert_eq!(result.id, user.id);
+        assert_ne!(result.email_verified_at, None);
+        assert_eq!(verify_email(&mut conn, &token).unwrap(), None);
+    }
+
+    #[test]
+    fn test_verify_email_invalid_token() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+
+        assert_eq!(verify_email(&mut conn, "invalid").unwrap(), None);
+    }
+
+    #[test]
+    fn test_update_user_unverifies_email() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let mailer = MemoryMailer::default();
+        send_verification_email(&mut conn, &mailer, user.id).unwrap();
+        verify_email(&mut conn, &sent_token(&mailer, &user)).unwrap();
+        let attrs = UpdateUserAttrs {
+            first_name: None,
+            last_name: None,
+            email_address: Some(format!("janet.{}@doe.com", user.id.simple())),
+        };
+
+        let result = update_user(&mut conn, user.id, attrs, None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.email_verified_at, None);
+    }
+}
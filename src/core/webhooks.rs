@@ -0,0 +1,742 @@
+use crate::core::encryption::decrypt_fields;
+use crate::core::models::schema::webhook_deliveries;
+use crate::core::models::schema::webhooks;
+use crate::core::models::Webhook;
+use crate::core::models::WebhookDelivery;
+use crate::core::outbox::backoff;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use diesel::prelude::*;
+use diesel::result::Error::SerializationError;
+use diesel::PgConnection;
+use hmac::Hmac;
+use hmac::Mac;
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+use ureq::Agent;
+use ureq::AgentBuilder;
+use url::Url;
+use uuid::Uuid;
+
+/// The status of a delivery that is waiting for its next attempt.
+pub const PENDING: &str = "pending";
+
+/// The status of a delivery that the receiver accepted.
+pub const DELIVERED: &str = "delivered";
+
+/// The status of a delivery that ran out of attempts.
+pub const FAILED: &str = "failed";
+
+/// The number of attempts before a delivery fails.
+const MAX_ATTEMPTS: i32 = 8;
+
+/// The number of consecutive failed attempts before a webhook is disabled.
+const DISABLE_AFTER_FAILURES: i32 = 20;
+
+/// The number of deliveries claimed at once.
+const BATCH_SIZE: i64 = 50;
+
+/// The wait between polls when there are no pending deliveries.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The time a receiver has to respond to a delivery.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The time a claimed batch is held by the worker posting it, long enough for
+/// every receiver to time out, after which the deliveries are attempted again
+/// in case the worker went away.
+const CLAIM_SECONDS: i64 = TIMEOUT.as_secs() as i64 * BATCH_SIZE;
+
+pub struct CreateWebhookAttrs {
+    pub url: String,
+    pub event_types: Vec<String>,
+}
+
+pub struct UpdateWebhookAttrs {
+    pub url: Option<String>,
+    pub event_types: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = webhooks)]
+struct WebhookChanges {
+    url: Option<String>,
+    event_types: Option<Vec<Option<String>>>,
+    enabled: Option<bool>,
+    failure_count: Option<i32>,
+    disabled_at: Option<Option<NaiveDateTime>>,
+    updated_at: NaiveDateTime,
+}
+
+/// Get the webhook.
+pub fn get_webhook(conn: &mut PgConnection, webhook_id: Uuid) -> Result<Option<Webhook>, Error> {
+    // TODO: Handle all the errors
+    let result = webhooks::table
+        .find(webhook_id)
+        .select(Webhook::as_select())
+        .first(conn)
+        .optional();
+
+    match result {
+        Ok(Some(webhook)) => Ok(Some(webhook)),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Create a webhook with a new signing secret.
+pub fn create_webhook(
+    conn: &mut PgConnection,
+    attrs: CreateWebhookAttrs,
+) -> Result<Option<Webhook>, Error> {
+    let timestamp = Utc::now().naive_utc();
+    let changes = Webhook {
+        id: Uuid::now_v7(),
+        url: attrs.url,
+        secret: format!("whsec_{}", Uuid::new_v4().simple()),
+        event_types: attrs.event_types.into_iter().map(Some).collect(),
+        enabled: true,
+        failure_count: 0,
+        created_at: timestamp,
+        updated_at: timestamp,
+        disabled_at: None,
+    };
+
+    // TODO: Handle all the errors
+    let result = diesel::insert_into(webhooks::table)
+        .values(&changes)
+        .returning(Webhook::as_returning())
+        .get_result(conn)
+        .optional();
+
+    match result {
+        Ok(Some(webhook)) => Ok(Some(webhook)),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Update a webhook. Enabling a webhook resets its failure count.
+pub fn update_webhook(
+    conn: &mut PgConnection,
+    webhook_id: Uuid,
+    attrs: UpdateWebhookAttrs,
+) -> Result<Option<Webhook>, Error> {
+    let timestamp = Utc::now().naive_utc();
+    let changes = WebhookChanges {
+        url: attrs.url,
+        event_types: attrs
+            .event_types
+            .map(|event_types| event_types.into_iter().map(Some).collect()),
+        enabled: attrs.enabled,
+        failure_count: attrs.enabled.filter(|enabled| *enabled).map(|_| 0),
+        disabled_at: attrs
+            .enabled
+            .map(|enabled| if enabled { None } else { Some(timestamp) }),
+        updated_at: timestamp,
+    };
+
+    // TODO: Handle all the errors
+    let result = diesel::update(webhooks::table.find(webhook_id))
+        .set(&changes)
+        .returning(Webhook::as_returning())
+        .get_result(conn)
+        .optional();
+
+    match result {
+        Ok(Some(webhook)) => Ok(Some(webhook)),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Delete a webhook and its delivery history.
+pub fn delete_webhook(conn: &mut PgConnection, webhook_id: Uuid) -> Result<Option<Webhook>, Error> {
+    // TODO: Handle all the errors
+    let result = diesel::delete(webhooks::table.find(webhook_id))
+        .returning(Webhook::as_returning())
+        .get_result(conn)
+        .optional();
+
+    match result {
+        Ok(Some(webhook)) => Ok(Some(webhook)),
+        Ok(None) => Ok(None),
+        Err(_) => Ok(None),
+    }
+}
+
+/// List the deliveries of a webhook, newest first, after the given delivery.
+pub fn list_deliveries(
+    conn: &mut PgConnection,
+    webhook_id: Uuid,
+    after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>, Error> {
+    let mut query = webhook_deliveries::table
+        .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+        .order(webhook_deliveries::id.desc())
+        .limit(limit)
+        .select(WebhookDelivery::as_select())
+        .into_boxed();
+    if let Some(after) = after {
+        query = query.filter(webhook_deliveries::id.lt(after));
+    }
+
+    // TODO: Handle all the errors
+    match query.load(conn) {
+        Ok(deliveries) => Ok(deliveries),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Schedule a delivery to every enabled webhook of the tenant subscribed to
+/// the event type, in the same transaction as the change it records.
+pub fn enqueue(
+    conn: &mut PgConnection,
+    tenant_id: Uuid,
+    kind: &str,
+    data: &impl Serialize,
+) -> QueryResult<()> {
+    let subscribed: Vec<Uuid> = webhooks::table
+        .filter(webhooks::tenant_id.eq(tenant_id))
+        .filter(webhooks::enabled.eq(true))
+        .filter(webhooks::event_types.contains(vec![Some(kind.to_string())]))
+        .select(webhooks::id)
+        .load(conn)?;
+    if subscribed.is_empty() {
+        return Ok(());
+    }
+
+    let timestamp = Utc::now().naive_utc();
+    let payload = serde_json::to_value(data).map_err(|e| SerializationError(Box::new(e)))?;
+    let deliveries: Vec<WebhookDelivery> = subscribed
+        .into_iter()
+        .map(|webhook_id| WebhookDelivery {
+            id: Uuid::now_v7(),
+            webhook_id,
+            event_type: kind.to_string(),
+            payload: payload.clone(),
+            status: PENDING.to_string(),
+            attempts: 0,
+            response_code: None,
+            last_error: None,
+            next_attempt_at: timestamp,
+            delivered_at: None,
+            created_at: timestamp,
+            updated_at: timestamp,
+        })
+        .collect();
+
+    let rows: Vec<_> = deliveries
+        .iter()
+        .map(|delivery| (delivery, webhook_deliveries::tenant_id.eq(tenant_id)))
+        .collect();
+
+    diesel::insert_into(webhook_deliveries::table)
+        .values(rows)
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Get the `host:port` of an HTTP(S) URL.
+pub fn netloc(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    Some(format!(
+        "{}:{}",
+        url.host_str()?,
+        url.port_or_known_default()?
+    ))
+}
+
+/// Check whether the address is reachable from the internet rather than only
+/// from the network of the server: loopback, private, link-local (which holds
+/// the cloud metadata endpoint), shared and multicast addresses are not.
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Resolve the `host:port` of a webhook to its public addresses only, failing
+/// when it has none, so that webhooks cannot reach internal services.
+fn resolve_public(netloc: &str) -> Result<Vec<SocketAddr>, Error> {
+    let addresses: Vec<SocketAddr> = netloc
+        .to_socket_addrs()?
+        .filter(|address| is_public_address(address.ip()))
+        .collect();
+    if addresses.is_empty() {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} does not resolve to a public address", netloc),
+        ));
+    }
+
+    Ok(addresses)
+}
+
+/// Build the HTTP agent that posts deliveries, which only connects to public
+/// addresses, resolved again on every request, and does not follow redirects.
+pub fn agent() -> Agent {
+    AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .redirects(0)
+        .resolver(resolve_public)
+        .build()
+}
+
+/// Sign the delivery body as the hex-encoded HMAC-SHA256 of `{timestamp}.{body}`.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// Post the delivery to the webhook, returning the response code if any.
+fn post(
+    agent: &Agent,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+) -> (Option<i32>, Result<(), String>) {
+    let timestamp = Utc::now().timestamp();
+    let data = match decrypt_fields(delivery.payload.clone()) {
+        Ok(data) => data,
+        Err(e) => return (None, Err(e.to_string())),
+    };
+    let body = json!({
+        "id": delivery.id,
+        "type": delivery.event_type,
+        "createdAt": delivery.created_at.and_utc().to_rfc3339(),
+        "data": data,
+    })
+    .to_string();
+    let signature = sign(&webhook.secret, timestamp, &body);
+    let result = agent
+        .post(&webhook.url)
+        .set("Content-Type", "application/json")
+        .set("Webhook-Id", &delivery.id.to_string())
+        .set("Webhook-Event", &delivery.event_type)
+        .set("Webhook-Timestamp", &timestamp.to_string())
+        .set("Webhook-Signature", &format!("sha256={}", signature))
+        .send_string(&body);
+
+    match result {
+        Ok(response) => (Some(response.status().into()), Ok(())),
+        Err(ureq::Error::Status(code, _)) => (Some(code.into()), Err(format!("HTTP {}", code))),
+        Err(e) => (None, Err(e.to_string())),
+    }
+}
+
+/// Claim a batch of due deliveries, by pushing back their next attempt so
+/// that no other worker attempts them while they are posted.
+fn claim_batch(conn: &mut PgConnection) -> QueryResult<Vec<(WebhookDelivery, Webhook)>> {
+    conn.transaction(|conn| {
+        let timestamp = Utc::now().naive_utc();
+        let batch: Vec<(WebhookDelivery, Webhook)> = webhook_deliveries::table
+            .inner_join(webhooks::table)
+            .filter(webhook_deliveries::status.eq(PENDING))
+            .filter(webhook_deliveries::next_attempt_at.le(timestamp))
+            .filter(webhooks::enabled.eq(true))
+            .order(webhook_deliveries::next_attempt_at.asc())
+            .limit(BATCH_SIZE)
+            .for_update()
+            .skip_locked()
+            .select((WebhookDelivery::as_select(), Webhook::as_select()))
+            .load(conn)?;
+        let ids: Vec<Uuid> = batch.iter().map(|(delivery, _)| delivery.id).collect();
+
+        diesel::update(webhook_deliveries::table.filter(webhook_deliveries::id.eq_any(ids)))
+            .set(
+                webhook_deliveries::next_attempt_at
+                    .eq(timestamp + chrono::Duration::seconds(CLAIM_SECONDS)),
+            )
+            .execute(conn)?;
+
+        Ok(batch)
+    })
+}
+
+/// Record the result of a delivery attempt. A failed delivery is retried with
+/// exponential backoff until it runs out of attempts, and a webhook that keeps
+/// failing is disabled.
+fn record_attempt(
+    conn: &mut PgConnection,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+    response_code: Option<i32>,
+    result: Result<(), String>,
+) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        let timestamp = Utc::now().naive_utc();
+        let attempts = delivery.attempts + 1;
+
+        match result {
+            Ok(()) => {
+                diesel::update(webhook_deliveries::table.find(delivery.id))
+                    .set((
+                        webhook_deliveries::status.eq(DELIVERED),
+                        webhook_deliveries::attempts.eq(attempts),
+                        webhook_deliveries::response_code.eq(response_code),
+                        webhook_deliveries::last_error.eq(None::<String>),
+                        webhook_deliveries::delivered_at.eq(timestamp),
+                        webhook_deliveries::updated_at.eq(timestamp),
+                    ))
+                    .execute(conn)?;
+                diesel::update(webhooks::table.find(webhook.id))
+                    .set(webhooks::failure_count.eq(0))
+                    .execute(conn)?;
+            }
+            Err(e) => {
+                let status = if attempts >= MAX_ATTEMPTS {
+                    FAILED
+                } else {
+                    PENDING
+                };
+                diesel::update(webhook_deliveries::table.find(delivery.id))
+                    .set((
+                        webhook_deliveries::status.eq(status),
+                        webhook_deliveries::attempts.eq(attempts),
+                        webhook_deliveries::response_code.eq(response_code),
+                        webhook_deliveries::last_error.eq(e),
+                        webhook_deliveries::next_attempt_at
+                            .eq(timestamp + backoff(delivery.attempts)),
+                        webhook_deliveries::updated_at.eq(timestamp),
+                    ))
+                    .execute(conn)?;
+                // The webhook may have been deleted while the delivery was posted.
+                let failure_count: Option<i32> = diesel::update(webhooks::table.find(webhook.id))
+                    .set(webhooks::failure_count.eq(webhooks::failure_count + 1))
+                    .returning(webhooks::failure_count)
+                    .get_result(conn)
+                    .optional()?;
+                if let Some(failure_count) =
+                    failure_count.filter(|count| *count >= DISABLE_AFTER_FAILURES)
+                {
+                    warn!(
+                        "Disabling webhook {} after {} failures",
+                        webhook.id, failure_count
+                    );
+                    diesel::update(webhooks::table.find(webhook.id))
+                        .set((
+                            webhooks::enabled.eq(false),
+                            webhooks::disabled_at.eq(timestamp),
+                            webhooks::updated_at.eq(timestamp),
+                        ))
+                        .execute(conn)?;
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Attempt a batch of due deliveries, returning the number attempted.
+///
+/// The batch is claimed in a short transaction, and every delivery is posted
+/// outside of any transaction, then recorded on its own.
+pub fn deliver_batch(conn: &mut PgConnection, agent: &Agent) -> QueryResult<usize> {
+    let batch = claim_batch(conn)?;
+    let attempted = batch.len();
+
+    for (delivery, webhook) in batch {
+        let (response_code, result) = post(agent, &webhook, &delivery);
+        record_attempt(conn, &webhook, &delivery, response_code, result)?;
+    }
+
+    Ok(attempted)
+}
+
+/// Deliver pending webhook deliveries until the process exits.
+pub async fn deliver(pool: Pool) {
+    let agent = agent();
+
+    loop {
+        let agent = agent.clone();
+        let result = match pool.get().await {
+            Ok(conn) => conn
+                .interact(move |conn| deliver_batch(conn, &agent))
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(Ok(attempted)) if attempted > 0 => continue,
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("Failed to deliver webhooks: {}", e),
+            Err(e) => warn!("Failed to deliver webhooks: {}", e),
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::repo;
+    use crate::core::users::create_user;
+    use crate::core::users::CreateUserAttrs;
+    use diesel::Connection;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Start a local stand-in receiver that answers one request with the status.
+    fn start_receiver(status: u16) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 8192];
+            let _ = stream.read(&mut buffer).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n",
+                status
+            )
+            .unwrap();
+        });
+
+        format!("http://{}/webhook", address)
+    }
+
+    fn insert_webhook(conn: &mut PgConnection, url: String, kind: &str) -> Webhook {
+        let attrs = CreateWebhookAttrs {
+            url,
+            event_types: vec![kind.to_string()],
+        };
+
+        create_webhook(conn, attrs).unwrap().unwrap()
+    }
+
+    /// Get an event type that only the calling test subscribes to.
+    fn unique_kind() -> String {
+        format!("test.{}", Uuid::now_v7())
+    }
+
+    #[test]
+    fn test_sign() {
+        let signature = sign("secret", 1720000000, "{}");
+
+        assert_eq!(signature.len(), 64);
+        assert_eq!(signature, sign("secret", 1720000000, "{}"));
+        assert_ne!(signature, sign("secret", 1720000001, "{}"));
+    }
+
+    #[test]
+    fn test_create_webhook() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let kind = unique_kind();
+        let webhook = insert_webhook(&mut conn, "http://localhost/webhook".to_string(), &kind);
+
+        assert!(webhook.secret.starts_with("whsec_"));
+        assert!(webhook.enabled);
+        assert_eq!(webhook.event_types, vec![Some(kind)]);
+
+        delete_webhook(&mut conn, webhook.id).unwrap();
+    }
+
+    #[test]
+    fn test_update_webhook_enable() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let webhook = insert_webhook(
+            &mut conn,
+            "http://localhost/webhook".to_string(),
+            &unique_kind(),
+        );
+        let attrs = UpdateWebhookAttrs {
+            url: None,
+            event_types: None,
+            enabled: Some(false),
+        };
+        let disabled = update_webhook(&mut conn, webhook.id, attrs)
+            .unwrap()
+            .unwrap();
+        let attrs = UpdateWebhookAttrs {
+            url: None,
+            event_types: None,
+            enabled: Some(true),
+        };
+        let enabled = update_webhook(&mut conn, webhook.id, attrs)
+            .unwrap()
+            .unwrap();
+
+        assert!(!disabled.enabled);
+        assert_ne!(disabled.disabled_at, None);
+        assert!(enabled.enabled);
+        assert_eq!(enabled.disabled_at, None);
+
+        delete_webhook(&mut conn, webhook.id).unwrap();
+    }
+
+    #[test]
+    fn test_enqueue_with_user() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        // Keep the webhook to this test, rather than subscribing it to the
+        // users created by all the others.
+        conn.begin_test_transaction().unwrap();
+        let webhook = insert_webhook(
+            &mut conn,
+            "http://localhost/webhook".to_string(),
+            "user.created",
+        );
+        let attrs = CreateUserAttrs {
+            first_name: "Jane".to_string(),
+            last_name: "Doe".to_string(),
+            email_address: format!("jane.{}@doe.com", Uuid::now_v7().simple()),
+        };
+        let user = create_user(&mut conn, attrs).unwrap().unwrap();
+        let deliveries = list_deliveries(&mut conn, webhook.id, None, 100).unwrap();
+
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].payload["id"], user.id.to_string());
+    }
+
+    #[test]
+    fn test_enqueue_other_tenant() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let kind = unique_kind();
+        let webhook = insert_webhook(&mut conn, "http://localhost/webhook".to_string(), &kind);
+        enqueue(&mut conn, Uuid::now_v7(), &kind, &json!({})).unwrap();
+        let deliveries = list_deliveries(&mut conn, webhook.id, None, 10).unwrap();
+
+        assert!(deliveries.is_empty());
+
+        delete_webhook(&mut conn, webhook.id).unwrap();
+    }
+
+    #[test]
+    fn test_deliver_batch() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let kind = unique_kind();
+        let webhook = insert_webhook(&mut conn, start_receiver(204), &kind);
+        enqueue(&mut conn, repo::DEFAULT_TENANT, &kind, &json!({})).unwrap();
+
+        // The stand-in receiver listens on loopback, which `agent` refuses.
+        while deliver_batch(&mut conn, &ureq::agent()).unwrap() > 0 {}
+        let deliveries = list_deliveries(&mut conn, webhook.id, None, 10).unwrap();
+
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].status, DELIVERED);
+        assert_eq!(deliveries[0].response_code, Some(204));
+        assert_eq!(deliveries[0].attempts, 1);
+
+        delete_webhook(&mut conn, webhook.id).unwrap();
+    }
+
+    #[test]
+    fn test_deliver_batch_failed() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let kind = unique_kind();
+        let webhook = insert_webhook(&mut conn, start_receiver(500), &kind);
+        diesel::update(webhooks::table.find(webhook.id))
+            .set(webhooks::failure_count.eq(DISABLE_AFTER_FAILURES - 1))
+            .execute(&mut conn)
+            .unwrap();
+        enqueue(&mut conn, repo::DEFAULT_TENANT, &kind, &json!({})).unwrap();
+
+        // The stand-in receiver listens on loopback, which `agent` refuses.
+        while deliver_batch(&mut conn, &ureq::agent()).unwrap() > 0 {}
+        let deliveries = list_deliveries(&mut conn, webhook.id, None, 10).unwrap();
+        let webhook = get_webhook(&mut conn, webhook.id).unwrap().unwrap();
+
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].status, PENDING);
+        assert_eq!(deliveries[0].response_code, Some(500));
+        assert_eq!(deliveries[0].last_error, Some("HTTP 500".to_string()));
+        assert!(!webhook.enabled);
+        assert_ne!(webhook.disabled_at, None);
+
+        delete_webhook(&mut conn, webhook.id).unwrap();
+    }
+
+    #[test]
+    fn test_is_public_address() {
+        let public = |ip: &str| is_public_address(ip.parse().unwrap());
+
+        assert!(public("93.184.216.34"));
+        assert!(public("2606:2800:220:1::"));
+        assert!(!public("127.0.0.1"));
+        assert!(!public("10.0.0.1"));
+        assert!(!public("172.16.0.1"));
+        assert!(!public("192.168.1.1"));
+        assert!(!public("169.254.169.254"));
+        assert!(!public("100.100.100.200"));
+        assert!(!public("0.0.0.0"));
+        assert!(!public("::1"));
+        assert!(!public("fd00:ec2::254"));
+        assert!(!public("fe80::1"));
+        assert!(!public("::ffff:127.0.0.1"));
+    }
+
+    #[test]
+    fn test_netloc() {
+        assert_eq!(
+            netloc("https://example.com/webhook"),
+            Some("example.com:443".to_string())
+        );
+        assert_eq!(
+            netloc("http://[::1]:8080/webhook"),
+            Some("[::1]:8080".to_string())
+        );
+        assert_eq!(netloc("ftp://example.com/webhook"), None);
+        assert_eq!(netloc("not a url"), None);
+    }
+
+    #[test]
+    fn test_agent_internal_address() {
+        let error = agent()
+            .post("http://127.0.0.1:9/webhook")
+            .send_string("{}")
+            .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("does not resolve to a public address"));
+    }
+}
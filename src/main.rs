@@ -1,9 +1,30 @@
+use crate::core::encryption;
+use crate::core::events;
+use crate::core::exports;
+use crate::core::exports::ExportFilter;
+use crate::core::exports::ExportFormat;
+use crate::core::imports;
+use crate::core::imports::ImportFormat;
+use crate::core::jobs;
+use crate::core::masking::MaskingPolicy;
+use crate::core::outbox;
+use crate::core::repo;
 use crate::core::repo::connect_database;
+use crate::core::repo::DEFAULT_TENANT;
+use crate::core::scheduler;
+use crate::core::webhooks;
 use crate::server::build_schema;
+use crate::server::build_validation_schema;
+use crate::server::hash_query;
 use crate::server::start_server;
+use crate::server::Manifest;
+use chrono::DateTime;
+use chrono::Utc;
 use clap::Parser;
+use clap::Subcommand;
 use config::get_config;
 use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
 
 mod config;
 mod core;
@@ -14,12 +35,70 @@ mod test;
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Export the GraphQL SDL
     #[arg(short, long, default_value_t = false)]
     export: bool,
+    /// Validate a persisted query manifest
+    #[arg(short, long)]
+    manifest: Option<String>,
+    /// Print a new encryption key, to add to the keys
+    #[arg(long, default_value_t = false)]
+    generate_key: bool,
+    /// Re-encrypt the personal data of users with the current key, and index it for search
+    #[arg(long, default_value_t = false)]
+    reencrypt: bool,
     /// Start the web server
     #[arg(short, long, default_value_t = false)]
     server: bool,
+    /// Start the job worker
+    #[arg(short, long, default_value_t = false)]
+    worker: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage users
+    Users {
+        #[command(subcommand)]
+        command: UsersCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum UsersCommand {
+    /// Import users from a CSV, JSON or NDJSON file
+    Import {
+        /// The file to import, in the format of its extension
+        path: String,
+        /// Check the imported users without saving them
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+        /// Import the users of the tenant, or the default tenant
+        #[arg(long)]
+        tenant: Option<Uuid>,
+    },
+    /// Export users to a CSV, NDJSON or Parquet file
+    Export {
+        /// The file to export to, in the format of its extension
+        path: String,
+        /// Export the users of the tenant, or the default tenant
+        #[arg(long)]
+        tenant: Option<Uuid>,
+        /// Export the deleted users too
+        #[arg(long, default_value_t = false)]
+        include_deleted: bool,
+        /// Export only the users whose email address is verified, or not
+        #[arg(long)]
+        email_verified: Option<bool>,
+        /// Export only the users created at or after the time
+        #[arg(long)]
+        created_after: Option<DateTime<Utc>>,
+        /// Export only the users created before the time
+        #[arg(long)]
+        created_before: Option<DateTime<Utc>>,
+    },
 }
 
 #[tokio::main]
@@ -29,6 +108,40 @@ async fn main() {
     if args.export {
         export_server_gql();
     }
+    if let Some(path) = args.manifest {
+        validate_manifest(&path).await;
+    }
+    if args.generate_key {
+        println!("{}", encryption::generate_key());
+    }
+    if args.reencrypt {
+        reencrypt_users().await;
+    }
+    if let Some(Command::Users { command }) = args.command {
+        match command {
+            UsersCommand::Import {
+                path,
+                dry_run,
+                tenant,
+            } => import_users(&path, tenant.unwrap_or(DEFAULT_TENANT), dry_run).await,
+            UsersCommand::Export {
+                path,
+                tenant,
+                include_deleted,
+                email_verified,
+                created_after,
+                created_before,
+            } => {
+                let filter = ExportFilter {
+                    include_deleted,
+                    email_verified,
+                    created_after: created_after.map(|datetime| datetime.naive_utc()),
+                    created_before: created_before.map(|datetime| datetime.naive_utc()),
+                };
+                export_users(&path, tenant.unwrap_or(DEFAULT_TENANT), filter).await;
+            }
+        }
+    }
     if args.server {
         tracing_subscriber::fmt()
             .with_env_filter(EnvFilter::from_default_env())
@@ -36,11 +149,151 @@ async fn main() {
 
         let config = get_config();
         let database = connect_database(&config.database_url);
+        events::listen(&config.database_url).await;
+        tokio::spawn(outbox::relay(
+            database.clone(),
+            outbox::build_sink(&config.outbox_sink),
+        ));
+        tokio::spawn(webhooks::deliver(database.clone()));
 
         start_server(&config.endpoint_url, database).await;
     }
+    if args.worker {
+        tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .init();
+
+        let config = get_config();
+        let database = connect_database(&config.database_url);
+        tokio::spawn(scheduler::start_scheduler(
+            database.clone(),
+            scheduler::scheduler(),
+        ));
+
+        jobs::start_worker(database, jobs::registry(), config.worker_concurrency).await;
+    }
 }
 
 fn export_server_gql() {
     std::fs::write("docs/server.gql", &build_schema().sdl()).unwrap();
 }
+
+async fn validate_manifest(path: &str) {
+    let manifest =
+        Manifest::load(path).unwrap_or_else(|e| panic!("Failed to load manifest: {:?}", e));
+    let schema = build_validation_schema();
+    let mut invalid = 0;
+
+    for operation in &manifest.operations {
+        let name = operation.name.as_deref().unwrap_or(&operation.id);
+
+        if hash_query(&operation.body) != operation.id {
+            eprintln!("{}: id does not match the body hash", name);
+            invalid += 1;
+            continue;
+        }
+
+        let response = schema.execute(operation.body.as_str()).await;
+        for error in &response.errors {
+            eprintln!("{}: {}", name, error.message);
+        }
+        if response.is_err() {
+            invalid += 1;
+        }
+    }
+
+    if invalid > 0 {
+        eprintln!(
+            "{} of {} operations are invalid",
+            invalid,
+            manifest.operations.len()
+        );
+        std::process::exit(1);
+    }
+    println!("{} operations are valid", manifest.operations.len());
+}
+
+async fn import_users(path: &str, tenant_id: Uuid, dry_run: bool) {
+    let format = ImportFormat::from_file_name(path)
+        .unwrap_or_else(|| panic!("Unknown import format: {}", path));
+    let file =
+        std::fs::File::open(path).unwrap_or_else(|e| panic!("Failed to open {}: {:?}", path, e));
+    let database = connect_database(&get_config().database_url);
+    let conn = database.get().await.unwrap();
+    let report = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                imports::import_users(conn, file, format, dry_run)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap_or_else(|e| panic!("Failed to import {}: {}", path, e));
+
+    for error in &report.errors {
+        eprintln!("row {}: {}", error.row, error.message);
+    }
+    match dry_run {
+        true => println!(
+            "{} of {} users would be imported",
+            report.imported, report.rows
+        ),
+        false => println!("{} of {} users imported", report.imported, report.rows),
+    }
+    if !report.errors.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+async fn export_users(path: &str, tenant_id: Uuid, filter: ExportFilter) {
+    let format = ExportFormat::from_file_name(path)
+        .unwrap_or_else(|| panic!("Unknown export format: {}", path));
+    let file = std::fs::File::create(path)
+        .unwrap_or_else(|e| panic!("Failed to create {}: {:?}", path, e));
+    let database = connect_database(&get_config().database_url);
+    let conn = database.get().await.unwrap();
+    let progress = |_: &mut _, count| {
+        eprintln!("{} users exported", count);
+        Ok(())
+    };
+    let count = conn
+        .interact(move |conn| {
+            let mut writer = std::io::BufWriter::new(file);
+            let masking = MaskingPolicy::default();
+            let count = exports::write_users(
+                conn,
+                tenant_id,
+                &mut writer,
+                format,
+                &filter,
+                &masking,
+                progress,
+            )?;
+            std::io::Write::flush(&mut writer)?;
+            Ok::<_, std::io::Error>(count)
+        })
+        .await
+        .unwrap()
+        .unwrap_or_else(|e| panic!("Failed to export {}: {}", path, e));
+
+    println!("{} users exported to {}", count, path);
+}
+
+async fn reencrypt_users() {
+    let database = connect_database(&get_config().database_url);
+    let conn = database.get().await.unwrap();
+    let reencrypted = conn
+        .interact(encryption::reencrypt_users)
+        .await
+        .unwrap()
+        .unwrap_or_else(|e| panic!("Failed to re-encrypt users: {}", e));
+
+    println!(
+        "{} users, {} versions, {} audit events, {} email changes and {} invitations re-encrypted",
+        reencrypted.users,
+        reencrypted.versions,
+        reencrypted.events,
+        reencrypted.email_changes,
+        reencrypted.invitations
+    );
+}
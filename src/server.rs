@@ -1,16 +1,32 @@
+use crate::server::idempotency::IdempotencyKey;
+pub use crate::server::persisted::hash_query;
+pub use crate::server::persisted::Manifest;
 pub use crate::server::schema::build_schema;
+pub use crate::server::schema::build_validation_schema;
 use crate::server::schema::create_schema;
 use crate::server::schema::GraphSchema;
+use crate::server::viewer::authenticate;
+use crate::server::viewer::Viewer;
 use async_graphql::http::GraphiQLSource;
+use async_graphql::http::ALL_WEBSOCKET_PROTOCOLS;
+use async_graphql::Data;
+use async_graphql_axum::GraphQLProtocol;
 use async_graphql_axum::GraphQLRequest;
 use async_graphql_axum::GraphQLResponse;
+use async_graphql_axum::GraphQLWebSocket;
+use axum::extract::ConnectInfo;
 use axum::extract::State;
+use axum::extract::WebSocketUpgrade;
+use axum::http::HeaderMap;
 use axum::http::StatusCode;
 use axum::response::Html;
+use axum::response::IntoResponse;
 use axum::response::Json;
+use axum::response::Response;
 use axum::routing::get;
 use axum::routing::post;
 use axum::serve;
+use axum::Extension;
 use axum::Router;
 use deadpool_diesel::postgres::Pool;
 use serde_json::json;
@@ -18,35 +34,93 @@ use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tracing::info;
 
+mod idempotency;
+mod limits;
+mod persisted;
 mod resolvers;
 mod schema;
+mod viewer;
 
 /// Start the web server
 pub async fn start_server(endpoint_url: &String, database: Pool) {
-    let schema = create_schema(database);
+    let schema = create_schema(database.clone());
     let server = Router::new()
         .route("/", get(graphql_html))
         .route("/graph", post(graphql_json))
+        .route("/graph/ws", get(graphql_ws))
         .fallback(fallback_json)
+        .layer(Extension(database))
         .with_state(schema);
     let address: SocketAddr = endpoint_url.parse().unwrap();
     let listener = TcpListener::bind(&address).await.unwrap();
 
     info!("Running endpoint at {} (http)", address);
-    serve(listener, server).await.unwrap();
+    serve(
+        listener,
+        server.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 /// Render the GraphiQL Playground HTML.
 async fn graphql_html() -> (StatusCode, Html<String>) {
     (
         StatusCode::OK,
-        Html(GraphiQLSource::build().endpoint("/graph").finish()),
+        Html(
+            GraphiQLSource::build()
+                .endpoint("/graph")
+                .subscription_endpoint("/graph/ws")
+                .finish(),
+        ),
     )
 }
 
-/// Render the GraphQL JSON.
-async fn graphql_json(state: State<GraphSchema>, req: GraphQLRequest) -> GraphQLResponse {
-    state.execute(req.into_inner()).await.into()
+/// Render the GraphQL JSON, for the viewer of the bearer token. Mutations sent
+/// with an `Idempotency-Key` header are run at most once per key.
+async fn graphql_json(
+    state: State<GraphSchema>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    let viewer = Viewer::from_headers(&headers).with_ip_address(address.ip());
+    let mut request = req.into_inner().data(viewer);
+    if let Some(key) = IdempotencyKey::from_headers(&headers) {
+        request = request.data(key);
+    }
+
+    state.execute(request).await.into()
+}
+
+/// Serve the GraphQL subscriptions over a websocket, for the viewer of the
+/// bearer token of the upgrade request or of the connection payload. The
+/// connection is refused without a signed-in viewer.
+async fn graphql_ws(
+    state: State<GraphSchema>,
+    Extension(pool): Extension<Pool>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    protocol: GraphQLProtocol,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let viewer = Viewer::from_headers(&headers).with_ip_address(address.ip());
+    let schema = state.0;
+
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            GraphQLWebSocket::new(stream, schema, protocol)
+                .on_connection_init(move |payload| async move {
+                    let viewer = viewer.with_connection_payload(&payload);
+                    authenticate(&pool, Some(&viewer)).await?;
+                    let mut data = Data::default();
+                    data.insert(viewer);
+                    Ok(data)
+                })
+                .serve()
+        })
+        .into_response()
 }
 
 /// Render the fallback JSON.
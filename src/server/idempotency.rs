@@ -0,0 +1,312 @@
+use crate::core::idempotency;
+use crate::core::idempotency::Claim;
+use crate::core::idempotency::MAX_KEY_LENGTH;
+use crate::core::repo;
+use crate::core::repo::DEFAULT_TENANT;
+use crate::server::resolvers::errors::server_error;
+use crate::server::resolvers::errors::GqlError;
+use crate::server::resolvers::errors::GqlError::IdempotencyKeyInProgress;
+use crate::server::resolvers::errors::GqlError::IdempotencyKeyMismatch;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use crate::server::viewer::Viewer;
+use async_graphql::async_trait::async_trait;
+use async_graphql::extensions::Extension;
+use async_graphql::extensions::ExtensionContext;
+use async_graphql::extensions::ExtensionFactory;
+use async_graphql::extensions::NextExecute;
+use async_graphql::extensions::NextParseQuery;
+use async_graphql::parser::types::ExecutableDocument;
+use async_graphql::parser::types::OperationType;
+use async_graphql::Response;
+use async_graphql::ServerResult;
+use async_graphql::Variables;
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use deadpool_diesel::postgres::Pool;
+use sha2::Digest;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// The header of the key a client sends to retry a mutation safely.
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// The header set on the responses replayed for a retry.
+const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// The idempotency key of a request, with the credentials it was sent with,
+/// so that a key never replays a response to another viewer.
+#[derive(Debug)]
+pub struct IdempotencyKey {
+    key: String,
+    credentials: Option<String>,
+}
+
+impl IdempotencyKey {
+    /// Take the key from the `Idempotency-Key` header, if any.
+    pub fn from_headers(headers: &HeaderMap) -> Option<IdempotencyKey> {
+        let key = headers
+            .get(IDEMPOTENCY_KEY)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())?;
+        let credentials = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+
+        Some(IdempotencyKey { key, credentials })
+    }
+}
+
+/// The request being run, once parsed: the hash of its content and the names
+/// of its mutations.
+struct Parsed {
+    hash: Sha256,
+    mutations: Vec<Option<String>>,
+}
+
+/// Run the mutations sent with an idempotency key at most once: the response
+/// is stored, and replayed to the retries of the same request until the key
+/// expires. Reusing a key for a different request fails. The keys are kept
+/// apart by tenant and viewer, anonymous viewers sharing the nil id in the
+/// default tenant.
+pub struct Idempotency;
+
+impl ExtensionFactory for Idempotency {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(IdempotencyExtension {
+            parsed: Mutex::new(None),
+        })
+    }
+}
+
+struct IdempotencyExtension {
+    parsed: Mutex<Option<Parsed>>,
+}
+
+#[async_trait]
+impl Extension for IdempotencyExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+
+        if let Some(key) = ctx.data_opt::<IdempotencyKey>() {
+            let mut hash = Sha256::new();
+            hash.update(serde_json::to_vec(&(&key.credentials, query, variables)).unwrap());
+            let mutations = document
+                .operations
+                .iter()
+                .filter(|(_, operation)| operation.node.ty == OperationType::Mutation)
+                .map(|(name, _)| name.map(|name| name.to_string()))
+                .collect();
+            *self.parsed.lock().unwrap() = Some(Parsed { hash, mutations });
+        }
+
+        Ok(document)
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let parsed = self.parsed.lock().unwrap().take();
+        let (key, mut hash) = match (ctx.data_opt::<IdempotencyKey>(), parsed) {
+            (Some(key), Some(parsed))
+                if parsed
+                    .mutations
+                    .iter()
+                    .any(|name| operation_name.is_none() || name.as_deref() == operation_name) =>
+            {
+                (key.key.clone(), parsed.hash)
+            }
+            _ => return next.run(ctx, operation_name).await,
+        };
+        if key.len() > MAX_KEY_LENGTH {
+            let reason = format!("The idempotency key exceeds {} characters", MAX_KEY_LENGTH);
+            return error_response(UnprocessableContent(reason));
+        }
+        hash.update(operation_name.unwrap_or_default().as_bytes());
+        let request_hash = format!("{:x}", hash.finalize());
+        let pool = ctx.data::<Pool>().unwrap();
+        let (tenant_id, viewer_id) = match ctx.data_opt::<Viewer>() {
+            Some(viewer) => match viewer.identity(pool).await {
+                Ok(identity) => identity.map_or((DEFAULT_TENANT, Uuid::nil()), |identity| {
+                    (identity.tenant_id, identity.user_id)
+                }),
+                // The resolvers report the credentials that are not valid.
+                Err(_) => return next.run(ctx, operation_name).await,
+            },
+            None => (DEFAULT_TENANT, Uuid::nil()),
+        };
+
+        let claim = {
+            let key = key.clone();
+            match pool.get().await {
+                Ok(conn) => {
+                    conn.interact(move |conn| {
+                        repo::with_tenant(conn, tenant_id, |conn| {
+                            idempotency::claim_key(conn, viewer_id, &key, &request_hash)
+                        })
+                    })
+                    .await
+                }
+                Err(_) => return error_response(InternalServer),
+            }
+        };
+        match claim {
+            Ok(Ok(Claim::Started)) => {}
+            Ok(Ok(Claim::Completed(stored))) => return replay(stored),
+            Ok(Ok(Claim::InProgress)) => return error_response(IdempotencyKeyInProgress),
+            Ok(Ok(Claim::Mismatch)) => return error_response(IdempotencyKeyMismatch),
+            Ok(Err(_)) | Err(_) => return error_response(InternalServer),
+        }
+
+        let response = next.run(ctx, operation_name).await;
+        // A request that failed on the server is released, so a retry runs it again.
+        let failed = response.errors.iter().any(|error| {
+            error
+                .extensions
+                .as_ref()
+                .and_then(|extensions| extensions.get("code"))
+                == Some(&"INTERNAL_SERVER".into())
+        });
+        let stored = serde_json::to_value(&response).unwrap();
+        if let Ok(conn) = pool.get().await {
+            let _ = conn
+                .interact(move |conn| {
+                    repo::with_tenant(conn, tenant_id, |conn| match failed {
+                        true => idempotency::release_key(conn, viewer_id, &key),
+                        false => idempotency::complete_key(conn, viewer_id, &key, stored),
+                    })
+                })
+                .await;
+        }
+
+        response
+    }
+}
+
+/// Rebuild the stored response of a request, for a retry.
+fn replay(stored: serde_json::Value) -> Response {
+    match serde_json::from_value::<Response>(stored) {
+        Ok(mut response) => {
+            response
+                .http_headers
+                .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+            response
+        }
+        Err(_) => error_response(InternalServer),
+    }
+}
+
+fn error_response(error: GqlError) -> Response {
+    Response::from_errors(vec![server_error(error)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::repo;
+    use crate::server::schema::Mutation;
+    use crate::server::schema::Query;
+    use crate::server::schema::Subscription;
+    use async_graphql::Request;
+    use async_graphql::Schema;
+    use async_graphql::Value;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    const CREATE_USER: &str = "mutation($email: String!) {
+        createUser(input: {firstName: \"Jane\", lastName: \"Doe\", emailAddress: $email}) { id }
+    }";
+
+    async fn execute(pool: &Pool, key: &str, email: &str) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert(IDEMPOTENCY_KEY, HeaderValue::from_str(key).unwrap());
+        let request = Request::new(CREATE_USER)
+            .variables(Variables::from_json(json!({ "email": email })))
+            .data(IdempotencyKey::from_headers(&headers).unwrap());
+
+        Schema::build(
+            Query::default(),
+            Mutation::default(),
+            Subscription::default(),
+        )
+        .data(pool.clone())
+        .extension(Idempotency)
+        .finish()
+        .execute(request)
+        .await
+    }
+
+    fn error_code(response: &Response) -> Value {
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+
+        extensions.get("code").unwrap().clone()
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+
+        assert!(IdempotencyKey::from_headers(&headers).is_none());
+
+        headers.insert(IDEMPOTENCY_KEY, HeaderValue::from_static(" abc "));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer xyz"));
+        let key = IdempotencyKey::from_headers(&headers).unwrap();
+
+        assert_eq!(key.key, "abc");
+        assert_eq!(key.credentials, Some("Bearer xyz".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let pool = repo::connect_database(&config::get_config().database_url);
+        let key = Uuid::now_v7().to_string();
+        let email = format!("jane.{}@doe.com", Uuid::now_v7().simple());
+
+        let first = execute(&pool, &key, &email).await;
+        let retry = execute(&pool, &key, &email).await;
+
+        assert!(first.errors.is_empty());
+        assert_eq!(retry.data, first.data);
+        assert!(retry.errors.is_empty());
+        assert_eq!(retry.http_headers.get(IDEMPOTENT_REPLAYED).unwrap(), "true");
+        assert!(first.http_headers.get(IDEMPOTENT_REPLAYED).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mismatch() {
+        let pool = repo::connect_database(&config::get_config().database_url);
+        let key = Uuid::now_v7().to_string();
+
+        let first = execute(&pool, &key, &format!("jane.{}@doe.com", Uuid::now_v7())).await;
+        let other = execute(&pool, &key, &format!("john.{}@doe.com", Uuid::now_v7())).await;
+
+        assert!(first.errors.is_empty());
+        assert_eq!(error_code(&other), "IDEMPOTENCY_KEY_MISMATCH".into());
+    }
+
+    #[tokio::test]
+    async fn test_key_too_long() {
+        let pool = repo::connect_database(&config::get_config().database_url);
+        let key = "k".repeat(MAX_KEY_LENGTH + 1);
+
+        let response = execute(&pool, &key, "jane@doe.com").await;
+
+        assert_eq!(error_code(&response), "UNPROCESSABLE_CONTENT".into());
+    }
+}
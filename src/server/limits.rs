@@ -0,0 +1,334 @@
+use crate::server::resolvers::errors::server_error;
+use crate::server::resolvers::errors::GqlError::QueryTooComplex;
+use crate::server::resolvers::errors::GqlError::QueryTooDeep;
+use crate::server::resolvers::errors::GqlError::TooManyAliases;
+use crate::server::resolvers::errors::GqlError::TooManyRootFields;
+use crate::server::resolvers::errors::GqlError::TooManyTokens;
+use crate::server::resolvers::pagination::page_size;
+use async_graphql::async_trait::async_trait;
+use async_graphql::extensions::Extension;
+use async_graphql::extensions::ExtensionContext;
+use async_graphql::extensions::ExtensionFactory;
+use async_graphql::extensions::NextParseQuery;
+use async_graphql::extensions::NextValidation;
+use async_graphql::parser::types::ExecutableDocument;
+use async_graphql::parser::types::Selection;
+use async_graphql::parser::types::SelectionSet;
+use async_graphql::Name;
+use async_graphql::ServerError;
+use async_graphql::ServerResult;
+use async_graphql::ValidationResult;
+use async_graphql::Variables;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::Arc;
+
+/// The limits a query must stay within before it is executed.
+#[derive(Clone, Copy, Debug)]
+pub struct QueryLimits {
+    pub max_depth: usize,
+    pub max_complexity: usize,
+    pub max_aliases: usize,
+    pub max_root_fields: usize,
+    pub max_tokens: usize,
+}
+
+impl ExtensionFactory for QueryLimits {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(*self)
+    }
+}
+
+#[async_trait]
+impl Extension for QueryLimits {
+    /// Reject queries with too many tokens, aliases or root fields.
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        if count_tokens(query) > self.max_tokens {
+            return Err(server_error(TooManyTokens(self.max_tokens)));
+        }
+
+        let document = next.run(ctx, query, variables).await?;
+        let mut aliases = SelectionCounter::new(&document, Count::Aliases);
+        let mut root_fields = SelectionCounter::new(&document, Count::RootFields);
+
+        for (_, operation) in document.operations.iter() {
+            if aliases.count(&operation.node.selection_set.node) > self.max_aliases {
+                return Err(server_error(TooManyAliases(self.max_aliases)));
+            }
+            if root_fields.count(&operation.node.selection_set.node) > self.max_root_fields {
+                return Err(server_error(TooManyRootFields(self.max_root_fields)));
+            }
+        }
+
+        Ok(document)
+    }
+
+    /// Reject queries that are nested too deep or are too complex.
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+
+        if result.depth > self.max_depth {
+            return Err(vec![server_error(QueryTooDeep(self.max_depth))]);
+        }
+        if result.complexity > self.max_complexity {
+            return Err(vec![server_error(QueryTooComplex(self.max_complexity))]);
+        }
+
+        Ok(result)
+    }
+}
+
+/// Get the complexity of a connection field, whose children are resolved once
+/// for every requested node.
+pub fn connection_complexity(first: Option<i32>, child_complexity: usize) -> usize {
+    5 + page_size(first) as usize * child_complexity
+}
+
+/// Count the lexical tokens of a query, ignoring whitespace, commas and comments.
+fn count_tokens(query: &str) -> usize {
+    let mut chars = query.chars().peekable();
+    let mut count = 0;
+
+    while let Some(char) = chars.next() {
+        match char {
+            ' ' | '\t' | '\n' | '\r' | ',' | '\u{feff}' => continue,
+            '#' => {
+                while chars
+                    .next_if(|&char| char != '\n' && char != '\r')
+                    .is_some()
+                {}
+                continue;
+            }
+            '"' if chars.next_if_eq(&'"').is_some() => skip_block_string(&mut chars),
+            '"' => {
+                let mut escaped = false;
+                for char in chars.by_ref() {
+                    match char {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => break,
+                        _ => escaped = false,
+                    }
+                }
+            }
+            '.' => while chars.next_if_eq(&'.').is_some() {},
+            char if char.is_ascii_alphanumeric() || char == '_' || char == '-' => {
+                while chars
+                    .next_if(|&char| char.is_ascii_alphanumeric() || "_.+-".contains(char))
+                    .is_some()
+                {}
+            }
+            _ => {}
+        }
+        count += 1;
+    }
+
+    count
+}
+
+/// Skip a block string after its first two quotes, or nothing if they are an
+/// empty string.
+fn skip_block_string(chars: &mut Peekable<Chars>) {
+    if chars.next_if_eq(&'"').is_none() {
+        return;
+    }
+    let mut quotes = 0;
+    for char in chars.by_ref() {
+        quotes = if char == '"' { quotes + 1 } else { 0 };
+        if quotes == 3 {
+            break;
+        }
+    }
+}
+
+/// What a `SelectionCounter` counts.
+#[derive(Clone, Copy)]
+enum Count {
+    /// Every aliased field, at any depth.
+    Aliases,
+    /// Every field at the root of the operation.
+    RootFields,
+}
+
+/// Count selections, expanding fragment spreads once per fragment so that
+/// fragment amplification is counted without being walked repeatedly.
+struct SelectionCounter<'a> {
+    document: &'a ExecutableDocument,
+    target: Count,
+    fragments: HashMap<&'a Name, usize>,
+    visiting: Vec<&'a Name>,
+}
+
+impl<'a> SelectionCounter<'a> {
+    fn new(document: &'a ExecutableDocument, target: Count) -> Self {
+        Self {
+            document,
+            target,
+            fragments: HashMap::new(),
+            visiting: Vec::new(),
+        }
+    }
+
+    fn count(&mut self, selection_set: &'a SelectionSet) -> usize {
+        let mut count: usize = 0;
+
+        for selection in &selection_set.items {
+            let selections = match &selection.node {
+                Selection::Field(field) => match self.target {
+                    Count::Aliases => usize::from(field.node.alias.is_some())
+                        .saturating_add(self.count(&field.node.selection_set.node)),
+                    Count::RootFields => 1,
+                },
+                Selection::InlineFragment(fragment) => {
+                    self.count(&fragment.node.selection_set.node)
+                }
+                Selection::FragmentSpread(spread) => {
+                    self.count_fragment(&spread.node.fragment_name.node)
+                }
+            };
+            count = count.saturating_add(selections);
+        }
+
+        count
+    }
+
+    fn count_fragment(&mut self, name: &'a Name) -> usize {
+        if let Some(count) = self.fragments.get(name) {
+            return *count;
+        }
+        // Fragment cycles are rejected during validation, so skip them here.
+        if self.visiting.contains(&name) {
+            return 0;
+        }
+        let Some(fragment) = self.document.fragments.get(name) else {
+            return 0;
+        };
+
+        self.visiting.push(name);
+        let count = self.count(&fragment.node.selection_set.node);
+        self.visiting.pop();
+        self.fragments.insert(name, count);
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::schema::Mutation;
+    use crate::server::schema::Query;
+    use crate::server::schema::Subscription;
+    use async_graphql::Schema;
+
+    async fn execute_code(limits: QueryLimits, query: &str) -> async_graphql::Value {
+        let schema = Schema::build(
+            Query::default(),
+            Mutation::default(),
+            Subscription::default(),
+        )
+        .extension(limits)
+        .finish();
+        let response = schema.execute(query).await;
+
+        response.errors[0]
+            .extensions
+            .as_ref()
+            .unwrap()
+            .get("code")
+            .unwrap()
+            .clone()
+    }
+
+    fn limits() -> QueryLimits {
+        QueryLimits {
+            max_depth: 10,
+            max_complexity: 100,
+            max_aliases: 2,
+            max_root_fields: 2,
+            max_tokens: 100,
+        }
+    }
+
+    #[test]
+    fn test_connection_complexity() {
+        assert_eq!(connection_complexity(Some(10), 2), 25);
+        assert_eq!(connection_complexity(Some(10_000), 1), 105);
+        assert_eq!(connection_complexity(None, 1), 25);
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        assert_eq!(count_tokens("{ user(id: \"a, b\") { id } }"), 11);
+        assert_eq!(count_tokens("# comment\n{ ...F }"), 4);
+        assert_eq!(count_tokens("\"\"\"a \"b\" c\"\"\" \"\""), 2);
+    }
+
+    #[tokio::test]
+    async fn test_too_many_tokens() {
+        let query = format!("{{ {} }}", "__typename ".repeat(100));
+
+        assert_eq!(
+            execute_code(limits(), &query).await,
+            "TOO_MANY_TOKENS".into()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_too_many_aliases() {
+        let query = "
+        query { ...F ...F }
+        fragment F on Query { a: __typename b: __typename c: __typename }
+        ";
+
+        assert_eq!(
+            execute_code(limits(), query).await,
+            "TOO_MANY_ALIASES".into()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_too_many_root_fields() {
+        let query = "{ __typename __typename __typename }";
+
+        assert_eq!(
+            execute_code(limits(), query).await,
+            "TOO_MANY_ROOT_FIELDS".into()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_query_too_deep() {
+        let limits = QueryLimits {
+            max_depth: 1,
+            ..limits()
+        };
+        let query = "{ user(id: \"0190a0c8-0000-7000-8000-000000000000\") { id } }";
+
+        assert_eq!(execute_code(limits, query).await, "QUERY_TOO_DEEP".into())
+    }
+
+    #[tokio::test]
+    async fn test_query_too_complex() {
+        let limits = QueryLimits {
+            max_complexity: 5,
+            ..limits()
+        };
+        let query = "{ user(id: \"0190a0c8-0000-7000-8000-000000000000\") { id firstName } }";
+
+        assert_eq!(
+            execute_code(limits, query).await,
+            "QUERY_TOO_COMPLEX".into()
+        )
+    }
+}
@@ -0,0 +1,357 @@
+use crate::server::resolvers::errors::server_error;
+use crate::server::resolvers::errors::GqlError::PersistedQueryNotAllowed;
+use crate::server::resolvers::errors::GqlError::PersistedQueryNotFound;
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use async_graphql::async_trait::async_trait;
+use async_graphql::extensions::Extension;
+use async_graphql::extensions::ExtensionContext;
+use async_graphql::extensions::ExtensionFactory;
+use async_graphql::extensions::NextExecute;
+use async_graphql::extensions::NextPrepareRequest;
+use async_graphql::from_value;
+use async_graphql::Request;
+use async_graphql::Response;
+use async_graphql::ServerResult;
+use async_graphql::Value;
+use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// The maximum number of automatic persisted queries kept in memory.
+const CACHE_CAPACITY: usize = 1000;
+
+/// An Apollo persisted query manifest.
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    pub operations: Vec<ManifestOperation>,
+}
+
+/// An operation in the manifest, identified by the SHA-256 hash of its body.
+#[derive(Debug, Deserialize)]
+pub struct ManifestOperation {
+    pub id: String,
+    pub name: Option<String>,
+    pub body: String,
+}
+
+impl Manifest {
+    /// Load the manifest from a JSON file.
+    pub fn load(path: &str) -> Result<Manifest, Error> {
+        Manifest::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse the manifest, checking that every operation hashes to its id.
+    pub fn parse(contents: &str) -> Result<Manifest, Error> {
+        let manifest: Manifest =
+            serde_json::from_str(contents).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        match manifest
+            .operations
+            .iter()
+            .find(|operation| hash_query(&operation.body) != operation.id)
+        {
+            Some(operation) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("The operation {} does not hash to its id", operation.id),
+            )),
+            None => Ok(manifest),
+        }
+    }
+}
+
+/// The automatic persisted queries, evicting the least recently used once
+/// full so that no set of hashes can hold the cache.
+#[derive(Default)]
+struct QueryCache {
+    queries: HashMap<String, (String, u64)>,
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl QueryCache {
+    fn get(&mut self, hash: &str) -> Option<String> {
+        let (query, used) = self.queries.get_mut(hash)?;
+        self.recency.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.recency.insert(self.clock, hash.to_string());
+
+        Some(query.clone())
+    }
+
+    fn insert(&mut self, hash: String, query: String) {
+        if let Some((_, used)) = self.queries.remove(&hash) {
+            self.recency.remove(&used);
+        } else if self.queries.len() >= CACHE_CAPACITY {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.queries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.recency.insert(self.clock, hash.clone());
+        self.queries.insert(hash, (query, self.clock));
+    }
+}
+
+/// The `persistedQuery` request extension sent by Apollo clients.
+#[derive(Deserialize)]
+struct PersistedQuery {
+    version: i32,
+    #[serde(rename = "sha256Hash")]
+    sha256_hash: String,
+}
+
+/// Resolve queries by their hash from the manifest or the automatic persisted
+/// query cache. In allowlist mode, only queries in the manifest are executable.
+#[derive(Clone)]
+pub struct PersistedQueries {
+    manifest: Arc<HashMap<String, String>>,
+    cache: Arc<Mutex<QueryCache>>,
+    allowlist: bool,
+}
+
+impl PersistedQueries {
+    pub fn new(manifest: Option<Manifest>, allowlist: bool) -> PersistedQueries {
+        let operations = manifest
+            .map(|manifest| manifest.operations)
+            .unwrap_or_default();
+
+        PersistedQueries {
+            manifest: Arc::new(
+                operations
+                    .into_iter()
+                    .map(|operation| (operation.id, operation.body))
+                    .collect(),
+            ),
+            cache: Arc::new(Mutex::new(QueryCache::default())),
+            allowlist,
+        }
+    }
+
+    fn get(&self, hash: &str) -> Option<String> {
+        if let Some(query) = self.manifest.get(hash) {
+            return Some(query.clone());
+        }
+        if self.allowlist {
+            return None;
+        }
+
+        self.cache.lock().unwrap().get(hash)
+    }
+
+    fn set(&self, hash: String, query: String) {
+        self.cache.lock().unwrap().insert(hash, query);
+    }
+}
+
+impl ExtensionFactory for PersistedQueries {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(self.clone())
+    }
+}
+
+#[async_trait]
+impl Extension for PersistedQueries {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        mut request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        if let Some(value) = request.extensions.remove("persistedQuery") {
+            let persisted: PersistedQuery = from_value(value).map_err(|_| {
+                server_error(UnprocessableContent("Invalid persisted query".to_string()))
+            })?;
+            if persisted.version != 1 {
+                return Err(server_error(UnprocessableContent(
+                    "Unsupported persisted query version".to_string(),
+                )));
+            }
+
+            if request.query.is_empty() {
+                request.query = match self.get(&persisted.sha256_hash) {
+                    Some(query) => query,
+                    None if self.allowlist => return Err(server_error(PersistedQueryNotAllowed)),
+                    None => return Err(server_error(PersistedQueryNotFound)),
+                };
+            } else if hash_query(&request.query) != persisted.sha256_hash {
+                return Err(server_error(UnprocessableContent(
+                    "Persisted query hash does not match the query".to_string(),
+                )));
+            } else if !self.allowlist {
+                self.set(persisted.sha256_hash, request.query.clone());
+            }
+        }
+
+        if self.allowlist && !self.manifest.contains_key(&hash_query(&request.query)) {
+            return Err(server_error(PersistedQueryNotAllowed));
+        }
+
+        next.run(ctx, request).await
+    }
+}
+
+/// Validate queries without executing them.
+pub struct ValidateOnly;
+
+impl ExtensionFactory for ValidateOnly {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ValidateOnly)
+    }
+}
+
+#[async_trait]
+impl Extension for ValidateOnly {
+    async fn execute(
+        &self,
+        _ctx: &ExtensionContext<'_>,
+        _operation_name: Option<&str>,
+        _next: NextExecute<'_>,
+    ) -> Response {
+        Response::new(Value::Null)
+    }
+}
+
+/// Hash the query as the hex-encoded SHA-256 digest of its text.
+pub fn hash_query(query: &str) -> String {
+    format!("{:x}", Sha256::digest(query.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::schema::build_validation_schema;
+    use crate::server::schema::Mutation;
+    use crate::server::schema::Query;
+    use crate::server::schema::Subscription;
+    use async_graphql::Schema;
+    use serde_json::json;
+
+    const QUERY: &str = "{ __typename }";
+
+    fn manifest() -> Manifest {
+        Manifest {
+            operations: vec![ManifestOperation {
+                id: hash_query(QUERY),
+                name: None,
+                body: QUERY.to_string(),
+            }],
+        }
+    }
+
+    async fn execute(persisted: PersistedQueries, request: Request) -> Response {
+        Schema::build(
+            Query::default(),
+            Mutation::default(),
+            Subscription::default(),
+        )
+        .extension(persisted)
+        .finish()
+        .execute(request)
+        .await
+    }
+
+    fn persisted_request(query: &str, hash: &str) -> Request {
+        let mut request = Request::new(query);
+        request.extensions.insert(
+            "persistedQuery".to_string(),
+            Value::from_json(json!({"version": 1, "sha256Hash": hash})).unwrap(),
+        );
+        request
+    }
+
+    fn error_code(response: &Response) -> Value {
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+
+        extensions.get("code").unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_automatic_persisted_query() {
+        let persisted = PersistedQueries::new(None, false);
+        let hash = hash_query(QUERY);
+
+        let response = execute(persisted.clone(), persisted_request("", &hash)).await;
+        assert_eq!(error_code(&response), "PERSISTED_QUERY_NOT_FOUND".into());
+
+        let response = execute(persisted.clone(), persisted_request(QUERY, &hash)).await;
+        assert!(response.errors.is_empty());
+
+        let response = execute(persisted, persisted_request("", &hash)).await;
+        assert!(response.errors.is_empty());
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            json!({"__typename": "Query"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_automatic_persisted_query_hash_mismatch() {
+        let persisted = PersistedQueries::new(None, false);
+        let response = execute(persisted, persisted_request(QUERY, "invalid")).await;
+
+        assert_eq!(error_code(&response), "UNPROCESSABLE_CONTENT".into());
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let mut cache = QueryCache::default();
+        for i in 0..CACHE_CAPACITY {
+            cache.insert(i.to_string(), format!("query {}", i));
+        }
+        cache.get("0");
+
+        cache.insert("new".to_string(), "query new".to_string());
+
+        assert_eq!(cache.queries.len(), CACHE_CAPACITY);
+        assert_eq!(cache.get("0").unwrap(), "query 0");
+        assert_eq!(cache.get("1"), None);
+        assert_eq!(cache.get("new").unwrap(), "query new");
+    }
+
+    #[test]
+    fn test_manifest_parse() {
+        let operation = |id: &str| json!({"operations": [{"id": id, "body": QUERY}]}).to_string();
+
+        assert_eq!(
+            Manifest::parse(&operation(&hash_query(QUERY)))
+                .unwrap()
+                .operations
+                .len(),
+            1
+        );
+        let error = Manifest::parse(&operation(&hash_query("{ user { id } }"))).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test_allowlist() {
+        let persisted = PersistedQueries::new(Some(manifest()), true);
+
+        let response = execute(persisted.clone(), persisted_request("", &hash_query(QUERY))).await;
+        assert!(response.errors.is_empty());
+
+        let response = execute(persisted.clone(), Request::new(QUERY)).await;
+        assert!(response.errors.is_empty());
+
+        let response = execute(persisted, Request::new("{ __schema { types { name } } }")).await;
+        assert_eq!(error_code(&response), "PERSISTED_QUERY_NOT_ALLOWED".into());
+    }
+
+    #[tokio::test]
+    async fn test_validate_only() {
+        let schema = build_validation_schema();
+
+        let response = schema.execute("{ user(id: null) { id } }").await;
+        assert!(response.errors.is_empty());
+
+        let response = schema.execute("{ user { unknown } }").await;
+        assert!(!response.errors.is_empty());
+    }
+}
@@ -1,2 +1,11 @@
+pub mod audit_resolver;
 pub mod errors;
+pub mod export_resolver;
+pub mod invitation_resolver;
+pub mod organization_resolver;
+pub mod pagination;
+pub mod privacy_resolver;
+pub mod role_resolver;
+pub mod session_resolver;
 pub mod user_resolver;
+pub mod webhook_resolver;
@@ -0,0 +1,127 @@
+use crate::core::audit;
+use crate::core::repo;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::pagination::decode_cursor;
+use crate::server::resolvers::pagination::page_size;
+use crate::server::schema::audit_schema::AuditEvent;
+use crate::server::schema::audit_schema::AuditEventFilter;
+use crate::server::viewer::tenant;
+use crate::server::viewer::Viewer;
+use async_graphql::connection::Connection;
+use async_graphql::connection::Edge;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use deadpool_diesel::postgres::Pool;
+
+pub async fn audit_events(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    filter: Option<AuditEventFilter>,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<Connection<String, AuditEvent>, Error> {
+    let tenant_id = tenant(pool, viewer).await?;
+    let after = decode_cursor(after)?;
+    let limit = page_size(first);
+    let filter = filter.map_or_else(audit::EventFilter::default, |filter| audit::EventFilter {
+        actor_id: filter.actor_id,
+        action: filter.action,
+        target_type: filter.target_type,
+        target_id: filter.target_id,
+    });
+    let conn = pool.get().await.unwrap();
+    // Fetch one extra event to know whether there is a next page.
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                audit::list_events(conn, &filter, after, limit + 1)
+            })
+        })
+        .await;
+
+    let mut events = match result {
+        Ok(Ok(events)) => events,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+    let has_next_page = events.len() as i64 > limit;
+    events.truncate(limit as usize);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection.edges.extend(
+        events
+            .into_iter()
+            .map(|event| Edge::new(event.id.to_string(), AuditEvent::from(event))),
+    );
+
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::authz;
+    use crate::core::sessions;
+    use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+    use crate::server::resolvers::user_resolver;
+    use crate::server::schema::user_schema::UserInput;
+    use crate::test::factory;
+    use diesel::Connection as _;
+    use diesel::PgConnection;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_audit_events() {
+        let admin = factory::insert_user();
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        authz::grant_role(&mut conn, admin.id, "admin").unwrap();
+        let (_, token) = sessions::create_session(&mut conn, admin.id).unwrap();
+        let viewer = Viewer::new(Some(token));
+        let input = UserInput {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+
+        user_resolver::update_user(&pool, Some(&viewer), Some(user.id), Some(input), None)
+            .await
+            .unwrap();
+
+        let filter = AuditEventFilter {
+            actor_id: None,
+            action: Some("user.updated".to_string()),
+            target_type: Some("user".to_string()),
+            target_id: Some(user.id),
+        };
+        let events = audit_events(&pool, Some(&viewer), Some(filter), None, None)
+            .await
+            .unwrap();
+        assert_eq!(events.edges.len(), 1);
+        let event = &events.edges[0].node;
+        assert_eq!(event.actor_id, Some(admin.id));
+        assert_eq!(event.after.as_ref().unwrap()["first_name"], "Janet");
+
+        let error = audit_events(&pool, None, None, None, Some("invalid".to_string()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error,
+            UnprocessableContent("Invalid cursor".to_string()).extend()
+        );
+        let filter = AuditEventFilter {
+            actor_id: Some(Uuid::now_v7()),
+            action: None,
+            target_type: None,
+            target_id: None,
+        };
+        let events = audit_events(&pool, None, Some(filter), None, None)
+            .await
+            .unwrap();
+        assert!(events.edges.is_empty());
+    }
+}
@@ -1,11 +1,26 @@
 use async_graphql::Error;
 use async_graphql::ErrorExtensions;
+use async_graphql::ServerError;
 
 #[derive(Debug)]
 pub enum GqlError {
     // NotFound,
     InternalServer,
     UnprocessableContent(String),
+    QueryTooDeep(usize),
+    QueryTooComplex(usize),
+    TooManyAliases(usize),
+    TooManyRootFields(usize),
+    TooManyTokens(usize),
+    PersistedQueryNotFound,
+    PersistedQueryNotAllowed,
+    InvalidToken,
+    InvalidCredentials,
+    Unauthenticated,
+    Forbidden(String),
+    Conflict(i32),
+    IdempotencyKeyMismatch,
+    IdempotencyKeyInProgress,
 }
 
 impl std::fmt::Display for GqlError {
@@ -26,6 +41,98 @@ impl ErrorExtensions for GqlError {
                 e.set("message", "Internal server");
                 e.set("code", "INTERNAL_SERVER");
             }
+            GqlError::QueryTooDeep(limit) => {
+                e.set("message", "Query too deep");
+                e.set("code", "QUERY_TOO_DEEP");
+                e.set("reason", format!("The query depth exceeds {limit}"));
+            }
+            GqlError::QueryTooComplex(limit) => {
+                e.set("message", "Query too complex");
+                e.set("code", "QUERY_TOO_COMPLEX");
+                e.set("reason", format!("The query complexity exceeds {limit}"));
+            }
+            GqlError::TooManyAliases(limit) => {
+                e.set("message", "Too many aliases");
+                e.set("code", "TOO_MANY_ALIASES");
+                e.set(
+                    "reason",
+                    format!("The query uses more than {limit} aliases"),
+                );
+            }
+            GqlError::TooManyRootFields(limit) => {
+                e.set("message", "Too many root fields");
+                e.set("code", "TOO_MANY_ROOT_FIELDS");
+                e.set(
+                    "reason",
+                    format!("The query selects more than {limit} root fields"),
+                );
+            }
+            GqlError::TooManyTokens(limit) => {
+                e.set("message", "Too many tokens");
+                e.set("code", "TOO_MANY_TOKENS");
+                e.set(
+                    "reason",
+                    format!("The query contains more than {limit} tokens"),
+                );
+            }
+            GqlError::PersistedQueryNotFound => {
+                e.set("message", "Persisted query not found");
+                e.set("code", "PERSISTED_QUERY_NOT_FOUND");
+            }
+            GqlError::PersistedQueryNotAllowed => {
+                e.set("message", "Persisted query not allowed");
+                e.set("code", "PERSISTED_QUERY_NOT_ALLOWED");
+            }
+            GqlError::InvalidToken => {
+                e.set("message", "Invalid token");
+                e.set("code", "INVALID_TOKEN");
+                e.set("reason", "The token is invalid, expired or already used");
+            }
+            GqlError::InvalidCredentials => {
+                e.set("message", "Invalid credentials");
+                e.set("code", "INVALID_CREDENTIALS");
+                e.set("reason", "The email address or password is incorrect");
+            }
+            GqlError::Unauthenticated => {
+                e.set("message", "Unauthenticated");
+                e.set("code", "UNAUTHENTICATED");
+                e.set("reason", "A valid session token is required");
+            }
+            GqlError::Forbidden(reason) => {
+                e.set("message", "Forbidden");
+                e.set("code", "FORBIDDEN");
+                e.set("reason", reason);
+            }
+            GqlError::Conflict(current_version) => {
+                e.set("message", "Conflict");
+                e.set("code", "CONFLICT");
+                e.set("reason", "The user was changed since the expected version");
+                e.set("currentVersion", *current_version);
+            }
+            GqlError::IdempotencyKeyMismatch => {
+                e.set("message", "Idempotency key mismatch");
+                e.set("code", "IDEMPOTENCY_KEY_MISMATCH");
+                e.set(
+                    "reason",
+                    "The idempotency key was used for a different request",
+                );
+            }
+            GqlError::IdempotencyKeyInProgress => {
+                e.set("message", "Idempotency key in progress");
+                e.set("code", "IDEMPOTENCY_KEY_IN_PROGRESS");
+                e.set(
+                    "reason",
+                    "A request with the idempotency key is still running",
+                );
+            }
         })
     }
 }
+
+/// Convert the error into a server error, for errors raised outside a resolver.
+pub fn server_error(error: GqlError) -> ServerError {
+    let error = error.extend();
+    let mut server_error = ServerError::new(error.message, None);
+    server_error.extensions = error.extensions;
+    server_error
+}
@@ -0,0 +1,104 @@
+use crate::core::exports;
+use crate::core::repo;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::schema::export_schema::UserExport;
+use crate::server::schema::export_schema::UserExportFilter;
+use crate::server::schema::export_schema::UserExportFormat;
+use crate::server::viewer::authenticate;
+use crate::server::viewer::Viewer;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use deadpool_diesel::postgres::Pool;
+use uuid::Uuid;
+
+pub async fn export_users(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    format: UserExportFormat,
+    filter: Option<UserExportFilter>,
+) -> Result<UserExport, Error> {
+    let identity = authenticate(pool, viewer).await?;
+    let (tenant_id, user_id) = (identity.tenant_id, identity.user_id);
+    let filter = exports::ExportFilter::from(filter.unwrap_or_default());
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                exports::request_export(conn, format.into(), &filter, Some(user_id))
+            })
+        })
+        .await;
+
+    match result {
+        Ok(Ok(export)) => Ok(UserExport::from(export)),
+        Ok(Err(_)) => Err(InternalServer.extend()),
+        Err(_) => Err(InternalServer.extend()),
+    }
+}
+
+pub async fn user_export(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Uuid,
+) -> Result<Option<UserExport>, Error> {
+    let tenant_id = authenticate(pool, viewer).await?.tenant_id;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| exports::get_export(conn, id))
+        })
+        .await;
+
+    match result {
+        Ok(Ok(export)) => Ok(export.map(UserExport::from)),
+        Ok(Err(_)) => Err(InternalServer.extend()),
+        Err(_) => Err(InternalServer.extend()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::authz;
+    use crate::core::sessions;
+    use crate::server::resolvers::errors::GqlError::Unauthenticated;
+    use crate::test::factory;
+    use diesel::Connection;
+    use diesel::PgConnection;
+
+    #[tokio::test]
+    async fn test_export_users() {
+        let admin = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let (_, token) = sessions::create_session(&mut conn, admin.id).unwrap();
+        authz::grant_role(&mut conn, admin.id, "admin").unwrap();
+        let viewer = Viewer::new(Some(token));
+
+        let export = export_users(&pool, Some(&viewer), UserExportFormat::Csv, None)
+            .await
+            .unwrap();
+        let found = user_export(&pool, Some(&viewer), export.id.unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(export.status, Some(exports::PENDING.to_string()));
+        assert_eq!(export.format, Some(UserExportFormat::Csv));
+        assert_eq!(export.requested_by, Some(admin.id));
+        assert_eq!(found, Some(export));
+    }
+
+    #[tokio::test]
+    async fn test_export_users_unauthenticated() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+
+        let error = export_users(&pool, None, UserExportFormat::Csv, None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error, Unauthenticated.extend());
+    }
+}
@@ -0,0 +1,290 @@
+use crate::core::audit;
+use crate::core::invitations;
+use crate::core::invitations::NewInvitee;
+use crate::core::mailer::Mailer;
+use crate::core::organizations::ADMIN;
+use crate::core::organizations::OWNER;
+use crate::core::repo;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::errors::GqlError::InvalidToken;
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use crate::server::resolvers::organization_resolver::authorize_membership;
+use crate::server::resolvers::organization_resolver::membership_error;
+use crate::server::resolvers::pagination::decode_cursor;
+use crate::server::resolvers::pagination::page_size;
+use crate::server::resolvers::session_resolver::validate_password;
+use crate::server::resolvers::user_resolver::validate_email_address;
+use crate::server::schema::invitation_schema::Invitation;
+use crate::server::schema::invitation_schema::InviteeInput;
+use crate::server::schema::organization_schema::MembershipRole;
+use crate::server::schema::user_schema::User;
+use crate::server::viewer::actor;
+use crate::server::viewer::tenant;
+use crate::server::viewer::Viewer;
+use async_graphql::connection::Connection;
+use async_graphql::connection::Edge;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use deadpool_diesel::postgres::Pool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub async fn create_invitation(
+    pool: &Pool,
+    mailer: Arc<dyn Mailer>,
+    viewer: Option<&Viewer>,
+    organization_id: Uuid,
+    email_address: String,
+    role: MembershipRole,
+) -> Result<Option<Invitation>, Error> {
+    if role == MembershipRole::Owner {
+        let reason = "The owner is changed with transferOwnership".to_string();
+        return Err(UnprocessableContent(reason).extend());
+    }
+    validate_email_address(&email_address)?;
+    let (viewer_id, _) =
+        authorize_membership(pool, viewer, organization_id, &[OWNER, ADMIN]).await?;
+    let tenant_id = tenant(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                invitations::create_invitation(
+                    conn,
+                    mailer.as_ref(),
+                    organization_id,
+                    viewer_id,
+                    &email_address,
+                    role.as_str(),
+                )
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(membership_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(Invitation::from))
+}
+
+pub async fn revoke_invitation(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Uuid,
+) -> Result<Option<Invitation>, Error> {
+    let tenant_id = tenant(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                invitations::get_invitation(conn, id)
+            })
+        })
+        .await;
+
+    let invitation = match result {
+        Ok(Ok(Some(invitation))) => invitation,
+        Ok(Ok(None)) => return Ok(None),
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+    authorize_membership(pool, viewer, invitation.organization_id, &[OWNER, ADMIN]).await?;
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                invitations::revoke_invitation(conn, id)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(Invitation::from))
+}
+
+pub async fn accept_invitation(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    token: String,
+    input: Option<InviteeInput>,
+) -> Result<Option<User>, Error> {
+    if let Some(password) = input.as_ref().and_then(|input| input.password.as_deref()) {
+        validate_password(password)?;
+    }
+    let invitee = input.map(|input| NewInvitee {
+        first_name: input.first_name,
+        last_name: input.last_name,
+        password: input.password,
+    });
+    let actor = actor(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            audit::with_actor(conn, &actor, |conn| {
+                invitations::accept_invitation(conn, &token, invitee)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(membership_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    match option {
+        Some((_, user)) => Ok(Some(User::from(user))),
+        None => Err(InvalidToken.extend()),
+    }
+}
+
+pub async fn organization_invitations(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    organization_id: Option<Uuid>,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<Connection<String, Invitation>, Error> {
+    let organization_id = organization_id.unwrap();
+    authorize_membership(pool, viewer, organization_id, &[OWNER, ADMIN]).await?;
+    let tenant_id = tenant(pool, viewer).await?;
+    let after = decode_cursor(after)?;
+    let limit = page_size(first);
+    let conn = pool.get().await.unwrap();
+    // Fetch one extra invitation to know whether there is a next page.
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                invitations::list_invitations(conn, organization_id, after, limit + 1)
+            })
+        })
+        .await;
+
+    let mut invitations = match result {
+        Ok(Ok(invitations)) => invitations,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+    let has_next_page = invitations.len() as i64 > limit;
+    invitations.truncate(limit as usize);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection.edges.extend(
+        invitations
+            .into_iter()
+            .map(|invitation| Edge::new(invitation.id.to_string(), Invitation::from(invitation))),
+    );
+
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::mailer::MemoryMailer;
+    use crate::core::organizations;
+    use crate::core::repo;
+    use crate::core::sessions;
+    use crate::test::factory;
+    use diesel::Connection as _;
+    use diesel::PgConnection;
+
+    /// Sign in as the user.
+    fn viewer_of(conn: &mut PgConnection, user_id: Uuid) -> Viewer {
+        let (_, token) = sessions::create_session(conn, user_id).unwrap();
+
+        Viewer::new(Some(token))
+    }
+
+    /// Get the code of the error.
+    fn code(error: Error) -> String {
+        error.extensions.unwrap().get("code").unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_invitation_authorization() {
+        let owner = factory::insert_user();
+        let member = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let mailer = Arc::new(MemoryMailer::default());
+        let created = organizations::create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        organizations::add_member(&mut conn, created.id, &member.email_address, "member").unwrap();
+        let owner_viewer = viewer_of(&mut conn, owner.id);
+        let member_viewer = viewer_of(&mut conn, member.id);
+        let email_address = format!("invitee.{}@doe.com", Uuid::new_v4());
+
+        let error = create_invitation(
+            &pool,
+            mailer.clone(),
+            Some(&member_viewer),
+            created.id,
+            email_address.clone(),
+            MembershipRole::Member,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(code(error), "\"FORBIDDEN\"");
+        let error = create_invitation(
+            &pool,
+            mailer.clone(),
+            Some(&owner_viewer),
+            created.id,
+            email_address.clone(),
+            MembershipRole::Owner,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(code(error), "\"UNPROCESSABLE_CONTENT\"");
+
+        let invitation = create_invitation(
+            &pool,
+            mailer.clone(),
+            Some(&owner_viewer),
+            created.id,
+            email_address.clone(),
+            MembershipRole::Admin,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(invitation.role, Some(MembershipRole::Admin));
+        assert_ne!(mailer.last_sent_to(&email_address), None);
+
+        let pending =
+            organization_invitations(&pool, Some(&owner_viewer), Some(created.id), None, None)
+                .await
+                .unwrap();
+        assert_eq!(pending.edges.len(), 1);
+        let error =
+            organization_invitations(&pool, Some(&member_viewer), Some(created.id), None, None)
+                .await
+                .err()
+                .unwrap();
+        assert_eq!(code(error), "\"FORBIDDEN\"");
+        let error = revoke_invitation(&pool, Some(&member_viewer), invitation.id.unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(code(error), "\"FORBIDDEN\"");
+        revoke_invitation(&pool, Some(&owner_viewer), invitation.id.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            accept_invitation(&pool, None, "invalid".to_string(), None)
+                .await
+                .unwrap_err(),
+            InvalidToken.extend()
+        );
+    }
+}
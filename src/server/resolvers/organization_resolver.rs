@@ -0,0 +1,454 @@
+use crate::core::authz::MANAGE_ORGANIZATIONS;
+use crate::core::organizations;
+use crate::core::organizations::ADMIN;
+use crate::core::organizations::MEMBER;
+use crate::core::organizations::OWNER;
+use crate::core::repo;
+use crate::server::resolvers::errors::GqlError::Forbidden;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use crate::server::resolvers::pagination::decode_cursor;
+use crate::server::resolvers::pagination::page_size;
+use crate::server::schema::organization_schema::MembershipFields;
+use crate::server::schema::organization_schema::MembershipRole;
+use crate::server::schema::organization_schema::Organization;
+use crate::server::schema::user_schema::User;
+use crate::server::viewer::authenticate;
+use crate::server::viewer::tenant;
+use crate::server::viewer::Viewer;
+use async_graphql::connection::Connection;
+use async_graphql::connection::Edge;
+use async_graphql::connection::EmptyFields;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use deadpool_diesel::postgres::Pool;
+use std::io::ErrorKind;
+use uuid::Uuid;
+
+/// The longest name of an organization.
+const MAX_NAME_LENGTH: usize = 100;
+
+/// Check that the name is neither blank nor too long, and trim it.
+fn validate_name(name: &str) -> Result<String, Error> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        let reason = format!(
+            "The name must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        );
+        return Err(UnprocessableContent(reason).extend());
+    }
+
+    Ok(name.to_string())
+}
+
+/// Map the error of a membership change, where a missing user, an existing
+/// member or an invalid change is the caller's.
+pub fn membership_error(error: std::io::Error) -> Error {
+    match error.kind() {
+        ErrorKind::NotFound | ErrorKind::AlreadyExists | ErrorKind::InvalidInput => {
+            UnprocessableContent(error.to_string()).extend()
+        }
+        _ => InternalServer.extend(),
+    }
+}
+
+/// Get the role of the user in the organization, if it is a member.
+async fn membership_role(
+    pool: &Pool,
+    tenant_id: Uuid,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<String>, Error> {
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                organizations::get_membership(conn, organization_id, user_id)
+            })
+        })
+        .await;
+
+    match result {
+        Ok(Ok(membership)) => Ok(membership.map(|membership| membership.role)),
+        Ok(Err(_)) => Err(InternalServer.extend()),
+        Err(_) => Err(InternalServer.extend()),
+    }
+}
+
+/// Get the viewer and its role in the organization, failing unless the role
+/// is one of the given roles. Viewers who manage every organization act as
+/// its owner.
+pub async fn authorize_membership(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    organization_id: Uuid,
+    roles: &[&str],
+) -> Result<(Uuid, String), Error> {
+    let identity = authenticate(pool, viewer).await?;
+    if identity.grants.has_permission(MANAGE_ORGANIZATIONS) {
+        return Ok((identity.user_id, OWNER.to_string()));
+    }
+
+    match membership_role(pool, identity.tenant_id, organization_id, identity.user_id).await? {
+        Some(role) if roles.contains(&role.as_str()) => Ok((identity.user_id, role)),
+        Some(_) => {
+            let reason = format!(
+                "The {} role in the organization is required",
+                roles.join(" or ")
+            );
+            Err(Forbidden(reason).extend())
+        }
+        None => {
+            let reason = "Only the members of the organization have access".to_string();
+            Err(Forbidden(reason).extend())
+        }
+    }
+}
+
+pub async fn organization(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Uuid,
+) -> Result<Option<Organization>, Error> {
+    authorize_membership(pool, viewer, id, &[OWNER, ADMIN, MEMBER]).await?;
+    let tenant_id = tenant(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                organizations::get_organization(conn, id)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(Organization::from))
+}
+
+pub async fn create_organization(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    name: String,
+) -> Result<Option<Organization>, Error> {
+    let name = validate_name(&name)?;
+    let identity = authenticate(pool, viewer).await?;
+    let (owner_id, tenant_id) = (identity.user_id, identity.tenant_id);
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                organizations::create_organization(conn, owner_id, &name)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(Organization::from))
+}
+
+pub async fn invite_member(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    organization_id: Uuid,
+    email_address: String,
+    role: MembershipRole,
+) -> Result<Option<User>, Error> {
+    if role == MembershipRole::Owner {
+        let reason = "The owner is changed with transferOwnership".to_string();
+        return Err(UnprocessableContent(reason).extend());
+    }
+    authorize_membership(pool, viewer, organization_id, &[OWNER, ADMIN]).await?;
+    let tenant_id = tenant(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                organizations::add_member(conn, organization_id, &email_address, role.as_str())
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(membership_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(|(_, user)| User::from(user)))
+}
+
+pub async fn remove_member(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<User>, Error> {
+    let identity = authenticate(pool, viewer).await?;
+    let (viewer_id, tenant_id) = (identity.user_id, identity.tenant_id);
+    // Members can always leave, but only the owner can remove an admin.
+    if viewer_id != user_id {
+        let (_, role) =
+            authorize_membership(pool, viewer, organization_id, &[OWNER, ADMIN]).await?;
+        let target = membership_role(pool, tenant_id, organization_id, user_id).await?;
+        if target.as_deref() == Some(ADMIN) && role != OWNER {
+            let reason = "Only the owner can remove an admin".to_string();
+            return Err(Forbidden(reason).extend());
+        }
+    }
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                organizations::remove_member(conn, organization_id, user_id)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(membership_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(|(_, user)| User::from(user)))
+}
+
+pub async fn transfer_ownership(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<Organization>, Error> {
+    authorize_membership(pool, viewer, organization_id, &[OWNER]).await?;
+    let tenant_id = tenant(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                organizations::transfer_ownership(conn, organization_id, user_id)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(membership_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(Organization::from))
+}
+
+pub async fn organization_members(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    organization_id: Option<Uuid>,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<Connection<String, User, EmptyFields, MembershipFields>, Error> {
+    let organization_id = organization_id.unwrap();
+    authorize_membership(pool, viewer, organization_id, &[OWNER, ADMIN, MEMBER]).await?;
+    let tenant_id = tenant(pool, viewer).await?;
+    let after = decode_cursor(after)?;
+    let limit = page_size(first);
+    let conn = pool.get().await.unwrap();
+    // Fetch one extra member to know whether there is a next page.
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                organizations::list_members(conn, organization_id, after, limit + 1)
+            })
+        })
+        .await;
+
+    let mut members = match result {
+        Ok(Ok(members)) => members,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+    let has_next_page = members.len() as i64 > limit;
+    members.truncate(limit as usize);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection
+        .edges
+        .extend(members.into_iter().map(|(membership, user)| {
+            Edge::with_additional_fields(
+                user.id.to_string(),
+                User::from(user),
+                MembershipFields::from(membership),
+            )
+        }));
+
+    Ok(connection)
+}
+
+pub async fn user_organizations(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    user_id: Option<Uuid>,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<Connection<String, Organization, EmptyFields, MembershipFields>, Error> {
+    let user_id = user_id.unwrap();
+    let identity = authenticate(pool, viewer).await?;
+    if identity.user_id != user_id && !identity.grants.has_permission(MANAGE_ORGANIZATIONS) {
+        let reason = "Only the user can list its organizations".to_string();
+        return Err(Forbidden(reason).extend());
+    }
+    let after = decode_cursor(after)?;
+    let limit = page_size(first);
+    let tenant_id = identity.tenant_id;
+    let conn = pool.get().await.unwrap();
+    // Fetch one extra organization to know whether there is a next page.
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                organizations::list_organizations(conn, user_id, after, limit + 1)
+            })
+        })
+        .await;
+
+    let mut organizations = match result {
+        Ok(Ok(organizations)) => organizations,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+    let has_next_page = organizations.len() as i64 > limit;
+    organizations.truncate(limit as usize);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection
+        .edges
+        .extend(organizations.into_iter().map(|(membership, organization)| {
+            Edge::with_additional_fields(
+                organization.id.to_string(),
+                Organization::from(organization),
+                MembershipFields::from(membership),
+            )
+        }));
+
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::repo;
+    use crate::core::sessions;
+    use crate::test::factory;
+    use diesel::Connection as _;
+    use diesel::PgConnection;
+
+    /// Sign in as the user.
+    fn viewer_of(conn: &mut PgConnection, user_id: Uuid) -> Viewer {
+        let (_, token) = sessions::create_session(conn, user_id).unwrap();
+
+        Viewer::new(Some(token))
+    }
+
+    /// Get the code of the error.
+    fn code(error: Error) -> String {
+        error.extensions.unwrap().get("code").unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_create_organization() {
+        let owner = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = viewer_of(&mut conn, owner.id);
+
+        let result = create_organization(&pool, Some(&viewer), " Doe Inc. ".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        let organizations = user_organizations(&pool, Some(&viewer), Some(owner.id), None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.name, Some("Doe Inc.".to_string()));
+        assert_eq!(organizations.edges.len(), 1);
+        assert_eq!(organizations.edges[0].node.id, result.id);
+        assert_eq!(
+            code(
+                create_organization(&pool, None, "Doe Inc.".to_string())
+                    .await
+                    .unwrap_err()
+            ),
+            "\"UNAUTHENTICATED\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_membership_authorization() {
+        let owner = factory::insert_user();
+        let admin = factory::insert_user();
+        let member = factory::insert_user();
+        let outsider = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let created = organizations::create_organization(&mut conn, owner.id, "Doe Inc.")
+            .unwrap()
+            .unwrap();
+        organizations::add_member(&mut conn, created.id, &admin.email_address, ADMIN).unwrap();
+        let owner_viewer = viewer_of(&mut conn, owner.id);
+        let admin_viewer = viewer_of(&mut conn, admin.id);
+        let member_viewer = viewer_of(&mut conn, member.id);
+        let outsider_viewer = viewer_of(&mut conn, outsider.id);
+
+        let email_address = member.email_address.clone();
+        invite_member(
+            &pool,
+            Some(&admin_viewer),
+            created.id,
+            email_address,
+            MembershipRole::Member,
+        )
+        .await
+        .unwrap();
+        let members =
+            organization_members(&pool, Some(&member_viewer), Some(created.id), None, None)
+                .await
+                .unwrap();
+        assert_eq!(members.edges.len(), 3);
+
+        let error = organization(&pool, Some(&outsider_viewer), created.id)
+            .await
+            .unwrap_err();
+        assert_eq!(code(error), "\"FORBIDDEN\"");
+        let error = remove_member(&pool, Some(&member_viewer), created.id, admin.id)
+            .await
+            .unwrap_err();
+        assert_eq!(code(error), "\"FORBIDDEN\"");
+        let error = transfer_ownership(&pool, Some(&admin_viewer), created.id, admin.id)
+            .await
+            .unwrap_err();
+        assert_eq!(code(error), "\"FORBIDDEN\"");
+
+        remove_member(&pool, Some(&member_viewer), created.id, member.id)
+            .await
+            .unwrap();
+        transfer_ownership(&pool, Some(&owner_viewer), created.id, admin.id)
+            .await
+            .unwrap();
+        let error = remove_member(&pool, Some(&admin_viewer), created.id, admin.id)
+            .await
+            .unwrap_err();
+        assert_eq!(code(error), "\"UNPROCESSABLE_CONTENT\"");
+    }
+}
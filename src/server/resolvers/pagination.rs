@@ -0,0 +1,39 @@
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use uuid::Uuid;
+
+/// The number of nodes returned when `first` is not given.
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+
+/// The largest number of nodes a connection returns at once.
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Get the number of nodes to return, clamped to the allowed page sizes.
+pub fn page_size(first: Option<i32>) -> i64 {
+    first
+        .map(|first| i64::from(first).clamp(1, MAX_PAGE_SIZE))
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+}
+
+/// Decode an `after` cursor into the id of the last node seen.
+pub fn decode_cursor(after: Option<String>) -> Result<Option<Uuid>, Error> {
+    match after {
+        Some(after) => Uuid::parse_str(&after)
+            .map(Some)
+            .map_err(|_| UnprocessableContent("Invalid cursor".to_string()).extend()),
+        None => Ok(None),
+    }
+}
+
+/// Decode an `after` cursor into the offset of the next node, for connections
+/// ordered by something else than the id of their nodes.
+pub fn decode_offset(after: Option<String>) -> Result<i64, Error> {
+    match after {
+        Some(after) => match after.parse::<i64>() {
+            Ok(offset) if offset >= 0 => Ok(offset + 1),
+            _ => Err(UnprocessableContent("Invalid cursor".to_string()).extend()),
+        },
+        None => Ok(0),
+    }
+}
@@ -0,0 +1,121 @@
+use crate::core::audit;
+use crate::core::privacy;
+use crate::core::repo;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::schema::user_schema::User;
+use crate::server::viewer::actor;
+use crate::server::viewer::authenticate;
+use crate::server::viewer::Viewer;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use async_graphql::Json;
+use deadpool_diesel::postgres::Pool;
+use serde_json::Value;
+use uuid::Uuid;
+
+pub async fn export_my_data(pool: &Pool, viewer: Option<&Viewer>) -> Result<Json<Value>, Error> {
+    let identity = authenticate(pool, viewer).await?;
+    let (tenant_id, user_id) = (identity.tenant_id, identity.user_id);
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                privacy::export_user_data(conn, user_id)
+            })
+        })
+        .await;
+
+    match result {
+        Ok(Ok(Some(data))) => Ok(Json(data)),
+        Ok(Ok(None)) => Err(InternalServer.extend()),
+        Ok(Err(_)) => Err(InternalServer.extend()),
+        Err(_) => Err(InternalServer.extend()),
+    }
+}
+
+pub async fn erase_user(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Uuid,
+) -> Result<Option<User>, Error> {
+    let tenant_id = authenticate(pool, viewer).await?.tenant_id;
+    let actor = actor(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                audit::with_actor(conn, &actor, |conn| privacy::erase_user(conn, id))
+            })
+        })
+        .await;
+
+    match result {
+        Ok(Ok(option)) => Ok(option.map(User::from)),
+        Ok(Err(_)) => Err(InternalServer.extend()),
+        Err(_) => Err(InternalServer.extend()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::authz;
+    use crate::core::sessions;
+    use crate::server::resolvers::errors::GqlError::Unauthenticated;
+    use crate::test::factory;
+    use diesel::Connection;
+    use diesel::PgConnection;
+
+    #[tokio::test]
+    async fn test_export_my_data() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let (_, token) = sessions::create_session(&mut conn, user.id).unwrap();
+        let viewer = Viewer::new(Some(token));
+
+        let Json(data) = export_my_data(&pool, Some(&viewer)).await.unwrap();
+
+        assert_eq!(data["profile"]["id"], user.id.to_string());
+        assert_eq!(data["sessions"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_export_my_data_unauthenticated() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+
+        let error = export_my_data(&pool, None).await.unwrap_err();
+
+        assert_eq!(error, Unauthenticated.extend());
+    }
+
+    #[tokio::test]
+    async fn test_erase_user() {
+        let admin = factory::insert_user();
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let (_, token) = sessions::create_session(&mut conn, admin.id).unwrap();
+        authz::grant_role(&mut conn, admin.id, "admin").unwrap();
+        let viewer = Viewer::new(Some(token));
+
+        let erased = erase_user(&pool, Some(&viewer), user.id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(erased.first_name, Some(String::new()));
+        assert_ne!(erased.email_address, Some(user.email_address));
+        assert!(erased.deleted_at.is_some());
+        assert_eq!(
+            erase_user(&pool, Some(&viewer), Uuid::now_v7())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::core::authz;
+use crate::core::repo;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use crate::server::schema::user_schema::User;
+use crate::server::viewer::tenant;
+use crate::server::viewer::Viewer;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use deadpool_diesel::postgres::Pool;
+use std::io::ErrorKind;
+use uuid::Uuid;
+
+/// Map the error of a role change, where an unknown role is the caller's.
+fn role_error(error: std::io::Error) -> Error {
+    if error.kind() == ErrorKind::NotFound {
+        UnprocessableContent(error.to_string()).extend()
+    } else {
+        InternalServer.extend()
+    }
+}
+
+pub async fn grant_role(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    user_id: Uuid,
+    role: String,
+) -> Result<Option<User>, Error> {
+    let tenant_id = tenant(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                authz::grant_role(conn, user_id, &role)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(role_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(User::from))
+}
+
+pub async fn revoke_role(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    user_id: Uuid,
+    role: String,
+) -> Result<Option<User>, Error> {
+    let tenant_id = tenant(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                authz::revoke_role(conn, user_id, &role)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(role_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(User::from))
+}
+
+pub async fn user_roles(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    user_id: Uuid,
+) -> Result<Vec<String>, Error> {
+    let tenant_id = tenant(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| authz::get_role_names(conn, user_id))
+        })
+        .await;
+
+    match result {
+        Ok(Ok(roles)) => Ok(roles),
+        Ok(Err(_)) => Err(InternalServer.extend()),
+        Err(_) => Err(InternalServer.extend()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::repo;
+    use crate::core::sessions;
+    use crate::server::schema;
+    use crate::server::viewer::Viewer;
+    use crate::test::factory;
+    use async_graphql::Request;
+    use diesel::Connection;
+    use diesel::PgConnection;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_grant_role() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        grant_role(&pool, None, user.id, "admin".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            user_roles(&pool, None, user.id).await.unwrap(),
+            vec!["admin".to_string()]
+        );
+
+        revoke_role(&pool, None, user.id, "admin".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(user_roles(&pool, None, user.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_grant_role_unknown() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let result = grant_role(&pool, None, user.id, "unknown".to_string())
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            result,
+            UnprocessableContent("Unknown role: unknown".to_string()).extend()
+        )
+    }
+
+    /// Execute a `grantRole` mutation as the viewer of the token.
+    async fn execute_grant_role(user_id: Uuid, token: Option<String>) -> async_graphql::Response {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let schema = schema::build_schema();
+        let query = format!(
+            r#"mutation {{ grantRole(userId: "{}", role: "admin") {{ id }} }}"#,
+            user_id
+        );
+        let request = Request::new(query).data(pool).data(Viewer::new(token));
+
+        schema.execute(request).await
+    }
+
+    #[tokio::test]
+    async fn test_grant_role_guard() {
+        let admin = factory::insert_user();
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        authz::grant_role(&mut conn, admin.id, "admin").unwrap();
+        let (_, admin_token) = sessions::create_session(&mut conn, admin.id).unwrap();
+        let (_, user_token) = sessions::create_session(&mut conn, user.id).unwrap();
+
+        let response = execute_grant_role(user.id, None).await;
+        assert_eq!(
+            response.errors[0].extensions.as_ref().unwrap().get("code"),
+            Some(&"UNAUTHENTICATED".into())
+        );
+
+        let response = execute_grant_role(user.id, Some(user_token)).await;
+        assert_eq!(
+            response.errors[0].extensions.as_ref().unwrap().get("code"),
+            Some(&"FORBIDDEN".into())
+        );
+
+        let response = execute_grant_role(user.id, Some(admin_token)).await;
+        assert!(response.errors.is_empty());
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            json!({ "grantRole": { "id": user.id } })
+        );
+    }
+}
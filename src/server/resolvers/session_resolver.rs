@@ -0,0 +1,146 @@
+use crate::core::mailer::Mailer;
+use crate::core::passwords;
+use crate::core::passwords::MIN_PASSWORD_LENGTH;
+use crate::core::sessions;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::errors::GqlError::InvalidCredentials;
+use crate::server::resolvers::errors::GqlError::InvalidToken;
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use crate::server::schema::session_schema::Session;
+use crate::server::schema::user_schema::User;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use deadpool_diesel::postgres::Pool;
+use std::sync::Arc;
+
+/// Check that the password is long enough.
+pub fn validate_password(password: &str) -> Result<(), Error> {
+    if password.chars().count() >= MIN_PASSWORD_LENGTH {
+        Ok(())
+    } else {
+        let reason = format!(
+            "The password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        );
+        Err(UnprocessableContent(reason).extend())
+    }
+}
+
+pub async fn sign_in(
+    pool: &Pool,
+    email_address: String,
+    password: String,
+) -> Result<Session, Error> {
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| sessions::sign_in(conn, &email_address, &password))
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    match option {
+        Some(signed_in) => Ok(Session::from(signed_in)),
+        None => Err(InvalidCredentials.extend()),
+    }
+}
+
+pub async fn request_password_reset(
+    pool: &Pool,
+    mailer: Arc<dyn Mailer>,
+    email_address: String,
+) -> Result<bool, Error> {
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            passwords::request_password_reset(conn, mailer.as_ref(), &email_address)
+        })
+        .await;
+
+    match result {
+        Ok(Ok(())) => Ok(true),
+        Ok(Err(_)) => Err(InternalServer.extend()),
+        Err(_) => Err(InternalServer.extend()),
+    }
+}
+
+pub async fn reset_password(
+    pool: &Pool,
+    token: String,
+    new_password: String,
+) -> Result<Option<User>, Error> {
+    validate_password(&new_password)?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| passwords::reset_password(conn, &token, &new_password))
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    match option {
+        Some(user) => Ok(Some(User::from(user))),
+        None => Err(InvalidToken.extend()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::mailer::MemoryMailer;
+    use crate::core::repo;
+    use crate::test::factory;
+
+    #[tokio::test]
+    async fn test_sign_in_invalid_credentials() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let result = sign_in(&pool, user.email_address, "wrong horse".to_string()).await;
+
+        assert_eq!(result.unwrap_err(), InvalidCredentials.extend())
+    }
+
+    #[tokio::test]
+    async fn test_request_password_reset_unknown_email() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let mailer = Arc::new(MemoryMailer::default());
+        let result = request_password_reset(&pool, mailer.clone(), "nobody@doe.com".to_string())
+            .await
+            .unwrap();
+
+        assert!(result);
+        assert_eq!(mailer.last_sent_to("nobody@doe.com"), None);
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_invalid_token() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let result = reset_password(&pool, "invalid".to_string(), "new password".to_string()).await;
+
+        assert_eq!(result.unwrap_err(), InvalidToken.extend())
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_too_short() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let error = reset_password(&pool, "invalid".to_string(), "short".to_string())
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.extensions.unwrap().get("code").unwrap().clone(),
+            "UNPROCESSABLE_CONTENT".into()
+        );
+    }
+}
@@ -1,10 +1,50 @@
+use crate::core::audit;
+use crate::core::authz::DELETE_USERS;
+use crate::core::authz::READ_AUDIT;
+use crate::core::authz::WRITE_USERS;
+use crate::core::email_changes;
+use crate::core::events;
+use crate::core::events::Change;
+use crate::core::events::Event;
+use crate::core::imports;
+use crate::core::imports::ImportFormat;
+use crate::core::mailer::Mailer;
+use crate::core::repo;
+use crate::core::search;
 use crate::core::users;
+use crate::core::users::VersionConflict;
+use crate::core::verification;
+use crate::server::resolvers::errors::GqlError::Conflict;
+use crate::server::resolvers::errors::GqlError::Forbidden;
 use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::errors::GqlError::InvalidToken;
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use crate::server::resolvers::pagination::decode_cursor;
+use crate::server::resolvers::pagination::decode_offset;
+use crate::server::resolvers::pagination::page_size;
 use crate::server::schema::user_schema::User;
+use crate::server::schema::user_schema::UserImport;
 use crate::server::schema::user_schema::UserInput;
+use crate::server::schema::user_schema::UserSearchConnection;
+use crate::server::schema::user_schema::UserSearchFields;
+use crate::server::schema::user_schema::UserVersion;
+use crate::server::viewer::actor;
+use crate::server::viewer::authenticate;
+use crate::server::viewer::tenant;
+use crate::server::viewer::Viewer;
+use async_graphql::async_stream::stream;
+use async_graphql::connection::Connection;
+use async_graphql::connection::Edge;
+use async_graphql::futures_util::Stream;
 use async_graphql::Error;
 use async_graphql::ErrorExtensions;
+use async_graphql::UploadValue;
+use chrono::DateTime;
+use chrono::Utc;
 use deadpool_diesel::postgres::Pool;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
 use uuid::Uuid;
 
 // struct UserParams {
@@ -15,12 +55,31 @@ use uuid::Uuid;
 //     input: UserInput,
 // }
 
-pub async fn user(pool: &Pool, id: Option<Uuid>) -> Result<Option<User>, Error> {
+/// Check that the email address has a local part and a domain.
+pub fn validate_email_address(email_address: &str) -> Result<(), Error> {
+    match users::is_valid_email_address(email_address) {
+        true => Ok(()),
+        false => Err(UnprocessableContent("The email address is invalid".to_string()).extend()),
+    }
+}
+
+pub async fn user(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+    as_of: Option<DateTime<Utc>>,
+) -> Result<Option<User>, Error> {
     // TODO: Validate input parameters
     // TODO: Set directives for input objects
+    if let Some(as_of) = as_of {
+        return user_version(pool, viewer, id.unwrap(), as_of).await;
+    }
+    let tenant_id = tenant(pool, viewer).await?;
     let conn = pool.get().await.unwrap();
     let result = conn
-        .interact(move |conn| users::get_user(conn, id.unwrap()))
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| users::get_user(conn, id.unwrap()))
+        })
         .await;
 
     // TODO: Handle specific database errors, like `NotFound`
@@ -31,22 +90,176 @@ pub async fn user(pool: &Pool, id: Option<Uuid>) -> Result<Option<User>, Error>
         Err(_) => return Err(InternalServer.extend()),
     };
 
-    match option {
-        Some(user) => Ok(Some(User {
-            id: Some(user.id),
-            first_name: Some(user.first_name),
-            last_name: Some(user.last_name),
-            email_address: Some(user.email_address),
-            created_at: Some(user.created_at.and_utc()),
-            updated_at: Some(user.updated_at.and_utc()),
-            deleted_at: user.deleted_at.map(|datetime| datetime.and_utc()),
-        })),
+    Ok(option.map(User::from))
+}
+
+/// Check that the viewer is the user, or can read the audit log, and get its
+/// tenant.
+async fn authorize_history(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    user_id: Uuid,
+) -> Result<Uuid, Error> {
+    let identity = authenticate(pool, viewer).await?;
+    if identity.user_id != user_id && !identity.grants.has_permission(READ_AUDIT) {
+        let reason = "Only the user and auditors can see its history".to_string();
+        return Err(Forbidden(reason).extend());
+    }
+
+    Ok(identity.tenant_id)
+}
+
+/// Require the viewer to be the user, or to have the permission to change
+/// other users, and get its tenant.
+async fn authorize_change(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    user_id: Uuid,
+    permission: &str,
+) -> Result<Uuid, Error> {
+    let identity = authenticate(pool, viewer).await?;
+    if identity.user_id != user_id && !identity.grants.has_permission(permission) {
+        let reason = format!(
+            "Only the user and the {} permission can change it",
+            permission
+        );
+        return Err(Forbidden(reason).extend());
+    }
+
+    Ok(identity.tenant_id)
+}
+
+/// Get the user as it was at the time.
+async fn user_version(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Uuid,
+    as_of: DateTime<Utc>,
+) -> Result<Option<User>, Error> {
+    let tenant_id = authorize_history(pool, viewer, id).await?;
+    let timestamp = as_of.naive_utc();
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                users::get_user_version(conn, id, timestamp)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(User::from))
+}
+
+pub async fn user_history(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    user_id: Option<Uuid>,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<Connection<String, UserVersion>, Error> {
+    let user_id = user_id.unwrap();
+    let tenant_id = authorize_history(pool, viewer, user_id).await?;
+    let after = decode_cursor(after)?;
+    let limit = page_size(first);
+    let conn = pool.get().await.unwrap();
+    // Fetch one extra version to know whether there is a next page.
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                users::list_user_versions(conn, user_id, after, limit + 1)
+            })
+        })
+        .await;
+
+    let mut versions = match result {
+        Ok(Ok(versions)) => versions,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+    let has_next_page = versions.len() as i64 > limit;
+    versions.truncate(limit as usize);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection.edges.extend(
+        versions
+            .into_iter()
+            .map(|version| Edge::new(version.version_id.to_string(), UserVersion::from(version))),
+    );
+
+    Ok(connection)
+}
+
+/// Search the users of the tenant of the viewer, best match first. The cursor
+/// of a user is its position in the results. The limits of the matching of
+/// typos are those of `search::search_users`.
+pub async fn search_users(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    query: String,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<UserSearchConnection, Error> {
+    let identity = authenticate(pool, viewer).await?;
+    let offset = decode_offset(after)?;
+    let limit = page_size(first);
+    let tenant_id = identity.tenant_id;
+    let conn = pool.get().await.unwrap();
+    let terms = query.clone();
+    // Fetch one extra user to know whether there is a next page.
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                search::search_users(conn, &terms, offset, limit + 1)
+            })
+        })
+        .await;
+
+    let mut users = match result {
+        Ok(Ok(users)) => users,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+    let has_next_page = users.len() as i64 > limit;
+    users.truncate(limit as usize);
+
+    let mut connection = UserSearchConnection::new(offset > 0, has_next_page);
+    connection
+        .edges
+        .extend(users.into_iter().enumerate().map(|(i, found)| {
+            let cursor = (offset + i as i64).to_string();
+            let fields = UserSearchFields::new(&found, &query, &identity.masking);
+            Edge::with_additional_fields(cursor, User::from(found.user), fields)
+        }));
+
+    Ok(connection)
+}
+
+pub async fn viewer(pool: &Pool, viewer: Option<&Viewer>) -> Result<Option<User>, Error> {
+    let identity = match viewer {
+        Some(viewer) => viewer.identity(pool).await?,
+        None => None,
+    };
+
+    match identity {
+        Some(identity) => user(pool, viewer, Some(identity.user_id), None).await,
         None => Ok(None),
     }
 }
 
-pub async fn create_user(pool: &Pool, input: Option<UserInput>) -> Result<Option<User>, Error> {
+pub async fn create_user(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    input: Option<UserInput>,
+) -> Result<Option<User>, Error> {
     // TODO: Validate input parameters
+    let tenant_id = tenant(pool, viewer).await?;
+    let actor = actor(pool, viewer).await?;
     let conn = pool.get().await.unwrap();
     let attrs = input.unwrap();
     let attrs = users::CreateUserAttrs {
@@ -54,7 +267,13 @@ pub async fn create_user(pool: &Pool, input: Option<UserInput>) -> Result<Option
         last_name: attrs.last_name.unwrap(),
         email_address: attrs.email_address.unwrap(),
     };
-    let result = conn.interact(|conn| users::create_user(conn, attrs)).await;
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                audit::with_actor(conn, &actor, |conn| users::create_user(conn, attrs))
+            })
+        })
+        .await;
 
     // TODO: Handle specific database errors, like `NotFound`
     // TODO: Write an abstraction (any db error returns internal server error)
@@ -64,39 +283,329 @@ pub async fn create_user(pool: &Pool, input: Option<UserInput>) -> Result<Option
         Err(_) => return Err(InternalServer.extend()),
     };
 
+    Ok(option.map(User::from))
+}
+
+/// Import users from an uploaded file, in the format of its extension unless
+/// given.
+pub async fn import_users(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    upload: UploadValue,
+    format: Option<ImportFormat>,
+    dry_run: bool,
+) -> Result<UserImport, Error> {
+    let format = match format.or_else(|| ImportFormat::from_file_name(&upload.filename)) {
+        Some(format) => format,
+        None => {
+            let reason = "The format of the file is unknown".to_string();
+            return Err(UnprocessableContent(reason).extend());
+        }
+    };
+    let tenant_id = tenant(pool, viewer).await?;
+    let actor = actor(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                audit::with_actor(conn, &actor, |conn| {
+                    imports::import_users(conn, upload.into_read(), format, dry_run)
+                })
+            })
+        })
+        .await;
+
+    match result {
+        Ok(Ok(report)) => Ok(UserImport::new(report, dry_run)),
+        Ok(Err(e)) if e.kind() == ErrorKind::InvalidData => {
+            Err(UnprocessableContent(e.to_string()).extend())
+        }
+        Ok(Err(_)) => Err(InternalServer.extend()),
+        Err(_) => Err(InternalServer.extend()),
+    }
+}
+
+/// Map the failure to change a user to its error, like a version conflict.
+fn change_error(e: std::io::Error) -> Error {
+    match e
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<VersionConflict>())
+    {
+        Some(conflict) => Conflict(conflict.current_version).extend(),
+        None => InternalServer.extend(),
+    }
+}
+
+pub async fn update_user(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+    input: Option<UserInput>,
+    expected_version: Option<i32>,
+) -> Result<Option<User>, Error> {
+    // TODO: Validate input parameters
+    let id = id.unwrap();
+    let tenant_id = authorize_change(pool, viewer, id, WRITE_USERS).await?;
+    let actor = actor(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let attrs = input.unwrap();
+    if attrs.email_address.is_some() {
+        let reason = "The email address can only be changed with requestEmailChange";
+        return Err(UnprocessableContent(reason.to_string()).extend());
+    }
+    let attrs = users::UpdateUserAttrs {
+        first_name: attrs.first_name,
+        last_name: attrs.last_name,
+        email_address: attrs.email_address,
+    };
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                audit::with_actor(conn, &actor, |conn| {
+                    users::update_user(conn, id, attrs, expected_version)
+                })
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(change_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(User::from))
+}
+
+pub async fn delete_user(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+    expected_version: Option<i32>,
+) -> Result<Option<User>, Error> {
+    // TODO: Validate input parameters
+    let id = id.unwrap();
+    let tenant_id = authorize_change(pool, viewer, id, DELETE_USERS).await?;
+    let actor = actor(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                audit::with_actor(conn, &actor, |conn| {
+                    users::delete_user(conn, id, expected_version)
+                })
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) => return Err(change_error(e)),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(User::from))
+}
+
+pub async fn send_verification_email(
+    pool: &Pool,
+    mailer: Arc<dyn Mailer>,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+) -> Result<Option<User>, Error> {
+    let id = id.unwrap();
+    let tenant_id = authorize_change(pool, viewer, id, WRITE_USERS).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                verification::send_verification_email(conn, mailer.as_ref(), id)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(User::from))
+}
+
+pub async fn verify_email(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    token: String,
+) -> Result<Option<User>, Error> {
+    let actor = actor(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            audit::with_actor(conn, &actor, |conn| {
+                verification::verify_email(conn, &token)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
     match option {
-        Some(user) => Ok(Some(User {
-            id: Some(user.id),
-            first_name: Some(user.first_name),
-            last_name: Some(user.last_name),
-            email_address: Some(user.email_address),
-            created_at: Some(user.created_at.and_utc()),
-            updated_at: Some(user.updated_at.and_utc()),
-            deleted_at: user.deleted_at.map(|datetime| datetime.and_utc()),
-        })),
-        None => Ok(None),
+        Some(user) => Ok(Some(User::from(user))),
+        None => Err(InvalidToken.extend()),
     }
 }
 
+pub async fn request_email_change(
+    pool: &Pool,
+    mailer: Arc<dyn Mailer>,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+    new_email_address: String,
+) -> Result<Option<User>, Error> {
+    validate_email_address(&new_email_address)?;
+    let id = id.unwrap();
+    let tenant_id = authorize_change(pool, viewer, id, WRITE_USERS).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                email_changes::request_email_change(conn, mailer.as_ref(), id, &new_email_address)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(User::from))
+}
+
+pub async fn confirm_email_change(
+    pool: &Pool,
+    mailer: Arc<dyn Mailer>,
+    viewer: Option<&Viewer>,
+    token: String,
+) -> Result<Option<User>, Error> {
+    let actor = actor(pool, viewer).await?;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            audit::with_actor(conn, &actor, |conn| {
+                email_changes::confirm_email_change(conn, mailer.as_ref(), &token)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(e)) if e.kind() == ErrorKind::AlreadyExists => {
+            let reason = "The email address is already in use";
+            return Err(UnprocessableContent(reason.to_string()).extend());
+        }
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    match option {
+        Some(user) => Ok(Some(User::from(user))),
+        None => Err(InvalidToken.extend()),
+    }
+}
+
+pub async fn user_created(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+) -> Result<impl Stream<Item = Option<User>>, Error> {
+    user_events(pool, viewer, |event| event.change == Change::Created).await
+}
+
+pub async fn user_updated(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+) -> Result<impl Stream<Item = Option<User>>, Error> {
+    user_events(pool, viewer, move |event| {
+        event.change == Change::Updated && id.is_none_or(|id| id == event.user.id)
+    })
+    .await
+}
+
+pub async fn user_deleted(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+) -> Result<impl Stream<Item = Option<User>>, Error> {
+    user_events(pool, viewer, |event| event.change == Change::Deleted).await
+}
+
+/// Stream the users of the events of the tenant of the viewer selected by
+/// `filter`.
+async fn user_events(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    filter: impl Fn(&Event) -> bool + Send + 'static,
+) -> Result<impl Stream<Item = Option<User>>, Error> {
+    let tenant_id = authenticate(pool, viewer).await?.tenant_id;
+    let mut receiver = events::subscribe();
+
+    Ok(stream! {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    if event.tenant_id == tenant_id && filter(&event) {
+                        yield Some(User::from(event.user));
+                    }
+                }
+                // A slow subscriber skips the events it missed.
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config;
+    use crate::core::mailer::MemoryMailer;
     use crate::core::repo;
-    use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+    use crate::server::resolvers::errors::GqlError;
     use crate::server::resolvers::user_resolver;
     use crate::server::schema;
     use crate::test::factory;
+    use async_graphql::futures_util;
+    use async_graphql::futures_util::StreamExt;
     use async_graphql::Value::Null;
 
     // TODO: Add a shared test setup, maybe with a database transaction.
 
+    /// Sign in as the user.
+    fn viewer_of(user_id: Uuid) -> Viewer {
+        use crate::core::sessions;
+        use diesel::prelude::*;
+
+        let mut conn = PgConnection::establish(&config::get_config().database_url).unwrap();
+        let (_, token) = sessions::create_session(&mut conn, user_id).unwrap();
+
+        Viewer::new(Some(token))
+    }
+
     #[tokio::test]
     async fn test_user() {
         let user = factory::insert_user();
         let config = config::get_config();
         let pool = repo::connect_database(&config.database_url);
-        let result = user_resolver::user(&pool, Some(user.id)).await.unwrap();
+        let result = user_resolver::user(&pool, None, Some(user.id), None)
+            .await
+            .unwrap();
 
         assert_eq!(
             result,
@@ -108,6 +617,8 @@ mod tests {
                 created_at: Some(user.created_at.and_utc()),
                 updated_at: Some(user.updated_at.and_utc()),
                 deleted_at: user.deleted_at.map(|datetime| datetime.and_utc()),
+                email_verified_at: None,
+                version: Some(1),
             })
         )
     }
@@ -117,7 +628,9 @@ mod tests {
         let config = config::get_config();
         let pool = repo::connect_database(&config.database_url);
         let id = Uuid::now_v7();
-        let result = user_resolver::user(&pool, Some(id)).await.unwrap();
+        let result = user_resolver::user(&pool, None, Some(id), None)
+            .await
+            .unwrap();
 
         assert_eq!(result, None);
     }
@@ -127,7 +640,9 @@ mod tests {
         // TODO: Add a function `connect_database` to `test` module and return test connection
         let config = config::get_config();
         let pool = repo::connect_database(&config.database_url);
-        let result = user_resolver::user(&pool, None).await.unwrap_err();
+        let result = user_resolver::user(&pool, None, None, None)
+            .await
+            .unwrap_err();
 
         // TODO: Check the error object
         assert_eq!(result, UnprocessableContent("reason".to_string()).extend())
@@ -157,7 +672,8 @@ mod tests {
         let response = schema
             .execute(
                 async_graphql::Request::new(query)
-                    .variables(async_graphql::Variables::from_json(variables)),
+                    .variables(async_graphql::Variables::from_json(variables))
+                    .data(viewer_of(user.id)),
             )
             .await;
 
@@ -184,19 +700,20 @@ mod tests {
     async fn test_create_user() {
         let config = config::get_config();
         let pool = repo::connect_database(&config.database_url);
+        let email = format!("jane.{}@example.com", Uuid::now_v7().simple());
         let input = UserInput {
             first_name: Some("Jane".to_string()),
             last_name: Some("Doe".to_string()),
-            email_address: Some("jane.doe@example.com".to_string()),
+            email_address: Some(email.clone()),
         };
-        let result = user_resolver::create_user(&pool, Some(input))
+        let result = user_resolver::create_user(&pool, None, Some(input))
             .await
             .unwrap();
 
         if let Some(user) = result {
             assert_eq!(user.first_name, Some("Jane".to_string()));
             assert_eq!(user.last_name, Some("Doe".to_string()));
-            assert_eq!(user.email_address, Some("jane.doe@example.com".to_string()));
+            assert_eq!(user.email_address, Some(email));
             assert_eq!(user.created_at, user.updated_at);
             assert_eq!(user.deleted_at, None);
         } else {
@@ -204,11 +721,157 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_update_user() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let input = UserInput {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+        let viewer = viewer_of(user.id);
+        let result =
+            user_resolver::update_user(&pool, Some(&viewer), Some(user.id), Some(input), None)
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(result.id, Some(user.id));
+        assert_eq!(result.first_name, Some("Janet".to_string()));
+        assert_eq!(result.last_name, Some(user.last_name));
+    }
+
+    #[tokio::test]
+    async fn test_update_user_forbidden() {
+        let user = factory::insert_user();
+        let other = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let input = || UserInput {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+
+        let anonymous = user_resolver::update_user(&pool, None, Some(user.id), Some(input()), None)
+            .await
+            .unwrap_err();
+        let viewer = viewer_of(other.id);
+        let forbidden =
+            user_resolver::update_user(&pool, Some(&viewer), Some(user.id), Some(input()), None)
+                .await
+                .unwrap_err();
+        let deleted = user_resolver::delete_user(&pool, Some(&viewer), Some(user.id), None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(anonymous, GqlError::Unauthenticated.extend());
+        let code = |error: Error| error.extensions.unwrap().get("code").cloned();
+        assert_eq!(code(forbidden), Some("FORBIDDEN".into()));
+        assert_eq!(code(deleted), Some("FORBIDDEN".into()));
+    }
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = viewer_of(user.id);
+        let result = user_resolver::delete_user(&pool, Some(&viewer), Some(user.id), None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.id, Some(user.id));
+        assert_ne!(result.deleted_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_user_updated() {
+        use crate::core::models::schema::tenants;
+        use chrono::Utc;
+        use diesel::prelude::*;
+
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        events::listen(&config.database_url).await;
+        let tenant_id = diesel::insert_into(tenants::table)
+            .values((
+                tenants::id.eq(Uuid::now_v7()),
+                tenants::name.eq("Doe Inc."),
+                tenants::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .returning(tenants::id)
+            .get_result::<Uuid>(&mut conn)
+            .unwrap();
+        let attrs = users::CreateUserAttrs {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            email_address: format!("john.{}@doe.com", Uuid::now_v7().simple()),
+        };
+        let other = repo::with_tenant(&mut conn, tenant_id, |conn| users::create_user(conn, attrs))
+            .unwrap()
+            .unwrap();
+        let (viewer, other_viewer) = (viewer_of(user.id), viewer_of(other.id));
+        let stream = user_resolver::user_updated(&pool, Some(&other_viewer), None)
+            .await
+            .unwrap();
+        let input = |first_name: &str| UserInput {
+            first_name: Some(first_name.to_string()),
+            last_name: None,
+            email_address: None,
+        };
+        // The change to the user of the other tenant is not streamed.
+        user_resolver::update_user(
+            &pool,
+            Some(&viewer),
+            Some(user.id),
+            Some(input("Janet")),
+            None,
+        )
+        .await
+        .unwrap();
+        user_resolver::update_user(
+            &pool,
+            Some(&other_viewer),
+            Some(other.id),
+            Some(input("Johnny")),
+            None,
+        )
+        .await
+        .unwrap();
+
+        futures_util::pin_mut!(stream);
+        let result = stream.next().await.unwrap().unwrap();
+
+        assert_eq!(result.id, Some(other.id));
+        assert_eq!(result.first_name, Some("Johnny".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_user_updated_unauthenticated() {
+        let config = config::get_config();
+        let schema = schema::create_schema(repo::connect_database(&config.database_url));
+        let mut stream = schema.execute_stream("subscription { userUpdated { id } }");
+
+        let response = stream.next().await.unwrap();
+
+        assert_eq!(response.errors.len(), 1);
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&"UNAUTHENTICATED".into()));
+    }
+
     #[tokio::test]
     async fn test_create_user_missing_input() {
         let config = config::get_config();
         let pool = repo::connect_database(&config.database_url);
-        let result = user_resolver::create_user(&pool, None).await.unwrap_err();
+        let result = user_resolver::create_user(&pool, None, None)
+            .await
+            .unwrap_err();
 
         // TODO: Check the error object
         assert_eq!(result, UnprocessableContent("reason".to_string()).extend())
@@ -223,11 +886,628 @@ mod tests {
             last_name: Some("D".to_string()),
             email_address: Some("jane.doe@@example.com".to_string()),
         };
-        let result = user_resolver::create_user(&pool, Some(input))
+        let result = user_resolver::create_user(&pool, None, Some(input))
             .await
             .unwrap_err();
 
         // TODO: Check the error object
         assert_eq!(result, UnprocessableContent("reason".to_string()).extend())
     }
+
+    #[tokio::test]
+    async fn test_send_verification_email() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let mailer = Arc::new(MemoryMailer::default());
+        let viewer = viewer_of(user.id);
+        let result = user_resolver::send_verification_email(
+            &pool,
+            mailer.clone(),
+            Some(&viewer),
+            Some(user.id),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.unwrap().email_verified_at, None);
+        assert_eq!(
+            mailer.last_sent_to(&user.email_address).unwrap().to,
+            user.email_address
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_verification_email_forbidden() {
+        let user = factory::insert_user();
+        let other = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let mailer = Arc::new(MemoryMailer::default());
+        let viewer = viewer_of(other.id);
+
+        let error = user_resolver::send_verification_email(
+            &pool,
+            mailer.clone(),
+            Some(&viewer),
+            Some(user.id),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Forbidden("Only the user and the users:write permission can change it".to_string())
+                .extend()
+        );
+
+        let error = user_resolver::send_verification_email(&pool, mailer, None, Some(user.id))
+            .await
+            .unwrap_err();
+        assert_eq!(error, GqlError::Unauthenticated.extend());
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_invalid_token() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let result = user_resolver::verify_email(&pool, None, "invalid".to_string())
+            .await
+            .unwrap_err();
+
+        assert_eq!(result, InvalidToken.extend())
+    }
+
+    #[tokio::test]
+    async fn test_update_user_email_address() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = viewer_of(user.id);
+        let input = UserInput {
+            first_name: None,
+            last_name: None,
+            email_address: Some("janet@doe.com".to_string()),
+        };
+        let result =
+            user_resolver::update_user(&pool, Some(&viewer), Some(user.id), Some(input), None)
+                .await
+                .unwrap_err();
+
+        assert_eq!(
+            result,
+            UnprocessableContent(
+                "The email address can only be changed with requestEmailChange".to_string()
+            )
+            .extend()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_request_email_change() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let mailer = Arc::new(MemoryMailer::default());
+        let viewer = viewer_of(user.id);
+        let new_email_address = format!("janet.{}@doe.com", user.id.simple());
+        let result = user_resolver::request_email_change(
+            &pool,
+            mailer.clone(),
+            Some(&viewer),
+            Some(user.id),
+            new_email_address.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.unwrap().email_address, Some(user.email_address));
+        assert_ne!(mailer.last_sent_to(&new_email_address), None);
+    }
+
+    #[tokio::test]
+    async fn test_request_email_change_forbidden() {
+        let user = factory::insert_user();
+        let other = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let mailer = Arc::new(MemoryMailer::default());
+        let viewer = viewer_of(other.id);
+        let new_email_address = format!("janet.{}@doe.com", user.id.simple());
+
+        let error = user_resolver::request_email_change(
+            &pool,
+            mailer.clone(),
+            Some(&viewer),
+            Some(user.id),
+            new_email_address.clone(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            Forbidden("Only the user and the users:write permission can change it".to_string())
+                .extend()
+        );
+        assert_eq!(mailer.last_sent_to(&new_email_address), None);
+    }
+
+    #[tokio::test]
+    async fn test_request_email_change_invalid_email() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let mailer = Arc::new(MemoryMailer::default());
+        let result = user_resolver::request_email_change(
+            &pool,
+            mailer,
+            None,
+            Some(user.id),
+            "janet@@doe.com".to_string(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            result,
+            UnprocessableContent("The email address is invalid".to_string()).extend()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_confirm_email_change_invalid_token() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let mailer = Arc::new(MemoryMailer::default());
+        let result =
+            user_resolver::confirm_email_change(&pool, mailer, None, "invalid".to_string())
+                .await
+                .unwrap_err();
+
+        assert_eq!(result, InvalidToken.extend())
+    }
+
+    #[tokio::test]
+    async fn test_viewer() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let conn = pool.get().await.unwrap();
+        let (_, token) = conn
+            .interact(move |conn| crate::core::sessions::create_session(conn, user.id))
+            .await
+            .unwrap()
+            .unwrap();
+        let result = user_resolver::viewer(&pool, Some(&Viewer::new(Some(token))))
+            .await
+            .unwrap();
+
+        assert_eq!(result.unwrap().id, Some(user.id));
+        assert_eq!(user_resolver::viewer(&pool, None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_user_other_tenant() {
+        use crate::core::models::schema::tenants;
+        use crate::core::users::CreateUserAttrs;
+        use diesel::prelude::*;
+
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let tenant_id = diesel::insert_into(tenants::table)
+            .values((
+                tenants::id.eq(Uuid::now_v7()),
+                tenants::name.eq("Doe Inc."),
+                tenants::created_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .returning(tenants::id)
+            .get_result(&mut conn)
+            .unwrap();
+        let attrs = CreateUserAttrs {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            email_address: format!("john.{}@doe.com", Uuid::now_v7().simple()),
+        };
+        let other = repo::with_tenant(&mut conn, tenant_id, |conn| {
+            crate::core::users::create_user(conn, attrs)
+        })
+        .unwrap()
+        .unwrap();
+        let (_, token) = crate::core::sessions::create_session(&mut conn, other.id).unwrap();
+        let other_viewer = Viewer::new(Some(token));
+
+        let result = user_resolver::user(&pool, Some(&other_viewer), Some(user.id), None)
+            .await
+            .unwrap();
+        assert_eq!(result, None);
+        let result = user_resolver::user(&pool, None, Some(other.id), None)
+            .await
+            .unwrap();
+        assert_eq!(result, None);
+        let result = user_resolver::user(&pool, Some(&other_viewer), Some(other.id), None)
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap().id, Some(other.id));
+    }
+
+    #[tokio::test]
+    async fn test_user_history() {
+        use crate::core::authz;
+        use crate::core::sessions;
+        use diesel::prelude::*;
+
+        let janet = factory::insert_user();
+        let other = factory::insert_user();
+        let auditor = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        authz::grant_role(&mut conn, auditor.id, "admin").unwrap();
+        let mut viewer_of = |user_id| {
+            let (_, token) = sessions::create_session(&mut conn, user_id).unwrap();
+            Viewer::new(Some(token))
+        };
+        let (user_viewer, other_viewer, auditor_viewer) = (
+            viewer_of(janet.id),
+            viewer_of(other.id),
+            viewer_of(auditor.id),
+        );
+        let input = UserInput {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+        update_user(&pool, Some(&user_viewer), Some(janet.id), Some(input), None)
+            .await
+            .unwrap();
+
+        let history = user_history(&pool, Some(&user_viewer), Some(janet.id), None, None)
+            .await
+            .unwrap();
+        assert_eq!(history.edges.len(), 2);
+        let created = &history.edges[1].node;
+        assert_eq!(created.first_name, Some(janet.first_name.clone()));
+        let history = user_history(&pool, Some(&auditor_viewer), Some(janet.id), None, None)
+            .await
+            .unwrap();
+        assert_eq!(history.edges.len(), 2);
+        let error = user_history(&pool, Some(&other_viewer), Some(janet.id), None, None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.extensions.unwrap().get("code").unwrap().to_string(),
+            "\"FORBIDDEN\""
+        );
+
+        let as_of = created.valid_from;
+        let result = user_resolver::user(&pool, Some(&auditor_viewer), Some(janet.id), as_of)
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap().first_name, Some(janet.first_name));
+        let result = user_resolver::user(&pool, Some(&user_viewer), Some(janet.id), None)
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap().first_name, Some("Janet".to_string()));
+        assert!(user_resolver::user(&pool, None, Some(janet.id), as_of)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_user_conflict() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = viewer_of(user.id);
+        let input = || UserInput {
+            first_name: Some("Janet".to_string()),
+            last_name: None,
+            email_address: None,
+        };
+
+        let updated = update_user(
+            &pool,
+            Some(&viewer),
+            Some(user.id),
+            Some(input()),
+            Some(user.version),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.unwrap().version, Some(user.version + 1));
+        let error = update_user(
+            &pool,
+            Some(&viewer),
+            Some(user.id),
+            Some(input()),
+            Some(user.version),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error, Conflict(user.version + 1).extend());
+        let extensions = error.extensions.unwrap();
+        assert_eq!(extensions.get("currentVersion").unwrap().to_string(), "2");
+        let error = delete_user(&pool, Some(&viewer), Some(user.id), Some(user.version))
+            .await
+            .unwrap_err();
+        assert_eq!(error, Conflict(user.version + 1).extend());
+    }
+
+    /// Upload the content as a file with the name.
+    fn upload(filename: &str, content: &str) -> UploadValue {
+        let path = std::env::temp_dir().join(format!("{}-{}", Uuid::now_v7(), filename));
+        std::fs::write(&path, content).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        UploadValue {
+            filename: filename.to_string(),
+            content_type: None,
+            content: file,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_users() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+        let content = format!(
+            "firstName,lastName,emailAddress\nJane,Doe,jane.{}@doe.com\nJohn,Doe,\n",
+            Uuid::now_v7().simple()
+        );
+
+        let upload = upload("users.csv", &content);
+        let result = user_resolver::import_users(&pool, None, upload, None, false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            (result.rows, result.imported, result.dry_run),
+            (2, 1, false)
+        );
+        assert_eq!(result.errors[0].row, 2);
+        assert_eq!(result.errors[0].message, "The email address is required");
+    }
+
+    #[tokio::test]
+    async fn test_import_users_invalid_file() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+
+        let unknown = user_resolver::import_users(&pool, None, upload("users.txt", ""), None, true)
+            .await
+            .unwrap_err();
+        let invalid =
+            user_resolver::import_users(&pool, None, upload("users.json", "{}"), None, true)
+                .await
+                .unwrap_err();
+
+        let reason = "The format of the file is unknown".to_string();
+        assert_eq!(unknown, UnprocessableContent(reason).extend());
+        assert_eq!(
+            invalid.extensions.unwrap().get("code"),
+            Some(&"UNPROCESSABLE_CONTENT".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_user_masked() {
+        use crate::core::authz;
+        use crate::core::sessions;
+        use diesel::prelude::*;
+
+        let user = factory::insert_user();
+        let support = factory::insert_user();
+        let admin = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let schema = schema::create_schema(repo::connect_database(&config.database_url));
+        authz::grant_role(&mut conn, support.id, "support").unwrap();
+        authz::grant_role(&mut conn, admin.id, "admin").unwrap();
+        let mut viewer_of = |user_id| {
+            let (_, token) = sessions::create_session(&mut conn, user_id).unwrap();
+            Viewer::new(Some(token))
+        };
+        let query = format!(
+            "{{ user(id: \"{}\") {{ emailAddress fullName }} }}",
+            user.id
+        );
+        let masked = serde_json::json!({"emailAddress": "j***@doe.com", "fullName": "Jane D***"});
+        let full = serde_json::json!({
+            "emailAddress": user.email_address,
+            "fullName": format!("{} {}", user.first_name, user.last_name),
+        });
+        let hidden = serde_json::json!({"emailAddress": null, "fullName": null});
+
+        for (viewer, expected) in [
+            (viewer_of(support.id), &masked),
+            (viewer_of(admin.id), &full),
+            (viewer_of(user.id), &full),
+            (Viewer::default(), &hidden),
+        ] {
+            let response = schema
+                .execute(async_graphql::Request::new(&query).data(viewer))
+                .await;
+
+            assert!(response.errors.is_empty());
+            assert_eq!(&response.data.into_json().unwrap()["user"], expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_user_history_masked() {
+        use crate::core::authz;
+        use crate::core::models::schema::permissions;
+        use crate::core::models::schema::roles;
+        use crate::core::sessions;
+        use chrono::Utc;
+        use diesel::prelude::*;
+
+        let user = factory::insert_user();
+        let auditor = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let schema = schema::create_schema(repo::connect_database(&config.database_url));
+        let role = format!("auditor-{}", Uuid::now_v7().simple());
+        let role_id = diesel::insert_into(roles::table)
+            .values((
+                roles::id.eq(Uuid::now_v7()),
+                roles::name.eq(&role),
+                roles::description.eq(""),
+                roles::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .returning(roles::id)
+            .get_result::<Uuid>(&mut conn)
+            .unwrap();
+        diesel::insert_into(permissions::table)
+            .values((
+                permissions::role_id.eq(role_id),
+                permissions::name.eq(READ_AUDIT),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        authz::grant_role(&mut conn, auditor.id, "support").unwrap();
+        authz::grant_role(&mut conn, auditor.id, &role).unwrap();
+        let (_, token) = sessions::create_session(&mut conn, auditor.id).unwrap();
+        let query = format!(
+            "{{ user(id: \"{}\") {{ history {{ edges {{ node {{ firstName emailAddress }} }} }} }} }}",
+            user.id
+        );
+
+        let response = schema
+            .execute(async_graphql::Request::new(&query).data(Viewer::new(Some(token))))
+            .await;
+
+        assert!(response.errors.is_empty());
+        let data = response.data.into_json().unwrap();
+        assert_eq!(
+            data["user"]["history"]["edges"][0]["node"],
+            serde_json::json!({"firstName": user.first_name, "emailAddress": "j***@doe.com"})
+        );
+        assert!(!data.to_string().contains(&user.email_address));
+    }
+
+    #[tokio::test]
+    async fn test_user_updated_masked() {
+        use crate::core::authz;
+        use crate::core::sessions;
+        use diesel::prelude::*;
+        use std::time::Duration;
+
+        let user = factory::insert_user();
+        let support = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let schema = schema::create_schema(pool.clone());
+        events::listen(&config.database_url).await;
+        authz::grant_role(&mut conn, support.id, "support").unwrap();
+        let (_, token) = sessions::create_session(&mut conn, support.id).unwrap();
+        let query = format!(
+            "subscription {{ userUpdated(id: \"{}\") {{ lastName }} }}",
+            user.id
+        );
+        let request = async_graphql::Request::new(query).data(Viewer::new(Some(token)));
+        let mut stream = schema.execute_stream(request);
+        // Start the subscription before the update.
+        let started = tokio::time::timeout(Duration::from_millis(100), stream.next()).await;
+        assert!(started.is_err());
+        let input = UserInput {
+            first_name: None,
+            last_name: Some("Smith".to_string()),
+            email_address: None,
+        };
+        let viewer = viewer_of(user.id);
+        user_resolver::update_user(&pool, Some(&viewer), Some(user.id), Some(input), None)
+            .await
+            .unwrap();
+
+        let response = stream.next().await.unwrap();
+
+        assert!(response.errors.is_empty());
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({"userUpdated": {"lastName": "S***"}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_users() {
+        use crate::core::authz;
+        use crate::core::sessions;
+        use diesel::prelude::*;
+
+        let name = format!("Kim{}", Uuid::now_v7().simple());
+        let pool = repo::connect_database(&config::get_config().database_url);
+        let attrs = UserInput {
+            first_name: Some(name.clone()),
+            last_name: Some("Doe".to_string()),
+            email_address: Some(format!("{}@doe.com", name.to_lowercase())),
+        };
+        let user = user_resolver::create_user(&pool, None, Some(attrs))
+            .await
+            .unwrap()
+            .unwrap();
+        let support = factory::insert_user();
+        let admin = factory::insert_user();
+        let mut conn = PgConnection::establish(&config::get_config().database_url).unwrap();
+        let schema = schema::create_schema(pool);
+        authz::grant_role(&mut conn, support.id, "support").unwrap();
+        authz::grant_role(&mut conn, admin.id, "admin").unwrap();
+        let mut viewer_of = |user_id| {
+            let (_, token) = sessions::create_session(&mut conn, user_id).unwrap();
+            Viewer::new(Some(token))
+        };
+        let query = format!(
+            "{{ searchUsers(query: \"{}\") {{ edges {{ cursor rank highlights {{ field snippet }} \
+             node {{ id emailAddress }} }} }} }}",
+            name
+        );
+        let edge = |highlights, email_address| {
+            serde_json::json!([{
+                "cursor": "0",
+                "rank": 1.0,
+                "highlights": highlights,
+                "node": {"id": user.id, "emailAddress": email_address},
+            }])
+        };
+        let full = edge(
+            serde_json::json!([
+                {"field": "FIRST_NAME", "snippet": format!("<b>{}</b>", name)},
+                {
+                    "field": "EMAIL_ADDRESS",
+                    "snippet": format!("<b>{}</b>@doe.com", name.to_lowercase()),
+                },
+            ]),
+            user.email_address.clone().unwrap(),
+        );
+        let masked = edge(
+            serde_json::json!([{"field": "FIRST_NAME", "snippet": format!("<b>{}</b>", name)}]),
+            "k***@doe.com".to_string(),
+        );
+
+        for (viewer, expected) in [
+            (viewer_of(admin.id), &full),
+            (viewer_of(support.id), &masked),
+        ] {
+            let response = schema
+                .execute(async_graphql::Request::new(&query).data(viewer))
+                .await;
+
+            assert!(response.errors.is_empty());
+            assert_eq!(
+                &response.data.into_json().unwrap()["searchUsers"]["edges"],
+                expected
+            );
+        }
+        let viewer = viewer_of(user.id.unwrap());
+        let response = schema
+            .execute(async_graphql::Request::new(&query).data(viewer))
+            .await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&"FORBIDDEN".into()));
+    }
 }
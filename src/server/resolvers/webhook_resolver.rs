@@ -0,0 +1,406 @@
+use crate::core::repo;
+use crate::core::webhooks;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::errors::GqlError::UnprocessableContent;
+use crate::server::resolvers::pagination::decode_cursor;
+use crate::server::resolvers::pagination::page_size;
+use crate::server::schema::webhook_schema::Webhook;
+use crate::server::schema::webhook_schema::WebhookDelivery;
+use crate::server::schema::webhook_schema::WebhookEventType;
+use crate::server::schema::webhook_schema::WebhookInput;
+use crate::server::viewer::authenticate;
+use crate::server::viewer::Viewer;
+use async_graphql::connection::Connection;
+use async_graphql::connection::Edge;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use deadpool_diesel::postgres::Pool;
+use tokio::net::lookup_host;
+use uuid::Uuid;
+
+/// Check that the URL is an HTTP(S) URL of a public host, so that webhooks
+/// cannot be pointed at internal services.
+async fn validate_url(url: &str) -> Result<(), Error> {
+    let Some(netloc) = webhooks::netloc(url) else {
+        return Err(UnprocessableContent("The URL must be an HTTP(S) URL".to_string()).extend());
+    };
+    let addresses: Vec<_> = match lookup_host(netloc).await {
+        Ok(addresses) => addresses.collect(),
+        Err(_) => Vec::new(),
+    };
+
+    if addresses.is_empty()
+        || !addresses
+            .iter()
+            .all(|address| webhooks::is_public_address(address.ip()))
+    {
+        return Err(
+            UnprocessableContent("The URL must resolve to a public address".to_string()).extend(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Check that at least one event type is subscribed to.
+fn validate_event_types(event_types: &[WebhookEventType]) -> Result<Vec<String>, Error> {
+    if event_types.is_empty() {
+        return Err(
+            UnprocessableContent("At least one event type is required".to_string()).extend(),
+        );
+    }
+
+    Ok(event_types
+        .iter()
+        .map(|event_type| event_type.as_str().to_string())
+        .collect())
+}
+
+pub async fn webhook(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+) -> Result<Option<Webhook>, Error> {
+    let tenant_id = authenticate(pool, viewer).await?.tenant_id;
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                webhooks::get_webhook(conn, id.unwrap())
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(Webhook::from))
+}
+
+pub async fn create_webhook(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    input: Option<WebhookInput>,
+) -> Result<Option<Webhook>, Error> {
+    let tenant_id = authenticate(pool, viewer).await?.tenant_id;
+    let input = input.unwrap();
+    let url = input.url.unwrap_or_default();
+    validate_url(&url).await?;
+    let attrs = webhooks::CreateWebhookAttrs {
+        url,
+        event_types: validate_event_types(&input.event_types.unwrap_or_default())?,
+    };
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                webhooks::create_webhook(conn, attrs)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    // The secret is only ever returned here, so that it can be stored by the receiver.
+    Ok(option.map(|webhook| Webhook {
+        secret: Some(webhook.secret.clone()),
+        ..Webhook::from(webhook)
+    }))
+}
+
+pub async fn update_webhook(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+    input: Option<WebhookInput>,
+) -> Result<Option<Webhook>, Error> {
+    let tenant_id = authenticate(pool, viewer).await?.tenant_id;
+    let id = id.unwrap();
+    let input = input.unwrap();
+    if let Some(url) = &input.url {
+        validate_url(url).await?;
+    }
+    let attrs = webhooks::UpdateWebhookAttrs {
+        url: input.url,
+        event_types: input
+            .event_types
+            .map(|event_types| validate_event_types(&event_types))
+            .transpose()?,
+        enabled: input.enabled,
+    };
+    let conn = pool.get().await.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                webhooks::update_webhook(conn, id, attrs)
+            })
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(Webhook::from))
+}
+
+pub async fn delete_webhook(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    id: Option<Uuid>,
+) -> Result<Option<Webhook>, Error> {
+    let tenant_id = authenticate(pool, viewer).await?.tenant_id;
+    let conn = pool.get().await.unwrap();
+    let id = id.unwrap();
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| webhooks::delete_webhook(conn, id))
+        })
+        .await;
+
+    let option = match result {
+        Ok(Ok(option)) => option,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+
+    Ok(option.map(Webhook::from))
+}
+
+pub async fn webhook_deliveries(
+    pool: &Pool,
+    viewer: Option<&Viewer>,
+    webhook_id: Option<Uuid>,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<Connection<String, WebhookDelivery>, Error> {
+    let tenant_id = authenticate(pool, viewer).await?.tenant_id;
+    let webhook_id = webhook_id.unwrap();
+    let after = decode_cursor(after)?;
+    let limit = page_size(first);
+    let conn = pool.get().await.unwrap();
+    // Fetch one extra delivery to know whether there is a next page.
+    let result = conn
+        .interact(move |conn| {
+            repo::with_tenant(conn, tenant_id, |conn| {
+                webhooks::list_deliveries(conn, webhook_id, after, limit + 1)
+            })
+        })
+        .await;
+
+    let mut deliveries = match result {
+        Ok(Ok(deliveries)) => deliveries,
+        Ok(Err(_)) => return Err(InternalServer.extend()),
+        Err(_) => return Err(InternalServer.extend()),
+    };
+    let has_next_page = deliveries.len() as i64 > limit;
+    deliveries.truncate(limit as usize);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection.edges.extend(
+        deliveries
+            .into_iter()
+            .map(|delivery| Edge::new(delivery.id.to_string(), WebhookDelivery::from(delivery))),
+    );
+
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::authz;
+    use crate::core::models::schema::tenants;
+    use crate::core::sessions;
+    use crate::core::users;
+    use crate::server::resolvers::errors::GqlError::Unauthenticated;
+    use crate::test::factory;
+    use chrono::Utc;
+    use diesel::prelude::*;
+    use diesel::Connection;
+    use diesel::PgConnection;
+    use serde_json::json;
+
+    /// A URL of a public address that nothing listens on.
+    const URL: &str = "http://203.0.113.9/webhook";
+
+    fn webhook_input(url: &str) -> Option<WebhookInput> {
+        Some(WebhookInput {
+            url: Some(url.to_string()),
+            event_types: Some(vec![WebhookEventType::UserUpdated]),
+            enabled: None,
+        })
+    }
+
+    /// Sign in a new admin.
+    fn admin_viewer(conn: &mut PgConnection) -> Viewer {
+        let admin = factory::insert_user();
+        let (_, token) = sessions::create_session(conn, admin.id).unwrap();
+        authz::grant_role(conn, admin.id, "admin").unwrap();
+
+        Viewer::new(Some(token))
+    }
+
+    fn error_code(error: Error) -> async_graphql::Value {
+        error.extensions.unwrap().get("code").unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_create_webhook() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = admin_viewer(&mut conn);
+        let result = create_webhook(&pool, Some(&viewer), webhook_input(URL))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(result.secret.unwrap().starts_with("whsec_"));
+        assert_eq!(
+            result.event_types,
+            Some(vec![WebhookEventType::UserUpdated])
+        );
+
+        delete_webhook(&pool, Some(&viewer), result.id)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_webhook_unauthenticated() {
+        let config = config::get_config();
+        let pool = repo::connect_database(&config.database_url);
+
+        let error = create_webhook(&pool, None, webhook_input(URL))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error, Unauthenticated.extend());
+    }
+
+    #[tokio::test]
+    async fn test_create_webhook_invalid_url() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = admin_viewer(&mut conn);
+
+        for url in [
+            "ftp://203.0.113.9/webhook",
+            "http://127.0.0.1:9/webhook",
+            "http://localhost/webhook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1/webhook",
+            "http://[::1]/webhook",
+        ] {
+            let error = create_webhook(&pool, Some(&viewer), webhook_input(url))
+                .await
+                .unwrap_err();
+
+            assert_eq!(error_code(error), "UNPROCESSABLE_CONTENT".into(), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_other_tenant() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = admin_viewer(&mut conn);
+        let created = create_webhook(&pool, Some(&viewer), webhook_input(URL))
+            .await
+            .unwrap()
+            .unwrap();
+        let tenant_id = diesel::insert_into(tenants::table)
+            .values((
+                tenants::id.eq(Uuid::now_v7()),
+                tenants::name.eq("Doe Inc."),
+                tenants::created_at.eq(Utc::now().naive_utc()),
+            ))
+            .returning(tenants::id)
+            .get_result::<Uuid>(&mut conn)
+            .unwrap();
+        let attrs = users::CreateUserAttrs {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            email_address: format!("john.{}@doe.com", Uuid::now_v7().simple()),
+        };
+        let other = repo::with_tenant(&mut conn, tenant_id, |conn| users::create_user(conn, attrs))
+            .unwrap()
+            .unwrap();
+        authz::grant_role(&mut conn, other.id, "admin").unwrap();
+        let (_, token) = sessions::create_session(&mut conn, other.id).unwrap();
+        let other_viewer = Viewer::new(Some(token));
+
+        let found = webhook(&pool, Some(&other_viewer), created.id)
+            .await
+            .unwrap();
+        let deleted = delete_webhook(&pool, Some(&other_viewer), created.id)
+            .await
+            .unwrap();
+
+        assert_eq!(found, None);
+        assert_eq!(deleted, None);
+
+        delete_webhook(&pool, Some(&viewer), created.id)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_deliveries() {
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = admin_viewer(&mut conn);
+        let webhook = create_webhook(&pool, Some(&viewer), webhook_input(URL))
+            .await
+            .unwrap()
+            .unwrap();
+        let id = webhook.id.unwrap();
+        let conn = pool.get().await.unwrap();
+        conn.interact(move |conn| {
+            // Subscribe to an event type no other test sends.
+            let kind = format!("test.{}", id);
+            let attrs = webhooks::UpdateWebhookAttrs {
+                url: None,
+                event_types: Some(vec![kind.clone()]),
+                enabled: None,
+            };
+            webhooks::update_webhook(conn, id, attrs).unwrap();
+            for _ in 0..3 {
+                webhooks::enqueue(conn, repo::DEFAULT_TENANT, &kind, &json!({})).unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        let page = webhook_deliveries(&pool, Some(&viewer), webhook.id, Some(2), None)
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 2);
+        assert!(page.has_next_page);
+
+        let after = page.edges[1].cursor.clone();
+        let page = webhook_deliveries(&pool, Some(&viewer), webhook.id, Some(2), Some(after))
+            .await
+            .unwrap();
+        assert_eq!(page.edges.len(), 1);
+        assert!(!page.has_next_page);
+
+        delete_webhook(&pool, Some(&viewer), webhook.id)
+            .await
+            .unwrap();
+    }
+}
@@ -1,31 +1,112 @@
+use crate::config::get_config;
+use crate::core::mailer::build_mailer;
+use crate::server::idempotency::Idempotency;
+use crate::server::limits::QueryLimits;
+use crate::server::persisted::Manifest;
+use crate::server::persisted::PersistedQueries;
+use crate::server::persisted::ValidateOnly;
+use crate::server::schema::audit_schema::AuditQuery;
+use crate::server::schema::export_schema::ExportMutation;
+use crate::server::schema::export_schema::ExportQuery;
+use crate::server::schema::invitation_schema::InvitationMutation;
+use crate::server::schema::organization_schema::OrganizationMutation;
+use crate::server::schema::organization_schema::OrganizationQuery;
+use crate::server::schema::privacy_schema::PrivacyMutation;
+use crate::server::schema::role_schema::RoleMutation;
+use crate::server::schema::session_schema::SessionMutation;
 use crate::server::schema::user_schema::UserMutation;
 use crate::server::schema::user_schema::UserQuery;
-use async_graphql::EmptySubscription;
+use crate::server::schema::user_schema::UserSubscription;
+use crate::server::schema::webhook_schema::WebhookMutation;
+use crate::server::schema::webhook_schema::WebhookQuery;
 use async_graphql::MergedObject;
+use async_graphql::MergedSubscription;
 use async_graphql::Schema;
 use deadpool_diesel::postgres::Pool;
 
+pub mod audit_schema;
+pub mod export_schema;
+pub mod invitation_schema;
+pub mod organization_schema;
+pub mod privacy_schema;
+pub mod role_schema;
+pub mod session_schema;
 pub mod user_schema;
+pub mod webhook_schema;
 
 /// The GraphQL schema type.
-pub type GraphSchema = Schema<Query, Mutation, EmptySubscription>;
+pub type GraphSchema = Schema<Query, Mutation, Subscription>;
 
 /// The parent query object, merged from child modules.
 #[derive(MergedObject, Default)]
-pub struct Query(UserQuery);
+pub struct Query(
+    UserQuery,
+    OrganizationQuery,
+    AuditQuery,
+    ExportQuery,
+    WebhookQuery,
+);
 
 /// The parent mutation object, merged from child modules.
 #[derive(MergedObject, Default)]
-pub struct Mutation(UserMutation);
+pub struct Mutation(
+    UserMutation,
+    SessionMutation,
+    RoleMutation,
+    OrganizationMutation,
+    InvitationMutation,
+    WebhookMutation,
+    ExportMutation,
+    PrivacyMutation,
+);
+
+/// The parent subscription object, merged from child modules.
+#[derive(MergedSubscription, Default)]
+pub struct Subscription(UserSubscription);
 
 /// Create a GraphQL schema.
 pub fn create_schema(database: Pool) -> GraphSchema {
-    Schema::build(Query::default(), Mutation::default(), EmptySubscription)
-        .data(database)
-        .finish()
+    let config = get_config();
+    let manifest = config.graph_manifest_path.as_ref().map(|path| {
+        Manifest::load(path).unwrap_or_else(|e| panic!("Failed to load manifest: {:?}", e))
+    });
+
+    Schema::build(
+        Query::default(),
+        Mutation::default(),
+        Subscription::default(),
+    )
+    .data(database)
+    .data(build_mailer(&config.mailer, &config.mail_from))
+    .extension(PersistedQueries::new(manifest, config.graph_allowlist))
+    .extension(QueryLimits {
+        max_depth: config.graph_max_depth,
+        max_complexity: config.graph_max_complexity,
+        max_aliases: config.graph_max_aliases,
+        max_root_fields: config.graph_max_root_fields,
+        max_tokens: config.graph_max_tokens,
+    })
+    .extension(Idempotency)
+    .finish()
 }
 
 /// Build a GraphQL schema.
 pub fn build_schema() -> GraphSchema {
-    Schema::build(Query::default(), Mutation::default(), EmptySubscription).finish()
+    Schema::build(
+        Query::default(),
+        Mutation::default(),
+        Subscription::default(),
+    )
+    .finish()
+}
+
+/// Build a GraphQL schema that validates queries without executing them.
+pub fn build_validation_schema() -> GraphSchema {
+    Schema::build(
+        Query::default(),
+        Mutation::default(),
+        Subscription::default(),
+    )
+    .extension(ValidateOnly)
+    .finish()
 }
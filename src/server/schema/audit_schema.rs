@@ -0,0 +1,85 @@
+use crate::core::authz::READ_AUDIT;
+use crate::core::models;
+use crate::server::resolvers::audit_resolver::audit_events;
+use crate::server::schema::user_schema::authorize;
+use crate::server::viewer::PermissionGuard;
+use crate::server::viewer::Viewer;
+use async_graphql::connection::Connection;
+use async_graphql::Context;
+use async_graphql::InputObject;
+use async_graphql::Json;
+use async_graphql::Object;
+use async_graphql::Result;
+use async_graphql::SimpleObject;
+use chrono::DateTime;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// A change to an audited row: who made it, from which request, and the
+/// fields it changed before and after.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct AuditEvent {
+    pub id: Option<Uuid>,
+    pub actor_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<Uuid>,
+    pub before: Option<Json<Value>>,
+    pub after: Option<Json<Value>>,
+    pub request_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<models::AuditEvent> for AuditEvent {
+    fn from(event: models::AuditEvent) -> Self {
+        AuditEvent {
+            id: Some(event.id),
+            actor_id: event.actor_id,
+            action: Some(event.action),
+            target_type: Some(event.target_type),
+            target_id: Some(event.target_id),
+            before: event.before.map(Json),
+            after: event.after.map(Json),
+            request_id: event.request_id,
+            ip_address: event.ip_address,
+            created_at: Some(event.created_at.and_utc()),
+        }
+    }
+}
+
+/// The audit events to list, like the changes of a user with
+/// `{ targetType: "user", targetId: ... }`.
+#[derive(InputObject)]
+pub struct AuditEventFilter {
+    pub actor_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<Uuid>,
+}
+
+#[derive(Default)]
+pub struct AuditQuery;
+
+#[Object]
+impl AuditQuery {
+    /// Get the audit events of the tenant, newest first.
+    #[graphql(
+        complexity = "crate::server::limits::connection_complexity(first, child_complexity)",
+        guard = "PermissionGuard::new(READ_AUDIT)",
+        directive = authorize::apply(None, Some(vec![READ_AUDIT.to_string()]))
+    )]
+    async fn audit_events(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<AuditEventFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, AuditEvent>> {
+        let pool = ctx.data::<Pool>().unwrap();
+
+        audit_events(pool, ctx.data_opt::<Viewer>(), filter, first, after).await
+    }
+}
@@ -0,0 +1,143 @@
+use crate::core::authz::EXPORT_USERS;
+use crate::core::exports;
+use crate::core::models;
+use crate::server::resolvers::export_resolver::export_users;
+use crate::server::resolvers::export_resolver::user_export;
+use crate::server::schema::user_schema::authorize;
+use crate::server::viewer::PermissionGuard;
+use crate::server::viewer::Viewer;
+use async_graphql::Context;
+use async_graphql::Enum;
+use async_graphql::InputObject;
+use async_graphql::Object;
+use async_graphql::Result;
+use async_graphql::SimpleObject;
+use chrono::DateTime;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use uuid::Uuid;
+
+/// The format of a file of exported users.
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UserExportFormat {
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+impl From<UserExportFormat> for exports::ExportFormat {
+    fn from(format: UserExportFormat) -> Self {
+        match format {
+            UserExportFormat::Csv => exports::ExportFormat::Csv,
+            UserExportFormat::Ndjson => exports::ExportFormat::Ndjson,
+            UserExportFormat::Parquet => exports::ExportFormat::Parquet,
+        }
+    }
+}
+
+impl From<exports::ExportFormat> for UserExportFormat {
+    fn from(format: exports::ExportFormat) -> Self {
+        match format {
+            exports::ExportFormat::Csv => UserExportFormat::Csv,
+            exports::ExportFormat::Ndjson => UserExportFormat::Ndjson,
+            exports::ExportFormat::Parquet => UserExportFormat::Parquet,
+        }
+    }
+}
+
+/// The users to export: by default, those not deleted.
+#[derive(Default, InputObject)]
+pub struct UserExportFilter {
+    pub include_deleted: Option<bool>,
+    pub email_verified: Option<bool>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl From<UserExportFilter> for exports::ExportFilter {
+    fn from(filter: UserExportFilter) -> Self {
+        exports::ExportFilter {
+            include_deleted: filter.include_deleted.unwrap_or(false),
+            email_verified: filter.email_verified,
+            created_after: filter.created_after.map(|datetime| datetime.naive_utc()),
+            created_before: filter.created_before.map(|datetime| datetime.naive_utc()),
+        }
+    }
+}
+
+/// An export of users to a file in the export directory, with its progress:
+/// `pending`, `running`, `completed`, or `failed` until it is retried.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct UserExport {
+    pub id: Option<Uuid>,
+    pub format: Option<UserExportFormat>,
+    pub status: Option<String>,
+    pub rows_exported: Option<i32>,
+    pub file_name: Option<String>,
+    pub last_error: Option<String>,
+    pub requested_by: Option<Uuid>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl From<models::UserExport> for UserExport {
+    fn from(export: models::UserExport) -> Self {
+        UserExport {
+            id: Some(export.id),
+            format: Some(exports::ExportFormat::parse(&export.format).into()),
+            status: Some(export.status),
+            rows_exported: Some(export.rows_exported),
+            file_name: Some(export.file_name),
+            last_error: export.last_error,
+            requested_by: export.requested_by,
+            created_at: Some(export.created_at.and_utc()),
+            updated_at: Some(export.updated_at.and_utc()),
+            completed_at: export.completed_at.map(|datetime| datetime.and_utc()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ExportQuery;
+
+#[Object]
+impl ExportQuery {
+    /// Get an export of users, to follow its progress.
+    #[graphql(
+        complexity = "5 + child_complexity",
+        guard = "PermissionGuard::new(EXPORT_USERS)",
+        directive = authorize::apply(None, Some(vec![EXPORT_USERS.to_string()]))
+    )]
+    async fn user_export(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<UserExport>> {
+        user_export(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), id).await
+    }
+}
+
+#[derive(Default)]
+pub struct ExportMutation;
+
+#[Object]
+impl ExportMutation {
+    /// Export the users of the tenant matching the filter to a file, written
+    /// by a job. Follow its progress with `userExport`.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        guard = "PermissionGuard::new(EXPORT_USERS)",
+        directive = authorize::apply(None, Some(vec![EXPORT_USERS.to_string()]))
+    )]
+    async fn export_users(
+        &self,
+        ctx: &Context<'_>,
+        format: UserExportFormat,
+        filter: Option<UserExportFilter>,
+    ) -> Result<UserExport> {
+        export_users(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            format,
+            filter,
+        )
+        .await
+    }
+}
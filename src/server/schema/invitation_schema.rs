@@ -0,0 +1,98 @@
+use crate::core::mailer::Mailer;
+use crate::core::models;
+use crate::server::resolvers::invitation_resolver::accept_invitation;
+use crate::server::resolvers::invitation_resolver::create_invitation;
+use crate::server::resolvers::invitation_resolver::revoke_invitation;
+use crate::server::schema::organization_schema::MembershipRole;
+use crate::server::schema::user_schema::User;
+use crate::server::viewer::Viewer;
+use async_graphql::Context;
+use async_graphql::InputObject;
+use async_graphql::Object;
+use async_graphql::Result;
+use async_graphql::SimpleObject;
+use chrono::DateTime;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// An invitation to join an organization, mailed to an email address.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct Invitation {
+    pub id: Option<Uuid>,
+    pub organization_id: Option<Uuid>,
+    pub email_address: Option<String>,
+    pub role: Option<MembershipRole>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<models::Invitation> for Invitation {
+    fn from(invitation: models::Invitation) -> Self {
+        Invitation {
+            id: Some(invitation.id),
+            organization_id: Some(invitation.organization_id),
+            email_address: Some(invitation.email_address),
+            role: MembershipRole::parse(&invitation.role),
+            expires_at: Some(invitation.expires_at.and_utc()),
+            created_at: Some(invitation.created_at.and_utc()),
+        }
+    }
+}
+
+/// The user to create when accepting an invitation to an email address
+/// without an account.
+#[derive(InputObject)]
+pub struct InviteeInput {
+    pub first_name: String,
+    pub last_name: String,
+    pub password: Option<String>,
+}
+
+#[derive(Default)]
+pub struct InvitationMutation;
+
+#[Object]
+impl InvitationMutation {
+    /// Mail an invitation to join an organization to an email address, which
+    /// expires after 7 days. Only its owner and admins can invite.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn create_invitation(
+        &self,
+        ctx: &Context<'_>,
+        organization_id: Uuid,
+        email_address: String,
+        role: MembershipRole,
+    ) -> Result<Option<Invitation>> {
+        let pool = ctx.data::<Pool>().unwrap();
+        let mailer = ctx.data::<Arc<dyn Mailer>>().unwrap().clone();
+        let viewer = ctx.data_opt::<Viewer>();
+
+        create_invitation(pool, mailer, viewer, organization_id, email_address, role).await
+    }
+
+    /// Revoke a pending invitation.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn revoke_invitation(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<Invitation>> {
+        revoke_invitation(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), id).await
+    }
+
+    /// Join an organization with a mailed invitation token. The user of the
+    /// invited email address is created from the input if there is none.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn accept_invitation(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        input: Option<InviteeInput>,
+    ) -> Result<Option<User>> {
+        accept_invitation(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            token,
+            input,
+        )
+        .await
+    }
+}
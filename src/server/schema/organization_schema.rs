@@ -0,0 +1,198 @@
+use crate::core::models;
+use crate::core::organizations;
+use crate::server::resolvers::invitation_resolver::organization_invitations;
+use crate::server::resolvers::organization_resolver::create_organization;
+use crate::server::resolvers::organization_resolver::invite_member;
+use crate::server::resolvers::organization_resolver::organization;
+use crate::server::resolvers::organization_resolver::organization_members;
+use crate::server::resolvers::organization_resolver::remove_member;
+use crate::server::resolvers::organization_resolver::transfer_ownership;
+use crate::server::schema::invitation_schema::Invitation;
+use crate::server::schema::user_schema::User;
+use crate::server::viewer::Viewer;
+use async_graphql::connection::Connection;
+use async_graphql::connection::EmptyFields;
+use async_graphql::ComplexObject;
+use async_graphql::Context;
+use async_graphql::Enum;
+use async_graphql::Object;
+use async_graphql::Result;
+use async_graphql::SimpleObject;
+use chrono::DateTime;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use uuid::Uuid;
+
+/// The role of a member in an organization.
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MembershipRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+impl MembershipRole {
+    /// Get the role as it is stored.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MembershipRole::Owner => organizations::OWNER,
+            MembershipRole::Admin => organizations::ADMIN,
+            MembershipRole::Member => organizations::MEMBER,
+        }
+    }
+
+    /// Parse the role as it is stored.
+    pub fn parse(role: &str) -> Option<MembershipRole> {
+        match role {
+            organizations::OWNER => Some(MembershipRole::Owner),
+            organizations::ADMIN => Some(MembershipRole::Admin),
+            organizations::MEMBER => Some(MembershipRole::Member),
+            _ => None,
+        }
+    }
+}
+
+/// The membership that links a user and an organization, on the edges of
+/// `User.organizations` and `Organization.members`.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct MembershipFields {
+    pub role: Option<MembershipRole>,
+    pub joined_at: Option<DateTime<Utc>>,
+}
+
+impl From<models::Membership> for MembershipFields {
+    fn from(membership: models::Membership) -> Self {
+        MembershipFields {
+            role: MembershipRole::parse(&membership.role),
+            joined_at: Some(membership.created_at.and_utc()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, SimpleObject)]
+#[graphql(complex)]
+pub struct Organization {
+    pub id: Option<Uuid>,
+    pub name: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<models::Organization> for Organization {
+    fn from(organization: models::Organization) -> Self {
+        Organization {
+            id: Some(organization.id),
+            name: Some(organization.name),
+            created_at: Some(organization.created_at.and_utc()),
+            updated_at: Some(organization.updated_at.and_utc()),
+        }
+    }
+}
+
+#[ComplexObject]
+impl Organization {
+    /// Get the members, visible to the other members.
+    #[graphql(complexity = "crate::server::limits::connection_complexity(first, child_complexity)")]
+    async fn members(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, User, EmptyFields, MembershipFields>> {
+        let pool = ctx.data::<Pool>().unwrap();
+
+        organization_members(pool, ctx.data_opt::<Viewer>(), self.id, first, after).await
+    }
+
+    /// Get the pending invitations, visible to the owner and admins.
+    #[graphql(complexity = "crate::server::limits::connection_complexity(first, child_complexity)")]
+    async fn invitations(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Invitation>> {
+        let pool = ctx.data::<Pool>().unwrap();
+
+        organization_invitations(pool, ctx.data_opt::<Viewer>(), self.id, first, after).await
+    }
+}
+
+#[derive(Default)]
+pub struct OrganizationMutation;
+
+#[Object]
+impl OrganizationMutation {
+    /// Create an organization owned by the viewer.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn create_organization(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+    ) -> Result<Option<Organization>> {
+        create_organization(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), name).await
+    }
+
+    /// Add the user with the email address to an organization. Only its
+    /// owner and admins can add members. Addresses without a user are
+    /// invited with `createInvitation`.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn invite_member(
+        &self,
+        ctx: &Context<'_>,
+        organization_id: Uuid,
+        email_address: String,
+        role: MembershipRole,
+    ) -> Result<Option<User>> {
+        let pool = ctx.data::<Pool>().unwrap();
+
+        invite_member(
+            pool,
+            ctx.data_opt::<Viewer>(),
+            organization_id,
+            email_address,
+            role,
+        )
+        .await
+    }
+
+    /// Remove a member from an organization. Members can remove themselves,
+    /// admins can remove members and the owner can remove anyone but itself.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn remove_member(
+        &self,
+        ctx: &Context<'_>,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<User>> {
+        let pool = ctx.data::<Pool>().unwrap();
+
+        remove_member(pool, ctx.data_opt::<Viewer>(), organization_id, user_id).await
+    }
+
+    /// Make a member the owner of an organization, and the previous owner an
+    /// admin. Only the owner can transfer the ownership.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn transfer_ownership(
+        &self,
+        ctx: &Context<'_>,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Organization>> {
+        let pool = ctx.data::<Pool>().unwrap();
+
+        transfer_ownership(pool, ctx.data_opt::<Viewer>(), organization_id, user_id).await
+    }
+}
+
+#[derive(Default)]
+pub struct OrganizationQuery;
+
+#[Object]
+impl OrganizationQuery {
+    /// Get an organization the viewer is a member of.
+    #[graphql(complexity = "5 + child_complexity")]
+    async fn organization(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<Organization>> {
+        organization(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), id).await
+    }
+}
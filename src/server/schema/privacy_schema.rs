@@ -0,0 +1,41 @@
+use crate::core::authz::ERASE_USERS;
+use crate::server::resolvers::privacy_resolver::erase_user;
+use crate::server::resolvers::privacy_resolver::export_my_data;
+use crate::server::schema::user_schema::authorize;
+use crate::server::schema::user_schema::User;
+use crate::server::viewer::PermissionGuard;
+use crate::server::viewer::Viewer;
+use async_graphql::Context;
+use async_graphql::Json;
+use async_graphql::Object;
+use async_graphql::Result;
+use deadpool_diesel::postgres::Pool;
+use serde_json::Value;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub struct PrivacyMutation;
+
+#[Object]
+impl PrivacyMutation {
+    /// Export everything held about the signed-in user, as a JSON bundle: its
+    /// profile and past versions, sessions, memberships, roles, pending email
+    /// change, and audit events.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn export_my_data(&self, ctx: &Context<'_>) -> Result<Json<Value>> {
+        export_my_data(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>()).await
+    }
+
+    /// Erase the personal data of a user: it is anonymized and deleted in
+    /// place, its sessions are revoked, and its personal data is scrubbed
+    /// from its history and audit events. The erasure is audited as
+    /// `user.erased`.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        guard = "PermissionGuard::new(ERASE_USERS)",
+        directive = authorize::apply(None, Some(vec![ERASE_USERS.to_string()]))
+    )]
+    async fn erase_user(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<User>> {
+        erase_user(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), id).await
+    }
+}
@@ -0,0 +1,60 @@
+use crate::core::authz::MANAGE_ROLES;
+use crate::server::resolvers::role_resolver::grant_role;
+use crate::server::resolvers::role_resolver::revoke_role;
+use crate::server::schema::user_schema::authorize;
+use crate::server::schema::user_schema::User;
+use crate::server::viewer::PermissionGuard;
+use crate::server::viewer::Viewer;
+use async_graphql::Context;
+use async_graphql::Object;
+use async_graphql::Result;
+use deadpool_diesel::postgres::Pool;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub struct RoleMutation;
+
+#[Object]
+impl RoleMutation {
+    /// Grant a role to a user.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        guard = "PermissionGuard::new(MANAGE_ROLES)",
+        directive = authorize::apply(None, Some(vec![MANAGE_ROLES.to_string()]))
+    )]
+    async fn grant_role(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Uuid,
+        role: String,
+    ) -> Result<Option<User>> {
+        grant_role(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            user_id,
+            role,
+        )
+        .await
+    }
+
+    /// Revoke a role from a user.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        guard = "PermissionGuard::new(MANAGE_ROLES)",
+        directive = authorize::apply(None, Some(vec![MANAGE_ROLES.to_string()]))
+    )]
+    async fn revoke_role(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Uuid,
+        role: String,
+    ) -> Result<Option<User>> {
+        revoke_role(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            user_id,
+            role,
+        )
+        .await
+    }
+}
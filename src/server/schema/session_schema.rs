@@ -0,0 +1,77 @@
+use crate::core::mailer::Mailer;
+use crate::core::sessions::SignedIn;
+use crate::server::resolvers::session_resolver::request_password_reset;
+use crate::server::resolvers::session_resolver::reset_password;
+use crate::server::resolvers::session_resolver::sign_in;
+use crate::server::schema::user_schema::User;
+use async_graphql::Context;
+use async_graphql::Object;
+use async_graphql::Result;
+use async_graphql::SimpleObject;
+use chrono::DateTime;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A session opened by signing in.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct Session {
+    pub id: Option<Uuid>,
+    /// The bearer token of the session, only returned when signing in.
+    pub token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub user: Option<User>,
+}
+
+impl From<SignedIn> for Session {
+    fn from(signed_in: SignedIn) -> Self {
+        Session {
+            id: Some(signed_in.session.id),
+            token: Some(signed_in.token),
+            expires_at: Some(signed_in.session.expires_at.and_utc()),
+            user: Some(User::from(signed_in.user)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SessionMutation;
+
+#[Object]
+impl SessionMutation {
+    /// Open a session with an email address and password.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn sign_in(
+        &self,
+        ctx: &Context<'_>,
+        email_address: String,
+        password: String,
+    ) -> Result<Session> {
+        sign_in(ctx.data::<Pool>().unwrap(), email_address, password).await
+    }
+
+    /// Mail a token to reset the password of the user with the email address.
+    /// Succeeds whether or not the address is known.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn request_password_reset(
+        &self,
+        ctx: &Context<'_>,
+        email_address: String,
+    ) -> Result<bool> {
+        let mailer = ctx.data::<Arc<dyn Mailer>>().unwrap().clone();
+
+        request_password_reset(ctx.data::<Pool>().unwrap(), mailer, email_address).await
+    }
+
+    /// Set a new password with a mailed token, signing out all sessions.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn reset_password(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        new_password: String,
+    ) -> Result<Option<User>> {
+        reset_password(ctx.data::<Pool>().unwrap(), token, new_password).await
+    }
+}
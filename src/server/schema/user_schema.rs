@@ -1,16 +1,55 @@
+use crate::core::authz::DELETE_USERS;
+use crate::core::authz::IMPORT_USERS;
+use crate::core::authz::READ_USERS;
+use crate::core::authz::WRITE_USERS;
+use crate::core::imports;
+use crate::core::mailer::Mailer;
+use crate::core::masking;
+use crate::core::masking::Field;
+use crate::core::models;
+use crate::core::search;
+use crate::server::resolvers::organization_resolver::user_organizations;
+use crate::server::resolvers::role_resolver::user_roles;
+use crate::server::resolvers::user_resolver::confirm_email_change;
 use crate::server::resolvers::user_resolver::create_user;
+use crate::server::resolvers::user_resolver::delete_user;
+use crate::server::resolvers::user_resolver::import_users;
+use crate::server::resolvers::user_resolver::request_email_change;
+use crate::server::resolvers::user_resolver::search_users;
+use crate::server::resolvers::user_resolver::send_verification_email;
+use crate::server::resolvers::user_resolver::update_user;
 use crate::server::resolvers::user_resolver::user;
+use crate::server::resolvers::user_resolver::user_created;
+use crate::server::resolvers::user_resolver::user_deleted;
+use crate::server::resolvers::user_resolver::user_history;
+use crate::server::resolvers::user_resolver::user_updated;
+use crate::server::resolvers::user_resolver::verify_email;
+use crate::server::resolvers::user_resolver::viewer;
+use crate::server::schema::organization_schema::MembershipFields;
+use crate::server::schema::organization_schema::Organization;
+use crate::server::viewer::masking_policy;
+use crate::server::viewer::PermissionGuard;
+use crate::server::viewer::Viewer;
+use async_graphql::connection::Connection;
+use async_graphql::connection::ConnectionNameType;
+use async_graphql::connection::EdgeNameType;
+use async_graphql::connection::EmptyFields;
+use async_graphql::futures_util::Stream;
 use async_graphql::ComplexObject;
 use async_graphql::Context;
 use async_graphql::Enum;
 use async_graphql::InputObject;
 use async_graphql::Object;
+use async_graphql::OutputType;
 use async_graphql::Result;
 use async_graphql::SimpleObject;
+use async_graphql::Subscription;
 use async_graphql::TypeDirective;
+use async_graphql::Upload;
 use chrono::DateTime;
 use chrono::Utc;
 use deadpool_diesel::postgres::Pool;
+use std::sync::Arc;
 use uuid::Uuid;
 
 /// The system role.
@@ -22,8 +61,10 @@ pub enum Role {
     Me,
 }
 
+/// Document who may resolve a field: viewers with one of the roles, or with
+/// one of the permissions.
 #[TypeDirective(location = "FieldDefinition")]
-fn authorize(role: Vec<Role>) {}
+pub fn authorize(role: Option<Vec<Role>>, permission: Option<Vec<String>>) {}
 
 #[TypeDirective(location = "InputFieldDefinition")]
 fn validate(required: bool) {}
@@ -32,26 +73,203 @@ fn validate(required: bool) {}
 #[graphql(complex)]
 pub struct User {
     pub id: Option<Uuid>,
+    #[graphql(skip)]
     pub first_name: Option<String>,
+    #[graphql(skip)]
     pub last_name: Option<String>,
+    #[graphql(skip)]
     pub email_address: Option<String>,
-    #[graphql(directive = authorize::apply(vec![Role::Admin, Role::Me, Role::User]))]
+    #[graphql(directive = authorize::apply(Some(vec![Role::Admin, Role::Me, Role::User]), None))]
     pub created_at: Option<DateTime<Utc>>,
-    #[graphql(directive = authorize::apply(vec![Role::Admin, Role::Me]))]
+    #[graphql(directive = authorize::apply(Some(vec![Role::Admin, Role::Me]), None))]
     pub updated_at: Option<DateTime<Utc>>,
-    #[graphql(directive = authorize::apply(vec![Role::Admin, Role::Me]))]
+    #[graphql(directive = authorize::apply(Some(vec![Role::Admin, Role::Me]), None))]
     pub deleted_at: Option<DateTime<Utc>>,
+    #[graphql(directive = authorize::apply(Some(vec![Role::Admin, Role::Me]), None))]
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// The version of the user, increased by every change to it.
+    pub version: Option<i32>,
+}
+
+impl From<models::User> for User {
+    fn from(user: models::User) -> Self {
+        User {
+            id: Some(user.id),
+            first_name: Some(user.first_name),
+            last_name: Some(user.last_name),
+            email_address: Some(user.email_address),
+            created_at: Some(user.created_at.and_utc()),
+            updated_at: Some(user.updated_at.and_utc()),
+            deleted_at: user.deleted_at.map(|datetime| datetime.and_utc()),
+            email_verified_at: user.email_verified_at.map(|datetime| datetime.and_utc()),
+            version: Some(user.version),
+        }
+    }
+}
+
+impl From<models::UserVersion> for User {
+    fn from(version: models::UserVersion) -> Self {
+        User {
+            id: Some(version.id),
+            first_name: Some(version.first_name),
+            last_name: Some(version.last_name),
+            email_address: Some(version.email_address),
+            created_at: Some(version.created_at.and_utc()),
+            updated_at: Some(version.updated_at.and_utc()),
+            deleted_at: version.deleted_at.map(|datetime| datetime.and_utc()),
+            email_verified_at: version.email_verified_at.map(|datetime| datetime.and_utc()),
+            version: Some(version.version),
+        }
+    }
+}
+
+/// Mask the value of the field of the user as the policy of the viewer says.
+async fn masked(
+    ctx: &Context<'_>,
+    id: Option<Uuid>,
+    field: Field,
+    value: &Option<String>,
+) -> Result<Option<String>> {
+    match (id, value) {
+        (Some(id), Some(value)) => {
+            let pool = ctx.data::<Pool>().unwrap();
+            let policy = masking_policy(pool, ctx.data_opt::<Viewer>()).await?;
+
+            Ok(policy.mask(id, field, value))
+        }
+        _ => Ok(value.clone()),
+    }
 }
 
 #[ComplexObject]
 impl User {
-    async fn full_name(&self) -> Result<Option<String>> {
-        if let (Some(first_name), Some(last_name)) = (&self.first_name, &self.last_name) {
+    /// The first name, masked for the roles of the viewer.
+    async fn first_name(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        masked(ctx, self.id, Field::FirstName, &self.first_name).await
+    }
+
+    /// The last name, masked for the roles of the viewer.
+    async fn last_name(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        masked(ctx, self.id, Field::LastName, &self.last_name).await
+    }
+
+    /// The email address, masked for the roles of the viewer.
+    async fn email_address(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        masked(ctx, self.id, Field::EmailAddress, &self.email_address).await
+    }
+
+    async fn full_name(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        let first_name = self.first_name(ctx).await?;
+        let last_name = self.last_name(ctx).await?;
+
+        if let (Some(first_name), Some(last_name)) = (first_name, last_name) {
             Ok(Some(format!("{} {}", first_name, last_name)))
         } else {
             Ok(None)
         }
     }
+
+    /// The names of the roles of the user.
+    async fn roles(&self, ctx: &Context<'_>) -> Result<Option<Vec<String>>> {
+        match self.id {
+            Some(id) => {
+                let pool = ctx.data::<Pool>().unwrap();
+
+                user_roles(pool, ctx.data_opt::<Viewer>(), id)
+                    .await
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Get the organizations of the user, visible to the user itself.
+    #[graphql(complexity = "crate::server::limits::connection_complexity(first, child_complexity)")]
+    async fn organizations(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Organization, EmptyFields, MembershipFields>> {
+        let pool = ctx.data::<Pool>().unwrap();
+
+        user_organizations(pool, ctx.data_opt::<Viewer>(), self.id, first, after).await
+    }
+
+    /// Get the versions of the user, newest first, visible to the user itself
+    /// and to auditors.
+    #[graphql(complexity = "crate::server::limits::connection_complexity(first, child_complexity)")]
+    async fn history(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, UserVersion>> {
+        user_history(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            self.id,
+            first,
+            after,
+        )
+        .await
+    }
+}
+
+/// A version of a user, as it was from `validFrom` until `validTo`, or until
+/// now for the current version.
+#[derive(Debug, PartialEq, SimpleObject)]
+#[graphql(complex)]
+pub struct UserVersion {
+    pub version_id: Option<Uuid>,
+    #[graphql(skip)]
+    pub id: Option<Uuid>,
+    #[graphql(skip)]
+    pub first_name: Option<String>,
+    #[graphql(skip)]
+    pub last_name: Option<String>,
+    #[graphql(skip)]
+    pub email_address: Option<String>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub version: Option<i32>,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_to: Option<DateTime<Utc>>,
+}
+
+impl From<models::UserVersion> for UserVersion {
+    fn from(version: models::UserVersion) -> Self {
+        UserVersion {
+            version_id: Some(version.version_id),
+            id: Some(version.id),
+            first_name: Some(version.first_name),
+            last_name: Some(version.last_name),
+            email_address: Some(version.email_address),
+            deleted_at: version.deleted_at.map(|datetime| datetime.and_utc()),
+            email_verified_at: version.email_verified_at.map(|datetime| datetime.and_utc()),
+            version: Some(version.version),
+            valid_from: Some(version.valid_from.and_utc()),
+            valid_to: version.valid_to.map(|datetime| datetime.and_utc()),
+        }
+    }
+}
+
+#[ComplexObject]
+impl UserVersion {
+    /// The first name, masked for the roles of the viewer.
+    async fn first_name(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        masked(ctx, self.id, Field::FirstName, &self.first_name).await
+    }
+
+    /// The last name, masked for the roles of the viewer.
+    async fn last_name(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        masked(ctx, self.id, Field::LastName, &self.last_name).await
+    }
+
+    /// The email address, masked for the roles of the viewer.
+    async fn email_address(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        masked(ctx, self.id, Field::EmailAddress, &self.email_address).await
+    }
 }
 
 #[derive(InputObject)]
@@ -64,18 +282,273 @@ pub struct UserInput {
     pub email_address: Option<String>,
 }
 
+/// The format of a file of users to import.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum UserImportFormat {
+    Csv,
+    Json,
+    Ndjson,
+}
+
+impl From<UserImportFormat> for imports::ImportFormat {
+    fn from(format: UserImportFormat) -> Self {
+        match format {
+            UserImportFormat::Csv => imports::ImportFormat::Csv,
+            UserImportFormat::Json => imports::ImportFormat::Json,
+            UserImportFormat::Ndjson => imports::ImportFormat::Ndjson,
+        }
+    }
+}
+
+/// The outcome of an import of users.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct UserImport {
+    pub rows: usize,
+    pub imported: usize,
+    pub dry_run: bool,
+    pub errors: Vec<UserImportError>,
+}
+
+/// A row of an import that was not imported, numbered from 1 without the CSV
+/// header.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct UserImportError {
+    pub row: usize,
+    pub message: String,
+}
+
+impl UserImport {
+    pub fn new(report: imports::ImportReport, dry_run: bool) -> Self {
+        UserImport {
+            rows: report.rows,
+            imported: report.imported,
+            dry_run,
+            errors: report
+                .errors
+                .into_iter()
+                .map(|error| UserImportError {
+                    row: error.row,
+                    message: error.message,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A field of a user holding personal data.
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UserField {
+    FirstName,
+    LastName,
+    EmailAddress,
+}
+
+impl From<Field> for UserField {
+    fn from(field: Field) -> Self {
+        match field {
+            Field::FirstName => UserField::FirstName,
+            Field::LastName => UserField::LastName,
+            Field::EmailAddress => UserField::EmailAddress,
+        }
+    }
+}
+
+/// A field of a user matching a search, with the matching parts in `<b>`
+/// tags and the rest escaped as HTML.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct UserHighlight {
+    pub field: UserField,
+    pub snippet: String,
+}
+
+/// How a user matches a search, on the edges of `Query.searchUsers`. Only
+/// the fields the viewer sees in full are highlighted.
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct UserSearchFields {
+    /// How well the user matches, from 0 to 1.
+    pub rank: f64,
+    pub highlights: Vec<UserHighlight>,
+}
+
+impl UserSearchFields {
+    pub fn new(found: &search::UserMatch, query: &str, policy: &masking::MaskingPolicy) -> Self {
+        let user = &found.user;
+
+        UserSearchFields {
+            rank: found.rank,
+            highlights: Field::ALL
+                .into_iter()
+                .filter(|field| policy.strategy(user.id, *field) == masking::Strategy::Full)
+                .filter_map(|field| {
+                    search::highlight(field.value(user), query).map(|snippet| UserHighlight {
+                        field: field.into(),
+                        snippet,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Name the connection of `Query.searchUsers` apart from the other
+/// connections of users, as its edges have other fields.
+pub struct UserSearchConnectionName;
+
+impl ConnectionNameType for UserSearchConnectionName {
+    fn type_name<T: OutputType>() -> String {
+        "UserSearchConnection".to_string()
+    }
+}
+
+pub struct UserSearchEdgeName;
+
+impl EdgeNameType for UserSearchEdgeName {
+    fn type_name<T: OutputType>() -> String {
+        "UserSearchEdge".to_string()
+    }
+}
+
+/// The users matching a search, best match first.
+pub type UserSearchConnection = Connection<
+    String,
+    User,
+    EmptyFields,
+    UserSearchFields,
+    UserSearchConnectionName,
+    UserSearchEdgeName,
+>;
+
 #[derive(Default)]
 pub struct UserMutation;
 
 #[Object]
 impl UserMutation {
     /// Create a user.
+    #[graphql(complexity = "10 + child_complexity")]
     async fn create_user(
         &self,
         ctx: &Context<'_>,
         input: Option<UserInput>,
     ) -> Result<Option<User>> {
-        create_user(ctx.data::<Pool>().unwrap(), input).await
+        create_user(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), input).await
+    }
+
+    /// Import users from a CSV, JSON or NDJSON file, in the format of its
+    /// extension unless given. Invalid rows are reported and skipped. With
+    /// `dryRun`, the rows are checked but no user is saved.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        guard = "PermissionGuard::new(IMPORT_USERS)",
+        directive = authorize::apply(None, Some(vec![IMPORT_USERS.to_string()]))
+    )]
+    async fn import_users(
+        &self,
+        ctx: &Context<'_>,
+        upload: Upload,
+        format: Option<UserImportFormat>,
+        #[graphql(default)] dry_run: bool,
+    ) -> Result<UserImport> {
+        let upload = upload.value(ctx)?;
+        let format = format.map(imports::ImportFormat::from);
+
+        import_users(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            upload,
+            format,
+            dry_run,
+        )
+        .await
+    }
+
+    /// Update a user. The email address is changed with `requestEmailChange`.
+    /// With an `expectedVersion`, it fails with a `CONFLICT` error if the user
+    /// was changed since that version.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        directive = authorize::apply(Some(vec![Role::Me]), Some(vec![WRITE_USERS.to_string()]))
+    )]
+    async fn update_user(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<Uuid>,
+        input: Option<UserInput>,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        let pool = ctx.data::<Pool>().unwrap();
+
+        update_user(pool, ctx.data_opt::<Viewer>(), id, input, expected_version).await
+    }
+
+    /// Delete a user. With an `expectedVersion`, it fails with a `CONFLICT`
+    /// error if the user was changed since that version.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        directive = authorize::apply(Some(vec![Role::Me]), Some(vec![DELETE_USERS.to_string()]))
+    )]
+    async fn delete_user(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<Uuid>,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        delete_user(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            id,
+            expected_version,
+        )
+        .await
+    }
+
+    /// Mail a token to verify the email address of a user.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        directive = authorize::apply(Some(vec![Role::Me]), Some(vec![WRITE_USERS.to_string()]))
+    )]
+    async fn send_verification_email(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<Uuid>,
+    ) -> Result<Option<User>> {
+        let mailer = ctx.data::<Arc<dyn Mailer>>().unwrap().clone();
+        let viewer = ctx.data_opt::<Viewer>();
+
+        send_verification_email(ctx.data::<Pool>().unwrap(), mailer, viewer, id).await
+    }
+
+    /// Verify the email address of a user with a mailed token.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn verify_email(&self, ctx: &Context<'_>, token: String) -> Result<Option<User>> {
+        verify_email(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), token).await
+    }
+
+    /// Request a change of the email address of a user, which takes effect
+    /// once confirmed with a token mailed to the new address.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        directive = authorize::apply(Some(vec![Role::Me]), Some(vec![WRITE_USERS.to_string()]))
+    )]
+    async fn request_email_change(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<Uuid>,
+        new_email: String,
+    ) -> Result<Option<User>> {
+        let mailer = ctx.data::<Arc<dyn Mailer>>().unwrap().clone();
+        let viewer = ctx.data_opt::<Viewer>();
+
+        request_email_change(ctx.data::<Pool>().unwrap(), mailer, viewer, id, new_email).await
+    }
+
+    /// Confirm a change of email address with a mailed token, and notify the
+    /// previous address.
+    #[graphql(complexity = "10 + child_complexity")]
+    async fn confirm_email_change(&self, ctx: &Context<'_>, token: String) -> Result<Option<User>> {
+        let mailer = ctx.data::<Arc<dyn Mailer>>().unwrap().clone();
+        let viewer = ctx.data_opt::<Viewer>();
+
+        confirm_email_change(ctx.data::<Pool>().unwrap(), mailer, viewer, token).await
     }
 }
 
@@ -84,8 +557,83 @@ pub struct UserQuery;
 
 #[Object]
 impl UserQuery {
-    /// Get a user.
-    async fn user(&self, ctx: &Context<'_>, id: Option<Uuid>) -> Result<Option<User>> {
-        user(ctx.data::<Pool>().unwrap(), id).await
+    /// Get a user, or the user as it was at the `asOf` time, which only the
+    /// user itself and auditors can see.
+    #[graphql(complexity = "5 + child_complexity")]
+    async fn user(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<Uuid>,
+        as_of: Option<DateTime<Utc>>,
+    ) -> Result<Option<User>> {
+        user(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            id,
+            as_of,
+        )
+        .await
+    }
+
+    /// Get the signed-in user.
+    #[graphql(complexity = "5 + child_complexity")]
+    async fn viewer(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        viewer(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>()).await
+    }
+
+    /// Search the users by their names and email address, best match first.
+    /// Partial words and typos match too: one typo per word of 4 to 24
+    /// characters, like a character added, removed, replaced or swapped.
+    #[graphql(
+        complexity = "crate::server::limits::connection_complexity(first, child_complexity)",
+        guard = "PermissionGuard::new(READ_USERS)",
+        directive = authorize::apply(None, Some(vec![READ_USERS.to_string()]))
+    )]
+    async fn search_users(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<UserSearchConnection> {
+        search_users(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            query,
+            first,
+            after,
+        )
+        .await
+    }
+}
+
+#[derive(Default)]
+pub struct UserSubscription;
+
+#[Subscription]
+impl UserSubscription {
+    /// Subscribe to created users of the tenant of the viewer, with the
+    /// `users:read` permission.
+    #[graphql(guard = "PermissionGuard::new(READ_USERS)")]
+    async fn user_created(&self, ctx: &Context<'_>) -> Result<impl Stream<Item = Option<User>>> {
+        user_created(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>()).await
+    }
+
+    /// Subscribe to updated users of the tenant of the viewer, optionally of a
+    /// single user, with the `users:read` permission.
+    #[graphql(guard = "PermissionGuard::new(READ_USERS)")]
+    async fn user_updated(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<Uuid>,
+    ) -> Result<impl Stream<Item = Option<User>>> {
+        user_updated(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), id).await
+    }
+
+    /// Subscribe to deleted users of the tenant of the viewer, with the
+    /// `users:read` permission.
+    #[graphql(guard = "PermissionGuard::new(READ_USERS)")]
+    async fn user_deleted(&self, ctx: &Context<'_>) -> Result<impl Stream<Item = Option<User>>> {
+        user_deleted(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>()).await
     }
 }
@@ -0,0 +1,240 @@
+use crate::core::authz::MANAGE_WEBHOOKS;
+use crate::core::models;
+use crate::core::webhooks;
+use crate::server::resolvers::webhook_resolver::create_webhook;
+use crate::server::resolvers::webhook_resolver::delete_webhook;
+use crate::server::resolvers::webhook_resolver::update_webhook;
+use crate::server::resolvers::webhook_resolver::webhook;
+use crate::server::resolvers::webhook_resolver::webhook_deliveries;
+use crate::server::schema::user_schema::authorize;
+use crate::server::viewer::PermissionGuard;
+use crate::server::viewer::Viewer;
+use async_graphql::connection::Connection;
+use async_graphql::ComplexObject;
+use async_graphql::Context;
+use async_graphql::Enum;
+use async_graphql::InputObject;
+use async_graphql::Json;
+use async_graphql::Object;
+use async_graphql::Result;
+use async_graphql::SimpleObject;
+use chrono::DateTime;
+use chrono::Utc;
+use deadpool_diesel::postgres::Pool;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// The events a webhook can subscribe to.
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+#[allow(
+    clippy::enum_variant_names,
+    reason = "the variants are exposed as the USER_* values of the GraphQL enum"
+)]
+pub enum WebhookEventType {
+    UserCreated,
+    UserUpdated,
+    UserDeleted,
+}
+
+impl WebhookEventType {
+    /// Get the event type as it is stored and delivered.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::UserCreated => "user.created",
+            WebhookEventType::UserUpdated => "user.updated",
+            WebhookEventType::UserDeleted => "user.deleted",
+        }
+    }
+
+    fn parse(event_type: &str) -> Option<WebhookEventType> {
+        match event_type {
+            "user.created" => Some(WebhookEventType::UserCreated),
+            "user.updated" => Some(WebhookEventType::UserUpdated),
+            "user.deleted" => Some(WebhookEventType::UserDeleted),
+            _ => None,
+        }
+    }
+}
+
+/// The state of a webhook delivery.
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Debug, PartialEq, SimpleObject)]
+#[graphql(complex)]
+pub struct Webhook {
+    pub id: Option<Uuid>,
+    pub url: Option<String>,
+    /// The signing secret, only returned when the webhook is created.
+    pub secret: Option<String>,
+    pub event_types: Option<Vec<WebhookEventType>>,
+    pub enabled: Option<bool>,
+    pub failure_count: Option<i32>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+impl From<models::Webhook> for Webhook {
+    fn from(webhook: models::Webhook) -> Self {
+        Webhook {
+            id: Some(webhook.id),
+            url: Some(webhook.url),
+            secret: None,
+            event_types: Some(
+                webhook
+                    .event_types
+                    .iter()
+                    .flatten()
+                    .filter_map(|event_type| WebhookEventType::parse(event_type))
+                    .collect(),
+            ),
+            enabled: Some(webhook.enabled),
+            failure_count: Some(webhook.failure_count),
+            created_at: Some(webhook.created_at.and_utc()),
+            updated_at: Some(webhook.updated_at.and_utc()),
+            disabled_at: webhook.disabled_at.map(|datetime| datetime.and_utc()),
+        }
+    }
+}
+
+#[ComplexObject]
+impl Webhook {
+    /// Get the deliveries, newest first.
+    #[graphql(
+        complexity = "crate::server::limits::connection_complexity(first, child_complexity)",
+        guard = "PermissionGuard::new(MANAGE_WEBHOOKS)"
+    )]
+    async fn deliveries(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, WebhookDelivery>> {
+        webhook_deliveries(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            self.id,
+            first,
+            after,
+        )
+        .await
+    }
+}
+
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct WebhookDelivery {
+    pub id: Option<Uuid>,
+    pub event_type: Option<String>,
+    pub payload: Option<Json<Value>>,
+    pub status: Option<WebhookDeliveryStatus>,
+    pub attempts: Option<i32>,
+    /// The HTTP status code of the last response, if the receiver responded.
+    pub response_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<models::WebhookDelivery> for WebhookDelivery {
+    fn from(delivery: models::WebhookDelivery) -> Self {
+        let status = match delivery.status.as_str() {
+            webhooks::DELIVERED => WebhookDeliveryStatus::Delivered,
+            webhooks::FAILED => WebhookDeliveryStatus::Failed,
+            _ => WebhookDeliveryStatus::Pending,
+        };
+
+        WebhookDelivery {
+            id: Some(delivery.id),
+            event_type: Some(delivery.event_type),
+            payload: Some(Json(delivery.payload)),
+            status: Some(status),
+            attempts: Some(delivery.attempts),
+            response_code: delivery.response_code,
+            last_error: delivery.last_error,
+            next_attempt_at: Some(delivery.next_attempt_at.and_utc()),
+            delivered_at: delivery.delivered_at.map(|datetime| datetime.and_utc()),
+            created_at: Some(delivery.created_at.and_utc()),
+        }
+    }
+}
+
+#[derive(InputObject)]
+pub struct WebhookInput {
+    pub url: Option<String>,
+    pub event_types: Option<Vec<WebhookEventType>>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Default)]
+pub struct WebhookMutation;
+
+#[Object]
+impl WebhookMutation {
+    /// Create a webhook for the tenant, returning its signing secret. The URL
+    /// must resolve to a public address.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        guard = "PermissionGuard::new(MANAGE_WEBHOOKS)",
+        directive = authorize::apply(None, Some(vec![MANAGE_WEBHOOKS.to_string()]))
+    )]
+    async fn create_webhook(
+        &self,
+        ctx: &Context<'_>,
+        input: Option<WebhookInput>,
+    ) -> Result<Option<Webhook>> {
+        create_webhook(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), input).await
+    }
+
+    /// Update a webhook. Enabling it resets its failure count.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        guard = "PermissionGuard::new(MANAGE_WEBHOOKS)",
+        directive = authorize::apply(None, Some(vec![MANAGE_WEBHOOKS.to_string()]))
+    )]
+    async fn update_webhook(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<Uuid>,
+        input: Option<WebhookInput>,
+    ) -> Result<Option<Webhook>> {
+        update_webhook(
+            ctx.data::<Pool>().unwrap(),
+            ctx.data_opt::<Viewer>(),
+            id,
+            input,
+        )
+        .await
+    }
+
+    /// Delete a webhook and its delivery history.
+    #[graphql(
+        complexity = "10 + child_complexity",
+        guard = "PermissionGuard::new(MANAGE_WEBHOOKS)",
+        directive = authorize::apply(None, Some(vec![MANAGE_WEBHOOKS.to_string()]))
+    )]
+    async fn delete_webhook(&self, ctx: &Context<'_>, id: Option<Uuid>) -> Result<Option<Webhook>> {
+        delete_webhook(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), id).await
+    }
+}
+
+#[derive(Default)]
+pub struct WebhookQuery;
+
+#[Object]
+impl WebhookQuery {
+    /// Get a webhook of the tenant.
+    #[graphql(
+        complexity = "5 + child_complexity",
+        guard = "PermissionGuard::new(MANAGE_WEBHOOKS)",
+        directive = authorize::apply(None, Some(vec![MANAGE_WEBHOOKS.to_string()]))
+    )]
+    async fn webhook(&self, ctx: &Context<'_>, id: Option<Uuid>) -> Result<Option<Webhook>> {
+        webhook(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>(), id).await
+    }
+}
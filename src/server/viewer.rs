@@ -0,0 +1,284 @@
+use crate::core::audit::Actor;
+use crate::core::authz;
+use crate::core::authz::Grants;
+use crate::core::masking;
+use crate::core::masking::MaskingPolicy;
+use crate::core::repo::DEFAULT_TENANT;
+use crate::core::sessions;
+use crate::core::users;
+use crate::server::resolvers::errors::GqlError::Forbidden;
+use crate::server::resolvers::errors::GqlError::InternalServer;
+use crate::server::resolvers::errors::GqlError::Unauthenticated;
+use async_graphql::Context;
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use async_graphql::Guard;
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use deadpool_diesel::postgres::Pool;
+use std::net::IpAddr;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+/// A signed-in user, its tenant, its grants and how it sees the personal data
+/// of other users.
+#[derive(Debug)]
+pub struct Identity {
+    pub user_id: Uuid,
+    pub tenant_id: Uuid,
+    pub grants: Grants,
+    pub masking: MaskingPolicy,
+}
+
+/// The header of the id of a request, set by the proxies in front of the
+/// server.
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// The user making a request, identified by the bearer token of a session.
+/// It is resolved at most once per request, when first needed.
+#[derive(Debug, Default)]
+pub struct Viewer {
+    token: Option<String>,
+    request_id: Option<String>,
+    ip_address: Option<IpAddr>,
+    identity: OnceCell<Option<Identity>>,
+}
+
+impl Viewer {
+    pub fn new(token: Option<String>) -> Viewer {
+        Viewer {
+            token,
+            request_id: None,
+            ip_address: None,
+            identity: OnceCell::new(),
+        }
+    }
+
+    /// Take the bearer token from the `Authorization` header, if any, and the
+    /// request id from the `X-Request-Id` header, or a new one.
+    pub fn from_headers(headers: &HeaderMap) -> Viewer {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let request_id = headers
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| Uuid::now_v7().to_string());
+
+        Viewer {
+            request_id: Some(request_id),
+            ..Viewer::new(token)
+        }
+    }
+
+    /// Take the bearer token from the `Authorization` field of the payload
+    /// initializing a subscription connection, if any, since browsers cannot
+    /// set the headers of websockets.
+    pub fn with_connection_payload(self, payload: &serde_json::Value) -> Viewer {
+        let token = payload
+            .get("Authorization")
+            .and_then(|value| value.as_str())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+
+        match token {
+            Some(token) => Viewer {
+                token: Some(token),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// Set the address the request comes from.
+    pub fn with_ip_address(self, ip_address: IpAddr) -> Viewer {
+        Viewer {
+            ip_address: Some(ip_address),
+            ..self
+        }
+    }
+
+    /// Get the signed-in user and its grants, or `None` for an anonymous or
+    /// expired session.
+    pub async fn identity(&self, pool: &Pool) -> Result<Option<&Identity>, Error> {
+        let identity = self
+            .identity
+            .get_or_try_init(|| async {
+                let token = match &self.token {
+                    Some(token) => token.clone(),
+                    None => return Ok(None),
+                };
+                let conn = pool.get().await.map_err(|_| InternalServer.extend())?;
+                let result = conn
+                    .interact(move |conn| {
+                        let user_id = match sessions::get_session_user(conn, &token)? {
+                            Some(user_id) => user_id,
+                            None => return Ok(None),
+                        };
+                        let tenant_id = match users::get_tenant_id(conn, user_id)? {
+                            Some(tenant_id) => tenant_id,
+                            None => return Ok(None),
+                        };
+                        let grants = authz::get_grants(conn, user_id)?;
+                        let masking = masking::get_masking_policy(conn, user_id)?;
+                        let identity = Identity {
+                            user_id,
+                            tenant_id,
+                            grants,
+                            masking,
+                        };
+                        Ok::<_, diesel::result::Error>(Some(identity))
+                    })
+                    .await;
+
+                match result {
+                    Ok(Ok(identity)) => Ok(identity),
+                    Ok(Err(_)) => Err(InternalServer.extend()),
+                    Err(_) => Err(InternalServer.extend()),
+                }
+            })
+            .await?;
+
+        Ok(identity.as_ref())
+    }
+}
+
+/// Get the signed-in viewer, failing if there is none.
+pub async fn authenticate<'a>(
+    pool: &Pool,
+    viewer: Option<&'a Viewer>,
+) -> Result<&'a Identity, Error> {
+    let identity = match viewer {
+        Some(viewer) => viewer.identity(pool).await?,
+        None => None,
+    };
+
+    identity.ok_or_else(|| Unauthenticated.extend())
+}
+
+/// Get the tenant the request is scoped to: the tenant of the signed-in
+/// viewer, or the default tenant for anonymous requests.
+pub async fn tenant(pool: &Pool, viewer: Option<&Viewer>) -> Result<Uuid, Error> {
+    let identity = match viewer {
+        Some(viewer) => viewer.identity(pool).await?,
+        None => None,
+    };
+
+    Ok(identity.map_or(DEFAULT_TENANT, |identity| identity.tenant_id))
+}
+
+/// Get who makes the changes of the request, for the audit log.
+pub async fn actor(pool: &Pool, viewer: Option<&Viewer>) -> Result<Actor, Error> {
+    let viewer = match viewer {
+        Some(viewer) => viewer,
+        None => return Ok(Actor::default()),
+    };
+    let identity = viewer.identity(pool).await?;
+
+    Ok(Actor {
+        user_id: identity.map(|identity| identity.user_id),
+        request_id: viewer.request_id.clone(),
+        ip_address: viewer.ip_address.map(|ip_address| ip_address.to_string()),
+    })
+}
+
+/// Get how the viewer sees the personal data of users: not at all for
+/// anonymous requests.
+pub async fn masking_policy(pool: &Pool, viewer: Option<&Viewer>) -> Result<MaskingPolicy, Error> {
+    let identity = match viewer {
+        Some(viewer) => viewer.identity(pool).await?,
+        None => None,
+    };
+
+    Ok(identity
+        .map(|identity| identity.masking.clone())
+        .unwrap_or_else(MaskingPolicy::anonymous))
+}
+
+/// Require the viewer to have a permission.
+pub struct PermissionGuard {
+    permission: &'static str,
+}
+
+impl PermissionGuard {
+    pub fn new(permission: &'static str) -> PermissionGuard {
+        PermissionGuard { permission }
+    }
+}
+
+impl Guard for PermissionGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<(), Error> {
+        let identity = authenticate(ctx.data::<Pool>().unwrap(), ctx.data_opt::<Viewer>()).await?;
+
+        if identity.grants.has_permission(self.permission) {
+            Ok(())
+        } else {
+            let reason = format!("The {} permission is required", self.permission);
+            Err(Forbidden(reason).extend())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::core::repo;
+    use crate::test::factory;
+    use axum::http::HeaderValue;
+    use diesel::Connection;
+    use diesel::PgConnection;
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(Viewer::from_headers(&headers).token, None);
+        assert_ne!(Viewer::from_headers(&headers).request_id, None);
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer abc"));
+        headers.insert(REQUEST_ID, HeaderValue::from_static("req-1"));
+
+        assert_eq!(
+            Viewer::from_headers(&headers).token,
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            Viewer::from_headers(&headers).request_id,
+            Some("req-1".to_string())
+        );
+
+        let payload = serde_json::json!({"Authorization": "Bearer def"});
+        let viewer = Viewer::from_headers(&headers).with_connection_payload(&payload);
+        assert_eq!(viewer.token, Some("def".to_string()));
+        assert_eq!(viewer.request_id, Some("req-1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_identity() {
+        let user = factory::insert_user();
+        let config = config::get_config();
+        let mut conn = PgConnection::establish(&config.database_url).unwrap();
+        let (_, token) = sessions::create_session(&mut conn, user.id).unwrap();
+        authz::grant_role(&mut conn, user.id, "admin").unwrap();
+        let pool = repo::connect_database(&config.database_url);
+        let viewer = Viewer::new(Some(token));
+
+        let identity = viewer.identity(&pool).await.unwrap().unwrap();
+
+        assert_eq!(identity.user_id, user.id);
+        assert_eq!(identity.tenant_id, DEFAULT_TENANT);
+        assert!(identity.grants.has_permission(authz::MANAGE_ROLES));
+        assert!(Viewer::default().identity(&pool).await.unwrap().is_none());
+        assert!(Viewer::new(Some("invalid".to_string()))
+            .identity(&pool)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -3,6 +3,7 @@ use crate::core::models::User;
 use crate::core::users;
 use diesel::Connection;
 use diesel::PgConnection;
+use uuid::Uuid;
 
 pub fn insert_user() -> User {
     let config = config::get_config();
@@ -13,7 +14,7 @@ pub fn insert_user() -> User {
         users::CreateUserAttrs {
             first_name: "Jane".to_string(),
             last_name: "Doe".to_string(),
-            email_address: "jane@doe.com".to_string(),
+            email_address: format!("jane.{}@doe.com", Uuid::now_v7().simple()),
         },
     )
     .unwrap()